@@ -1,15 +1,26 @@
 use clap_derive::Parser;
-use r3lib::R3Command;
 
 #[derive(Debug, Parser)]
+#[clap(trailing_var_arg = true)]
 pub struct Args {
     /// Path to the r3 socket
-    /// If not provided, r3-msg will try to read it from the R3_SOCKET_PATH on
+    /// If not provided, r3-msg will use $R3SOCK, or try to read it from the R3_SOCKET_PATH on
     /// the root window of the running X server
     #[clap(long = "socket", short = 's')]
     pub socket: Option<String>,
 
-    /// The command to send
-    #[clap(subcommand)]
-    pub command: R3Command,
+    /// The type of message to send, e.g. `get_tree`. This is the name of a command (with underscores
+    /// or dashes), and any words given after it are passed to that command. `command` sends the words
+    /// as they are
+    #[clap(long = "type", short = 't')]
+    pub message_type: Option<String>,
+
+    /// Print replies on a single line, rather than pretty-printing them
+    #[clap(long, short = 'r')]
+    pub raw: bool,
+
+    /// The command to send, written the same way as in key bindings, e.g. `close-window` or
+    /// `'focus-urgent --oldest'` (the words may be quoted together or given separately). Several
+    /// commands may be separated by `;`, and are run one after the other
+    pub command: Vec<String>,
 }
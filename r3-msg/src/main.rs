@@ -1,11 +1,12 @@
 mod args;
 
 use std::error::Error;
-use std::io::{Read, Write};
-use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::{env, process};
 
 use clap::Parser;
+use r3lib::ipc::{self, ErrorReply, Message, VersionReply};
+use r3lib::R3Command;
 use xcb::x::{GetProperty, ATOM_ANY};
 use xcb::{x, Connection, ProtocolError};
 
@@ -18,22 +19,133 @@ xcb::atoms_struct! {
     }
 }
 
-pub fn main() -> Result<(), Box<dyn Error>> {
+/// r3 replied to the command with an error
+const EXIT_FAILED: i32 = 1;
+/// The command couldn't be parsed
+const EXIT_INVALID_COMMAND: i32 = 2;
+/// r3 couldn't be found or talked to
+const EXIT_NO_CONNECTION: i32 = 3;
+
+pub fn main() {
     let args = Args::parse();
 
-    // If no socket path was provided, connect to X and look for the atom on the root window
-    let socket_path = args.socket.map_or_else(get_socket_path, |s| Ok(s))?;
+    let message = match parse_command(&args) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("Invalid command: {}", e);
+            process::exit(EXIT_INVALID_COMMAND);
+        }
+    };
+
+    match send_command(args.socket, &message, args.raw) {
+        Ok(true) => {}
+        Ok(false) => process::exit(EXIT_FAILED),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_NO_CONNECTION);
+        }
+    }
+}
+
+/// The command given on the command line, with the message type in front of it if there is one. This
+/// may be a chain of commands separated by `;`.
+fn parse_command(args: &Args) -> Result<Message, String> {
+    let message_type = match args.message_type.as_deref() {
+        None | Some("command") => None,
+        Some(message_type) => Some(message_type.replace('_', "-")),
+    };
+
+    let words = message_type
+        .into_iter()
+        .chain(args.command.iter().cloned())
+        .collect::<Vec<_>>();
+    let mut commands = r3lib::parse_commands(&words.join(" "))?;
+    match commands.len() {
+        1 => Ok(Message::Command(commands.remove(0))),
+        _ => Ok(Message::Chain(commands)),
+    }
+}
+
+/// Send the command to r3 and print its replies. Returns whether the command (or every command in a
+/// chain) succeeded.
+fn send_command(socket: Option<String>, message: &Message, raw: bool) -> Result<bool, Box<dyn Error>> {
+    // If no socket path was provided, use the one r3 gave the processes it started, otherwise connect
+    // to X and look for the atom on the root window
+    let socket_path = match socket.or_else(|| env::var(ipc::SOCKET_PATH_ENV).ok()) {
+        Some(path) => path,
+        None => get_socket_path()?,
+    };
+
+    let mut conn =
+        UnixStream::connect(&socket_path).map_err(|e| format!("Failed to connect to r3 at {}: {}", socket_path, e))?;
+
+    // Make sure r3 will understand us (unless we're only asking for its version)
+    if !matches!(message, Message::Command(R3Command::GetVersion)) {
+        check_protocol_version(&mut conn)?;
+    }
 
-    // TODO: right now r3 only supports a single command per read (reads until EOF)
-    //  we need to consider the case of long-lived IPC connections, and potentially buffer per line?
-    let mut conn = UnixStream::connect(socket_path)?;
-    conn.write_all(&serde_json::to_vec(&args.command)?)?;
-    conn.shutdown(Shutdown::Write)?;
+    ipc::send(&mut conn, message)?;
 
-    // Read response
-    let mut buffer = String::new();
-    conn.read_to_string(&mut buffer)?;
-    println!("response: {}", buffer);
+    // Subscribers are sent a message per event until they disconnect
+    if matches!(message, Message::Command(R3Command::Subscribe)) {
+        while let Some(event) = ipc::read_message(&mut conn)? {
+            print_message(&event, true)?;
+        }
+        return Ok(true);
+    }
+
+    let reply = ipc::read_message(&mut conn)?.ok_or("r3 closed the connection without replying")?;
+    if let Ok(ErrorReply { error }) = serde_json::from_slice(&reply) {
+        eprintln!("{}", error);
+        return Ok(false);
+    }
+
+    print_message(&reply, raw)?;
+    match message {
+        Message::Command(_) => Ok(true),
+        Message::Chain(_) => {
+            let replies = serde_json::from_slice::<Vec<serde_json::Value>>(&reply)?;
+            Ok(!replies
+                .into_iter()
+                .any(|reply| serde_json::from_value::<ErrorReply>(reply).is_ok()))
+        }
+    }
+}
+
+/// Ask r3 which version of the protocol it speaks, and fail if it isn't ours.
+fn check_protocol_version(conn: &mut UnixStream) -> Result<(), Box<dyn Error>> {
+    ipc::send(conn, &R3Command::GetVersion)?;
+    let reply = ipc::recv::<VersionReply>(conn)
+        .ok()
+        .flatten()
+        .ok_or("r3 didn't reply with its version, it may be older than r3-msg")?;
+
+    if reply.protocol_version != ipc::PROTOCOL_VERSION {
+        return Err(format!(
+            "r3 {} speaks version {} of the IPC protocol, but r3-msg speaks version {}",
+            reply.version,
+            reply.protocol_version,
+            ipc::PROTOCOL_VERSION
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Print a message from r3, pretty-printing it unless `raw` is set.
+fn print_message(message: &[u8], raw: bool) -> Result<(), Box<dyn Error>> {
+    match raw {
+        true => println!("{}", String::from_utf8_lossy(message)),
+        false => {
+            let value = serde_json::from_slice::<serde_json::Value>(message)?;
+            // Strings are printed as they are, so text (such as a tree) can be read or piped
+            match value {
+                serde_json::Value::String(s) => println!("{}", s),
+                value => println!("{}", serde_json::to_string_pretty(&value)?),
+            }
+        }
+    }
 
     Ok(())
 }
@@ -44,9 +156,7 @@ fn get_socket_path() -> Result<String, Box<dyn Error>> {
     let (conn, default_screen) = match Connection::connect(None) {
         Ok(inner) => inner,
         Err(_) => {
-            return Err(format!(
-                "Failed to connect to X. Ensure the DISPLAY environment variable is set."
-            ))?
+            return Err("Failed to connect to X. Ensure the DISPLAY environment variable is set.".into());
         }
     };
     let root_window = conn.get_setup().roots().nth(default_screen as usize).unwrap().root();
@@ -54,7 +164,7 @@ fn get_socket_path() -> Result<String, Box<dyn Error>> {
     // Fetch the Atom from the X server
     let atoms = Atoms::intern_all(&conn)?;
     if atoms.r3_socket_path == x::ATOM_NONE {
-        return Err("The X server isn't aware of R3_SOCKET_PATH. Is r3 running?")?;
+        return Err("The X server isn't aware of R3_SOCKET_PATH. Is r3 running?".into());
     }
 
     // Read the atom on the root X window
@@ -69,7 +179,7 @@ fn get_socket_path() -> Result<String, Box<dyn Error>> {
 
     let reply = match conn.wait_for_reply(cookie) {
         Err(xcb::Error::Protocol(ProtocolError::X(x::Error::Atom(_), _))) => {
-            return Err("Failed to find R3_SOCKET_PATH atom on the root window. Is r3 running?")?;
+            return Err("Failed to find R3_SOCKET_PATH atom on the root window. Is r3 running?".into());
         }
         Err(e) => panic!("{}", e),
         Ok(reply) => reply,
@@ -82,7 +192,7 @@ fn get_socket_path() -> Result<String, Box<dyn Error>> {
     };
 
     if value.is_empty() {
-        return Err("Found R3_SOCKET_PATH, but it was empty.")?;
+        return Err("Found R3_SOCKET_PATH, but it was empty.".into());
     }
 
     Ok(value)
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use clap_derive::Parser;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Path to the configuration file
+    /// If not provided, r3 reads $XDG_CONFIG_HOME/r3/config.toml if it exists
+    #[clap(long = "config", short = 'c')]
+    pub config: Option<PathBuf>,
+
+    /// Check the configuration file is valid and exit, with a non-zero status if it isn't
+    #[clap(long = "check-config")]
+    pub check_config: bool,
+
+    /// Translate an i3 configuration file into an r3 one and print it, reporting anything which
+    /// couldn't be translated
+    #[clap(long = "import-i3", value_name = "I3_CONFIG")]
+    pub import_i3: Option<PathBuf>,
+}
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use r3lib::R3Command;
+use serde::{de, ser, Deserialize, Deserializer, Serializer};
+use xcb::x;
+
+/// Modifiers which can be used in key combinations, and the names they're written with. `mod` is the
+/// Super (or Windows) key. There's no `mod2`, since that's Num Lock on most keyboards and bindings
+/// ignore it (see `IGNORED_MODIFIERS`).
+const MODIFIERS: &[(&str, x::ModMask)] = &[
+    ("shift", x::ModMask::SHIFT),
+    ("ctrl", x::ModMask::CONTROL),
+    ("control", x::ModMask::CONTROL),
+    ("alt", x::ModMask::N1),
+    ("mod1", x::ModMask::N1),
+    ("mod3", x::ModMask::N3),
+    ("mod", x::ModMask::N4),
+    ("super", x::ModMask::N4),
+    ("mod4", x::ModMask::N4),
+    ("mod5", x::ModMask::N5),
+];
+
+/// Names of keys which aren't written as the character they type. See `X11/keysymdef.h` and
+/// `X11/XF86keysym.h` for their values.
+const KEYSYMS: &[(&str, x::Keysym)] = &[
+    ("space", 0x0020),
+    ("apostrophe", 0x0027),
+    ("plus", 0x002b),
+    ("comma", 0x002c),
+    ("minus", 0x002d),
+    ("period", 0x002e),
+    ("slash", 0x002f),
+    ("semicolon", 0x003b),
+    ("equal", 0x003d),
+    ("bracketleft", 0x005b),
+    ("backslash", 0x005c),
+    ("bracketright", 0x005d),
+    ("grave", 0x0060),
+    ("BackSpace", 0xff08),
+    ("Tab", 0xff09),
+    ("Return", 0xff0d),
+    ("Escape", 0xff1b),
+    ("Home", 0xff50),
+    ("Left", 0xff51),
+    ("Up", 0xff52),
+    ("Right", 0xff53),
+    ("Down", 0xff54),
+    ("Page_Up", 0xff55),
+    ("Page_Down", 0xff56),
+    ("End", 0xff57),
+    ("Print", 0xff61),
+    ("Insert", 0xff63),
+    ("Menu", 0xff67),
+    ("Delete", 0xffff),
+    ("XF86AudioLowerVolume", 0x1008ff11),
+    ("XF86AudioMute", 0x1008ff12),
+    ("XF86AudioRaiseVolume", 0x1008ff13),
+    ("XF86AudioPlay", 0x1008ff14),
+    ("XF86AudioNext", 0x1008ff17),
+    ("XF86AudioPrev", 0x1008ff16),
+    ("XF86MonBrightnessUp", 0x1008ff02),
+    ("XF86MonBrightnessDown", 0x1008ff03),
+];
+
+/// The keysym of F1, the other function keys follow it
+const KEYSYM_F1: x::Keysym = 0xffbe;
+
+/// A key pressed while holding some modifiers, written like `ctrl+shift+q` or `mod+Return`. Names are
+/// case-insensitive, and keys which type a character can be written as that character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub modifiers: x::ModMask,
+    pub keysym: x::Keysym,
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = match parts.pop() {
+            Some(key) if !key.is_empty() => key,
+            _ => return Err(format!("expected a key at the end of \"{}\"", s)),
+        };
+
+        let mut modifiers = x::ModMask::empty();
+        for part in parts {
+            match MODIFIERS.iter().find(|(name, _)| name.eq_ignore_ascii_case(part)) {
+                Some((_, modifier)) => modifiers |= *modifier,
+                None => return Err(format!("unknown modifier \"{}\" in \"{}\"", part, s)),
+            }
+        }
+
+        let keysym = parse_keysym(key).ok_or_else(|| format!("unknown key \"{}\" in \"{}\"", key, s))?;
+        Ok(KeyCombo { modifiers, keysym })
+    }
+}
+
+fn parse_keysym(key: &str) -> Option<x::Keysym> {
+    if let Some((_, keysym)) = KEYSYMS.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)) {
+        return Some(*keysym);
+    }
+
+    // Function keys
+    if let Some(n) = key.strip_prefix(['F', 'f']).and_then(|n| n.parse::<x::Keysym>().ok()) {
+        return (1..=35).contains(&n).then(|| KEYSYM_F1 + n - 1);
+    }
+
+    // Keys which type a (printable ASCII) character have that character as their keysym. Letters are
+    // always lowercase, since shift is written as a modifier
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_graphic() => Some(c.to_ascii_lowercase() as x::Keysym),
+        _ => None,
+    }
+}
+
+/// Run a command when a key combination is pressed
+#[derive(Debug, Clone)]
+pub struct KeyBinding {
+    pub combo: KeyCombo,
+    pub command: R3Command,
+}
+
+/// The bindings used if none are configured, as they'd be written in the configuration
+pub const DEFAULT_BINDINGS: [(&str, &str); 2] = [("ctrl+shift+q", "exit"), ("ctrl+q", "close-window")];
+
+/// The bindings used if none are configured.
+pub fn default_bindings() -> Vec<KeyBinding> {
+    DEFAULT_BINDINGS
+        .iter()
+        .map(|(combo, command)| KeyBinding {
+            combo: combo.parse().unwrap(),
+            command: command.parse().unwrap(),
+        })
+        .collect()
+}
+
+/// Find the modifier with the given name, e.g. `super`.
+pub fn parse_modifier(name: &str) -> Option<x::ModMask> {
+    MODIFIERS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, modifier)| *modifier)
+}
+
+/// Deserialise a single modifier, e.g. `"super"`.
+pub fn deserialize_modifier<'de, D: Deserializer<'de>>(deserializer: D) -> Result<x::ModMask, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_modifier(&name).ok_or_else(|| de::Error::custom(format!("unknown modifier \"{}\"", name)))
+}
+
+/// Serialise a single modifier by its name, e.g. `"ctrl"`.
+pub fn serialize_modifier<S: Serializer>(modifier: &x::ModMask, serializer: S) -> Result<S::Ok, S::Error> {
+    match MODIFIERS.iter().find(|(_, m)| m == modifier) {
+        Some((name, _)) => serializer.serialize_str(name),
+        None => Err(ser::Error::custom(format!("{:?} isn't a single modifier", modifier))),
+    }
+}
+
+/// Deserialise bindings from a table of key combinations and the commands they run, e.g.
+/// `"mod+Return" = "exec alacritty"`.
+pub fn deserialize_bindings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<KeyBinding>, D::Error> {
+    let mut bindings = vec![];
+    let mut names = vec![];
+    for (name, command) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let combo = name.parse::<KeyCombo>().map_err(de::Error::custom)?;
+        if let Some(i) = bindings.iter().position(|binding: &KeyBinding| binding.combo == combo) {
+            return Err(de::Error::custom(format!(
+                "\"{}\" and \"{}\" are bound to the same keys",
+                names[i], name
+            )));
+        }
+
+        bindings.push(KeyBinding {
+            combo,
+            command: command
+                .parse()
+                .map_err(|e| de::Error::custom(format!("invalid command for \"{}\": {}", name, e)))?,
+        });
+        names.push(name);
+    }
+
+    Ok(bindings)
+}
+
+/// Deserialise the bindings of specific devices, from a table of device names and the bindings of each
+/// device, e.g. `[device_bindings."Macro Pad"]` followed by `"a" = "exec alacritty"`.
+pub fn deserialize_device_bindings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Vec<KeyBinding>>, D::Error> {
+    #[derive(Deserialize)]
+    struct Bindings(#[serde(deserialize_with = "deserialize_bindings")] Vec<KeyBinding>);
+
+    Ok(BTreeMap::<String, Bindings>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(device, bindings)| (device, bindings.0))
+        .collect())
+}
+
+/// Which way a swipe moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// A swipe made with some fingers on a touchscreen, written like `3-finger-swipe-left`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gesture {
+    pub fingers: usize,
+    pub direction: SwipeDirection,
+}
+
+impl FromStr for Gesture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a gesture like \"3-finger-swipe-left\", not \"{}\"", s);
+        let (fingers, direction) = s.split_once("-finger-swipe-").ok_or_else(invalid)?;
+        let fingers = match fingers.parse() {
+            Ok(fingers) if (2..=5).contains(&fingers) => fingers,
+            _ => return Err(format!("gestures need 2 to 5 fingers, not \"{}\"", fingers)),
+        };
+        let direction = match direction.to_ascii_lowercase().as_str() {
+            "left" => SwipeDirection::Left,
+            "right" => SwipeDirection::Right,
+            "up" => SwipeDirection::Up,
+            "down" => SwipeDirection::Down,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Gesture { fingers, direction })
+    }
+}
+
+/// Run a command when a gesture is made
+#[derive(Debug, Clone)]
+pub struct GestureBinding {
+    pub gesture: Gesture,
+    pub command: R3Command,
+}
+
+/// Deserialise gesture bindings from a table of gestures and the commands they run, e.g.
+/// `"3-finger-swipe-left" = "focus-last"`.
+pub fn deserialize_gestures<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<GestureBinding>, D::Error> {
+    let mut bindings = vec![];
+    for (name, command) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let gesture = name.parse::<Gesture>().map_err(de::Error::custom)?;
+        if bindings
+            .iter()
+            .any(|binding: &GestureBinding| binding.gesture == gesture)
+        {
+            return Err(de::Error::custom(format!("\"{}\" is bound more than once", name)));
+        }
+
+        bindings.push(GestureBinding {
+            gesture,
+            command: command
+                .parse()
+                .map_err(|e| de::Error::custom(format!("invalid command for \"{}\": {}", name, e)))?,
+        });
+    }
+
+    Ok(bindings)
+}
+
+/// A part of a frame which can be clicked
+// TODO: tabs, once frames can hold several windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRegion {
+    Titlebar,
+    /// The border along an edge of the frame (or the frame around the window, where it shows)
+    Border,
+    /// The border near a corner of the frame
+    Corner,
+}
+
+/// A mouse button pressed on a part of a frame, written like `titlebar+button2`. Buttons 4 and 5 are
+/// usually scrolling up and down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameClick {
+    pub region: FrameRegion,
+    pub button: u8,
+}
+
+impl FromStr for FrameClick {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (region, button) = s
+            .split_once('+')
+            .ok_or_else(|| format!("expected a click like \"titlebar+button2\", not \"{}\"", s))?;
+        let region = match region.trim().to_ascii_lowercase().as_str() {
+            "titlebar" => FrameRegion::Titlebar,
+            "border" => FrameRegion::Border,
+            "corner" => FrameRegion::Corner,
+            _ => return Err(format!("unknown part of a frame \"{}\" in \"{}\"", region, s)),
+        };
+        let button = parse_button(button)
+            .ok_or_else(|| format!("expected button1 to button5, not \"{}\" in \"{}\"", button, s))?;
+
+        Ok(FrameClick { region, button })
+    }
+}
+
+/// Parse a mouse button written like `button2`.
+fn parse_button(button: &str) -> Option<u8> {
+    match button
+        .trim()
+        .to_ascii_lowercase()
+        .strip_prefix("button")
+        .map(str::parse)
+    {
+        Some(Ok(button @ 1..=5)) => Some(button),
+        _ => None,
+    }
+}
+
+/// What clicking a part of a frame does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameAction {
+    /// Move the window by dragging it
+    Move,
+    /// Resize the window by dragging it
+    Resize,
+    /// Run a command, on the clicked window if it acts on a window (e.g. `close-window`)
+    Command(R3Command),
+}
+
+impl FromStr for FrameAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "move" => Ok(FrameAction::Move),
+            "resize" => Ok(FrameAction::Resize),
+            command => command.parse().map(FrameAction::Command),
+        }
+    }
+}
+
+/// Do something when a part of a frame is clicked
+#[derive(Debug, Clone)]
+pub struct FrameBinding {
+    pub click: FrameClick,
+    pub action: FrameAction,
+}
+
+/// Deserialise frame bindings from a table of clicks and what they do, e.g.
+/// `"titlebar+button2" = "close-window"` or `"corner+button1" = "resize"`.
+pub fn deserialize_frame_bindings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<FrameBinding>, D::Error> {
+    let mut bindings = vec![];
+    for (name, action) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let click = name.parse::<FrameClick>().map_err(de::Error::custom)?;
+        if bindings.iter().any(|binding: &FrameBinding| binding.click == click) {
+            return Err(de::Error::custom(format!("\"{}\" is bound more than once", name)));
+        }
+
+        bindings.push(FrameBinding {
+            click,
+            action: action
+                .parse()
+                .map_err(|e| de::Error::custom(format!("invalid command for \"{}\": {}", name, e)))?,
+        });
+    }
+
+    Ok(bindings)
+}
+
+/// A mouse button pressed on the desktop (the root window) while holding some modifiers, written like
+/// `button2` or `mod+button4`. Buttons 4 and 5 are usually scrolling up and down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootClick {
+    pub modifiers: x::ModMask,
+    pub button: u8,
+}
+
+impl FromStr for RootClick {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let button = parts
+            .pop()
+            .and_then(parse_button)
+            .ok_or_else(|| format!("expected button1 to button5 at the end of \"{}\"", s))?;
+
+        let mut modifiers = x::ModMask::empty();
+        for part in parts {
+            modifiers |= parse_modifier(part).ok_or_else(|| format!("unknown modifier \"{}\" in \"{}\"", part, s))?;
+        }
+
+        Ok(RootClick { modifiers, button })
+    }
+}
+
+/// Run a command when the desktop is clicked
+#[derive(Debug, Clone)]
+pub struct RootBinding {
+    pub click: RootClick,
+    pub command: R3Command,
+}
+
+/// Deserialise desktop bindings from a table of clicks and the commands they run, e.g.
+/// `"button2" = "exec rofi -show run"`.
+pub fn deserialize_root_bindings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<RootBinding>, D::Error> {
+    let mut bindings = vec![];
+    for (name, command) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let click = name.parse::<RootClick>().map_err(de::Error::custom)?;
+        if bindings.iter().any(|binding: &RootBinding| binding.click == click) {
+            return Err(de::Error::custom(format!("\"{}\" is bound more than once", name)));
+        }
+
+        bindings.push(RootBinding {
+            click,
+            command: command
+                .parse()
+                .map_err(|e| de::Error::custom(format!("invalid command for \"{}\": {}", name, e)))?,
+        });
+    }
+
+    Ok(bindings)
+}
@@ -1,11 +1,753 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use toml::value::{Table, Value};
+use xcb::x;
+
+use crate::bindings::{
+    default_bindings, deserialize_bindings, deserialize_device_bindings, deserialize_frame_bindings,
+    deserialize_gestures, deserialize_modifier, deserialize_root_bindings, serialize_modifier, FrameBinding,
+    GestureBinding, KeyBinding, RootBinding, DEFAULT_BINDINGS,
+};
+use crate::color::Color;
+use crate::font::FontSpec;
+
+/// Selects which windows a [`ClassRemap`] applies to, e.g. `{ instance = "slack" }`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassSelector {
+    /// Windows with this class
+    Class(String),
+    /// Windows with this instance
+    Instance(String),
+    /// Windows whose process' command line (found via `_NET_WM_PID`) contains this string
+    Cmdline(String),
+}
+
+/// Treat matching windows as if they had a different class. This is useful for apps which all report
+/// the same class, e.g. Electron apps which report "Electron"
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassRemap {
+    pub selector: ClassSelector,
+    pub class: String,
+}
+
+/// Where to place new floating windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionPolicy {
+    /// Put windows wherever they ask to be
+    Honor,
+    /// Center windows on the screen
+    Center,
+    /// Put windows where they overlap other windows the least
+    Smart,
+}
+
+/// Commands to run when outputs (monitors) change, e.g. `[output_hooks]` followed by
+/// `connected = "autorandr --change"`. The name of the output is in `$R3_OUTPUT`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputHooks {
+    /// When an output is connected
+    pub connected: Option<String>,
+    /// When an output is disconnected
+    pub disconnected: Option<String>,
+    /// When a laptop's built-in panel turns off (usually because its lid was closed)
+    pub panel_off: Option<String>,
+    /// When a laptop's built-in panel turns on again
+    pub panel_on: Option<String>,
+}
+
+/// Who may use r3's sockets, e.g. `[ipc]` followed by `check_credentials = true`. Only r3's user (and
+/// root) can open the sockets, unless there are `read_only_users`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpcAccess {
+    /// Check who is running each client (with `SO_PEERCRED`), refusing clients run by other users
+    pub check_credentials: bool,
+    /// The ids of other users whose clients may ask for things and subscribe to events, but not run
+    /// commands (e.g. for monitoring tools). Their credentials are always checked, and every user can
+    /// open the sockets. NOTE: the sockets are in `$XDG_RUNTIME_DIR/r3`, and `$XDG_RUNTIME_DIR` is
+    /// usually only open to its owner, so these users can't reach the sockets unless it's made
+    /// searchable for them too (e.g. with `chmod o+x`)
+    pub read_only_users: Vec<u32>,
+}
+
+/// Which edge of the frame the titlebar is drawn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitlebarPosition {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    /// Don't draw a titlebar
+    None,
+}
+
+/// Space left around windows in pixels, e.g. `[gaps]` followed by `inner = 10`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gaps {
+    /// Between windows
+    pub inner: u16,
+    /// Between windows and the edges of the screen
+    pub outer: u16,
+}
+
+/// How the background of frames is painted, which is seen while a window is being resized (before it
+/// has repainted itself)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameBackground {
+    /// Painted with the background colour of the frame's state
+    Solid,
+    /// Not painted at all, so whatever was there before is left behind
+    None,
+    /// Painted with the root window's background
+    ParentRelative,
+}
+
+/// The colours of a frame in one state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FrameColors {
+    pub border: Color,
+    /// Background of the frame, which is seen behind the title
+    pub background: Color,
+    /// Colour of the title
+    pub text: Color,
+}
+
+/// The colours of frames, depending on the state of their window, e.g. `[colors.focused]` followed by
+/// `border = "#ff0000"`. Any colours which aren't given keep their default
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "PartialColorScheme")]
+pub struct ColorScheme {
+    pub focused: FrameColors,
+    pub unfocused: FrameColors,
+    /// Windows which have set the urgency hint (unless they're focused)
+    pub urgent: FrameColors,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        let colors = |border| FrameColors {
+            border: Color(border),
+            background: Color(0x0000ff),
+            text: Color(0xffffff),
+        };
+
+        ColorScheme {
+            focused: colors(0xff0000),
+            unfocused: colors(0xaaaaaa),
+            urgent: colors(0xffaa00),
+        }
+    }
+}
+
+/// A [`ColorScheme`] as it's written in the configuration, where any colour may be left out
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PartialColorScheme {
+    focused: PartialFrameColors,
+    unfocused: PartialFrameColors,
+    urgent: PartialFrameColors,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PartialFrameColors {
+    border: Option<Color>,
+    background: Option<Color>,
+    text: Option<Color>,
+}
+
+impl PartialFrameColors {
+    fn or(self, defaults: FrameColors) -> FrameColors {
+        FrameColors {
+            border: self.border.unwrap_or(defaults.border),
+            background: self.background.unwrap_or(defaults.background),
+            text: self.text.unwrap_or(defaults.text),
+        }
+    }
+}
+
+impl From<PartialColorScheme> for ColorScheme {
+    fn from(partial: PartialColorScheme) -> Self {
+        let defaults = ColorScheme::default();
+        ColorScheme {
+            focused: partial.focused.or(defaults.focused),
+            unfocused: partial.unfocused.or(defaults.unfocused),
+            urgent: partial.urgent.or(defaults.urgent),
+        }
+    }
+}
+
+/// Changes made to matching windows when they're mapped, e.g. `[[rules]]` followed by `class = "mpv"`
+/// and `border_width = 0`. Every criterion which is given must match, and when several rules change
+/// the same thing the last one wins
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowRule {
+    /// Windows with this class (after any class remaps are applied)
+    pub class: Option<String>,
+    /// Windows with this instance
+    pub instance: Option<String>,
+    /// Windows with this role (`WM_WINDOW_ROLE`), which some programs use to tell their windows apart,
+    /// e.g. GIMP's toolbox
+    pub role: Option<String>,
+    /// Windows whose title contains this string
+    pub title: Option<String>,
+
+    /// Width of the border around the window's frame in pixels
+    pub border_width: Option<u16>,
+    /// Where to draw the window's titlebar (or `none`). This applies even if `titlebar` is off
+    pub titlebar_position: Option<TitlebarPosition>,
+    /// Where to put the window (x, y), instead of using the position policy
+    pub position: Option<(i16, i16)>,
+    /// The size of the window (width, height), which is still kept within the floating size limits
+    pub size: Option<(u16, u16)>,
+    /// Whether the window floats when `tiling` is on. If this isn't set, dialogs and other windows
+    /// which shouldn't be tiled float and the rest are tiled
+    // TODO: assigning windows to a workspace, once we have workspaces
+    pub floating: Option<bool>,
+}
+
+impl WindowRule {
+    /// The criteria the rule matches windows with, written as they are in the file, e.g.
+    /// `class = "mpv", title = "x"`.
+    pub fn describe_criteria(&self) -> String {
+        [
+            ("class", &self.class),
+            ("instance", &self.instance),
+            ("role", &self.role),
+            ("title", &self.title),
+        ]
+        .iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| format!("{} = {:?}", key, value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// Configuration read from a TOML file, where each field is a key of the same name. Durations are
+/// written like `"100ms"`, and any keys which are missing use their default value.
+///
+/// The file may also have:
+/// - `include = ["other.toml"]`: files (relative to this one) which are read first, with this file's
+///   values taking precedence. Tables such as `[bindings]` are merged rather than replaced
+/// - a `[variables]` table, e.g. `mod = "super"`: `$mod` in any other key or value is replaced with
+///   `super`. References to variables which aren't defined are left alone (e.g. `exec echo $HOME`)
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub focus_follows_mouse: bool,
+    /// How long the pointer has to stay on a window before it's focused (when `focus_follows_mouse`
+    /// is on), so sweeping the pointer across windows doesn't focus each of them
+    #[serde(with = "humantime_serde")]
+    pub focus_follows_mouse_delay: Duration,
+    /// Move the pointer to the middle of windows which are focused by a command (e.g. a key binding),
+    /// unless it's already on them
+    pub mouse_warping: bool,
+    /// When moving or resizing windows with the mouse, only draw an outline of the window and apply
+    /// the change when the drag is finished
+    pub drag_outline: bool,
+    /// Holding this modifier (e.g. `"super"`) while dragging a window with the mouse moves or resizes
+    /// it. Dragging a window by its titlebar doesn't need the modifier
+    #[serde(deserialize_with = "deserialize_modifier", serialize_with = "serialize_modifier")]
+    pub drag_modifier: x::ModMask,
+    /// The mouse button which moves windows when they're dragged (1 is the left button)
+    #[serde(deserialize_with = "deserialize_button")]
+    pub drag_move_button: u8,
+    /// The mouse button which resizes windows when they're dragged (3 is the right button)
+    #[serde(deserialize_with = "deserialize_button")]
+    pub drag_resize_button: u8,
+    /// What clicking on parts of frames does, e.g. `"titlebar+button2" = "close-window"`. The values are
+    /// `move` or `resize` to drag the window, or a command, which acts on the clicked window. Clicks
+    /// which aren't bound move or resize the window with the drag buttons
+    #[serde(deserialize_with = "deserialize_frame_bindings", skip_serializing)]
+    pub frame_bindings: Vec<FrameBinding>,
+    /// Commands run by clicking or scrolling on the desktop, e.g. `"button2" = "exec rofi -show run"`
+    #[serde(deserialize_with = "deserialize_root_bindings", skip_serializing)]
+    pub root_bindings: Vec<RootBinding>,
+    /// How often windows are updated while they're being dragged
+    #[serde(with = "humantime_serde")]
+    pub drag_update_interval: Duration,
+    /// The smallest size (width, height) floating windows can be
+    pub floating_minimum_size: Option<(u16, u16)>,
+    /// The largest size (width, height) floating windows can be
+    pub floating_maximum_size: Option<(u16, u16)>,
+    /// Where to place new windows. Windows which the user has asked to be placed somewhere (with the
+    /// `USPosition` hint, e.g. `xterm -geometry +100+100`) are always put where they ask to be
+    pub position_policy: PositionPolicy,
+    /// Tile new windows: they fill the screen between them, split side by side (or one above the other
+    /// after `split vertical`). Dialogs, fixed-size windows and windows which aren't normal application
+    /// windows (e.g. splash screens) still float. It's off by default until tiled windows can be
+    /// dragged and resized, and made to float, since until then they can't be rearranged by hand
+    pub tiling: bool,
+    /// Gaps which are kept when placing windows, and around and between tiled windows
+    pub gaps: Gaps,
+
+    /// Commands to run when keys are pressed, e.g. `"mod+Return" = "exec alacritty"`. Commands are
+    /// written the same way as they are with `r3-msg`. If any are configured, the default bindings are
+    /// replaced
+    #[serde(deserialize_with = "deserialize_bindings", skip_serializing)]
+    pub bindings: Vec<KeyBinding>,
+    /// Bindings which only work on a single keyboard, by the device's name as shown by `xinput list`,
+    /// e.g. `[device_bindings."Macro Pad"]` and then `"a" = "exec alacritty"`. The keys are grabbed on
+    /// that device alone, so they type as usual on other keyboards. Needs XInput 2
+    #[serde(deserialize_with = "deserialize_device_bindings", skip_serializing)]
+    pub device_bindings: BTreeMap<String, Vec<KeyBinding>>,
+    /// Commands to run when swiping with several fingers on a touchscreen, e.g.
+    /// `"3-finger-swipe-left" = "focus-last"`. While any are configured, windows only receive touches
+    /// once it's clear they aren't part of a gesture (e.g. when a single finger moves). Needs XInput 2.2
+    #[serde(deserialize_with = "deserialize_gestures", skip_serializing)]
+    pub gestures: Vec<GestureBinding>,
+
+    /// Width of the border around frames in pixels
+    pub border_width: u16,
+    /// Whether frames should draw a titlebar
+    pub titlebar: bool,
+    /// Which edge of the frame titlebars are drawn on. Titles on the left or right are drawn sideways
+    /// (reading upwards on the left and downwards on the right), for vertical tab strips
+    pub titlebar_position: TitlebarPosition,
+    /// Thickness of titlebars in pixels (their height, or their width on the left or right). If not
+    /// set, this is calculated from the font's metrics
+    pub titlebar_height: Option<u16>,
+    /// Font used to draw text, such as window titles
+    pub font: FontSpec,
+
+    /// Colours of frames and their titles
+    pub colors: ColorScheme,
+    /// How the background of frames is painted
+    pub frame_background: FrameBackground,
+    /// The least time between repainting frames and titles. Changes made in between are drawn together
+    /// once it's passed, so clients which change quickly (e.g. their titles) can't flood the server;
+    /// `16ms` draws at most roughly 60 times per second. By default changes are drawn immediately
+    #[serde(with = "humantime_serde")]
+    pub render_interval: Duration,
+    /// If set, the root window is painted this colour, so a wallpaper tool isn't needed for a plain
+    /// background
+    // TODO: a background (colour or image) per workspace, once we have workspaces
+    pub root_background: Option<Color>,
+
+    /// If set, the borders of urgent windows flash on and off at this interval
+    #[serde(with = "humantime_serde")]
+    pub urgent_flash_interval: Option<Duration>,
+
+    /// How often mirrored windows are copied
+    #[serde(with = "humantime_serde")]
+    pub mirror_update_interval: Duration,
+
+    /// Classes to use in place of the ones windows report, the first matching remap is used
+    pub class_remaps: Vec<ClassRemap>,
+
+    /// Rules which change how matching windows are managed
+    pub rules: Vec<WindowRule>,
+
+    /// Subscribed IPC clients are sent an `idle` event when the user hasn't used the keyboard or mouse
+    /// for each of these times, and a `resume` event when they do again
+    #[serde(deserialize_with = "deserialize_durations", serialize_with = "serialize_durations")]
+    pub idle_thresholds: Vec<Duration>,
+
+    /// Subscribed IPC clients are sent events when outputs are connected or disconnected, or the
+    /// built-in panel turns off or on, and these commands are run too
+    pub output_hooks: OutputHooks,
+
+    /// Who may use r3's sockets. Changes apply to clients which connect after them
+    pub ipc: IpcAccess,
+
+    /// Keep the clipboard's contents when the window they were copied from closes, by acting as a
+    /// clipboard manager (clients must support the `CLIPBOARD_MANAGER` protocol, as GTK and Qt do)
+    pub clipboard_manager: bool,
+
+    /// Commands to run once r3 has started, e.g. `["picom", "nm-applet"]`. These aren't run again
+    /// when the configuration is reloaded
+    pub autostart: Vec<String>,
+    /// The shell used to run commands
+    pub exec_shell: String,
+    /// The directory commands are run in (defaults to `$HOME`)
+    pub exec_working_dir: Option<PathBuf>,
+
+    /// `include` and `[variables]` are handled while the files are read (see `read_table`), but are
+    /// accepted here so each file can be checked on its own (see `locate_error`)
+    #[serde(rename = "include", skip_serializing)]
+    _include: de::IgnoredAny,
+    #[serde(rename = "variables", skip_serializing)]
+    _variables: de::IgnoredAny,
+
+    /// The files this configuration was read from (including included files), in the order they
+    /// were read
+    #[serde(skip)]
+    pub paths: Vec<PathBuf>,
+    /// The values set by the configuration files, with variables expanded. Anything which isn't in
+    /// here uses its default value
+    #[serde(skip)]
+    pub table: Table,
 }
 
-impl Config {
-    pub fn new() -> Config {
+impl Default for Config {
+    fn default() -> Self {
         Config {
             focus_follows_mouse: true,
+            focus_follows_mouse_delay: Duration::ZERO,
+            mouse_warping: false,
+            drag_outline: false,
+            drag_modifier: x::ModMask::CONTROL,
+            drag_move_button: 1,
+            drag_resize_button: 3,
+            frame_bindings: vec![],
+            root_bindings: vec![],
+            // Roughly 60 times per second
+            drag_update_interval: Duration::from_millis(16),
+            floating_minimum_size: None,
+            floating_maximum_size: None,
+            position_policy: PositionPolicy::Honor,
+            tiling: false,
+            gaps: Gaps::default(),
+
+            bindings: default_bindings(),
+            device_bindings: BTreeMap::new(),
+            gestures: vec![],
+
+            border_width: 10,
+            titlebar: false,
+            titlebar_position: TitlebarPosition::Top,
+            titlebar_height: None,
+            font: FontSpec::default(),
+
+            colors: ColorScheme::default(),
+            frame_background: FrameBackground::Solid,
+            render_interval: Duration::ZERO,
+            root_background: None,
+
+            urgent_flash_interval: None,
+
+            mirror_update_interval: Duration::from_millis(100),
+
+            class_remaps: vec![],
+
+            rules: vec![],
+
+            idle_thresholds: vec![],
+
+            output_hooks: OutputHooks::default(),
+
+            ipc: IpcAccess::default(),
+
+            clipboard_manager: false,
+
+            autostart: vec![],
+            exec_shell: "/bin/sh".into(),
+            exec_working_dir: None,
+
+            _include: de::IgnoredAny,
+            _variables: de::IgnoredAny,
+            paths: vec![],
+            table: Table::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The file configuration is read from if none is given: `$XDG_CONFIG_HOME/r3/config.toml`
+    /// (falling back to `~/.config` if `$XDG_CONFIG_HOME` isn't set)
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(dir.join("r3").join("config.toml"))
+    }
+
+    /// The system-wide configuration files, which are read before the user's file: `r3/config.toml` in
+    /// each of `$XDG_CONFIG_DIRS` (falling back to `/etc/xdg`). These are ordered from lowest to
+    /// highest priority, i.e. the reverse of `$XDG_CONFIG_DIRS`.
+    pub fn system_paths() -> Vec<PathBuf> {
+        let dirs = match env::var_os("XDG_CONFIG_DIRS") {
+            Some(dirs) if !dirs.is_empty() => dirs,
+            _ => "/etc/xdg".into(),
+        };
+
+        let mut paths = env::split_paths(&dirs)
+            // Relative paths are invalid, and should be ignored
+            .filter(|dir| dir.is_absolute())
+            .map(|dir| dir.join("r3").join("config.toml"))
+            .collect::<Vec<_>>();
+        paths.reverse();
+        paths
+    }
+
+    /// Read the configuration from the given file. If no file is given, then the system-wide files
+    /// (see [`Config::system_paths`]) and the default file are read if they exist, each overriding
+    /// the values in the ones before it. Anything which isn't set in any of them uses the default
+    /// configuration.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let paths = match path {
+            Some(path) => vec![path.to_path_buf()],
+            None => Config::system_paths()
+                .into_iter()
+                .chain(Config::default_path())
+                .filter(|path| path.exists())
+                .collect(),
+        };
+
+        let mut table = Table::new();
+        let mut read = vec![];
+        for path in &paths {
+            merge_tables(&mut table, read_table(path, &mut vec![], &mut read)?);
         }
+
+        let variables = match table.remove(VARIABLES_KEY) {
+            Some(Value::Table(variables)) => variables
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Value::String(value) => Ok((name, value)),
+                    _ => Err(format!("Variable \"{}\" must be a string", name)),
+                })
+                .collect::<Result<BTreeMap<_, _>, _>>()?,
+            Some(_) => return Err(format!("`{}` must be a table", VARIABLES_KEY)),
+            None => BTreeMap::new(),
+        };
+
+        let table = match expand_value(Value::Table(table), &variables)? {
+            Value::Table(table) => table,
+            _ => unreachable!("expanding a table returns a table"),
+        };
+        let mut config = Config::from_table(table).map_err(|e| {
+            locate_error(&read, &variables)
+                .unwrap_or_else(|| format!("Failed to parse {}: {}", describe_paths(&paths), e))
+        })?;
+        config.paths = read;
+        Ok(config)
+    }
+
+    /// Build the configuration from the values in the table (which have already been expanded).
+    fn from_table(table: Table) -> Result<Config, String> {
+        let mut config: Config = Value::Table(table.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+        config.table = table;
+        Ok(config)
     }
+
+    /// A copy of this configuration with one value changed. The key may be in a table, with the keys
+    /// separated by dots (e.g. `gaps.inner`), and the value is written as it would be in the file
+    /// (though strings don't need to be quoted).
+    pub fn with_value(&self, key: &str, value: &str) -> Result<Config, String> {
+        let value = match toml::from_str::<Table>(&format!("value = {}", value)) {
+            Ok(mut table) => table.remove("value").unwrap_or_else(|| Value::String(value.into())),
+            Err(_) => Value::String(value.into()),
+        };
+
+        let mut table = self.table.clone();
+        let mut keys = key.split('.').collect::<Vec<_>>();
+        let last = keys.pop().unwrap_or(key);
+        let mut target = &mut table;
+        for key in keys {
+            target = match target
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(table) => table,
+                _ => return Err(format!("`{}` isn't a table", key)),
+            };
+        }
+        target.insert(last.into(), value);
+
+        let mut config = Config::from_table(table)?;
+        config.paths = self.paths.clone();
+        Ok(config)
+    }
+
+    /// Every value of this configuration, including those which weren't set and use their default.
+    /// Bindings are shown as they were written (or the default key bindings, if none were), since their
+    /// commands can't be written out again.
+    pub fn effective_table(&self) -> Result<Table, String> {
+        let mut table = match Value::try_from(self) {
+            Ok(Value::Table(table)) => table,
+            Ok(value) => return Err(format!("expected the configuration to be a table, not {}", value)),
+            Err(e) => return Err(format!("Failed to write out the configuration: {}", e)),
+        };
+
+        for key in BINDING_KEYS {
+            let value = match self.table.get(key) {
+                Some(value) => value.clone(),
+                None if key == "bindings" => Value::Table(
+                    DEFAULT_BINDINGS
+                        .iter()
+                        .map(|(combo, command)| (combo.to_string(), Value::String(command.to_string())))
+                        .collect(),
+                ),
+                None => Value::Table(Table::new()),
+            };
+            table.insert(key.into(), value);
+        }
+
+        Ok(table)
+    }
+}
+
+/// Keys of the tables of bindings, which are shown as they were written (see `Config::effective_table`)
+const BINDING_KEYS: [&str; 5] = [
+    "bindings",
+    "device_bindings",
+    "frame_bindings",
+    "gestures",
+    "root_bindings",
+];
+
+/// Key of the list of files to include in a configuration file
+const INCLUDE_KEY: &str = "include";
+/// Key of the table of variables in a configuration file
+pub const VARIABLES_KEY: &str = "variables";
+
+/// Read a configuration file and everything it includes into a single table. `including` is the chain
+/// of files which included this one, so include cycles can be detected, and every file which is read
+/// is added to `read`.
+fn read_table(path: &Path, including: &mut Vec<PathBuf>, read: &mut Vec<PathBuf>) -> Result<Table, String> {
+    // The same file can be reached by different paths (e.g. `a/../a.toml`), so compare where they lead
+    let canonical = fs::canonicalize(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if including.contains(&canonical) {
+        return Err(format!("{} includes itself", path.display()));
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    read.push(path.to_path_buf());
+    let mut table =
+        toml::from_str::<Table>(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    let includes = match table.remove(INCLUDE_KEY) {
+        Some(includes) => includes
+            .try_into::<Vec<PathBuf>>()
+            .map_err(|_| format!("`{}` in {} must be a list of paths", INCLUDE_KEY, path.display()))?,
+        None => vec![],
+    };
+
+    // Included files are relative to the file including them
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = Table::new();
+    including.push(canonical);
+    for include in includes {
+        merge_tables(&mut merged, read_table(&dir.join(include), including, read)?);
+    }
+    including.pop();
+
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+/// Since the values in the merged table don't remember where they came from, errors found when it's
+/// deserialized don't say where the mistake is. This finds it by checking each file on its own
+/// (latest first, as its values take precedence), so the error names the file, line and column.
+/// Returns `None` if the error only happens once the files are merged (e.g. conflicting bindings).
+fn locate_error(paths: &[PathBuf], variables: &BTreeMap<String, String>) -> Option<String> {
+    paths.iter().rev().find_map(|path| {
+        let contents = expand_variables(&fs::read_to_string(path).ok()?, variables);
+        toml::from_str::<Config>(&contents)
+            .err()
+            .map(|e| format!("Failed to parse {}: {}", path.display(), e))
+    })
+}
+
+/// The paths as a list for error messages, e.g. `a.toml, b.toml`
+fn describe_paths(paths: &[PathBuf]) -> String {
+    match paths {
+        [] => "the default configuration".into(),
+        paths => paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Add the values in `overrides` to `table`, replacing any which exist except for tables, which are
+/// merged.
+fn merge_tables(table: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(value)) => merge_tables(existing, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replace variables in all of the keys and strings in the value.
+fn expand_value(value: Value, variables: &BTreeMap<String, String>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(s) => Value::String(expand_variables(&s, variables)),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| expand_value(value, variables))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Table(table) => {
+            let mut expanded = Table::new();
+            for (key, value) in table {
+                let key = expand_variables(&key, variables);
+                if expanded.contains_key(&key) {
+                    return Err(format!(
+                        "\"{}\" is defined more than once after variables are replaced",
+                        key
+                    ));
+                }
+                expanded.insert(key, expand_value(value, variables)?);
+            }
+            Value::Table(expanded)
+        }
+        value => value,
+    })
+}
+
+/// Replace every `$name` in the string with the value of the variable `name`, if it's defined.
+pub fn expand_variables(s: &str, variables: &BTreeMap<String, String>) -> String {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let name_len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+        match variables.get(&after[..name_len]) {
+            Some(value) => expanded.push_str(value),
+            None => expanded.push_str(&rest[i..i + 1 + name_len]),
+        }
+        rest = &after[name_len..];
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/// Deserialise a mouse button, which X numbers from 1 to 5.
+fn deserialize_button<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    match u8::deserialize(deserializer)? {
+        button @ 1..=5 => Ok(button),
+        button => Err(de::Error::custom(format!(
+            "mouse buttons are numbered 1 to 5, got {}",
+            button
+        ))),
+    }
+}
+
+/// Deserialise a list of durations, each written like `"100ms"`.
+fn deserialize_durations<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Duration>, D::Error> {
+    let durations = Vec::<humantime_serde::Serde<Duration>>::deserialize(deserializer)?;
+    Ok(durations.into_iter().map(humantime_serde::Serde::into_inner).collect())
+}
+
+/// Serialise a list of durations in the same form they're written in.
+fn serialize_durations<S: Serializer>(durations: &[Duration], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(durations.iter().copied().map(humantime_serde::Serde::from))
 }
@@ -0,0 +1,128 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A font specification, in one of two forms:
+///
+/// * `pango:<family> [style...] <size>`, e.g. `pango:JetBrains Mono Bold 10`
+/// * an X core font name or XLFD pattern, e.g. `fixed` or `-misc-fixed-medium-r-*-*-13-*-*-*-*-*-*-*`
+///
+/// r3 draws titles with X core fonts, so pango-style specifications are translated into an XLFD
+/// pattern which the X server uses to find the closest matching font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontSpec {
+    Pango {
+        family: String,
+        bold: bool,
+        italic: bool,
+        /// Size in points
+        size: u16,
+    },
+    Core(String),
+}
+
+impl FontSpec {
+    /// The name to pass to the X server's `OpenFont` request
+    pub fn x_font_name(&self) -> String {
+        match self {
+            FontSpec::Pango {
+                family,
+                bold,
+                italic,
+                size,
+            } => format!(
+                "-*-{}-{}-{}-normal-*-*-{}-*-*-*-*-iso10646-1",
+                family.to_lowercase(),
+                if *bold { "bold" } else { "medium" },
+                if *italic { "i" } else { "r" },
+                // XLFD point sizes are in tenths of a point
+                size * 10
+            ),
+            FontSpec::Core(name) => name.clone(),
+        }
+    }
+}
+
+impl Default for FontSpec {
+    fn default() -> Self {
+        FontSpec::Core("fixed".into())
+    }
+}
+
+impl FromStr for FontSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let desc = match s.strip_prefix("pango:") {
+            Some(desc) => desc,
+            None if s.is_empty() => return Err("font cannot be empty".into()),
+            None => return Ok(FontSpec::Core(s.into())),
+        };
+
+        let mut words = desc.split_whitespace().collect::<Vec<_>>();
+        let size = match words.pop().map(str::parse::<u16>) {
+            Some(Ok(size)) if size > 0 => size,
+            _ => return Err(format!("expected a size at the end of font \"{}\"", s)),
+        };
+
+        let mut bold = false;
+        let mut italic = false;
+        while let Some(word) = words.last() {
+            match word.to_lowercase().as_str() {
+                "bold" => bold = true,
+                "italic" | "oblique" => italic = true,
+                _ => break,
+            }
+            words.pop();
+        }
+
+        if words.is_empty() {
+            return Err(format!("expected a font family in font \"{}\"", s));
+        }
+
+        Ok(FontSpec::Pango {
+            family: words.join(" "),
+            bold,
+            italic,
+            size,
+        })
+    }
+}
+
+impl Display for FontSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontSpec::Pango {
+                family,
+                bold,
+                italic,
+                size,
+            } => {
+                write!(f, "pango:{}", family)?;
+                if *bold {
+                    write!(f, " Bold")?;
+                }
+                if *italic {
+                    write!(f, " Italic")?;
+                }
+                write!(f, " {}", size)
+            }
+            FontSpec::Core(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Fonts are written in the same form as they're displayed
+impl<'de> Deserialize<'de> for FontSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for FontSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,480 @@
+//! A subset of i3's IPC protocol, served on a second socket so tools written for i3 (status bars,
+//! `i3-msg`, `i3-save-tree`, etc) work with r3. Each message is the magic string `i3-ipc`, then the
+//! length of its payload and its type, both as native-endian `u32`s, then the payload. Replies have the
+//! same type as the message they reply to, and events have the highest bit of their type set.
+//!
+//! Everything is translated to and from r3's own commands, which are run by the event loop in the
+//! same way as those sent over r3's socket (see `ipc::serve_client`).
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use mio::Waker;
+use r3lib::ipc::{CommandReply, Encoding, ErrorReply, VersionReply, MAX_MESSAGE_LEN};
+use r3lib::{parse_commands, NodeType, OutputInfo, R3Command, R3Event, Rect, Split, TreeFormat, TreeNode, WMCommand};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::import_i3::translate_command;
+use crate::ipc::{self, Access, CommandQueue};
+
+/// The environment variable i3 clients look for the socket in. Like i3, the path is also in the
+/// `I3_SOCKET_PATH` property of the root window
+pub const SOCKET_PATH_ENV: &str = "I3SOCK";
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const GET_MARKS: u32 = 5;
+const GET_VERSION: u32 = 7;
+const GET_BINDING_MODES: u32 = 8;
+const SEND_TICK: u32 = 10;
+const SYNC: u32 = 11;
+
+const EVENT_OUTPUT: u32 = 1 << 31 | 1;
+const EVENT_TICK: u32 = 1 << 31 | 7;
+const EVENT_NAMES: &[&str] = &[
+    "workspace",
+    "output",
+    "mode",
+    "window",
+    "barconfig_update",
+    "binding",
+    "shutdown",
+    "tick",
+];
+
+/// i3's name for the screen when RandR isn't available
+const FALLBACK_OUTPUT: &str = "xroot-0";
+
+// TODO: use real workspaces once we have them. Until then there's a single workspace on the primary
+// output which holds every window, and these are the ids of it and the nodes above it in the tree
+const WORKSPACE_NAME: &str = "1";
+const WORKSPACE_ID: u32 = 1;
+const OUTPUT_ID: u32 = 2;
+const CONTENT_ID: u32 = 3;
+
+/// Read messages from the client until it disconnects, translating each one to r3's commands and
+/// replying like i3 would. Once a client subscribes it's only sent events, like with r3's socket.
+/// This blocks, so it's run on its own thread.
+pub fn serve_client(mut socket: UnixStream, access: Access, commands: CommandQueue, waker: Arc<Waker>) {
+    let client = Client { commands, waker };
+    loop {
+        let (message_type, payload) = match read_message(&mut socket) {
+            Ok(Some(message)) => message,
+            // The client has finished
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read i3 IPC message: {}", e);
+                break;
+            }
+        };
+
+        let payload = String::from_utf8_lossy(&payload);
+        if message_type == SUBSCRIBE {
+            client.subscribe(&mut socket, &payload);
+            break;
+        }
+
+        let reply = match message_type {
+            RUN_COMMAND | SEND_TICK if access == Access::ReadOnly => {
+                let error = json!({ "success": false, "error": "read-only clients can't run commands" });
+                match message_type {
+                    RUN_COMMAND => json!([error]),
+                    _ => error,
+                }
+            }
+            RUN_COMMAND => client.run_command(&payload),
+            GET_WORKSPACES => client.get_workspaces(),
+            GET_OUTPUTS => client.get_outputs(),
+            GET_TREE => client.get_tree(),
+            GET_MARKS => json!([]),
+            GET_VERSION => client.get_version(),
+            GET_BINDING_MODES => json!(["default"]),
+            SEND_TICK => client.send_tick(&payload),
+            SYNC => json!({ "success": true }),
+            _ => json!({
+                "success": false,
+                "error": format!("r3 doesn't support i3 IPC messages of type {}", message_type),
+            }),
+        };
+        if write_message(&mut socket, message_type, reply.to_string().as_bytes()).is_err() {
+            break;
+        }
+    }
+
+    // NOTE: this fails if the client has already disconnected
+    let _ = socket.shutdown(Shutdown::Both);
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let mut header = [0; 14];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message doesn't start with i3-ipc",
+        ));
+    }
+
+    // NOTE: the slices are both 4 bytes long, so these can't fail
+    let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message is too long ({} bytes)", len),
+        ));
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((message_type, payload)))
+}
+
+fn write_message(writer: &mut impl Write, message_type: u32, payload: &[u8]) -> io::Result<()> {
+    let len =
+        u32::try_from(payload.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message is too long"))?;
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&len.to_ne_bytes())?;
+    writer.write_all(&message_type.to_ne_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// The single workspace i3 clients are shown
+struct Workspace {
+    output: String,
+    rect: Rect,
+}
+
+struct Client {
+    commands: CommandQueue,
+    waker: Arc<Waker>,
+}
+
+impl Client {
+    /// Run the commands and wait for their replies, which are `null` if they had nothing to reply with.
+    fn request_chain(&self, chain: Vec<R3Command>) -> Vec<String> {
+        ipc::queue(&self.commands, &self.waker, Encoding::Json, chain)
+            .into_iter()
+            .map(|reply| {
+                reply
+                    .recv()
+                    .map_or_else(|_| "null".into(), |reply| String::from_utf8_lossy(&reply).into())
+            })
+            .collect()
+    }
+
+    /// Run a query and read its reply.
+    fn request<T: DeserializeOwned>(&self, command: R3Command) -> Option<T> {
+        let reply = self.request_chain(vec![command]).pop()?;
+        match serde_json::from_str(&reply) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("Unexpected reply {:?} for i3 IPC client: {}", reply, e);
+                None
+            }
+        }
+    }
+
+    /// Commands are read as r3 commands first, then as i3 commands which have an r3 equivalent. Each
+    /// command is replied to with its success (and error), like i3 does.
+    fn run_command(&self, payload: &str) -> Value {
+        let chain = parse_commands(payload).or_else(|e| {
+            translate_command(payload)
+                .ok()
+                .and_then(|command| parse_commands(&command).ok())
+                .ok_or(e)
+        });
+        let chain = match chain {
+            Ok(chain) if chain.contains(&R3Command::Subscribe) => {
+                return json!([CommandReply::failed("subscribe can't be run as a command")])
+            }
+            Ok(chain) => chain,
+            Err(e) => return json!([CommandReply::failed(e)]),
+        };
+
+        let replies = self
+            .request_chain(chain)
+            .into_iter()
+            .map(|reply| {
+                // Queries reply with what was asked for, which i3 clients don't expect
+                serde_json::from_str::<CommandReply>(&reply)
+                    .or_else(|_| serde_json::from_str::<ErrorReply>(&reply).map(|e| CommandReply::failed(e.error)))
+                    .unwrap_or_else(|_| CommandReply::ok(vec![]))
+            })
+            .collect::<Vec<_>>();
+        json!(replies)
+    }
+
+    fn send_tick(&self, payload: &str) -> Value {
+        let payload = payload.to_string();
+        let reply = self.request::<CommandReply>(R3Command::SendTick { payload });
+        json!({ "success": reply.is_some_and(|reply| reply.success) })
+    }
+
+    fn outputs(&self) -> Vec<OutputInfo> {
+        self.request(R3Command::WM(WMCommand::GetOutputs)).unwrap_or_default()
+    }
+
+    fn tree(&self) -> Option<TreeNode> {
+        self.request(R3Command::WM(WMCommand::GetTree {
+            format: TreeFormat::Json,
+        }))
+    }
+
+    /// The workspace is on the primary output (or the first active one), or the whole screen if
+    /// RandR isn't available.
+    fn workspace(&self, outputs: &[OutputInfo], tree: Option<&TreeNode>) -> Workspace {
+        let output = outputs
+            .iter()
+            .filter(|output| output.active)
+            .max_by_key(|output| output.primary);
+        match (output, tree) {
+            (
+                Some(OutputInfo {
+                    name, rect: Some(rect), ..
+                }),
+                _,
+            ) => Workspace {
+                output: name.clone(),
+                rect: *rect,
+            },
+            (_, tree) => Workspace {
+                output: FALLBACK_OUTPUT.into(),
+                rect: tree.map(|tree| tree.rect).unwrap_or_default(),
+            },
+        }
+    }
+
+    fn get_workspaces(&self) -> Value {
+        let workspace = self.workspace(&self.outputs(), self.tree().as_ref());
+        json!([{
+            "id": WORKSPACE_ID,
+            "num": 1,
+            "name": WORKSPACE_NAME,
+            "visible": true,
+            "focused": true,
+            "urgent": false,
+            "rect": workspace.rect,
+            "output": workspace.output,
+        }])
+    }
+
+    fn get_outputs(&self) -> Value {
+        let outputs = self.outputs();
+        let workspace = self.workspace(&outputs, self.tree().as_ref());
+        if outputs.is_empty() {
+            return json!([{
+                "name": FALLBACK_OUTPUT,
+                "active": true,
+                "primary": true,
+                "rect": workspace.rect,
+                "current_workspace": WORKSPACE_NAME,
+            }]);
+        }
+
+        let outputs = outputs
+            .into_iter()
+            .map(|output| {
+                let current_workspace = (output.name == workspace.output).then_some(WORKSPACE_NAME);
+                json!({
+                    "name": output.name,
+                    "active": output.active,
+                    "primary": output.primary,
+                    "rect": output.rect.unwrap_or_default(),
+                    "current_workspace": current_workspace,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!(outputs)
+    }
+
+    /// Arrange the tree like i3's: the root holds an output, which holds a content container, which
+    /// holds the workspace. The workspace holds the tiled windows (in their containers), and each
+    /// floating frame is one of the workspace's floating containers, holding its window.
+    fn get_tree(&self) -> Value {
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return json!({ "success": false, "error": "failed to read the tree" }),
+        };
+        let workspace = self.workspace(&self.outputs(), Some(&tree));
+
+        let (tiled, floating) = tree
+            .nodes
+            .iter()
+            .partition::<Vec<_>, _>(|node| node.node_type == NodeType::Workspace);
+        let nodes = tiled
+            .iter()
+            .flat_map(|workspace| workspace.nodes.iter().map(i3_node))
+            .collect::<Vec<_>>();
+        let floating_nodes = floating.into_iter().map(i3_node).collect::<Vec<_>>();
+        let workspace_node = json!({
+            "id": WORKSPACE_ID,
+            "type": "workspace",
+            "num": 1,
+            "name": WORKSPACE_NAME,
+            "layout": i3_layout(tiled.first().and_then(|workspace| workspace.split)),
+            "rect": workspace.rect,
+            "focused": false,
+            "nodes": nodes,
+            "floating_nodes": floating_nodes,
+        });
+        let content = json!({
+            "id": CONTENT_ID,
+            "type": "con",
+            "name": "content",
+            "layout": "splith",
+            "rect": workspace.rect,
+            "focused": false,
+            "nodes": [workspace_node],
+            "floating_nodes": [],
+        });
+        let output = json!({
+            "id": OUTPUT_ID,
+            "type": "output",
+            "name": workspace.output,
+            "layout": "output",
+            "rect": workspace.rect,
+            "focused": false,
+            "nodes": [content],
+            "floating_nodes": [],
+        });
+        json!({
+            "id": tree.id,
+            "type": "root",
+            "name": "root",
+            "layout": "splith",
+            "rect": tree.rect,
+            "focused": false,
+            "nodes": [output],
+            "floating_nodes": [],
+        })
+    }
+
+    fn get_version(&self) -> Value {
+        let version = match self.request::<VersionReply>(R3Command::GetVersion) {
+            Some(version) => version,
+            None => return json!({ "success": false, "error": "failed to read the version" }),
+        };
+
+        let mut numbers = version.version.split('.').map(|n| n.parse::<u32>().unwrap_or(0));
+        json!({
+            "major": numbers.next().unwrap_or(0),
+            "minor": numbers.next().unwrap_or(0),
+            "patch": numbers.next().unwrap_or(0),
+            "human_readable": format!("r3 {}", version.version),
+            "loaded_config_file_name": version.config_paths.first(),
+        })
+    }
+
+    /// Subscribe to r3's events, and send those with an i3 equivalent to the client until it
+    /// disconnects. r3 only has output and tick events which i3 clients understand.
+    fn subscribe(&self, socket: &mut UnixStream, payload: &str) {
+        let names = match serde_json::from_str::<Vec<String>>(payload) {
+            Ok(names) if names.iter().all(|name| EVENT_NAMES.contains(&name.as_str())) => names,
+            _ => {
+                let reply = json!({ "success": false, "error": "expected an array of i3 event names" });
+                let _ = write_message(socket, SUBSCRIBE, reply.to_string().as_bytes());
+                return;
+            }
+        };
+
+        let reply = json!({ "success": true });
+        if write_message(socket, SUBSCRIBE, reply.to_string().as_bytes()).is_err() {
+            return;
+        }
+
+        // i3 sends a tick to new subscribers, so they know they're subscribed
+        let wants = |name: &str| names.iter().any(|n| n == name);
+        if wants("tick") {
+            let tick = json!({ "first": true, "payload": "" });
+            if write_message(socket, EVENT_TICK, tick.to_string().as_bytes()).is_err() {
+                return;
+            }
+        }
+
+        let events = ipc::queue(&self.commands, &self.waker, Encoding::Json, vec![R3Command::Subscribe]);
+        for event in events.into_iter().flatten() {
+            let (event_type, event) = match serde_json::from_slice::<R3Event>(&event) {
+                Ok(R3Event::OutputConnected { .. } | R3Event::OutputDisconnected { .. }) if wants("output") => {
+                    (EVENT_OUTPUT, json!({ "change": "unspecified" }))
+                }
+                Ok(R3Event::Tick { payload }) if wants("tick") => {
+                    (EVENT_TICK, json!({ "first": false, "payload": payload }))
+                }
+                _ => continue,
+            };
+            if write_message(socket, event_type, event.to_string().as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A floating frame is a floating container holding its window, and r3's containers are i3's split
+/// containers. Tiled frames are left out, since i3 doesn't have frames.
+fn i3_node(node: &TreeNode) -> Value {
+    let nodes = node.nodes.iter().map(i3_node).collect::<Vec<_>>();
+    match (node.node_type, &node.window) {
+        (NodeType::Frame, _) if !node.floating => nodes.into_iter().next().unwrap_or(Value::Null),
+        (NodeType::Container, _) => json!({
+            "id": node.id,
+            "type": "con",
+            "name": null,
+            "layout": i3_layout(node.split),
+            "rect": node.rect,
+            "focused": node.focused,
+            "nodes": nodes,
+            "floating_nodes": [],
+        }),
+        (NodeType::Window, Some(info)) => json!({
+            "id": node.id,
+            "type": "con",
+            "name": info.title,
+            "layout": "splith",
+            "rect": node.rect,
+            "focused": node.focused,
+            "urgent": false,
+            "window": info.id,
+            "window_properties": {
+                "class": info.class,
+                "instance": info.instance,
+                "title": info.title,
+            },
+            "nodes": nodes,
+            "floating_nodes": [],
+        }),
+        _ => json!({
+            "id": node.id,
+            "type": "floating_con",
+            "name": null,
+            "layout": "splith",
+            "floating": "user_on",
+            "rect": node.rect,
+            "focused": node.focused,
+            "nodes": nodes,
+            "floating_nodes": [],
+        }),
+    }
+}
+
+fn i3_layout(split: Option<Split>) -> &'static str {
+    match split {
+        Some(Split::Vertical) => "splitv",
+        _ => "splith",
+    }
+}
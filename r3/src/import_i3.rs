@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+
+use r3lib::R3Command;
+use toml::value::{Table, Value};
+
+use crate::bindings::{parse_modifier, KeyCombo};
+use crate::color::Color;
+use crate::config::{expand_variables, VARIABLES_KEY};
+use crate::font::FontSpec;
+
+/// The result of translating an i3 configuration
+pub struct Import {
+    /// The r3 configuration, as TOML
+    pub config: String,
+    /// Lines which couldn't be translated, and why
+    pub problems: Vec<String>,
+}
+
+/// Translate the parts of an i3 configuration file which r3 supports: key bindings, `exec`, colours,
+/// fonts, borders and a few settings. Everything else is reported as a problem.
+pub fn import_i3(contents: &str) -> Import {
+    let mut importer = Importer::default();
+
+    let mut depth = 0;
+    for (number, line) in join_continued_lines(contents) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Blocks (modes and bars) aren't supported, so skip everything inside them
+        if depth > 0 || line.ends_with('{') {
+            if depth == 0 {
+                importer.problem(number, line, "blocks (such as modes and bars) aren't supported");
+            }
+            depth += line.matches('{').count();
+            depth = depth.saturating_sub(line.matches('}').count());
+            continue;
+        }
+
+        if let Err(reason) = importer.import_line(line) {
+            importer.problem(number, line, &reason);
+        }
+    }
+
+    importer.finish()
+}
+
+#[derive(Default)]
+struct Importer {
+    variables: BTreeMap<String, String>,
+    settings: Table,
+    bindings: Table,
+    /// The key combinations which are bound, after variables are expanded
+    combos: Vec<KeyCombo>,
+    autostart: Vec<Value>,
+    problems: Vec<String>,
+}
+
+impl Importer {
+    fn problem(&mut self, number: usize, line: &str, reason: &str) {
+        self.problems.push(format!("line {}: `{}`: {}", number, line, reason));
+    }
+
+    fn import_line(&mut self, line: &str) -> Result<(), String> {
+        let (directive, rest) = split_word(line);
+        match directive {
+            "set" => {
+                let (name, value) = split_word(rest);
+                let name = name.strip_prefix('$').ok_or("variable names must start with `$`")?;
+                self.variables.insert(name.into(), unquote(value).into());
+            }
+            "bindsym" => self.import_binding(rest)?,
+            "exec" | "exec_always" => {
+                let command = strip_no_startup_id(rest);
+                self.autostart.push(Value::String(unquote(command).into()));
+            }
+            "floating_modifier" => {
+                parse_modifier(&self.expand(rest)).ok_or("unknown modifier")?;
+                self.settings.insert("drag_modifier".into(), Value::String(rest.into()));
+            }
+            "focus_follows_mouse" => {
+                self.settings
+                    .insert("focus_follows_mouse".into(), Value::Boolean(parse_yes_no(rest)?));
+            }
+            "mouse_warping" => {
+                let warp = match rest {
+                    "output" => true,
+                    "none" => false,
+                    _ => return Err("expected `output` or `none`".into()),
+                };
+                self.settings.insert("mouse_warping".into(), Value::Boolean(warp));
+            }
+            "font" => {
+                self.expand(rest).parse::<FontSpec>()?;
+                self.settings.insert("font".into(), Value::String(rest.into()));
+            }
+            "default_border" | "new_window" => {
+                let (style, width) = split_word(rest);
+                let width = match (style, width) {
+                    ("none", _) => 0,
+                    ("normal" | "pixel", "") => 2,
+                    ("normal" | "pixel", width) => width.parse().map_err(|_| "invalid border width")?,
+                    _ => return Err("expected `none`, `normal` or `pixel`".into()),
+                };
+                self.settings
+                    .insert("titlebar".into(), Value::Boolean(style == "normal"));
+                self.settings.insert("border_width".into(), Value::Integer(width));
+            }
+            _ => match directive.strip_prefix("client.") {
+                Some(class) => self.import_colors(class, rest)?,
+                None => return Err(format!("`{}` isn't supported", directive)),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// `bindsym <keys> <command>`
+    fn import_binding(&mut self, rest: &str) -> Result<(), String> {
+        let (keys, command) = split_word(rest);
+        if keys.starts_with("--") {
+            return Err(format!("`{}` isn't supported", keys));
+        }
+
+        let combo = self.expand(keys).parse::<KeyCombo>()?;
+        if self.combos.contains(&combo) {
+            return Err("these keys are already bound".into());
+        }
+
+        let command = translate_command(command)?;
+        self.expand(&command)
+            .parse::<R3Command>()
+            .map_err(|e| format!("invalid command: {}", e))?;
+
+        self.combos.push(combo);
+        self.bindings.insert(keys.into(), Value::String(command));
+        Ok(())
+    }
+
+    /// `client.<class> <border> <background> <text> [<indicator>] [<child_border>]`
+    fn import_colors(&mut self, class: &str, rest: &str) -> Result<(), String> {
+        let state = match class {
+            "focused" => "focused",
+            "unfocused" => "unfocused",
+            "urgent" => "urgent",
+            _ => return Err(format!("r3 has no colours for `client.{}`", class)),
+        };
+
+        let colors = rest.split_whitespace().collect::<Vec<_>>();
+        if colors.len() < 3 {
+            return Err("expected at least a border, background and text colour".into());
+        }
+        for color in &colors {
+            self.expand(color).parse::<Color>()?;
+        }
+
+        // r3 frames only have one border, which is closest to i3's border around the window itself
+        let border = colors.get(4).unwrap_or(&colors[0]);
+        let mut table = Table::new();
+        table.insert("border".into(), Value::String(border.to_string()));
+        table.insert("background".into(), Value::String(colors[1].into()));
+        table.insert("text".into(), Value::String(colors[2].into()));
+
+        let scheme = self
+            .settings
+            .entry("colors")
+            .or_insert_with(|| Value::Table(Table::new()));
+        if let Value::Table(scheme) = scheme {
+            scheme.insert(state.into(), Value::Table(table));
+        }
+
+        Ok(())
+    }
+
+    fn expand(&self, s: &str) -> String {
+        expand_variables(s, &self.variables)
+    }
+
+    fn finish(mut self) -> Import {
+        if !self.variables.is_empty() {
+            let variables = self.variables.into_iter().map(|(k, v)| (k, Value::String(v)));
+            self.settings
+                .insert(VARIABLES_KEY.into(), Value::Table(variables.collect()));
+        }
+        if !self.bindings.is_empty() {
+            self.settings.insert("bindings".into(), Value::Table(self.bindings));
+        }
+        if !self.autostart.is_empty() {
+            self.settings.insert("autostart".into(), Value::Array(self.autostart));
+        }
+
+        Import {
+            // NOTE: a table of strings, booleans and integers can always be serialised
+            config: toml::to_string(&Value::Table(self.settings)).unwrap(),
+            problems: self.problems,
+        }
+    }
+}
+
+/// Translate an i3 command into the equivalent r3 command.
+pub fn translate_command(command: &str) -> Result<String, String> {
+    let command = command.trim();
+    if !command.starts_with("exec") && (command.contains(';') || command.contains(',')) {
+        return Err("chained commands aren't supported".into());
+    }
+
+    let (name, rest) = split_word(command);
+    Ok(match (name, rest) {
+        ("exec", rest) => format!("exec {}", unquote(strip_no_startup_id(rest))),
+        ("kill", "") => "close-window".into(),
+        ("exit", "") => "exit".into(),
+        ("reload" | "restart", "") => "reload-config".into(),
+        ("[urgent=latest]", "focus") => "focus-urgent".into(),
+        ("[urgent=oldest]", "focus") => "focus-urgent --oldest".into(),
+        ("workspace", "back_and_forth") => "focus-last".into(),
+        ("split", "h" | "horizontal") => "split horizontal".into(),
+        ("split", "v" | "vertical") => "split vertical".into(),
+        _ => return Err("r3 has no equivalent command".into()),
+    })
+}
+
+/// Join lines which end with a backslash onto the next line, keeping the number of the first line.
+fn join_continued_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    let mut continued = false;
+    for (i, line) in contents.lines().enumerate() {
+        match lines.last_mut() {
+            Some((_, last)) if continued => last.push_str(line.trim_start()),
+            _ => lines.push((i + 1, line.into())),
+        }
+
+        let last = &mut lines.last_mut().unwrap().1;
+        continued = last.ends_with('\\');
+        if continued {
+            last.pop();
+        }
+    }
+
+    lines
+}
+
+/// Split off the first word of the string, returning it and the rest of the string.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s)
+}
+
+fn strip_no_startup_id(command: &str) -> &str {
+    command.trim().trim_start_matches("--no-startup-id").trim()
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, String> {
+    match s {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("expected `yes` or `no`".into()),
+    }
+}
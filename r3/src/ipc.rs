@@ -0,0 +1,506 @@
+use std::cmp::Reverse;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{io, mem};
+
+use mio::Waker;
+use nix::sys::socket::{getsockopt, sockopt};
+use nix::unistd::Uid;
+use r3lib::ipc::{self, CommandReply, Encoding, ErrorReply, Message};
+use r3lib::{Priority, QueuedCommandInfo, R3Command, WMCommand};
+use serde::Serialize;
+
+use crate::config::IpcAccess;
+
+/// How long a client may stay connected without sending anything
+const IDLE_TIMEOUT: Duration = Duration::from_secs(180);
+/// The most clients which may be connected at once (to either socket), since each one has a thread
+const MAX_CLIENTS: usize = 64;
+
+/// Where to send a reply, and how the client wants it encoded
+#[derive(Debug)]
+struct ReplyTo {
+    sender: Sender<Vec<u8>>,
+    encoding: Encoding,
+}
+
+impl ReplyTo {
+    /// Encode the value and send it, returning `false` if the client has gone away.
+    fn send<T: Serialize>(&self, value: &T) -> bool {
+        match self.encoding.encode(value) {
+            Ok(message) => self.sender.send(message).is_ok(),
+            Err(e) => {
+                eprintln!("Failed to serialise reply: {}", e);
+                true
+            }
+        }
+    }
+}
+
+/// A way to send a reply back to the IPC clients which sent a command. There's more than one if the
+/// command was queued again before it was run (see `PendingCommands`).
+/// Commands which didn't come from an IPC client (e.g. key bindings) have nowhere to reply to.
+#[derive(Debug)]
+pub struct Reply(Vec<ReplyTo>);
+
+impl Reply {
+    pub fn new(sender: Sender<Vec<u8>>, encoding: Encoding) -> Reply {
+        Reply(vec![ReplyTo { sender, encoding }])
+    }
+
+    pub fn none() -> Reply {
+        Reply(vec![])
+    }
+
+    /// Also send this reply to the other's clients.
+    fn merge(&mut self, other: Reply) {
+        self.0.extend(other.0);
+    }
+
+    /// Keep the client connected, so it can be sent events.
+    pub fn into_subscriber(self) -> Option<Subscriber> {
+        // NOTE: `Subscribe` is never merged, so there's only ever one client
+        self.0.into_iter().next().map(Subscriber)
+    }
+
+    /// Serialise the value and send it to the clients.
+    pub fn send<T: Serialize>(self, value: &T) {
+        for reply_to in self.0 {
+            // If this fails the client has already gone away, so there's nobody to tell
+            reply_to.send(value);
+        }
+    }
+}
+
+/// An IPC client which is sent events as they happen, one message per event.
+#[derive(Debug)]
+pub struct Subscriber(ReplyTo);
+
+impl Subscriber {
+    /// Serialise the event and send it to the client, returning `false` if the client has gone away.
+    pub fn send<T: Serialize>(&self, event: &T) -> bool {
+        self.0.send(event)
+    }
+}
+
+/// A command waiting to be processed by the event loop.
+#[derive(Debug)]
+pub struct QueuedCommand {
+    pub command: R3Command,
+    pub reply: Reply,
+    priority: Priority,
+}
+
+/// Commands waiting to be processed by the event loop. Each time the loop takes them, they're ordered
+/// by their priority, so commands the user is waiting on aren't held up by slower ones which were
+/// queued first.
+#[derive(Debug, Default)]
+pub struct PendingCommands(Vec<QueuedCommand>);
+
+impl PendingCommands {
+    pub fn push(&mut self, command: R3Command, reply: Reply) {
+        self.push_chain(vec![(command, reply)]);
+    }
+
+    /// Queue commands which are run one after the other. They all have the highest priority of any of
+    /// them, so nothing is run in between them.
+    ///
+    /// Bulk commands are run after everything else, so a bulk command which is already waiting to be
+    /// run would have the same result as running it again. Instead of queueing it again, its reply is
+    /// also sent to the new client. Ticks are the exception, since subscribers should see every one.
+    pub fn push_chain(&mut self, chain: Vec<(R3Command, Reply)>) {
+        let priority = match chain.iter().map(|(command, _)| priority(command)).max() {
+            Some(priority) => priority,
+            None => return,
+        };
+
+        if let [(command, _)] = chain.as_slice() {
+            let duplicate = self
+                .0
+                .iter()
+                .position(|queued| priority == Priority::Bulk && queued.command == *command)
+                .filter(|_| !matches!(command, R3Command::SendTick { .. }));
+            if let Some(i) = duplicate {
+                // NOTE: the chain has exactly one command, checked above
+                let (_, reply) = chain.into_iter().next().unwrap();
+                self.0[i].reply.merge(reply);
+                return;
+            }
+        }
+
+        self.0.extend(chain.into_iter().map(|(command, reply)| QueuedCommand {
+            command,
+            reply,
+            priority,
+        }));
+    }
+
+    /// Take every waiting command, in the order they should be run.
+    pub fn take(&mut self) -> Vec<QueuedCommand> {
+        let mut commands = mem::take(&mut self.0);
+        // NOTE: this is a stable sort, so chains stay together and in order
+        commands.sort_by_key(|queued| Reverse(queued.priority));
+        commands
+    }
+
+    /// Remove the command at the index (in the order `info` lists them), telling the clients waiting
+    /// for it that it won't be run.
+    pub fn cancel(&mut self, index: usize) -> CommandReply {
+        // NOTE: this is the order they'd be taken in, so sorting them now doesn't change anything
+        self.0.sort_by_key(|queued| Reverse(queued.priority));
+        if index >= self.0.len() {
+            return CommandReply::failed(format!("there are only {} commands queued", self.0.len()));
+        }
+
+        self.0.remove(index).reply.send(&CommandReply::failed("cancelled"));
+        CommandReply::ok(vec![])
+    }
+
+    /// Describe every waiting command, in the order they'll be run.
+    pub fn info(&self) -> Vec<QueuedCommandInfo> {
+        let mut info = self
+            .0
+            .iter()
+            .map(|queued| QueuedCommandInfo {
+                command: queued.command.clone(),
+                priority: queued.priority,
+                waiting: queued.reply.0.len(),
+            })
+            .collect::<Vec<_>>();
+        info.sort_by_key(|info| Reverse(info.priority));
+        info
+    }
+}
+
+/// Commands are pushed onto this queue from other threads, and processed in the event loop.
+pub type CommandQueue = Arc<Mutex<PendingCommands>>;
+
+/// What an IPC client may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Full,
+    /// Only ask for things and subscribe to events (see `is_read_only`)
+    ReadOnly,
+}
+
+/// The number of connected clients, shared by the threads serving them.
+#[derive(Debug, Clone, Default)]
+pub struct ClientCount(Arc<AtomicUsize>);
+
+impl ClientCount {
+    /// Count a newly connected client until the returned slot is dropped, or `None` if there are
+    /// already `MAX_CLIENTS` connected.
+    pub fn add(&self) -> Option<ClientSlot> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CLIENTS).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| ClientSlot(self.0.clone()))
+    }
+}
+
+/// A connected client's place in the `ClientCount`, which is given up when it's dropped.
+#[derive(Debug)]
+pub struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The permissions r3's sockets are created with: only r3's user may open them, unless other users
+/// are allowed read-only access.
+pub fn socket_mode(config: &IpcAccess) -> u32 {
+    match config.read_only_users.is_empty() {
+        true => 0o600,
+        false => 0o666,
+    }
+}
+
+/// The permissions of the directory r3's sockets are in. Other users may reach the sockets in it
+/// (which they can only open if `socket_mode` lets them), but can't list or change what's in it.
+pub const SOCKET_DIR_MODE: u32 = 0o711;
+
+/// Decide what a newly connected client may do, or `None` if it should be disconnected.
+pub fn client_access(socket: &UnixStream, config: &IpcAccess) -> Option<Access> {
+    if !config.check_credentials && config.read_only_users.is_empty() {
+        return Some(Access::Full);
+    }
+
+    let uid = match peer_uid(socket) {
+        Ok(uid) => uid,
+        Err(e) => {
+            eprintln!("Failed to read IPC client's credentials: {}", e);
+            return None;
+        }
+    };
+
+    if uid == Uid::current().as_raw() {
+        Some(Access::Full)
+    } else if config.read_only_users.contains(&uid) {
+        Some(Access::ReadOnly)
+    } else {
+        eprintln!("Refusing IPC client run by user {}", uid);
+        None
+    }
+}
+
+/// The user running the client. When built with the `test-hooks` feature, `R3_IPC_PEER_UID` pretends
+/// every client is run by that user, so the integration tests can act as other users.
+fn peer_uid(socket: &UnixStream) -> nix::Result<u32> {
+    #[cfg(feature = "test-hooks")]
+    if let Some(uid) = std::env::var("R3_IPC_PEER_UID").ok().and_then(|uid| uid.parse().ok()) {
+        return Ok(uid);
+    }
+
+    getsockopt(socket.as_raw_fd(), sockopt::PeerCredentials).map(|credentials| credentials.uid())
+}
+
+/// Whether the command only asks for something, rather than changing anything.
+pub fn is_read_only(command: &R3Command) -> bool {
+    match command {
+        R3Command::WM(command) | R3Command::WithCriteria { command, .. } => matches!(
+            command,
+            WMCommand::ExplainWindow { .. }
+                | WMCommand::GetPointer
+                | WMCommand::GetOutputs
+                | WMCommand::GetTree { .. }
+                | WMCommand::GetStateHash
+        ),
+        R3Command::CheckConsistency { repair } => !repair,
+        // NOTE: files are written as our user, so read-only users can't ask for them
+        R3Command::DumpState { file } => file.is_none(),
+        R3Command::GetVersion
+        | R3Command::GetConfig
+        | R3Command::GetConfigPaths
+        | R3Command::Subscribe
+        | R3Command::GetQueue
+        | R3Command::SetEncoding { .. }
+        | R3Command::Sync => true,
+        R3Command::SetConfig { .. } | R3Command::CancelQueued { .. } | R3Command::SendTick { .. } | R3Command::Exit => {
+            false
+        }
+    }
+}
+
+fn priority(command: &R3Command) -> Priority {
+    match command {
+        R3Command::WM(command) | R3Command::WithCriteria { command, .. } => match command {
+            WMCommand::CloseWindow
+            | WMCommand::KillMode
+            | WMCommand::PickWindow
+            | WMCommand::Focus
+            | WMCommand::FocusUrgent { .. }
+            | WMCommand::FocusLast
+            | WMCommand::FocusNextOfClass { .. }
+            | WMCommand::UndoGeometry
+            | WMCommand::Split { .. }
+            | WMCommand::MirrorWindow { .. }
+            | WMCommand::StopMirroring
+            | WMCommand::Exec { .. }
+            | WMCommand::FlashWindow { .. } => Priority::Interactive,
+            WMCommand::ExplainWindow { .. }
+            | WMCommand::GetPointer
+            | WMCommand::GetOutputs
+            | WMCommand::GetStateHash => Priority::Normal,
+            WMCommand::GetTree { .. } | WMCommand::ReloadConfig => Priority::Bulk,
+        },
+        R3Command::Exit => Priority::Interactive,
+        R3Command::GetVersion
+        | R3Command::GetConfig
+        | R3Command::SetConfig { .. }
+        | R3Command::GetConfigPaths
+        | R3Command::Subscribe
+        | R3Command::GetQueue
+        | R3Command::CancelQueued { .. }
+        | R3Command::SetEncoding { .. } => Priority::Normal,
+        // Syncs and ticks are run after everything queued before them, so they're bulk too
+        R3Command::CheckConsistency { .. }
+        | R3Command::DumpState { .. }
+        | R3Command::Sync
+        | R3Command::SendTick { .. } => Priority::Bulk,
+    }
+}
+
+/// Read commands from the client until it disconnects, passing each one to the event loop and sending
+/// back its replies (see `r3lib::ipc` for the protocol). This blocks, so it's run on its own thread.
+pub fn serve_client(mut socket: UnixStream, access: Access, commands: CommandQueue, waker: Arc<Waker>) {
+    if let Err(e) = socket.set_read_timeout(Some(IDLE_TIMEOUT)) {
+        eprintln!("Failed to set IPC client timeout: {}", e);
+    }
+
+    let mut encoding = Encoding::Json;
+    loop {
+        let message = match ipc::read_message(&mut socket) {
+            Ok(Some(message)) => message,
+            // The client has finished
+            Ok(None) => break,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                eprintln!("IPC client was idle for too long, disconnecting");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Failed to read IPC message: {}", e);
+                break;
+            }
+        };
+
+        let message = parse_message(&message, encoding).map_err(|e| {
+            format!(
+                "Invalid command (r3 speaks protocol version {}): {}",
+                ipc::PROTOCOL_VERSION,
+                e
+            )
+        });
+        let message = message.and_then(|message| check_access(access, message));
+        let sent = match message {
+            // The queue is read here, since it's empty by the time the event loop runs a command
+            Ok(Message::Command(R3Command::GetQueue)) => {
+                ipc::send_as(&mut socket, encoding, &commands.lock().unwrap().info())
+            }
+            Ok(Message::Command(R3Command::CancelQueued { index })) => {
+                ipc::send_as(&mut socket, encoding, &commands.lock().unwrap().cancel(index))
+            }
+            // The reply is the last message in the old encoding
+            Ok(Message::Command(R3Command::SetEncoding { encoding: new })) => {
+                let sent = ipc::send_as(&mut socket, encoding, &CommandReply::ok(vec![]));
+                encoding = new;
+                sent
+            }
+            Ok(Message::Command(command)) => {
+                let replies = queue(&commands, &waker, encoding, vec![command]);
+                send_replies(&mut socket, encoding, replies)
+            }
+            Ok(Message::Chain(chain)) => {
+                let replies = queue(&commands, &waker, encoding, chain);
+                send_chain_replies(&mut socket, encoding, replies)
+            }
+            Err(error) => ipc::send_as(&mut socket, encoding, &ErrorReply { error }),
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    // NOTE: this fails if the client has already disconnected
+    let _ = socket.shutdown(Shutdown::Both);
+}
+
+/// Read a command or a chain of commands. This is done by hand (rather than deserialising `Message`
+/// directly) so errors say what's wrong with the command, rather than that it matched neither.
+fn parse_message(message: &[u8], encoding: Encoding) -> Result<Message, String> {
+    let value = encoding
+        .decode::<serde_json::Value>(message)
+        .map_err(|e| e.to_string())?;
+    if !value.is_array() {
+        return serde_json::from_value(value)
+            .map(Message::Command)
+            .map_err(|e| e.to_string());
+    }
+
+    let chain = serde_json::from_value::<Vec<R3Command>>(value).map_err(|e| e.to_string())?;
+    match chain
+        .iter()
+        .any(|command| matches!(command, R3Command::Subscribe | R3Command::SetEncoding { .. }))
+    {
+        true => Err("subscribe and set-encoding can't be chained".into()),
+        false => Ok(Message::Chain(chain)),
+    }
+}
+
+/// Refuse messages with commands the client isn't allowed to run.
+fn check_access(access: Access, message: Message) -> Result<Message, String> {
+    let commands = match &message {
+        Message::Command(command) => std::slice::from_ref(command),
+        Message::Chain(chain) => chain.as_slice(),
+    };
+    match commands
+        .iter()
+        .find(|command| access == Access::ReadOnly && !is_read_only(command))
+    {
+        Some(command) => Err(format!("{:?} can't be run by a read-only client", command)),
+        None => Ok(message),
+    }
+}
+
+/// Pass the commands to the event loop, returning where each one's replies will be sent (encoded with
+/// the encoding). The commands are queued together, so they're run in the same iteration of the event
+/// loop.
+pub fn queue(
+    commands: &CommandQueue,
+    waker: &Waker,
+    encoding: Encoding,
+    chain: Vec<R3Command>,
+) -> Vec<Receiver<Vec<u8>>> {
+    let (chain, replies) = chain
+        .into_iter()
+        .map(|command| {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            ((command, Reply::new(reply_tx, encoding)), reply_rx)
+        })
+        .unzip();
+    commands.lock().unwrap().push_chain(chain);
+
+    if let Err(e) = waker.wake() {
+        eprintln!("Failed to wake the event loop: {}", e);
+    }
+    replies
+}
+
+/// Wait for the event loop to process the command and reply. If the command has nothing to reply
+/// with, the reply is dropped and we send back `null` so every command gets a reply. Subscribers are
+/// sent every event until they disconnect.
+fn send_replies(socket: &mut UnixStream, encoding: Encoding, replies: Vec<Receiver<Vec<u8>>>) -> io::Result<()> {
+    let mut replied = false;
+    for reply in replies.into_iter().flatten() {
+        replied = true;
+        ipc::write_message(socket, &reply)?;
+    }
+    match replied {
+        true => Ok(()),
+        false => ipc::send_as(socket, encoding, &()),
+    }
+}
+
+/// Wait for every command in a chain to reply, and send them back as an array. The replies are already
+/// encoded, so they're joined together rather than decoded and encoded again.
+fn send_chain_replies(socket: &mut UnixStream, encoding: Encoding, replies: Vec<Receiver<Vec<u8>>>) -> io::Result<()> {
+    let replies = replies
+        .into_iter()
+        .map(|reply| reply.recv().or_else(|_| encoding.encode(&())))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut message = vec![];
+    match encoding {
+        Encoding::Json => {
+            message.push(b'[');
+            message.extend(replies.join(&b","[..]));
+            message.push(b']');
+        }
+        Encoding::Cbor => {
+            // The header of an array of known length (see RFC 8949, section 3.1)
+            let len = replies.len();
+            match len {
+                0..=23 => message.push(0x80 | len as u8),
+                24..=0xff => message.extend([0x98, len as u8]),
+                0x100..=0xffff => {
+                    message.push(0x99);
+                    message.extend((len as u16).to_be_bytes());
+                }
+                _ => {
+                    message.push(0x9a);
+                    message.extend((len as u32).to_be_bytes());
+                }
+            }
+            message.extend(replies.concat());
+        }
+    }
+    ipc::write_message(socket, &message)
+}
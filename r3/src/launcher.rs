@@ -0,0 +1,81 @@
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use nix::unistd::{fork, setsid, ForkResult};
+
+/// Prefix of environment variables which only make sense for r3 itself
+const R3_ENV_PREFIX: &str = "R3_";
+
+/// Launches processes which are completely detached from r3, so they outlive it and r3 never has to
+/// reap them.
+#[derive(Debug, Clone)]
+pub struct Launcher {
+    /// The shell used to run commands
+    pub shell: String,
+    /// The directory processes are started in
+    pub working_dir: Option<PathBuf>,
+    /// The X display processes should connect to
+    pub display: Option<String>,
+}
+
+impl Launcher {
+    pub fn new(shell: String, working_dir: Option<PathBuf>, display: Option<String>) -> Launcher {
+        Launcher {
+            shell,
+            working_dir,
+            display,
+        }
+    }
+
+    /// Run the command with the shell. Errors are only returned if the process couldn't be started,
+    /// not if the command itself fails.
+    pub fn spawn(&self, command: &str) -> io::Result<()> {
+        self.spawn_with_env(command, &[])
+    }
+
+    /// Run the command with the shell, with some extra environment variables.
+    pub fn spawn_with_env(&self, command: &str, vars: &[(&str, &str)]) -> io::Result<()> {
+        let mut cmd = Command::new(&self.shell);
+        cmd.arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            // Output ends up in our log (stderr) rather than wherever our own output goes
+            .stdout(io::stderr())
+            .current_dir(self.working_dir.clone().or_else(home_dir).unwrap_or_else(|| "/".into()));
+
+        // Don't leak our own configuration into other processes
+        for (key, _) in std::env::vars_os() {
+            if key.to_string_lossy().starts_with(R3_ENV_PREFIX) {
+                cmd.env_remove(key);
+            }
+        }
+        if let Some(display) = &self.display {
+            cmd.env("DISPLAY", display);
+        }
+        cmd.envs(vars.iter().copied());
+
+        // Detach from r3: start a new session, then fork again and let the intermediate process exit
+        // so the command is re-parented to init (and we only have to wait on the intermediate process)
+        // SAFETY: only async-signal-safe functions are called between forking and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                setsid()?;
+                match fork()? {
+                    ForkResult::Parent { .. } => nix::libc::_exit(0),
+                    ForkResult::Child => Ok(()),
+                }
+            });
+        }
+
+        // NOTE: if the command can't be executed then `spawn` returns the error, even from the grandchild
+        cmd.spawn()?.wait()?;
+
+        Ok(())
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
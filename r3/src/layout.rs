@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use r3lib::{Rect, Split};
+use xcb::x;
+
+/// Containers' ids have the top bit set, which X never sets in the ids of windows
+const CONTAINER_ID_BIT: u32 = 1 << 31;
+static NEXT_CONTAINER_ID: AtomicU32 = AtomicU32::new(1);
+
+/// A tiled window, or a container of them
+#[derive(Debug)]
+pub enum Node {
+    Window(x::Window),
+    Container(Container),
+}
+
+/// Tiled windows (and other containers) split one way, which share the container's area equally.
+/// The workspace is the container at the top of the tree.
+#[derive(Debug)]
+pub struct Container {
+    /// Identifies the container in the tree sent to clients
+    pub id: u32,
+    pub split: Split,
+    pub children: Vec<Node>,
+}
+
+impl Container {
+    pub fn new(split: Split) -> Container {
+        Container {
+            id: CONTAINER_ID_BIT | NEXT_CONTAINER_ID.fetch_add(1, Ordering::Relaxed),
+            split,
+            children: vec![],
+        }
+    }
+
+    /// Whether the window is tiled in this container, or one of the containers in it.
+    pub fn contains(&self, window: x::Window) -> bool {
+        self.children.iter().any(|child| match child {
+            Node::Window(w) => *w == window,
+            Node::Container(container) => container.contains(window),
+        })
+    }
+
+    /// Add the window after `sibling` in the sibling's container, or at the end of this container if
+    /// there isn't a sibling (or it isn't tiled here).
+    pub fn insert(&mut self, window: x::Window, sibling: Option<x::Window>) {
+        if !sibling.is_some_and(|sibling| self.insert_after(window, sibling)) {
+            self.children.push(Node::Window(window));
+        }
+    }
+
+    fn insert_after(&mut self, window: x::Window, sibling: x::Window) -> bool {
+        for i in 0..self.children.len() {
+            match &mut self.children[i] {
+                Node::Window(w) if *w == sibling => {
+                    self.children.insert(i + 1, Node::Window(window));
+                    return true;
+                }
+                Node::Container(container) => {
+                    if container.insert_after(window, sibling) {
+                        return true;
+                    }
+                }
+                Node::Window(_) => {}
+            }
+        }
+
+        false
+    }
+
+    /// Remove the window, along with any containers which are left empty. Returns whether the window
+    /// was found.
+    pub fn remove(&mut self, window: x::Window) -> bool {
+        for i in 0..self.children.len() {
+            let (found, emptied) = match &mut self.children[i] {
+                Node::Window(w) => (*w == window, true),
+                Node::Container(container) => (container.remove(window), container.children.is_empty()),
+            };
+            if found {
+                if emptied {
+                    self.children.remove(i);
+                }
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Split the window's place in the tree, so windows opened next to it are arranged that way. Like
+    /// i3, if it's alone in its container then the container is split that way instead, otherwise the
+    /// window is put in a new container of its own. Returns whether the window was found.
+    pub fn split(&mut self, window: x::Window, split: Split) -> bool {
+        let position = self
+            .children
+            .iter()
+            .position(|child| matches!(child, Node::Window(w) if *w == window));
+        if let Some(i) = position {
+            if self.children.len() == 1 {
+                self.split = split;
+            } else {
+                let mut container = Container::new(split);
+                container.children.push(Node::Window(window));
+                self.children[i] = Node::Container(container);
+            }
+            return true;
+        }
+
+        for child in &mut self.children {
+            if let Node::Container(container) = child {
+                if container.split(window, split) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The areas of the container's children, which share its area equally and have `gap` pixels
+    /// between them.
+    pub fn child_rects(&self, rect: Rect, gap: u16) -> Vec<Rect> {
+        let count = self.children.len() as u32;
+        if count == 0 {
+            return vec![];
+        }
+
+        let (start, length) = match self.split {
+            Split::Horizontal => (rect.x, rect.width),
+            Split::Vertical => (rect.y, rect.height),
+        };
+        let available = (length as u32).saturating_sub(gap as u32 * (count - 1));
+        (0..count)
+            .map(|i| {
+                // Each child starts where the one before it ends, so rounding doesn't leave space at the end
+                let from = available * i / count;
+                let to = available * (i + 1) / count;
+                let offset = (start as i32 + (from + gap as u32 * i) as i32) as i16;
+                let size = (to - from).max(1) as u16;
+                match self.split {
+                    Split::Horizontal => Rect {
+                        x: offset,
+                        width: size,
+                        ..rect
+                    },
+                    Split::Vertical => Rect {
+                        y: offset,
+                        height: size,
+                        ..rect
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// The area of each window in the container (and the containers in it), when the container covers
+    /// the given area.
+    pub fn layout(&self, rect: Rect, gap: u16) -> Vec<(x::Window, Rect)> {
+        let mut tiles = vec![];
+        for (child, rect) in self.children.iter().zip(self.child_rects(rect, gap)) {
+            match child {
+                Node::Window(window) => tiles.push((*window, rect)),
+                Node::Container(container) => tiles.extend(container.layout(rect, gap)),
+            }
+        }
+
+        tiles
+    }
+}
@@ -1,23 +1,37 @@
+mod args;
+mod bindings;
+mod color;
 mod config;
+mod font;
+mod i3_ipc;
+mod import_i3;
+mod ipc;
+mod launcher;
+mod layout;
 mod macros;
 mod point;
 mod window_geometry;
 mod wm;
 
 use std::error::Error;
-use std::io::{self, Read, Write};
-use std::net::Shutdown;
-use std::os::unix::net::UnixListener;
-use std::os::unix::prelude::AsRawFd;
-use std::path::PathBuf;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::prelude::{AsRawFd, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{env, fs, process, thread};
 
+use args::Args;
+use clap::Parser;
+use config::{Config, IpcAccess};
+use ipc::{Access, ClientCount, CommandQueue, PendingCommands, QueuedCommand, Reply};
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token, Waker};
-use r3lib::R3Command;
-use signal_hook::consts::SIGTERM;
+use nix::unistd::Uid;
+use r3lib::ipc::CommandReply;
+use r3lib::{R3Command, WMCommand};
+use signal_hook::consts::{SIGHUP, SIGTERM};
 use signal_hook_mio::v0_8::Signals;
 use wm::WindowManager;
 use xcb::Connection;
@@ -26,22 +40,26 @@ const T_XCB: Token = Token(0);
 const T_IPC: Token = Token(1);
 const T_CMD: Token = Token(2);
 const T_SIG: Token = Token(3);
+const T_I3_IPC: Token = Token(4);
 
 fn get_socket_path() -> Result<PathBuf, Box<dyn Error>> {
+    // Nest in a directory of our own
     let dir = match env::var("XDG_RUNTIME_DIR") {
-        Ok(dir) => PathBuf::from(dir),
-        Err(e) => {
-            eprintln!("Failed to interpret XDG_RUNTIME_DIR: {}", e);
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("r3"),
+        result => {
+            if let Err(e) = result {
+                eprintln!("Failed to interpret XDG_RUNTIME_DIR: {}", e);
+            }
             eprintln!("Falling back to /tmp");
-            PathBuf::from("/tmp")
+            // NOTE: any user can create files in /tmp, so the directory is named after our user, and
+            // setting its permissions fails below if another user created it first
+            PathBuf::from("/tmp").join(format!("r3-{}", Uid::current()))
         }
     };
 
-    // Nest in a directory
-    let dir = dir.join("r3");
-
-    // Create the directory
+    // Create the directory, and make sure only we can change what's in it
     fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(ipc::SOCKET_DIR_MODE))?;
 
     // Return the socket path
     let socket_path = dir.join(format!("ipc-socket.{}", process::id()));
@@ -49,15 +67,106 @@ fn get_socket_path() -> Result<PathBuf, Box<dyn Error>> {
     Ok(socket_path)
 }
 
+/// Report whether the configuration is valid, exiting with a non-zero status if it isn't.
+fn check_config(path: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    match Config::load(path) {
+        Ok(config) => {
+            for path in &config.paths {
+                println!("Read {}", path.display());
+            }
+            println!("Configuration is valid");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Print the r3 equivalent of an i3 configuration file, and report anything which couldn't be
+/// translated.
+fn import_i3_config(path: &Path) -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let import = import_i3::import_i3(&contents);
+    print!("{}", import.config);
+
+    if !import.problems.is_empty() {
+        eprintln!("Some of {} couldn't be translated:", path.display());
+        for problem in import.problems {
+            eprintln!("  {}", problem);
+        }
+    }
+
+    Ok(())
+}
+
+/// Accept every client waiting to connect to the socket, and serve each one on its own thread. Clients
+/// which can't be served (e.g. there are too many of them) are logged and disconnected, rather than
+/// stopping r3.
+fn accept_clients(
+    socket: &UnixListener,
+    thread_name: &str,
+    ipc_access: &IpcAccess,
+    clients: &ClientCount,
+    serve: impl Fn(UnixStream, Access) + Clone + Send + 'static,
+) {
+    loop {
+        let socket = match socket.accept() {
+            Ok((socket, addr)) => {
+                println!("Client connection: {:?} - {:?}", socket, addr);
+                socket
+            }
+            // There are no more clients waiting (we'd start blocking)
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                eprintln!("Failed to accept {}: {}", thread_name, e);
+                break;
+            }
+        };
+
+        let access = match ipc::client_access(&socket, ipc_access) {
+            Some(access) => access,
+            None => continue,
+        };
+        let slot = match clients.add() {
+            Some(slot) => slot,
+            None => {
+                eprintln!("Refusing {}, since too many clients are connected", thread_name);
+                continue;
+            }
+        };
+        let serve = serve.clone();
+        let spawned = thread::Builder::new().name(thread_name.to_string()).spawn(move || {
+            serve(socket, access);
+            drop(slot);
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start a thread for {}: {}", thread_name, e);
+        }
+    }
+}
+
 // TODO: consider abstracting away X-specific items, and allowing Wayland impls too?
 //  unsure how difficult this will be (seems to be mostly X code for now)
 fn main() -> Result<(), Box<dyn Error>> {
     // Stop and wait for debugger if R3_DEBUG present
     #[cfg(feature = "debug")]
-    if matches!(std::env::var("R3_DEBUG"), Ok(_)) {
+    if std::env::var("R3_DEBUG").is_ok() {
         nix::sys::signal::raise(nix::sys::signal::SIGSTOP).unwrap();
     }
 
+    // Configuration setup:
+    //  Read the configuration before anything else, so we exit early if it's invalid
+    let args = Args::parse();
+    if args.check_config {
+        return check_config(args.config.as_deref());
+    }
+    if let Some(path) = args.import_i3 {
+        return import_i3_config(&path);
+    }
+    let config = Config::load(args.config.as_deref())?;
+
     // Event Loop setup:
     //  Register XCB events by listening to its file descriptor
     //  Register IPC events by listening to its file descriptor
@@ -67,29 +176,56 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut events = Events::with_capacity(128);
     let registry = poll.registry();
     let loop_waker = Arc::new(Waker::new(registry, T_CMD)?);
-    let command_queue: Arc<Mutex<Vec<R3Command>>> = Arc::new(Mutex::new(vec![]));
+    let command_queue: CommandQueue = Arc::new(Mutex::new(PendingCommands::default()));
+    let clients = ClientCount::default();
 
     // IPC setup:
     //  TODO: doc
     let socket_path = get_socket_path()?;
     let ipc_socket = UnixListener::bind(&socket_path)?;
+    let socket_permissions = fs::Permissions::from_mode(ipc::socket_mode(&config.ipc));
+    fs::set_permissions(&socket_path, socket_permissions.clone())?;
+    // The processes we start can find the socket without asking the X server
+    env::set_var(r3lib::ipc::SOCKET_PATH_ENV, &socket_path);
     ipc_socket.set_nonblocking(true)?;
     registry.register(&mut SourceFd(&ipc_socket.as_raw_fd()), T_IPC, Interest::READABLE)?;
 
+    // i3 IPC setup:
+    //  A second socket speaking i3's protocol, so tools written for i3 work with r3 too
+    let i3_socket_path = socket_path.with_file_name(format!("i3-ipc-socket.{}", process::id()));
+    let i3_ipc_socket = UnixListener::bind(&i3_socket_path)?;
+    fs::set_permissions(&i3_socket_path, socket_permissions)?;
+    env::set_var(i3_ipc::SOCKET_PATH_ENV, &i3_socket_path);
+    i3_ipc_socket.set_nonblocking(true)?;
+    registry.register(&mut SourceFd(&i3_ipc_socket.as_raw_fd()), T_I3_IPC, Interest::READABLE)?;
+
     // XCB setup:
     //  If display name is none, rust-xcb will use the DISPLAY environment variable
     //  TODO: doc
-    let (xcb_conn, xcb_default_screen) = Connection::connect_with_extensions(None, &[], &[])?;
+    let (xcb_conn, xcb_default_screen) = Connection::connect_with_extensions(
+        None,
+        &[],
+        &[
+            xcb::Extension::Composite,
+            xcb::Extension::Damage,
+            xcb::Extension::Input,
+            xcb::Extension::RandR,
+            xcb::Extension::ScreenSaver,
+            xcb::Extension::Shm,
+            xcb::Extension::Sync,
+        ],
+    )?;
     let mut wm = WindowManager::new(
         (&xcb_conn, xcb_default_screen),
         (loop_waker.clone(), command_queue.clone()),
+        (config, args.config),
     )?;
-    wm.become_window_manager(&socket_path)?;
+    wm.become_window_manager(&socket_path, &i3_socket_path)?;
     registry.register(&mut SourceFd(&xcb_conn.as_raw_fd()), T_XCB, Interest::READABLE)?;
 
     // Signal setup:
     //  TODO doc
-    let mut signals = Signals::new(&[SIGTERM])?;
+    let mut signals = Signals::new([SIGTERM, SIGHUP])?;
     registry.register(&mut signals, T_SIG, Interest::READABLE)?;
 
     // The event loop!
@@ -110,6 +246,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     Err(e) => wm.handle_event(Err(e))?,
                 }
             }
+            wm.tick()?;
             xcb_conn.flush()?;
         }
 
@@ -121,62 +258,50 @@ fn main() -> Result<(), Box<dyn Error>> {
                     // We do nothing here, since we process all XCB events before blocking the event loop
                 }
                 T_IPC => {
-                    // Loop until we've accepted all waiting IPC connections
-                    loop {
-                        match ipc_socket.accept() {
-                            // We got an IPC connection, read it and send a message back
-                            Ok((mut socket, addr)) => {
-                                println!("Client connection: {:?} - {:?}", socket, addr);
-                                let thread_waker = loop_waker.clone();
-                                let thread_commands = command_queue.clone();
-                                thread::Builder::new().name(format!("ipc-client")).spawn(move || {
-                                    // Timeout connection after periods of inactivity
-                                    socket.set_read_timeout(Some(Duration::from_secs(180))).unwrap();
-
-                                    let mut message = String::new();
-
-                                    // NOTE: the fastest way to deserialise right now is to read the entire body at once
-                                    // into a string and then deserialise that. See: https://github.com/serde-rs/json/issues/160
-                                    match socket.read_to_string(&mut message) {
-                                        Ok(_) => {
-                                            println!("Client message: {}", message);
-                                            let command: R3Command = serde_json::from_str(&message).unwrap();
-                                            println!("Client command: {:?}", command);
-                                            thread_commands.lock().unwrap().push(command);
-                                            thread_waker.wake().unwrap();
-
-                                            // TODO: construct JSON reply
-                                            socket.write_all(b"Hello from the server!").unwrap();
-                                            socket.shutdown(Shutdown::Both).unwrap();
-                                        }
-                                        // The read took to long, so drop it
-                                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                            eprintln!("IPC message took too long to read, dropping");
-                                            return;
-                                        }
-                                        // Some other error occurred when reading
-                                        Err(e) => panic!("ipc read error: {}", e),
-                                    }
-
-                                    println!("ipc client thread exit");
-                                })?;
-                            }
-                            // We tried to accept, but there are no more connections (we'd start blocking)
-                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
-                            // Some other error occurred when accepting/reading connections
-                            Err(e) => panic!("ipc error: {}", e),
-                        }
-                    }
+                    let (waker, commands) = (loop_waker.clone(), command_queue.clone());
+                    accept_clients(
+                        &ipc_socket,
+                        "ipc-client",
+                        wm.ipc_access(),
+                        &clients,
+                        move |socket, access| ipc::serve_client(socket, access, commands.clone(), waker.clone()),
+                    );
+                }
+                T_I3_IPC => {
+                    let (waker, commands) = (loop_waker.clone(), command_queue.clone());
+                    accept_clients(
+                        &i3_ipc_socket,
+                        "i3-ipc-client",
+                        wm.ipc_access(),
+                        &clients,
+                        move |socket, access| i3_ipc::serve_client(socket, access, commands.clone(), waker.clone()),
+                    );
                 }
                 T_CMD => {
-                    let cmds = command_queue.lock().unwrap().drain(..).collect::<Vec<_>>();
-                    for cmd in cmds {
+                    let cmds = command_queue.lock().unwrap().take();
+                    for QueuedCommand { command, reply, .. } in cmds {
                         // TODO: extend R3Command so there are WM-specific commands and app-specific commands
-                        match cmd {
-                            R3Command::WM(wm_cmd) => wm.handle_command(&wm_cmd)?,
-                            // TODO: how to send reply? wrap items in command_queue with a context/reply/etc field/closure?
-                            R3Command::GetConfig => todo!(),
-                            R3Command::GetVersion => todo!(),
+                        match command {
+                            R3Command::WM(wm_cmd) => wm.handle_command(&wm_cmd, reply)?,
+                            R3Command::WithCriteria { criteria, command } => {
+                                wm.handle_command_matching(&criteria, &command, reply)?
+                            }
+                            R3Command::GetConfig => wm.get_config(reply),
+                            R3Command::SetConfig { key, value } => wm.set_config(&key, &value, reply)?,
+                            R3Command::GetConfigPaths => wm.get_config_paths(reply),
+                            R3Command::Subscribe => wm.subscribe(reply),
+                            R3Command::CheckConsistency { repair } => wm.check_consistency(repair, reply)?,
+                            R3Command::DumpState { file } => wm.dump_state(file.as_deref(), reply),
+                            R3Command::GetVersion => wm.get_version(reply),
+                            R3Command::GetQueue => reply.send(&command_queue.lock().unwrap().info()),
+                            R3Command::CancelQueued { index } => {
+                                reply.send(&command_queue.lock().unwrap().cancel(index))
+                            }
+                            R3Command::SetEncoding { .. } => {
+                                reply.send(&CommandReply::failed("only IPC clients have an encoding"))
+                            }
+                            R3Command::Sync => wm.sync(reply)?,
+                            R3Command::SendTick { payload } => wm.send_tick(payload, reply),
                             R3Command::Exit => break 'event_loop,
                         }
                     }
@@ -188,6 +313,10 @@ fn main() -> Result<(), Box<dyn Error>> {
                                 eprintln!("Received: SIGTERM");
                                 break 'event_loop;
                             }
+                            SIGHUP => {
+                                eprintln!("Received: SIGHUP");
+                                wm.handle_command(&WMCommand::ReloadConfig, Reply::none())?;
+                            }
                             _ => unimplemented!("{:?}", sig),
                         }
                     }
@@ -201,6 +330,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     eprintln!("r3 exiting...");
     drop(ipc_socket);
     fs::remove_file(&socket_path)?;
+    drop(i3_ipc_socket);
+    fs::remove_file(&i3_socket_path)?;
 
     Ok(())
 }
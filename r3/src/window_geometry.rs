@@ -1,3 +1,5 @@
+use std::cmp;
+
 use crate::point::Point;
 
 #[derive(Debug, Clone, Copy)]
@@ -8,7 +10,7 @@ pub enum Quadrant {
     BottomRight,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct WindowGeometry {
     /// X coord relative to parent
     pub x: i16,
@@ -50,6 +52,17 @@ impl WindowGeometry {
         (self.x <= point.x && point.x <= end_x) && (self.y <= point.y && point.y <= end_y)
     }
 
+    /// The area of the intersection of this window and another (both including their borders)
+    pub fn overlap_area(&self, other: &WindowGeometry) -> u32 {
+        let overlap = |start: i16, len: u16, other_start: i16, other_len: u16| {
+            let end = cmp::min(start as i32 + len as i32, other_start as i32 + other_len as i32);
+            cmp::max(0, end - cmp::max(start, other_start) as i32) as u32
+        };
+
+        overlap(self.x, self.full_width(), other.x, other.full_width())
+            * overlap(self.y, self.full_height(), other.y, other.full_height())
+    }
+
     pub fn quadrant(&self, point: &Point) -> Option<Quadrant> {
         let horizonal_bound = self.x + (self.full_width() / 2) as i16;
         let vertical_bound = self.y + (self.full_height() / 2) as i16;
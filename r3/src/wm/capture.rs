@@ -0,0 +1,133 @@
+use std::ffi::c_void;
+use std::os::unix::io::RawFd;
+use std::{ptr, slice};
+
+use nix::libc::off_t;
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::{close, ftruncate};
+use xcb::{shm, x};
+
+use super::WindowManager;
+
+/// Images of at least this many bytes are read through shared memory, if the X server supports it
+const SHM_THRESHOLD: usize = 64 * 1024;
+
+impl<'a> WindowManager<'a> {
+    /// Read the top left of a drawable as a `ZPixmap` image, which is `size` bytes long. Big images are
+    /// read through shared memory when the X server supports it (MIT-SHM 1.2), rather than being sent
+    /// over the connection (which holds up everything else we're waiting for).
+    pub(super) fn get_image(
+        &self,
+        drawable: x::Drawable,
+        width: u16,
+        height: u16,
+        size: usize,
+    ) -> xcb::Result<Vec<u8>> {
+        if self.shm_available && size >= SHM_THRESHOLD {
+            match self.get_image_shm(drawable, width, height, size) {
+                Ok(Some(data)) => return Ok(data),
+                Ok(None) => {}
+                Err(xcb::Error::Protocol(e)) => eprintln!("Failed to read image through shared memory: {:?}", e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let image = self.conn.wait_for_reply(self.conn.send_request(&x::GetImage {
+            format: x::ImageFormat::ZPixmap,
+            drawable,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            plane_mask: u32::MAX,
+        }))?;
+
+        Ok(image.data().to_vec())
+    }
+
+    /// Read an image through shared memory. Returns `None` if the memory couldn't be created.
+    fn get_image_shm(
+        &self,
+        drawable: x::Drawable,
+        width: u16,
+        height: u16,
+        size: usize,
+    ) -> xcb::Result<Option<Vec<u8>>> {
+        let (memory, fd) = match SharedMemory::create(size) {
+            Ok(memory) => memory,
+            Err(e) => {
+                eprintln!("Failed to create shared memory for an image: {}", e);
+                return Ok(None);
+            }
+        };
+
+        // NOTE: xcb closes the file descriptor once it's been sent
+        let shmseg = self.conn.generate_id();
+        self.conn.send_and_check_request(&shm::AttachFd {
+            shmseg,
+            shm_fd: fd,
+            read_only: false,
+        })?;
+        let image = self.conn.wait_for_reply(self.conn.send_request(&shm::GetImage {
+            drawable,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            plane_mask: u32::MAX,
+            format: x::ImageFormat::ZPixmap as u8,
+            shmseg,
+            offset: 0,
+        }));
+        self.conn.send_and_check_request(&shm::Detach { shmseg })?;
+
+        let len = (image?.size() as usize).min(size);
+        Ok(Some(memory.as_slice()[..len].to_vec()))
+    }
+}
+
+/// Memory which can be shared with the X server, which is unmapped when dropped
+struct SharedMemory {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl SharedMemory {
+    /// Map `len` bytes of a new memory file, returning the memory and the file's descriptor.
+    fn create(len: usize) -> nix::Result<(SharedMemory, RawFd)> {
+        let fd = memfd_create(c"r3-image", MemFdCreateFlag::MFD_CLOEXEC)?;
+        let addr = ftruncate(fd, len as off_t).and_then(|_| unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                fd,
+                0,
+            )
+        });
+
+        match addr {
+            Ok(addr) => Ok((SharedMemory { addr, len }, fd)),
+            Err(e) => {
+                let _ = close(fd);
+                Err(e)
+            }
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the memory is mapped (and readable) until we're dropped
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        // SAFETY: nothing refers to the memory once we're dropped
+        if let Err(e) = unsafe { munmap(self.addr, self.len) } {
+            eprintln!("Failed to unmap shared memory: {}", e);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use xcb::{x, Xid};
+
+use super::WindowManager;
+
+/// The clipboard's contents converted to one target (i.e. format), e.g. `UTF8_STRING`
+#[derive(Debug)]
+struct SavedTarget {
+    target: x::Atom,
+    r#type: x::Atom,
+    data: PropertyData,
+}
+
+/// The value of a property, which is a list of 8, 16 or 32 bit values
+#[derive(Debug)]
+enum PropertyData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+/// A client which asked us to save the clipboard's contents before it exits
+#[derive(Debug)]
+struct SaveRequest {
+    requestor: x::Window,
+    property: x::Atom,
+    time: x::Timestamp,
+    /// The targets we haven't copied yet, or `None` if we haven't asked which targets there are
+    remaining: Option<Vec<x::Atom>>,
+    saved: Vec<SavedTarget>,
+}
+
+/// Implements the freedesktop.org clipboard manager specification: when a client which owns the
+/// clipboard exits, it asks us (the owner of `CLIPBOARD_MANAGER`) to save the clipboard's contents
+/// with the `SAVE_TARGETS` target. We copy every target from it, and then own the clipboard ourselves
+/// so its contents outlive the client.
+#[derive(Debug)]
+pub struct ClipboardManager {
+    /// Owns `CLIPBOARD_MANAGER`, and `CLIPBOARD` once we've saved its contents
+    window: x::Window,
+    /// The contents of the clipboard, if we own it
+    saved: Vec<SavedTarget>,
+    /// The save in progress, if any
+    save_request: Option<SaveRequest>,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Start or stop the clipboard manager, depending on the configuration.
+    pub(super) fn update_clipboard_manager(&mut self) -> xcb::Result<()> {
+        match (self.config.clipboard_manager, &self.clipboard) {
+            (true, None) => self.start_clipboard_manager(),
+            (false, Some(_)) => self.stop_clipboard_manager(),
+            _ => Ok(()),
+        }
+    }
+
+    fn stop_clipboard_manager(&mut self) -> xcb::Result<()> {
+        // Our selections are released when the window is destroyed
+        if let Some(clipboard) = self.clipboard.take() {
+            self.conn.send_and_check_request(&x::DestroyWindow {
+                window: clipboard.window,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn start_clipboard_manager(&mut self) -> xcb::Result<()> {
+        let owner = self.conn.wait_for_reply(self.conn.send_request(&x::GetSelectionOwner {
+            selection: self.atoms.clipboard_manager,
+        }))?;
+        if !owner.owner().is_none() {
+            eprintln!("Not starting the clipboard manager since another one is running");
+            return Ok(());
+        }
+
+        // A hidden window which is only used to own selections
+        let window = self.conn.generate_id();
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&x::CreateWindow {
+            depth: 0,
+            wid: window,
+            parent: root,
+            x: -1,
+            y: -1,
+            width: 1,
+            height: 1,
+            border_width: 0,
+            class: x::WindowClass::InputOnly,
+            visual: x::COPY_FROM_PARENT,
+            value_list: &[x::Cw::OverrideRedirect(true)],
+        })?;
+        self.conn.send_and_check_request(&x::SetSelectionOwner {
+            owner: window,
+            selection: self.atoms.clipboard_manager,
+            time: self.last_event_time,
+        })?;
+
+        // See ICCCM 2.8: tell clients there's a new manager
+        let data = x::ClientMessageData::Data32([
+            self.last_event_time,
+            self.atoms.clipboard_manager.resource_id(),
+            window.resource_id(),
+            0,
+            0,
+        ]);
+        self.conn.send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(root),
+            event_mask: x::EventMask::STRUCTURE_NOTIFY,
+            event: &x::ClientMessageEvent::new(root, self.atoms.manager, data),
+        })?;
+
+        self.clipboard = Some(ClipboardManager {
+            window,
+            saved: vec![],
+            save_request: None,
+        });
+
+        Ok(())
+    }
+
+    /// Called when a client asks for the contents of a selection we own.
+    pub(super) fn on_selection_request(&mut self, ev: x::SelectionRequestEvent) -> xcb::Result<()> {
+        let result = if ev.selection() == self.atoms.clipboard_manager {
+            self.on_clipboard_manager_request(&ev)
+        } else if ev.selection() == self.atoms.clipboard {
+            self.send_saved_target(&ev)
+        } else {
+            self.send_selection_notify(ev.requestor(), ev.selection(), ev.target(), x::ATOM_NONE, ev.time())
+        };
+
+        match result {
+            // The requestor was most likely destroyed
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!(
+                    "Failed to reply to selection request from {:?}: {:?}",
+                    ev.requestor(),
+                    e
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn on_clipboard_manager_request(&mut self, ev: &x::SelectionRequestEvent) -> xcb::Result<()> {
+        let window = match &self.clipboard {
+            Some(clipboard) => clipboard.window,
+            None => return Ok(()),
+        };
+
+        if ev.target() == self.atoms.targets {
+            let targets = [self.atoms.targets, self.atoms.save_targets];
+            let property = requested_property(ev);
+            self.set_window_property(ev.requestor(), property, x::ATOM_ATOM, &targets)?;
+            return self.send_selection_notify(ev.requestor(), ev.selection(), ev.target(), property, ev.time());
+        }
+
+        if ev.target() != self.atoms.save_targets {
+            return self.send_selection_notify(ev.requestor(), ev.selection(), ev.target(), x::ATOM_NONE, ev.time());
+        }
+
+        // Only one save can happen at a time, and the newest one wins
+        let request = SaveRequest {
+            requestor: ev.requestor(),
+            property: ev.property(),
+            time: ev.time(),
+            remaining: None,
+            saved: vec![],
+        };
+        if let Some(old) = self.clipboard.as_mut().and_then(|c| c.save_request.replace(request)) {
+            self.send_save_result(&old, false)?;
+        }
+
+        // First find out which targets the clipboard can be converted to
+        self.conn.send_and_check_request(&x::ConvertSelection {
+            requestor: window,
+            selection: self.atoms.clipboard,
+            target: self.atoms.targets,
+            property: self.atoms.r3_clipboard,
+            time: ev.time(),
+        })?;
+
+        Ok(())
+    }
+
+    /// Called when the clipboard's owner has converted it to a target we asked for, while saving it.
+    pub(super) fn on_selection_notify(&mut self, ev: x::SelectionNotifyEvent) -> xcb::Result<()> {
+        match self.continue_saving_clipboard(&ev) {
+            // The clipboard's owner or the client which asked us to save it was most likely destroyed
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Failed to save the clipboard: {:?}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn continue_saving_clipboard(&mut self, ev: &x::SelectionNotifyEvent) -> xcb::Result<()> {
+        let (window, mut request) = match &mut self.clipboard {
+            Some(clipboard) if clipboard.window == ev.requestor() => match clipboard.save_request.take() {
+                Some(request) => (clipboard.window, request),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        // If the conversion failed then we skip that target, unless we couldn't get the targets at all
+        let property = match ev.property().is_none() {
+            true => None,
+            false => Some(self.conn.wait_for_reply(self.conn.send_request(&x::GetProperty {
+                delete: true,
+                window,
+                property: ev.property(),
+                r#type: x::ATOM_ANY,
+                long_offset: 0,
+                long_length: u32::MAX / 4,
+            }))?),
+        };
+
+        match (&request.remaining, property) {
+            (None, Some(property)) if property.format() == 32 => {
+                let ignored = [
+                    self.atoms.targets,
+                    self.atoms.multiple,
+                    self.atoms.timestamp,
+                    self.atoms.save_targets,
+                ];
+                let targets = property.value::<x::Atom>();
+                request.remaining = Some(targets.iter().filter(|t| !ignored.contains(t)).copied().collect());
+            }
+            (None, _) => return self.send_save_result(&request, false),
+            // TODO: support incremental transfers, which are used for large clipboard contents
+            (Some(_), Some(property)) if property.r#type() != self.atoms.incr => {
+                let data = match property.format() {
+                    8 => PropertyData::U8(property.value().to_vec()),
+                    16 => PropertyData::U16(property.value().to_vec()),
+                    _ => PropertyData::U32(property.value().to_vec()),
+                };
+                request.saved.push(SavedTarget {
+                    target: ev.target(),
+                    r#type: property.r#type(),
+                    data,
+                });
+            }
+            (Some(_), _) => {}
+        }
+
+        // Ask for the next target, or take over the clipboard if there are none left
+        if let Some(target) = request.remaining.as_mut().and_then(|remaining| remaining.pop()) {
+            self.conn.send_and_check_request(&x::ConvertSelection {
+                requestor: window,
+                selection: self.atoms.clipboard,
+                target,
+                property: self.atoms.r3_clipboard,
+                time: request.time,
+            })?;
+            if let Some(clipboard) = &mut self.clipboard {
+                clipboard.save_request = Some(request);
+            }
+            return Ok(());
+        }
+
+        self.conn.send_and_check_request(&x::SetSelectionOwner {
+            owner: window,
+            selection: self.atoms.clipboard,
+            time: request.time,
+        })?;
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.saved = std::mem::take(&mut request.saved);
+        }
+
+        self.send_save_result(&request, true)
+    }
+
+    /// Called when another client takes ownership of a selection we own.
+    pub(super) fn on_selection_clear(&mut self, ev: x::SelectionClearEvent) -> xcb::Result<()> {
+        if ev.selection() == self.atoms.clipboard {
+            if let Some(clipboard) = &mut self.clipboard {
+                clipboard.saved.clear();
+            }
+        } else if ev.selection() == self.atoms.clipboard_manager && self.clipboard.is_some() {
+            eprintln!("Stopping the clipboard manager since another one has started");
+            self.stop_clipboard_manager()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reply to a request for the clipboard's contents, which we've saved.
+    fn send_saved_target(&self, ev: &x::SelectionRequestEvent) -> xcb::Result<()> {
+        let saved = match &self.clipboard {
+            Some(clipboard) => &clipboard.saved,
+            None => return Ok(()),
+        };
+
+        let property = requested_property(ev);
+        if ev.target() == self.atoms.targets {
+            let mut targets = vec![self.atoms.targets];
+            targets.extend(saved.iter().map(|saved| saved.target));
+            self.set_window_property(ev.requestor(), property, x::ATOM_ATOM, &targets)?;
+        } else {
+            match saved.iter().find(|saved| saved.target == ev.target()) {
+                Some(saved) => match &saved.data {
+                    PropertyData::U8(data) => self.set_window_property(ev.requestor(), property, saved.r#type, data)?,
+                    PropertyData::U16(data) => {
+                        self.set_window_property(ev.requestor(), property, saved.r#type, data)?
+                    }
+                    PropertyData::U32(data) => {
+                        self.set_window_property(ev.requestor(), property, saved.r#type, data)?
+                    }
+                },
+                None => {
+                    return self.send_selection_notify(
+                        ev.requestor(),
+                        ev.selection(),
+                        ev.target(),
+                        x::ATOM_NONE,
+                        ev.time(),
+                    )
+                }
+            }
+        }
+
+        self.send_selection_notify(ev.requestor(), ev.selection(), ev.target(), property, ev.time())
+    }
+
+    /// Tell the client which asked us to save the clipboard whether we did.
+    fn send_save_result(&self, request: &SaveRequest, saved: bool) -> xcb::Result<()> {
+        let property = if saved { request.property } else { x::ATOM_NONE };
+        self.send_selection_notify(
+            request.requestor,
+            self.atoms.clipboard_manager,
+            self.atoms.save_targets,
+            property,
+            request.time,
+        )
+    }
+
+    fn send_selection_notify(
+        &self,
+        requestor: x::Window,
+        selection: x::Atom,
+        target: x::Atom,
+        property: x::Atom,
+        time: x::Timestamp,
+    ) -> xcb::Result<()> {
+        self.conn.send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(requestor),
+            event_mask: x::EventMask::NO_EVENT,
+            event: &x::SelectionNotifyEvent::new(time, requestor, selection, target, property),
+        })?;
+
+        Ok(())
+    }
+}
+
+/// The property to put the selection's contents in. See ICCCM 2.2: obsolete clients don't give one,
+/// in which case the target is used.
+fn requested_property(ev: &x::SelectionRequestEvent) -> x::Atom {
+    match ev.property().is_none() {
+        true => ev.target(),
+        false => ev.property(),
+    }
+}
@@ -1,18 +1,219 @@
-use r3lib::WMCommand;
+use r3lib::ipc::CommandReply;
+use r3lib::{Criteria, WMCommand};
+use xcb::{x, Xid};
 
+use super::pick::PickAction;
 use super::WindowManager;
+use crate::ipc::Reply;
+
+/// The windows a command acted on, or why it failed
+type CommandResult = Result<Vec<x::Window>, String>;
 
 impl<'a> WindowManager<'a> {
-    pub fn handle_command(&mut self, cmd: &WMCommand) -> xcb::Result<()> {
+    pub fn handle_command(&mut self, cmd: &WMCommand, reply: Reply) -> xcb::Result<()> {
+        let focused_window = self.focused_window;
         match cmd {
-            WMCommand::CloseWindow => {
-                if let Some(window) = self.focused_window.take() {
-                    println!("focused: {:?}", window);
+            // Queries reply with what was asked for
+            WMCommand::PickWindow => self.start_pick(PickAction::Reply(reply))?,
+            WMCommand::ExplainWindow { id } => {
+                // NOTE: if the id isn't a window we manage, we reply with `null`
+                let explanation = match self.find_managed_window(*id) {
+                    Some(window) => self.explain_window(window)?,
+                    None => None,
+                };
+                reply.send(&explanation);
+            }
+            WMCommand::GetPointer => reply.send(&self.get_pointer_info()?),
+            WMCommand::GetOutputs => reply.send(&self.get_outputs()?),
+            WMCommand::GetTree { format } => self.dump_tree(*format, reply)?,
+            WMCommand::GetStateHash => self.get_state_hash(reply),
+            // Everything else replies with whether it worked, and the windows it acted on
+            cmd => {
+                let result = self.run_command(cmd, self.focused_window)?;
+                reply.send(&command_reply(result));
+            }
+        }
+
+        self.finish_command(focused_window)
+    }
+
+    /// Run the command on each window which matches the criteria, instead of the focused window.
+    pub fn handle_command_matching(&mut self, criteria: &Criteria, cmd: &WMCommand, reply: Reply) -> xcb::Result<()> {
+        let focused_window = self.focused_window;
+        let result = match acts_on_a_window(cmd) {
+            true => match self.windows_matching(criteria)? {
+                Ok(windows) => self.run_command_on(cmd, windows)?,
+                Err(e) => Err(e),
+            },
+            false => Err("this command can't be given criteria".into()),
+        };
+        reply.send(&command_reply(result));
+
+        self.finish_command(focused_window)
+    }
+
+    /// Run the command on each of the windows in turn, stopping if it fails.
+    fn run_command_on(&mut self, cmd: &WMCommand, windows: Vec<x::Window>) -> xcb::Result<CommandResult> {
+        let mut acted_on = vec![];
+        for window in windows {
+            match self.run_command(cmd, Some(window))? {
+                Ok(windows) => acted_on.extend(windows),
+                Err(e) => return Ok(Err(e)),
+            }
+        }
+
+        Ok(Ok(acted_on))
+    }
+
+    /// Warp the pointer to the focused window if the command changed it, and apply the changes.
+    fn finish_command(&mut self, focused_window: Option<x::Window>) -> xcb::Result<()> {
+        if self.config.mouse_warping && self.focused_window != focused_window {
+            if let Some(window) = self.focused_window {
+                self.warp_pointer_to(window)?;
+            }
+        }
+
+        self.refresh()
+    }
+
+    /// Run a command which isn't a query. Commands which act on a window act on `target`, which is the
+    /// focused window unless they were given criteria. Commands with nothing to act on (e.g. closing a
+    /// window when none are focused) succeed without acting on any windows.
+    fn run_command(&mut self, cmd: &WMCommand, target: Option<x::Window>) -> xcb::Result<CommandResult> {
+        Ok(match cmd {
+            WMCommand::CloseWindow => match target {
+                Some(window) => {
+                    if self.focused_window == Some(window) {
+                        self.focused_window = None;
+                    }
                     self.kill_window(window)?;
+                    Ok(vec![window])
+                }
+                None => Ok(vec![]),
+            },
+            WMCommand::Focus => self.focus_command(target)?,
+            WMCommand::KillMode => {
+                self.start_pick(PickAction::Kill)?;
+                Ok(vec![])
+            }
+            WMCommand::FocusUrgent { oldest } => {
+                let urgent = match oldest {
+                    true => self.urgent_windows.first(),
+                    false => self.urgent_windows.last(),
+                };
+                self.focus_command(urgent.copied())?
+            }
+            WMCommand::FocusLast => {
+                let window = self.last_focused_window();
+                self.focus_command(window)?
+            }
+            WMCommand::FocusNextOfClass { reverse } => {
+                let window = self.next_window_of_class(*reverse)?;
+                self.focus_command(window)?
+            }
+            WMCommand::Split { split } => match target.and_then(|target| self.get_frame_and_window(target)) {
+                Some((window, _)) => match self.split_window(window, *split) {
+                    true => Ok(vec![window]),
+                    false => Err("floating windows can't be split".into()),
+                },
+                None => Ok(vec![]),
+            },
+            WMCommand::UndoGeometry => match target {
+                Some(window) => Ok(self
+                    .without_enter_events(|wm| wm.undo_geometry(window))?
+                    .into_iter()
+                    .collect()),
+                None => Ok(vec![]),
+            },
+            WMCommand::MirrorWindow { x, y } => match target {
+                Some(window) => {
+                    self.start_mirror(window, (*x, *y).into())?;
+                    Ok(vec![window])
                 }
+                None => Ok(vec![]),
+            },
+            WMCommand::StopMirroring => {
+                self.stop_mirroring(None);
+                Ok(vec![])
             }
+            WMCommand::Exec { command } => match self.launcher.spawn(command) {
+                Ok(()) => Ok(vec![]),
+                Err(e) => {
+                    eprintln!("Failed to run {:?}: {}", command, e);
+                    Err(e.to_string())
+                }
+            },
+            WMCommand::FlashWindow { id } => {
+                let window = match id {
+                    Some(id) => match self.find_managed_window(*id) {
+                        Some(window) => Some(window),
+                        None => return Ok(Err(format!("No managed window has the id {:#x}", id))),
+                    },
+                    None => target,
+                };
+                if let Some(window) = window {
+                    self.flash_window(window);
+                }
+                Ok(window.into_iter().collect())
+            }
+            WMCommand::ReloadConfig => self.without_enter_events(|wm| wm.reload_config())?.map(|()| vec![]),
+            WMCommand::PickWindow
+            | WMCommand::ExplainWindow { .. }
+            | WMCommand::GetPointer
+            | WMCommand::GetOutputs
+            | WMCommand::GetTree { .. }
+            | WMCommand::GetStateHash => unreachable!("queries are handled by handle_command"),
+        })
+    }
+
+    /// Focus the window, if there is one.
+    fn focus_command(&mut self, window: Option<x::Window>) -> xcb::Result<CommandResult> {
+        if let Some(window) = window {
+            self.set_focus(Some(window))?;
         }
 
-        Ok(())
+        Ok(Ok(window.into_iter().collect()))
+    }
+
+    /// The managed window with the given X id.
+    fn find_managed_window(&self, id: u32) -> Option<x::Window> {
+        self.framed_clients
+            .left_values()
+            .find(|w| w.resource_id() == id)
+            .copied()
+    }
+}
+
+/// The reply sent to IPC clients after running a command.
+fn command_reply(result: CommandResult) -> CommandReply {
+    match result {
+        Ok(windows) => CommandReply::ok(windows.iter().map(|window| window.resource_id()).collect()),
+        Err(error) => CommandReply::failed(error),
+    }
+}
+
+/// Whether the command acts on a single window (the focused one, unless it's given criteria).
+pub(super) fn acts_on_a_window(cmd: &WMCommand) -> bool {
+    match cmd {
+        WMCommand::CloseWindow
+        | WMCommand::Focus
+        | WMCommand::UndoGeometry
+        | WMCommand::Split { .. }
+        | WMCommand::MirrorWindow { .. }
+        | WMCommand::FlashWindow { id: None } => true,
+        WMCommand::KillMode
+        | WMCommand::PickWindow
+        | WMCommand::FocusUrgent { .. }
+        | WMCommand::FocusLast
+        | WMCommand::FocusNextOfClass { .. }
+        | WMCommand::StopMirroring
+        | WMCommand::Exec { .. }
+        | WMCommand::FlashWindow { id: Some(_) }
+        | WMCommand::ExplainWindow { .. }
+        | WMCommand::GetPointer
+        | WMCommand::GetOutputs
+        | WMCommand::GetTree { .. }
+        | WMCommand::GetStateHash
+        | WMCommand::ReloadConfig => false,
     }
 }
@@ -0,0 +1,156 @@
+use xcb::x;
+
+use super::WindowManager;
+use crate::ipc::Reply;
+
+impl<'a> WindowManager<'a> {
+    /// Check our state against the X server's, and reply with a description of every problem found.
+    /// If `repair` is set, then problems are fixed as they're found.
+    pub fn check_consistency(&mut self, repair: bool, reply: Reply) -> xcb::Result<()> {
+        let mut problems = vec![];
+        let root = self.get_root_window()?;
+
+        // Every frame should exist, be a child of the root window, and contain only its client
+        let framed_clients = self.framed_clients.iter().map(|(w, f)| (*w, *f)).collect::<Vec<_>>();
+        for (window, frame) in framed_clients {
+            let tree = match self
+                .conn
+                .wait_for_reply(self.conn.send_request(&x::QueryTree { window: frame }))
+            {
+                Ok(tree) => tree,
+                Err(xcb::Error::Protocol(_)) => {
+                    problems.push(format!("frame {:?} of window {:?} no longer exists", frame, window));
+                    if repair {
+                        self.forget_window(window);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if tree.parent() != root {
+                problems.push(format!("frame {:?} is not a child of the root window", frame));
+            }
+
+            if !tree.children().contains(&window) {
+                problems.push(format!("window {:?} is not in its frame {:?}", window, frame));
+                if repair {
+                    self.forget_window(window);
+                    // NOTE: this fails if the frame was destroyed since we looked, which is just as good
+                    let _ = self.conn.send_and_check_request(&x::DestroyWindow { window: frame });
+                    continue;
+                }
+            }
+
+            if tree.children().len() > 1 {
+                problems.push(format!("frame {:?} contains {} windows", frame, tree.children().len()));
+            }
+        }
+
+        // Every frame on the root window should be one of ours
+        let tree = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::QueryTree { window: root }))?;
+        for child in tree.children() {
+            if self.framed_clients.contains_right(child) || !self.is_frame(*child)? {
+                continue;
+            }
+
+            problems.push(format!("frame {:?} isn't tracked", child));
+            if repair {
+                match self.destroy_orphan_frame(root, *child) {
+                    // The frame (or a window in it) was destroyed while we were repairing it
+                    Err(xcb::Error::Protocol(e)) => eprintln!("Failed to destroy frame {:?}: {:?}", child, e),
+                    result => result?,
+                }
+            }
+        }
+
+        // Anything we track should still exist
+        if let Some(window) = self.focused_window {
+            if !self.window_exists(window)? {
+                problems.push(format!("focused window {:?} no longer exists", window));
+                if repair {
+                    self.set_focus(None)?;
+                }
+            }
+        }
+
+        let unmanaged = |window: &x::Window| !self.framed_clients.contains_left(window);
+        for window in self.urgent_windows.iter().filter(|w| unmanaged(w)) {
+            problems.push(format!("urgent window {:?} isn't managed", window));
+        }
+        for window in self.window_properties.keys().filter(|w| unmanaged(w)) {
+            problems.push(format!("properties are cached for unmanaged window {:?}", window));
+        }
+        if repair {
+            let framed_clients = &self.framed_clients;
+            self.urgent_windows
+                .retain(|window| framed_clients.contains_left(window));
+            self.window_properties
+                .retain(|window, _| framed_clients.contains_left(window));
+        }
+
+        // TODO: check stacking order once we have layers (until then any order is valid)
+
+        for problem in &problems {
+            eprintln!("Inconsistency: {}", problem);
+        }
+
+        reply.send(&problems);
+        self.refresh()
+    }
+
+    /// Does the window have the property we set on all of our frames? Windows which have been destroyed
+    /// aren't frames (any more).
+    fn is_frame(&self, window: x::Window) -> xcb::Result<bool> {
+        let reply = self.conn.wait_for_reply(self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.atoms.r3_frame,
+            r#type: x::ATOM_STRING,
+            long_offset: 0,
+            long_length: 1,
+        }));
+
+        match reply {
+            Ok(reply) => Ok(!reply.value::<u8>().is_empty()),
+            Err(xcb::Error::Protocol(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn window_exists(&self, window: x::Window) -> xcb::Result<bool> {
+        match self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::GetWindowAttributes { window }))
+        {
+            Ok(_) => Ok(true),
+            Err(xcb::Error::Protocol(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Destroy a frame we don't know about, and frame any windows it contained again.
+    fn destroy_orphan_frame(&mut self, root: x::Window, frame: x::Window) -> xcb::Result<()> {
+        let tree = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::QueryTree { window: frame }))?;
+        for window in tree.children() {
+            self.conn.send_and_check_request(&x::ReparentWindow {
+                window: *window,
+                parent: root,
+                x: 0,
+                y: 0,
+            })?;
+        }
+
+        self.conn.send_and_check_request(&x::DestroyWindow { window: frame })?;
+
+        for window in tree.children() {
+            self.frame_window(*window, true)?;
+        }
+
+        Ok(())
+    }
+}
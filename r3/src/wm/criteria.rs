@@ -0,0 +1,77 @@
+use r3lib::Criteria;
+use regex::Regex;
+use xcb::{x, Xid};
+
+use super::WindowManager;
+
+/// Criteria with their regular expressions compiled
+struct CompiledCriteria {
+    class: Option<Regex>,
+    instance: Option<Regex>,
+    window_role: Option<Regex>,
+    title: Option<Regex>,
+    id: Option<u32>,
+}
+
+impl CompiledCriteria {
+    fn new(criteria: &Criteria) -> Result<CompiledCriteria, String> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| format!("invalid criteria: {}", e))
+        };
+
+        Ok(CompiledCriteria {
+            class: compile(&criteria.class)?,
+            instance: compile(&criteria.instance)?,
+            window_role: compile(&criteria.window_role)?,
+            title: compile(&criteria.title)?,
+            id: criteria.id,
+        })
+    }
+}
+
+/// Whether the value matches the pattern, or there isn't one.
+fn matches(pattern: &Option<Regex>, value: &str) -> bool {
+    pattern.as_ref().is_none_or(|pattern| pattern.is_match(value))
+}
+
+impl<'a> WindowManager<'a> {
+    /// The managed windows which match the criteria (ordered by their ids), or why the criteria can't
+    /// be used.
+    pub(super) fn windows_matching(&mut self, criteria: &Criteria) -> xcb::Result<Result<Vec<x::Window>, String>> {
+        let criteria = match CompiledCriteria::new(criteria) {
+            Ok(criteria) => criteria,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let mut windows = self.framed_clients.left_values().copied().collect::<Vec<_>>();
+        windows.sort_by_key(|window| window.resource_id());
+
+        let mut matching = vec![];
+        for window in windows {
+            if criteria.id.is_some_and(|id| id != window.resource_id()) {
+                continue;
+            }
+
+            let properties = self.get_window_properties(window)?;
+            if !matches(&criteria.class, &self.window_class(&properties))
+                || !matches(&criteria.instance, &properties.instance)
+                || !matches(&criteria.window_role, &properties.role)
+            {
+                continue;
+            }
+
+            // Only read the title if it's needed
+            if criteria.title.is_some() && !matches(&criteria.title, &self.get_window_title(window)?) {
+                continue;
+            }
+
+            matching.push(window);
+        }
+
+        Ok(Ok(matching))
+    }
+}
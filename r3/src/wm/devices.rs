@@ -0,0 +1,166 @@
+use r3lib::R3Command;
+use xcb::{x, xinput};
+
+use super::keys::{keycodes_typing, IGNORED_MODIFIERS, IGNORED_MODIFIER_COMBINATIONS};
+use super::WindowManager;
+use crate::ipc::Reply;
+
+/// A key we've grabbed on a single input device (with XInput2), and the command it runs
+#[derive(Debug)]
+pub struct DeviceGrab {
+    device: u16,
+    keycode: x::Keycode,
+    modifiers: x::ModMask,
+    command: R3Command,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Ask to be told when input devices are added, removed, enabled or disabled, so bindings for a
+    /// device work when it's plugged in after we've started.
+    pub(super) fn watch_devices(&mut self) -> xcb::Result<()> {
+        if !self.xinput_available {
+            return Ok(());
+        }
+
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&xinput::XiSelectEvents {
+            window: root,
+            masks: &[xinput::EventMaskBuf::new(
+                xinput::Device::All,
+                &[xinput::XiEventMask::HIERARCHY],
+            )],
+        })?;
+
+        Ok(())
+    }
+
+    pub(super) fn on_hierarchy(&mut self, ev: xinput::HierarchyEvent) -> xcb::Result<()> {
+        let changes = xinput::HierarchyMask::SLAVE_ADDED
+            | xinput::HierarchyMask::SLAVE_REMOVED
+            | xinput::HierarchyMask::DEVICE_ENABLED
+            | xinput::HierarchyMask::DEVICE_DISABLED;
+        if ev.flags().intersects(changes) && !self.config.device_bindings.is_empty() {
+            self.grab_bindings()?;
+        }
+
+        Ok(())
+    }
+
+    /// Grab the keys of the bindings of specific devices, on those devices only, replacing any
+    /// previous grabs. Keys pressed on other keyboards are passed through to windows as usual.
+    pub(super) fn grab_device_bindings(
+        &mut self,
+        mapping: &x::GetKeyboardMappingReply,
+        min_keycode: x::Keycode,
+    ) -> xcb::Result<()> {
+        let root = self.get_root_window()?;
+        for grab in self.device_grabs.drain(..) {
+            // NOTE: this fails if the device has been removed, which takes its grabs with it
+            let _ = self.conn.send_and_check_request(&xinput::XiPassiveUngrabDevice {
+                grab_window: root,
+                detail: grab.keycode as u32,
+                device: xinput::Device::Id(grab.device),
+                grab_type: xinput::GrabType::Keycode,
+                modifiers: &[xinput::ModifierMask::ANY.bits()],
+            });
+        }
+
+        if !self.xinput_available || self.config.device_bindings.is_empty() {
+            return Ok(());
+        }
+
+        // Bindings are configured by the device's name, so find the (slave) keyboards with each name
+        let devices = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&xinput::XiQueryDevice {
+                device: xinput::Device::All,
+            }))?;
+        let keyboards = devices
+            .infos()
+            .filter(|info| info.r#type() == xinput::DeviceType::SlaveKeyboard && info.enabled())
+            .map(|info| (info.device().id(), info.name().to_utf8().into_owned()))
+            .collect::<Vec<_>>();
+
+        for (name, bindings) in &self.config.device_bindings {
+            let devices = keyboards
+                .iter()
+                .filter(|(_, device_name)| device_name == name)
+                .map(|(device, _)| *device)
+                .collect::<Vec<_>>();
+            if devices.is_empty() {
+                eprintln!("No keyboard named {:?} is connected, its bindings aren't grabbed", name);
+                continue;
+            }
+
+            for binding in bindings {
+                let keycodes = keycodes_typing(mapping, min_keycode, binding.combo.keysym);
+                if keycodes.is_empty() {
+                    eprintln!("No key on the keyboard is bound to {:?}", binding);
+                }
+
+                for (device, (keycode, shift)) in devices.iter().flat_map(|d| keycodes.iter().map(move |k| (*d, *k))) {
+                    // Grab the key with every combination of the ignored modifiers
+                    let modifiers = binding.combo.modifiers | shift;
+                    let grab_modifiers = IGNORED_MODIFIER_COMBINATIONS
+                        .iter()
+                        .map(|ignored| (modifiers | *ignored).bits())
+                        .collect::<Vec<_>>();
+                    let result = self
+                        .conn
+                        .wait_for_reply(self.conn.send_request(&xinput::XiPassiveGrabDevice {
+                            time: x::CURRENT_TIME,
+                            grab_window: root,
+                            cursor: x::CURSOR_NONE,
+                            detail: keycode as u32,
+                            device: xinput::Device::Id(device),
+                            grab_type: xinput::GrabType::Keycode,
+                            grab_mode: xinput::GrabMode22::Async,
+                            paired_device_mode: x::GrabMode::Async,
+                            owner_events: xinput::GrabOwner::NoOwner,
+                            mask: &[xinput::XiEventMask::KEY_PRESS.bits()],
+                            modifiers: &grab_modifiers,
+                        }));
+                    // NOTE: the reply lists the modifiers which couldn't be grabbed, e.g. because
+                    // another client has already grabbed the key
+                    match result {
+                        Ok(reply) if reply.modifiers().is_empty() => {}
+                        Ok(_) => eprintln!("Failed to grab key on {:?} for {:?}", name, binding),
+                        Err(e) => eprintln!("Failed to grab key on {:?} for {:?}: {:?}", name, binding, e),
+                    }
+
+                    self.device_grabs.push(DeviceGrab {
+                        device,
+                        keycode,
+                        modifiers,
+                        command: binding.command.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn on_device_key_press(&mut self, ev: xinput::KeyPressEvent) -> xcb::Result<()> {
+        // While picking a window, all keys are swallowed
+        if self.pick.is_some() {
+            return Ok(());
+        }
+
+        let device = ev.source().id();
+        let modifiers = x::ModMask::from_bits_truncate(ev.mods().effective) - IGNORED_MODIFIERS;
+        let command = self
+            .device_grabs
+            .iter()
+            .find(|grab| grab.device == device && grab.keycode as u32 == ev.detail() && grab.modifiers == modifiers)
+            .map(|grab| grab.command.clone());
+
+        // Bound commands are run by the event loop, just like commands from IPC clients
+        if let Some(command) = command {
+            self.ev_queue.lock().unwrap().push(command, Reply::none());
+            self.ev_waker.wake().unwrap();
+        }
+
+        Ok(())
+    }
+}
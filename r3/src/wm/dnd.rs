@@ -0,0 +1,40 @@
+use xcb::x;
+
+use super::WindowManager;
+use crate::ret_ok_if_none;
+
+impl<'a> WindowManager<'a> {
+    /// Drag and drop sources (see the XDND spec) look for the top-level window under the pointer with
+    /// `XdndAware` set, which is our frame rather than the client. So we mark the frame as aware with
+    /// the client's supported version, and point its `XdndProxy` at the client so it gets the messages.
+    pub(super) fn update_dnd_proxy(&mut self, window: x::Window) -> xcb::Result<()> {
+        let frame = *ret_ok_if_none!(self.framed_clients.get_by_left(&window));
+
+        let aware = self.get_window_property::<x::Atom>(window, self.atoms.xdnd_aware, x::ATOM_ATOM)?;
+        if aware.is_empty() {
+            for property in [self.atoms.xdnd_aware, self.atoms.xdnd_proxy] {
+                self.conn.send_and_check_request(&x::DeleteProperty {
+                    window: frame,
+                    property,
+                })?;
+            }
+            return Ok(());
+        }
+
+        // Sources ignore a proxy unless its own `XdndProxy` points to itself, so if the client doesn't
+        // already use a proxy then make it its own
+        let proxy = match self
+            .get_window_property::<x::Window>(window, self.atoms.xdnd_proxy, x::ATOM_WINDOW)?
+            .first()
+        {
+            Some(proxy) => *proxy,
+            None => {
+                self.set_window_property(window, self.atoms.xdnd_proxy, x::ATOM_WINDOW, &[window])?;
+                window
+            }
+        };
+
+        self.set_window_property(frame, self.atoms.xdnd_aware, x::ATOM_ATOM, &aware)?;
+        self.set_window_property(frame, self.atoms.xdnd_proxy, x::ATOM_WINDOW, &[proxy])
+    }
+}
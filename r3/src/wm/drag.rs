@@ -0,0 +1,227 @@
+use std::time::Instant;
+
+use r3lib::{Criteria, R3Command};
+use xcb::{x, Xid};
+
+use super::cmd_handlers::acts_on_a_window;
+use super::keys::{IGNORED_MODIFIERS, IGNORED_MODIFIER_COMBINATIONS};
+use super::{DragType, WindowManager};
+use crate::bindings::{FrameAction, FrameClick, FrameRegion, RootClick};
+use crate::ipc::Reply;
+use crate::window_geometry::WindowGeometry;
+
+/// How far corners of frames reach along each edge, in pixels
+const CORNER_SIZE: i16 = 20;
+
+impl<'a> WindowManager<'a> {
+    /// Grab the mouse buttons on a client window, so we're told when it's clicked or dragged. This
+    /// replaces any previous grabs, so it should be called again when the configuration changes.
+    pub(super) fn grab_buttons(&self, window: x::Window) -> xcb::Result<()> {
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&x::UngrabButton {
+            button: x::ButtonIndex::Any,
+            grab_window: window,
+            modifiers: x::ModMask::ANY,
+        })?;
+
+        // Any click focuses the window. The pointer is frozen until we've seen the click, and then the
+        // click is replayed so the window gets it as well
+        self.conn.send_and_check_request(&x::GrabButton {
+            grab_window: window,
+            owner_events: false,
+            event_mask: x::EventMask::BUTTON_PRESS,
+            pointer_mode: x::GrabMode::Sync,
+            keyboard_mode: x::GrabMode::Async,
+            confine_to: xcb::Xid::none(),
+            cursor: xcb::Xid::none(),
+            button: x::ButtonIndex::Any,
+            modifiers: x::ModMask::ANY,
+        })?;
+
+        // Holding the drag modifier starts a drag instead, which the window doesn't see (grabs with
+        // specific modifiers take precedence over the one above)
+        for button in [self.config.drag_move_button, self.config.drag_resize_button] {
+            for ignored in IGNORED_MODIFIER_COMBINATIONS {
+                self.conn.send_and_check_request(&x::GrabButton {
+                    grab_window: window,
+                    owner_events: false,
+                    event_mask: x::EventMask::BUTTON_PRESS | x::EventMask::BUTTON_RELEASE | x::EventMask::BUTTON_MOTION,
+                    pointer_mode: x::GrabMode::Async,
+                    keyboard_mode: x::GrabMode::Async,
+                    confine_to: root,
+                    cursor: xcb::Xid::none(),
+                    button: button_index(button),
+                    modifiers: self.config.drag_modifier | ignored,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Is the drag modifier held in the given state?
+    pub(super) fn drag_modifier_held(&self, state: x::KeyButMask) -> bool {
+        let modifiers = x::ModMask::from_bits_truncate(state.bits()) - IGNORED_MODIFIERS;
+        modifiers == self.config.drag_modifier
+    }
+
+    /// The drag started by pressing the button, if it's one of the drag buttons.
+    pub(super) fn drag_action(&self, button: u8) -> Option<FrameAction> {
+        if button == self.config.drag_move_button {
+            Some(FrameAction::Move)
+        } else if button == self.config.drag_resize_button {
+            Some(FrameAction::Resize)
+        } else {
+            None
+        }
+    }
+
+    /// What clicking on the frame does: the binding for the part of the frame which was clicked, or
+    /// a drag if there isn't one.
+    pub(super) fn frame_action(
+        &self,
+        window: x::Window,
+        frame: x::Window,
+        ev: &x::ButtonPressEvent,
+    ) -> xcb::Result<Option<FrameAction>> {
+        let mut action = None;
+        if !self.config.frame_bindings.is_empty() {
+            let click = FrameClick {
+                region: self.frame_region(window, frame, (ev.event_x(), ev.event_y()))?,
+                button: ev.detail(),
+            };
+            action = self
+                .config
+                .frame_bindings
+                .iter()
+                .find(|binding| binding.click == click)
+                .map(|binding| binding.action.clone());
+        }
+
+        Ok(action.or_else(|| self.drag_action(ev.detail())))
+    }
+
+    /// The part of the frame at the position, relative to the inside of the frame's border.
+    fn frame_region(&self, window: x::Window, frame: x::Window, (x, y): (i16, i16)) -> xcb::Result<FrameRegion> {
+        let rect = self.get_window_rect(frame)?;
+        let (width, height, bw) = (rect.w as i16, rect.h as i16, rect.bw as i16);
+
+        // Clicks on the border are reported outside the frame's area
+        if x < 0 || y < 0 || x >= width || y >= height {
+            let near_edge =
+                |position: i16, size: i16| position + bw < CORNER_SIZE || position + bw >= size + bw * 2 - CORNER_SIZE;
+            return Ok(match near_edge(x, width) && near_edge(y, height) {
+                true => FrameRegion::Corner,
+                false => FrameRegion::Border,
+            });
+        }
+
+        let titlebar = self.titlebar(window).rect(rect.w, rect.h);
+        let in_titlebar = (titlebar.x..titlebar.x + titlebar.width as i16).contains(&x)
+            && (titlebar.y..titlebar.y + titlebar.height as i16).contains(&y);
+        Ok(match in_titlebar {
+            true => FrameRegion::Titlebar,
+            false => FrameRegion::Border,
+        })
+    }
+
+    /// Run the command bound to a click on the desktop, if there is one. Clicks on windows which don't
+    /// ask for clicks are reported on the root window too, and they're ignored.
+    pub(super) fn on_root_click(&self, ev: x::ButtonPressEvent) -> xcb::Result<()> {
+        if ev.child() != x::WINDOW_NONE {
+            return Ok(());
+        }
+
+        let click = RootClick {
+            modifiers: x::ModMask::from_bits_truncate(ev.state().bits()) - IGNORED_MODIFIERS,
+            button: ev.detail(),
+        };
+        let command = self
+            .config
+            .root_bindings
+            .iter()
+            .find(|binding| binding.click == click)
+            .map(|binding| binding.command.clone());
+
+        // Bound commands are run by the event loop, just like commands from IPC clients
+        if let Some(command) = command {
+            self.ev_queue.lock().unwrap().push(command, Reply::none());
+            self.ev_waker.wake().unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Run a command bound to a click on the window's frame. Commands which act on a window act on
+    /// the clicked one rather than the focused one.
+    pub(super) fn run_frame_command(&self, window: x::Window, command: R3Command) {
+        let command = match command {
+            R3Command::WM(command) if acts_on_a_window(&command) => R3Command::WithCriteria {
+                criteria: Criteria {
+                    id: Some(window.resource_id()),
+                    ..Criteria::default()
+                },
+                command,
+            },
+            command => command,
+        };
+
+        // Bound commands are run by the event loop, just like commands from IPC clients
+        self.ev_queue.lock().unwrap().push(command, Reply::none());
+        self.ev_waker.wake().unwrap();
+    }
+
+    /// Move or resize the window to the given frame rect.
+    pub(super) fn apply_drag(&self, window: x::Window, drag_type: DragType, rect: WindowGeometry) -> xcb::Result<()> {
+        match drag_type {
+            DragType::Move => self.move_window(window, (rect.x, rect.y).into()),
+            DragType::Resize => self.resize_window(window, rect),
+        }
+    }
+
+    /// Apply the latest drag position if enough time has passed since the last one was applied (or
+    /// immediately if `force` is true), so we don't flood the X server with configure requests.
+    pub(super) fn apply_pending_drag(&mut self, force: bool) -> xcb::Result<()> {
+        if self.pending_drag.is_none() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if !force && now.duration_since(self.last_drag_update) < self.config.drag_update_interval {
+            return Ok(());
+        }
+
+        // Don't resize a window again until it's finished redrawing after the last resize
+        if let Some((window, DragType::Resize, _)) = self.pending_drag {
+            if !force && self.awaiting_sync(window) {
+                return Ok(());
+            }
+        }
+
+        if let Some((window, drag_type, rect)) = self.pending_drag.take() {
+            self.last_drag_update = now;
+
+            // When dragging with an outline, only the outline is moved until the drag is finished
+            if self.config.drag_outline {
+                self.update_drag_outline(window, drag_type, rect)?;
+            } else {
+                if drag_type == DragType::Resize {
+                    self.send_sync_request(window)?;
+                }
+                self.apply_drag(window, drag_type, rect)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn button_index(button: u8) -> x::ButtonIndex {
+    match button {
+        1 => x::ButtonIndex::N1,
+        2 => x::ButtonIndex::N2,
+        3 => x::ButtonIndex::N3,
+        4 => x::ButtonIndex::N4,
+        _ => x::ButtonIndex::N5,
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use r3lib::ipc::CommandReply;
+use xcb::{x, Xid};
+
+use super::WindowManager;
+use crate::ipc::Reply;
+
+impl<'a> WindowManager<'a> {
+    /// Reply with a description of our internal state, or write it to the file (replying with whether
+    /// it could be written). This is meant to be attached to bug reports, so it includes everything
+    /// which may have confused us rather than just what's shown by `GetTree`.
+    pub fn dump_state(&self, file: Option<&Path>, reply: Reply) {
+        let state = self.describe_state();
+        match file {
+            None => reply.send(&state),
+            Some(path) => match fs::write(path, state) {
+                Ok(()) => reply.send(&CommandReply::ok(vec![])),
+                Err(e) => reply.send(&CommandReply::failed(format!(
+                    "failed to write {}: {}",
+                    path.display(),
+                    e
+                ))),
+            },
+        }
+    }
+
+    fn describe_state(&self) -> String {
+        // NOTE: writing to a `String` can't fail
+        let mut s = String::new();
+        writeln!(s, "# r3 {} (pid {})", env!("CARGO_PKG_VERSION"), std::process::id()).unwrap();
+        writeln!(s, "uptime: {:?}", self.start_time.elapsed()).unwrap();
+        writeln!(s, "tree generation: {}", self.tree_generation).unwrap();
+
+        writeln!(s, "\n## Windows (client -> frame)").unwrap();
+        let mut framed_clients = self.framed_clients.iter().collect::<Vec<_>>();
+        framed_clients.sort_by_key(|(window, _)| window.resource_id());
+        for (window, frame) in framed_clients {
+            writeln!(s, "{} -> {}", id(window), id(frame)).unwrap();
+            if let Some(properties) = self.window_properties.get(window) {
+                writeln!(s, "  properties: {:?}", properties).unwrap();
+            }
+            if let Some(titlebar) = self.titlebars.get(window) {
+                writeln!(s, "  titlebar: {:?}", titlebar).unwrap();
+            }
+            if let Some(colors) = self.painted_colors.get(frame) {
+                writeln!(s, "  painted colours: {:?}", colors).unwrap();
+            }
+            if let Some(history) = self.geometry_history.get(window) {
+                writeln!(s, "  geometry history: {:?}", history).unwrap();
+            }
+            if let Some(decisions) = self.decisions.get(window) {
+                writeln!(s, "  decisions: {:?}", decisions).unwrap();
+            }
+        }
+        writeln!(
+            s,
+            "override-redirect windows: [{}]",
+            ids(&self.override_redirect_windows)
+        )
+        .unwrap();
+        writeln!(s, "pending properties: {:?}", self.pending_properties).unwrap();
+        writeln!(s, "stale titles: [{}]", ids(&self.stale_titles)).unwrap();
+
+        writeln!(s, "\n## Layout").unwrap();
+        writeln!(s, "workspace: {:?}", self.workspace).unwrap();
+        writeln!(s, "pending: {}", self.layout_pending).unwrap();
+
+        writeln!(s, "\n## Focus").unwrap();
+        writeln!(s, "focused: {:?}", self.focused_window.as_ref().map(id)).unwrap();
+        writeln!(s, "applied focus: {:?}", self.applied_focus.as_ref().map(id)).unwrap();
+        writeln!(
+            s,
+            "hover focus: {:?}",
+            self.hover_focus.map(|(w, at)| (id(&w), at.elapsed()))
+        )
+        .unwrap();
+        writeln!(s, "history (most recent last): [{}]", ids(&self.focus_history)).unwrap();
+        writeln!(s, "urgent windows: [{}]", ids(&self.urgent_windows)).unwrap();
+        writeln!(s, "flashing windows: [{}]", ids(self.flashing_windows.keys())).unwrap();
+        writeln!(s, "last event time: {}", self.last_event_time).unwrap();
+
+        writeln!(s, "\n## Drag").unwrap();
+        writeln!(s, "type: {:?}", self.drag_type).unwrap();
+        writeln!(s, "start: {:?}", self.drag_start).unwrap();
+        writeln!(s, "start frame rect: {:?}", self.drag_start_frame_rect).unwrap();
+        writeln!(s, "pending: {:?}", self.pending_drag).unwrap();
+        writeln!(s, "outline: {:?}", self.drag_outline).unwrap();
+        writeln!(s, "sync request: {:?}", self.sync_request).unwrap();
+        writeln!(s, "pick: {:?}", self.pick).unwrap();
+        writeln!(s, "touch gesture: {:?}", self.touch_gesture).unwrap();
+
+        writeln!(s, "\n## Grabs").unwrap();
+        for key in &self.grabbed_keys {
+            writeln!(s, "{:?}", key).unwrap();
+        }
+        for grab in &self.device_grabs {
+            writeln!(s, "{:?}", grab).unwrap();
+        }
+
+        writeln!(s, "\n## Ignored sequences").unwrap();
+        writeln!(s, "{:?}", self.ignored_sequences).unwrap();
+
+        writeln!(s, "\n## Other").unwrap();
+        writeln!(s, "mirrors: {:?}", self.mirrors).unwrap();
+        writeln!(s, "outputs: {:?}", self.outputs).unwrap();
+        writeln!(s, "clipboard: {:?}", self.clipboard).unwrap();
+        writeln!(s, "idle thresholds passed: {}", self.idle_thresholds_passed).unwrap();
+        writeln!(s, "render pending: {}", self.render_pending).unwrap();
+        writeln!(s, "subscribers: {}", self.subscribers.len()).unwrap();
+        writeln!(
+            s,
+            "extensions: composite={} damage={} sync={} shm={} randr={} xinput={} screensaver={}",
+            self.composite_available,
+            self.damage_available,
+            self.sync_available,
+            self.shm_available,
+            self.randr_available,
+            self.xinput_available,
+            self.screensaver_available
+        )
+        .unwrap();
+
+        s
+    }
+}
+
+fn id(window: &x::Window) -> String {
+    format!("{:#x}", window.resource_id())
+}
+
+fn ids<'w>(windows: impl IntoIterator<Item = &'w x::Window>) -> String {
+    windows.into_iter().map(id).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,25 @@
+use r3lib::ipc::CommandReply;
+use r3lib::R3Event;
+
+use super::WindowManager;
+use crate::ipc::Reply;
+
+impl<'a> WindowManager<'a> {
+    /// Keep the client connected, and send it every event from now on.
+    pub fn subscribe(&mut self, reply: Reply) {
+        if let Some(subscriber) = reply.into_subscriber() {
+            self.subscribers.push(subscriber);
+        }
+    }
+
+    /// Send a tick to all subscribed clients.
+    pub fn send_tick(&mut self, payload: String, reply: Reply) {
+        self.emit_event(R3Event::Tick { payload });
+        reply.send(&CommandReply::ok(vec![]));
+    }
+
+    /// Send an event to all subscribed clients, forgetting any which have gone away.
+    pub(super) fn emit_event(&mut self, event: R3Event) {
+        self.subscribers.retain(|subscriber| subscriber.send(&event));
+    }
+}
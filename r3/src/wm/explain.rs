@@ -0,0 +1,60 @@
+use r3lib::WindowExplanation;
+use xcb::x;
+
+use super::WindowManager;
+
+impl<'a> WindowManager<'a> {
+    /// Remember a decision made about a window, so it can be explained later.
+    pub(super) fn record_decision(&mut self, window: x::Window, decision: impl Into<String>) {
+        self.decisions.entry(window).or_default().push(decision.into());
+    }
+
+    /// Explain why a managed window is the way it is.
+    pub(super) fn explain_window(&mut self, window: x::Window) -> xcb::Result<Option<WindowExplanation>> {
+        if !self.framed_clients.contains_left(&window) {
+            return Ok(None);
+        }
+
+        let info = self.get_window_info(window)?;
+        let properties = self.get_window_properties(window)?;
+
+        let mut protocols = vec![];
+        for atom in &properties.protocols {
+            let reply = self
+                .conn
+                .wait_for_reply(self.conn.send_request(&x::GetAtomName { atom: *atom }))?;
+            protocols.push(reply.name().to_string());
+        }
+
+        let mut hints = vec![format!(
+            "WM_CLASS: instance {:?}, class {:?}",
+            properties.instance, properties.class
+        )];
+        if info.class != properties.class {
+            hints.push(format!("class remapped to {:?}", info.class));
+        }
+        hints.push(format!("WM_PROTOCOLS: {}", protocols.join(", ")));
+        hints.push(format!("WM_NORMAL_HINTS: {:?}", properties.size_hints));
+        hints.push(format!("WM_HINTS: accepts input: {}", properties.accepts_input));
+        hints.push(format!("_NET_WM_PID: {:?}", properties.pid));
+
+        let rules = self
+            .matching_rule_indices(window)?
+            .into_iter()
+            .map(|i| {
+                let rule = &self.config.rules[i];
+                match rule.describe_criteria() {
+                    criteria if criteria.is_empty() => format!("rule {} (matches every window)", i + 1),
+                    criteria => format!("rule {}: {}", i + 1, criteria),
+                }
+            })
+            .collect();
+
+        Ok(Some(WindowExplanation {
+            info,
+            hints,
+            rules,
+            decisions: self.decisions.get(&window).cloned().unwrap_or_default(),
+        }))
+    }
+}
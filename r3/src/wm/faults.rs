@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use xcb::{x, Connection};
+
+/// The kinds of faults that can be injected into X requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The request fails with a `BadWindow` error
+    BadWindow,
+    /// The request fails with a `BadMatch` error
+    BadMatch,
+    /// The request is delayed by the given duration before being sent
+    Delay(Duration),
+}
+
+impl Fault {
+    fn parse(s: &str) -> Option<Fault> {
+        match s {
+            "BadWindow" => Some(Fault::BadWindow),
+            "BadMatch" => Some(Fault::BadMatch),
+            _ => s
+                .strip_prefix("delay:")
+                .and_then(|ms| ms.parse().ok())
+                .map(|ms| Fault::Delay(Duration::from_millis(ms))),
+        }
+    }
+}
+
+/// Injects faults into X requests at named points in the window manager, so that error handling
+/// paths can be exercised by the integration tests.
+///
+/// Faults are read from the `R3_FAULTS` environment variable (only when built with the `debug`
+/// feature) as a comma separated list of `point=fault` pairs, for example:
+///
+/// `R3_FAULTS=frame_window.reparent=BadWindow,unframe_window.unmap=delay:200`
+///
+/// Errors are produced by sending the X server a request which is known to fail in the desired
+/// way, so the window manager sees a real error from the server rather than a fabricated one.
+///
+/// NOTE: there's no `XConn` trait to swap in a faulty connection, since the window manager uses
+/// `xcb::Connection` directly everywhere and the tests run against a real server anyway. Naming the
+/// points also lets a test fail one request without failing every request of the same kind. If the
+/// X-specific code is ever moved behind a trait (see `TODO.md`), faults should be injected there.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    faults: HashMap<String, Fault>,
+}
+
+impl FaultInjector {
+    pub fn from_env() -> FaultInjector {
+        #[cfg(feature = "debug")]
+        if let Ok(value) = std::env::var("R3_FAULTS") {
+            return FaultInjector::parse(&value);
+        }
+
+        FaultInjector::default()
+    }
+
+    #[cfg_attr(not(feature = "debug"), allow(dead_code))]
+    fn parse(value: &str) -> FaultInjector {
+        let mut faults = HashMap::new();
+        for pair in value.split(',').filter(|s| !s.is_empty()) {
+            match pair
+                .split_once('=')
+                .and_then(|(point, fault)| Some((point, Fault::parse(fault)?)))
+            {
+                Some((point, fault)) => {
+                    faults.insert(point.to_string(), fault);
+                }
+                None => eprintln!("Ignoring invalid fault: {}", pair),
+            }
+        }
+
+        FaultInjector { faults }
+    }
+
+    /// Call just before making the request at `point`: if a fault was configured for that point
+    /// then it's injected here.
+    pub fn inject(&self, conn: &Connection, point: &str) -> xcb::Result<()> {
+        let fault = match self.faults.get(point) {
+            Some(fault) => *fault,
+            None => return Ok(()),
+        };
+
+        eprintln!("Injecting fault at {}: {:?}", point, fault);
+        match fault {
+            // Mapping a window that doesn't exist results in BadWindow
+            Fault::BadWindow => conn.send_and_check_request(&x::MapWindow {
+                window: conn.generate_id(),
+            })?,
+            // InputOnly windows can't have a border, so this results in BadMatch
+            Fault::BadMatch => {
+                let root = conn.get_setup().roots().next().unwrap().root();
+                conn.send_and_check_request(&x::CreateWindow {
+                    depth: 0,
+                    visual: x::COPY_FROM_PARENT,
+                    wid: conn.generate_id(),
+                    parent: root,
+                    x: 0,
+                    y: 0,
+                    width: 1,
+                    height: 1,
+                    border_width: 1,
+                    class: x::WindowClass::InputOnly,
+                    value_list: &[],
+                })?
+            }
+            Fault::Delay(duration) => thread::sleep(duration),
+        }
+
+        Ok(())
+    }
+}
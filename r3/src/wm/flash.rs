@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+use xcb::x;
+
+use super::WindowManager;
+
+/// How long each half of a pulse lasts (the window is drawn as urgent, then as it normally is)
+const FLASH_INTERVAL: Duration = Duration::from_millis(150);
+/// How many times a flashing window pulses
+const FLASH_PULSES: u32 = 3;
+
+impl<'a> WindowManager<'a> {
+    /// Pulse the window's border a few times with the urgent colours, so it's easy to find.
+    pub(super) fn flash_window(&mut self, window: x::Window) {
+        self.flashing_windows.insert(window, Instant::now());
+    }
+
+    /// Is the window flashing, and in the part of a pulse where it's drawn as urgent?
+    pub(super) fn should_draw_flash(&self, window: x::Window) -> bool {
+        self.flashing_windows
+            .get(&window)
+            .is_some_and(|since| (since.elapsed().as_millis() / FLASH_INTERVAL.as_millis()).is_multiple_of(2))
+    }
+
+    /// Draw the next part of each flashing window's pulse, and stop flashing those which are finished.
+    pub(super) fn update_flashing_windows(&mut self) -> xcb::Result<()> {
+        if self.flashing_windows.is_empty() {
+            return Ok(());
+        }
+
+        self.flashing_windows
+            .retain(|_, since| since.elapsed() < FLASH_INTERVAL * FLASH_PULSES * 2);
+        self.render()
+    }
+}
@@ -0,0 +1,229 @@
+use xcb::{x, BaseEvent, Cookie, Xid};
+
+use super::WindowManager;
+
+/// Get the timestamp of an event, if it has one.
+pub fn event_time(event: &xcb::Event) -> Option<x::Timestamp> {
+    match event {
+        xcb::Event::X(x::Event::KeyPress(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::KeyRelease(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::ButtonPress(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::ButtonRelease(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::MotionNotify(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::EnterNotify(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::LeaveNotify(ev)) => Some(ev.time()),
+        xcb::Event::X(x::Event::PropertyNotify(ev)) => Some(ev.time()),
+        _ => None,
+    }
+}
+
+impl<'a> WindowManager<'a> {
+    /// Change which window is focused. This only changes our state, the X server is told about it when
+    /// we next refresh.
+    pub(super) fn set_focus(&mut self, target: Option<x::Window>) -> xcb::Result<()> {
+        // Focusing the root window means nothing is focused
+        let root = self.get_root_window()?;
+        let target = target.filter(|target| *target != root);
+
+        // We always track the client window rather than its frame
+        self.focused_window = target.map(|target| *self.framed_clients.get_by_right(&target).unwrap_or(&target));
+
+        Ok(())
+    }
+
+    /// Focus the window the pointer is on, once it's been there for long enough.
+    pub(super) fn apply_hover_focus(&mut self) -> xcb::Result<()> {
+        match self.hover_focus {
+            Some((window, since)) if since.elapsed() >= self.config.focus_follows_mouse_delay => {
+                self.hover_focus = None;
+                // A menu may have opened while we were waiting
+                if self.menu_open()? {
+                    return Ok(());
+                }
+                self.set_focus(Some(window))?;
+                self.refresh()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Is a menu open? Menus are override-redirect windows (which we don't manage) and they usually
+    /// close when they lose focus, so focus shouldn't follow the mouse while one is open.
+    pub(super) fn menu_open(&self) -> xcb::Result<bool> {
+        let menu_types = [
+            self.atoms.net_wm_window_type_menu,
+            self.atoms.net_wm_window_type_dropdown_menu,
+            self.atoms.net_wm_window_type_popup_menu,
+            self.atoms.net_wm_window_type_combo,
+        ];
+
+        // The requests are all sent before waiting for any replies, so this only waits for the server
+        // once however many windows are open
+        let cookies = self
+            .override_redirect_windows
+            .iter()
+            .map(|window| {
+                self.conn.send_request(&x::GetProperty {
+                    delete: false,
+                    window: *window,
+                    property: self.atoms.net_wm_window_type,
+                    r#type: x::ATOM_ATOM,
+                    long_offset: 0,
+                    long_length: u32::MAX,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut open = false;
+        for cookie in cookies {
+            match self.conn.wait_for_reply(cookie) {
+                Ok(reply) => open |= reply.value::<x::Atom>().iter().any(|t| menu_types.contains(t)),
+                // NOTE: windows may be destroyed at any time, so errors just mean it isn't open
+                Err(xcb::Error::Protocol(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(open)
+    }
+
+    /// Run `f`, ignoring the EnterNotify events caused by the requests it makes. When we move, resize,
+    /// map or restack a bunch of windows the pointer may end up on a different one, and we don't want
+    /// focus to jump to it.
+    pub(super) fn without_enter_events<T>(&mut self, f: impl FnOnce(&mut Self) -> xcb::Result<T>) -> xcb::Result<T> {
+        let first = self.next_sequence()?;
+        let result = f(self);
+        let end = self.next_sequence()?;
+        self.ignored_sequences
+            .add_range(first, end, x::EnterNotifyEvent::NUMBER);
+
+        result
+    }
+
+    /// The sequence number of the next request we send, which events caused by it will have.
+    fn next_sequence(&self) -> xcb::Result<u16> {
+        // NOTE: events have the sequence of the last request the X server handled, so events caused
+        // by the pointer moving after this request have this sequence too
+        let cookie = self.conn.send_request_checked(&x::NoOperation {});
+        let sequence = cookie.sequence();
+        self.conn.check_request(cookie)?;
+
+        Ok(sequence as u16)
+    }
+
+    /// The most recently focused window other than the focused one.
+    pub(super) fn last_focused_window(&self) -> Option<x::Window> {
+        // TODO: switch workspaces if needed once we have them
+        self.focus_history
+            .iter()
+            .rev()
+            .find(|window| Some(**window) != self.focused_window)
+            .copied()
+    }
+
+    /// The next window (by the order they were framed) with the same class as the focused window.
+    pub(super) fn next_window_of_class(&mut self, reverse: bool) -> xcb::Result<Option<x::Window>> {
+        let focused = match self.focused_window {
+            Some(window) => window,
+            None => return Ok(None),
+        };
+
+        // TODO: optionally include windows on other workspaces once we have them
+        let properties = self.get_window_properties(focused)?;
+        let class = self.window_class(&properties);
+        let mut windows = vec![];
+        for window in self.frame_order.clone() {
+            let properties = self.get_window_properties(window)?;
+            if self.window_class(&properties) == class {
+                windows.push(window);
+            }
+        }
+
+        if reverse {
+            windows.reverse();
+        }
+
+        let index = windows.iter().position(|window| *window == focused);
+        Ok(index.and_then(|index| windows.get((index + 1) % windows.len()).copied()))
+    }
+
+    /// Remember the latest time we've seen from the X server, so focus changes aren't applied out of order.
+    pub(super) fn update_event_time(&mut self, time: x::Timestamp) {
+        if time != x::CURRENT_TIME {
+            self.last_event_time = time;
+        }
+    }
+
+    /// Does the focused window differ from the one the X server has focused?
+    pub(super) fn focus_changed(&self) -> bool {
+        self.focused_window != self.applied_focus
+    }
+
+    /// Give input focus to the focused window on the X server, install its colormap and update
+    /// `_NET_ACTIVE_WINDOW`.
+    pub(super) fn apply_focus(&mut self) -> xcb::Result<()> {
+        self.applied_focus = self.focused_window;
+        let root = self.get_root_window()?;
+
+        let window = match self.focused_window {
+            Some(window) => window,
+            // Nothing is focused, so give focus back to the root window
+            None => {
+                self.conn.send_and_check_request(&x::SetInputFocus {
+                    revert_to: x::InputFocus::PointerRoot,
+                    focus: root,
+                    time: self.last_event_time,
+                })?;
+                return self.set_active_window(root, x::Window::none());
+            }
+        };
+
+        // Remember the order windows were focused in (most recent last)
+        self.focus_history.retain(|w| *w != window);
+        self.focus_history.push(window);
+
+        // We need the window's colormap, which is asked for now so we don't have to wait for it later
+        let attrs = self.conn.send_request(&x::GetWindowAttributes { window });
+
+        // See ICCCM 4.1.7: windows may want us to focus them, to focus themselves, or both
+        let properties = self.get_window_properties(window)?;
+        if properties.accepts_input {
+            self.conn.send_and_check_request(&x::SetInputFocus {
+                revert_to: x::InputFocus::PointerRoot,
+                focus: window,
+                time: self.last_event_time,
+            })?;
+        }
+        if properties.protocols.contains(&self.atoms.wm_take_focus) {
+            let data =
+                x::ClientMessageData::Data32([self.atoms.wm_take_focus.resource_id(), self.last_event_time, 0, 0, 0]);
+            self.conn.send_and_check_request(&x::SendEvent {
+                propagate: false,
+                destination: x::SendEventDest::Window(window),
+                event_mask: x::EventMask::NO_EVENT,
+                event: &x::ClientMessageEvent::new(window, self.atoms.wm_protocols, data),
+            })?;
+        }
+
+        // Use the window's colours
+        let attrs = self.conn.wait_for_reply(attrs)?;
+        if !attrs.colormap().is_none() {
+            self.conn
+                .send_and_check_request(&x::InstallColormap { cmap: attrs.colormap() })?;
+        }
+
+        self.set_active_window(root, window)
+    }
+
+    fn set_active_window(&self, root: x::Window, window: x::Window) -> xcb::Result<()> {
+        self.conn.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: root,
+            property: self.atoms.wm_active_window,
+            r#type: x::ATOM_WINDOW,
+            data: &[window],
+        })?;
+
+        Ok(())
+    }
+}
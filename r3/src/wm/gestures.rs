@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use r3lib::R3Command;
+use xcb::{x, xinput};
+
+use super::WindowManager;
+use crate::bindings::{Gesture, SwipeDirection};
+use crate::ipc::Reply;
+
+/// How far (in pixels) fingers have to move on average for their touches to be a swipe
+const SWIPE_DISTANCE: i32 = 100;
+/// How far (in pixels) a touch can move before there are enough fingers down for a gesture, before
+/// it's passed on to windows
+const TOUCH_SLOP: i32 = 20;
+/// How long a touch is held back from windows while waiting for more fingers
+const GESTURE_START_TIME: Duration = Duration::from_millis(150);
+
+/// Where a touch began, and where it is now
+#[derive(Debug, Clone, Copy)]
+struct Touch {
+    start: (i32, i32),
+    now: (i32, i32),
+}
+
+/// The touches of a gesture which is being made. They're held back from windows until the gesture is
+/// recognised (and they're accepted) or it's clear they aren't a gesture (and they're rejected, so
+/// they're sent to windows as usual).
+#[derive(Debug)]
+pub struct TouchGesture {
+    /// The master device the touches come from
+    device: xinput::Device,
+    /// When the first touch began
+    started: Instant,
+    /// The touches, by their ids
+    touches: HashMap<u32, Touch>,
+    /// Touches which have ended
+    ended: Vec<u32>,
+}
+
+impl TouchGesture {
+    /// How far the fingers have moved on average.
+    fn movement(&self) -> (i32, i32) {
+        let (dx, dy) = self.touches.values().fold((0, 0), |(dx, dy), touch| {
+            (dx + touch.now.0 - touch.start.0, dy + touch.now.1 - touch.start.1)
+        });
+        let fingers = self.touches.len().max(1) as i32;
+        (dx / fingers, dy / fingers)
+    }
+
+    /// The swipe the touches made, if they made one.
+    fn swipe(&self) -> Option<Gesture> {
+        let (dx, dy) = self.movement();
+        let direction = match (dx, dy) {
+            _ if dx.abs().max(dy.abs()) < SWIPE_DISTANCE => return None,
+            _ if dx.abs() >= dy.abs() => match dx < 0 {
+                true => SwipeDirection::Left,
+                false => SwipeDirection::Right,
+            },
+            _ => match dy < 0 {
+                true => SwipeDirection::Up,
+                false => SwipeDirection::Down,
+            },
+        };
+
+        Some(Gesture {
+            fingers: self.touches.len(),
+            direction,
+        })
+    }
+}
+
+/// Where the touch is on the screen, in whole pixels.
+fn touch_position(ev: &xinput::TouchBeginEvent) -> (i32, i32) {
+    (ev.root_x() >> 16, ev.root_y() >> 16)
+}
+
+impl<'a> WindowManager<'a> {
+    /// Grab touches on touchscreens if there are gesture bindings, so we see them before windows do.
+    pub(super) fn grab_touches(&mut self) -> xcb::Result<()> {
+        if !self.xinput_available {
+            return Ok(());
+        }
+
+        let root = self.get_root_window()?;
+        self.reject_touches()?;
+        self.conn.send_and_check_request(&xinput::XiPassiveUngrabDevice {
+            grab_window: root,
+            detail: 0,
+            device: xinput::Device::AllMaster,
+            grab_type: xinput::GrabType::TouchBegin,
+            modifiers: &[xinput::ModifierMask::ANY.bits()],
+        })?;
+
+        if self.config.gestures.is_empty() {
+            return Ok(());
+        }
+
+        // NOTE: touch grabs have to be made with every modifier (`ANY`), and the reply lists any which
+        // couldn't be grabbed
+        let reply = self.conn.wait_for_reply(
+            self.conn.send_request(&xinput::XiPassiveGrabDevice {
+                time: x::CURRENT_TIME,
+                grab_window: root,
+                cursor: x::CURSOR_NONE,
+                detail: 0,
+                device: xinput::Device::AllMaster,
+                grab_type: xinput::GrabType::TouchBegin,
+                grab_mode: xinput::GrabMode22::Touch,
+                paired_device_mode: x::GrabMode::Async,
+                owner_events: xinput::GrabOwner::NoOwner,
+                mask: &[(xinput::XiEventMask::TOUCH_BEGIN
+                    | xinput::XiEventMask::TOUCH_UPDATE
+                    | xinput::XiEventMask::TOUCH_END)
+                    .bits()],
+                modifiers: &[xinput::ModifierMask::ANY.bits()],
+            }),
+        )?;
+        if !reply.modifiers().is_empty() {
+            eprintln!("Failed to grab touches for gestures, another client may have grabbed them");
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn on_touch_begin(&mut self, ev: xinput::TouchBeginEvent) -> xcb::Result<()> {
+        let most_fingers = self
+            .config
+            .gestures
+            .iter()
+            .map(|b| b.gesture.fingers)
+            .max()
+            .unwrap_or(0);
+        let gesture = self.touch_gesture.get_or_insert_with(|| TouchGesture {
+            device: ev.device(),
+            started: Instant::now(),
+            touches: HashMap::new(),
+            ended: vec![],
+        });
+        let position = touch_position(&ev);
+        gesture.touches.insert(
+            ev.detail(),
+            Touch {
+                start: position,
+                now: position,
+            },
+        );
+
+        // Too many fingers for any gesture we know
+        if gesture.touches.len() > most_fingers {
+            self.reject_touches()?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn on_touch_update(&mut self, ev: xinput::TouchUpdateEvent) -> xcb::Result<()> {
+        let fewest_fingers = self.fewest_gesture_fingers();
+        let gesture = match &mut self.touch_gesture {
+            Some(gesture) => gesture,
+            None => return Ok(()),
+        };
+        if let Some(touch) = gesture.touches.get_mut(&ev.detail()) {
+            touch.now = touch_position(&ev);
+        }
+
+        // If the fingers move before there are enough of them for a gesture, then they're dragging
+        // something in a window
+        if gesture.touches.len() < fewest_fingers {
+            let (dx, dy) = gesture.movement();
+            if dx.abs().max(dy.abs()) > TOUCH_SLOP {
+                self.reject_touches()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If the fingers are held for a while before there are enough of them for a gesture, then they're
+    /// pressing something in a window. This is checked every tick, since fingers which are held still
+    /// don't send any events.
+    pub(super) fn reject_held_touches(&mut self) -> xcb::Result<()> {
+        let fewest_fingers = self.fewest_gesture_fingers();
+        if self.touch_gesture.as_ref().is_some_and(|gesture| {
+            gesture.touches.len() < fewest_fingers && gesture.started.elapsed() > GESTURE_START_TIME
+        }) {
+            self.reject_touches()?;
+        }
+
+        Ok(())
+    }
+
+    /// The fewest fingers any bound gesture is made with.
+    fn fewest_gesture_fingers(&self) -> usize {
+        self.config
+            .gestures
+            .iter()
+            .map(|b| b.gesture.fingers)
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub(super) fn on_touch_end(&mut self, ev: xinput::TouchEndEvent) -> xcb::Result<()> {
+        let gesture = match &mut self.touch_gesture {
+            Some(gesture) => gesture,
+            None => return Ok(()),
+        };
+        if let Some(touch) = gesture.touches.get_mut(&ev.detail()) {
+            touch.now = touch_position(&ev);
+            gesture.ended.push(ev.detail());
+        }
+        if gesture.ended.len() < gesture.touches.len() {
+            return Ok(());
+        }
+
+        // Every finger has been lifted, so the gesture is finished
+        let command = gesture.swipe().and_then(|swipe| {
+            self.config
+                .gestures
+                .iter()
+                .find(|binding| binding.gesture == swipe)
+                .map(|binding| binding.command.clone())
+        });
+        match command {
+            Some(command) => self.accept_touches(command),
+            None => self.reject_touches(),
+        }
+    }
+
+    /// Keep the touches of the gesture from windows, and run the command it's bound to.
+    fn accept_touches(&mut self, command: R3Command) -> xcb::Result<()> {
+        self.allow_touches(xinput::EventMode::AcceptTouch)?;
+
+        // Bound commands are run by the event loop, just like commands from IPC clients
+        self.ev_queue.lock().unwrap().push(command, Reply::none());
+        self.ev_waker.wake().unwrap();
+
+        Ok(())
+    }
+
+    /// Pass the touches of the gesture being made (if there is one) on to windows.
+    fn reject_touches(&mut self) -> xcb::Result<()> {
+        self.allow_touches(xinput::EventMode::RejectTouch)
+    }
+
+    fn allow_touches(&mut self, event_mode: xinput::EventMode) -> xcb::Result<()> {
+        let gesture = match self.touch_gesture.take() {
+            Some(gesture) => gesture,
+            None => return Ok(()),
+        };
+
+        let root = self.get_root_window()?;
+        for touchid in gesture.touches.keys() {
+            if let Err(e) = self.conn.send_and_check_request(&xinput::XiAllowEvents {
+                time: x::CURRENT_TIME,
+                device: gesture.device,
+                event_mode,
+                touchid: *touchid,
+                grab_window: root,
+            }) {
+                // The touch has already gone (e.g. its device was unplugged), so there's nothing to allow
+                eprintln!("Failed to allow touch {}: {:?}", touchid, e);
+            }
+        }
+
+        Ok(())
+    }
+}
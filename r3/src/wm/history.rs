@@ -0,0 +1,30 @@
+use xcb::x;
+
+use super::WindowManager;
+use crate::window_geometry::WindowGeometry;
+
+/// How many previous geometries are remembered for each window
+const GEOMETRY_HISTORY_LENGTH: usize = 10;
+
+impl<'a> WindowManager<'a> {
+    /// Remember the window's frame rect before it's moved or resized, so it can be restored later.
+    // TODO: maximize and fullscreen should use this to restore windows once we have them
+    pub(super) fn remember_geometry(&mut self, window: x::Window, rect: WindowGeometry) {
+        let history = self.geometry_history.entry(window).or_default();
+        history.push(rect);
+        if history.len() > GEOMETRY_HISTORY_LENGTH {
+            history.remove(0);
+        }
+    }
+
+    /// Move and resize the window back to where it was before it was last moved or resized.
+    pub(super) fn undo_geometry(&mut self, window: x::Window) -> xcb::Result<Option<x::Window>> {
+        match self.geometry_history.get_mut(&window).and_then(|history| history.pop()) {
+            Some(rect) => {
+                self.resize_window(window, rect)?;
+                Ok(Some(window))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+use r3lib::R3Event;
+use xcb::{screensaver, x};
+
+use super::WindowManager;
+
+/// How often we ask the X server how long the user has been idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+impl<'a> WindowManager<'a> {
+    /// Send an event for each idle threshold the user has passed since we last checked, or if they've
+    /// stopped being idle.
+    pub(super) fn check_idle(&mut self) -> xcb::Result<()> {
+        if !self.screensaver_available
+            || self.config.idle_thresholds.is_empty()
+            || self.last_idle_check.elapsed() < IDLE_CHECK_INTERVAL
+        {
+            return Ok(());
+        }
+
+        self.last_idle_check = Instant::now();
+        let info = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&screensaver::QueryInfo {
+                drawable: x::Drawable::Window(self.get_root_window()?),
+            }))?;
+        let idle = Duration::from_millis(info.ms_since_user_input().into());
+
+        let mut thresholds = self.config.idle_thresholds.clone();
+        thresholds.sort();
+        let passed = thresholds.iter().filter(|threshold| idle >= **threshold).count();
+
+        // The idle time is reset by any input, so if it went backwards the user is back
+        if passed < self.idle_thresholds_passed {
+            self.emit_event(R3Event::Resume);
+            self.idle_thresholds_passed = 0;
+        }
+
+        for threshold in &thresholds[self.idle_thresholds_passed..passed] {
+            self.emit_event(R3Event::Idle {
+                threshold: threshold.as_millis() as u64,
+            });
+        }
+        self.idle_thresholds_passed = passed;
+
+        Ok(())
+    }
+}
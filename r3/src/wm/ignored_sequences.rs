@@ -3,7 +3,10 @@ use std::time::Instant;
 
 #[derive(Debug, PartialEq, Eq)]
 struct Inner {
-    sequence: u16,
+    /// The first sequence which is ignored
+    first: u16,
+    /// The sequence after the last one which is ignored
+    end: u16,
     response_type: Option<u32>,
     store_time: Instant,
 }
@@ -11,37 +14,49 @@ struct Inner {
 impl Inner {
     pub fn new(sequence: u16) -> Inner {
         Inner {
-            sequence,
+            first: sequence,
+            end: sequence.wrapping_add(1),
             response_type: None,
             store_time: Instant::now(),
         }
     }
 
     pub fn new_with_type(sequence: u16, response_type: u32) -> Inner {
+        Inner::new_range(sequence, sequence.wrapping_add(1), response_type)
+    }
+
+    pub fn new_range(first: u16, end: u16, response_type: u32) -> Inner {
         Inner {
-            sequence,
+            first,
+            end,
             response_type: Some(response_type),
             store_time: Instant::now(),
         }
     }
+
+    /// NOTE: sequences wrap around, so the range may too
+    fn contains(&self, sequence: u16) -> bool {
+        sequence.wrapping_sub(self.first) < self.end.wrapping_sub(self.first)
+    }
 }
 
-// Inner is sorted by store time
+// Inner is sorted by store time, oldest first (so old items can be popped off the heap)
 impl PartialOrd for Inner {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.store_time.partial_cmp(&other.store_time)
+        Some(self.cmp(other))
     }
 }
 
-// Inner is sorted by store time
+// Inner is sorted by store time, oldest first (so old items can be popped off the heap)
 impl Ord for Inner {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.store_time.cmp(&other.store_time)
+        other.store_time.cmp(&self.store_time)
     }
 }
 
+#[derive(Debug)]
 pub struct IgnoredSequences {
-    // Tuple of (sequence, Option<ResponseType>, timestamp)
+    // Ranges of sequences, the type of event ignored (or all of them) and when they were added
     heap: BinaryHeap<Inner>,
 }
 
@@ -60,6 +75,11 @@ impl IgnoredSequences {
         self.heap.push(Inner::new_with_type(sequence, response_type));
     }
 
+    /// Ignore events of the given type with sequences from `first` up to (but not including) `end`.
+    pub fn add_range(&mut self, first: u16, end: u16, response_type: u32) {
+        self.heap.push(Inner::new_range(first, end, response_type));
+    }
+
     pub fn is_ignored(&mut self, sequence: u16, response_type: u32) -> bool {
         // Clean out any old items automatically
         let now = Instant::now();
@@ -74,7 +94,7 @@ impl IgnoredSequences {
 
         // Check if the given event is ignored
         for inner in &self.heap {
-            if inner.sequence != sequence {
+            if !inner.contains(sequence) {
                 continue;
             }
             if let Some(inner_response_type) = inner.response_type {
@@ -0,0 +1,121 @@
+use r3lib::R3Command;
+use xcb::x;
+
+use super::WindowManager;
+
+/// Modifiers which don't change which binding a key triggers (Caps Lock, and Num Lock on most
+/// keyboards), so bindings work no matter whether they're on
+pub(super) const IGNORED_MODIFIERS: x::ModMask =
+    x::ModMask::from_bits_truncate(x::ModMask::LOCK.bits() | x::ModMask::N2.bits());
+
+/// Every combination of the ignored modifiers, since grabs have to be made with each of them
+pub(super) const IGNORED_MODIFIER_COMBINATIONS: [x::ModMask; 4] =
+    [x::ModMask::empty(), x::ModMask::LOCK, x::ModMask::N2, IGNORED_MODIFIERS];
+
+/// The keysym of Escape (see `X11/keysymdef.h`)
+const KEYSYM_ESCAPE: x::Keysym = 0xff1b;
+
+/// A key we've grabbed on the root window, and the command it runs
+#[derive(Debug)]
+pub struct GrabbedKey {
+    keycode: x::Keycode,
+    modifiers: x::ModMask,
+    command: R3Command,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Grab the keys of all configured bindings (including those of specific devices), and touches if
+    /// there are gesture bindings, on the root window, replacing any previous grabs. This should be
+    /// called again whenever the keyboard mapping changes.
+    pub(super) fn grab_bindings(&mut self) -> xcb::Result<()> {
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&x::UngrabKey {
+            key: x::Grab::Any as x::Keycode,
+            grab_window: root,
+            modifiers: x::ModMask::ANY,
+        })?;
+        self.grabbed_keys.clear();
+
+        // Find which keycodes type each binding's keysym
+        let setup = self.conn.get_setup();
+        let (min_keycode, max_keycode) = (setup.min_keycode(), setup.max_keycode());
+        let mapping = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::GetKeyboardMapping {
+                first_keycode: min_keycode,
+                count: max_keycode - min_keycode + 1,
+            }))?;
+        self.escape_keycodes = keycodes_typing(&mapping, min_keycode, KEYSYM_ESCAPE)
+            .into_iter()
+            .map(|(keycode, _)| keycode)
+            .collect();
+
+        for binding in &self.config.bindings {
+            let keycodes = keycodes_typing(&mapping, min_keycode, binding.combo.keysym);
+            if keycodes.is_empty() {
+                eprintln!("No key on the keyboard is bound to {:?}", binding);
+            }
+
+            for (keycode, shift) in keycodes {
+                let modifiers = binding.combo.modifiers | shift;
+                // Grab the key with every combination of the ignored modifiers
+                for ignored in IGNORED_MODIFIER_COMBINATIONS {
+                    // NOTE: this fails if another client has already grabbed the key
+                    let result = self.conn.send_and_check_request(&x::GrabKey {
+                        owner_events: false,
+                        grab_window: root,
+                        modifiers: modifiers | ignored,
+                        key: keycode,
+                        pointer_mode: x::GrabMode::Async,
+                        keyboard_mode: x::GrabMode::Async,
+                    });
+                    if let Err(e) = result {
+                        eprintln!("Failed to grab key for {:?}: {:?}", binding, e);
+                    }
+                }
+
+                self.grabbed_keys.push(GrabbedKey {
+                    keycode,
+                    modifiers,
+                    command: binding.command.clone(),
+                });
+            }
+        }
+
+        self.grab_device_bindings(&mapping, min_keycode)?;
+        self.grab_touches()
+    }
+
+    /// The command bound to the given key, if there is one.
+    pub(super) fn bound_command(&self, keycode: x::Keycode, state: x::KeyButMask) -> Option<&R3Command> {
+        let modifiers = x::ModMask::from_bits_truncate(state.bits()) - IGNORED_MODIFIERS;
+        self.grabbed_keys
+            .iter()
+            .find(|key| key.keycode == keycode && key.modifiers == modifiers)
+            .map(|key| &key.command)
+    }
+}
+
+/// The keycodes which type the keysym, given the keyboard mapping starting at `min_keycode`, with
+/// the modifiers needed to type it: Shift if it's the key's shifted symbol (e.g. `exclam` on `1`).
+/// Only the first group's symbols are considered, since those are what's typed without switching
+/// layouts.
+pub(super) fn keycodes_typing(
+    mapping: &x::GetKeyboardMappingReply,
+    min_keycode: x::Keycode,
+    keysym: x::Keysym,
+) -> Vec<(x::Keycode, x::ModMask)> {
+    mapping
+        .keysyms()
+        .chunks(mapping.keysyms_per_keycode() as usize)
+        .enumerate()
+        .filter_map(|(i, keysyms)| {
+            let keycode = min_keycode + i as x::Keycode;
+            match keysyms {
+                [unshifted, ..] if *unshifted == keysym => Some((keycode, x::ModMask::empty())),
+                [_, shifted, ..] if *shifted == keysym => Some((keycode, x::ModMask::SHIFT)),
+                _ => None,
+            }
+        })
+        .collect()
+}
@@ -24,10 +24,12 @@ impl Masks {
                 | EventMask::STRUCTURE_NOTIFY            // Frame gets destroyed
                 | EventMask::SUBSTRUCTURE_NOTIFY         // Subwindows get notifies
                 | EventMask::SUBSTRUCTURE_REDIRECT       // Inner application tries to configure itself (resize, etc)
-                | EventMask::ENTER_WINDOW, // Pointer is moved into the frame
+                | EventMask::ENTER_WINDOW                // Pointer is moved into the frame
+                | EventMask::LEAVE_WINDOW, // Pointer is moved out of the frame
             root_window_events: EventMask::BUTTON_PRESS  // Mouse pressed on root window
                 | EventMask::STRUCTURE_NOTIFY            // When a screen is added (another output) root window gets configure notify
                 | EventMask::SUBSTRUCTURE_REDIRECT
+                | EventMask::SUBSTRUCTURE_NOTIFY         // Override-redirect windows (e.g. menus) are mapped
                 | EventMask::POINTER_MOTION              // Pointer motion on root window
                 | EventMask::PROPERTY_CHANGE
                 | EventMask::FOCUS_CHANGE
@@ -0,0 +1,261 @@
+use std::cmp;
+use std::time::Instant;
+
+use xcb::{composite, damage, x, Xid};
+
+use super::WindowManager;
+use crate::point::Point;
+
+/// A live copy of a window's contents, drawn into another window
+#[derive(Debug)]
+pub struct Mirror {
+    /// The window being mirrored
+    source: x::Window,
+    /// The window the copy is drawn into
+    viewer: x::Window,
+    /// The viewer has the same visual as the source, so it may need its own colormap
+    colormap: x::Colormap,
+    /// Graphics context used to copy between the windows (it must match their depth)
+    gc: x::Gcontext,
+    /// Tracks which parts of the source have changed (only if the Damage extension is available)
+    damage: Option<damage::Damage>,
+    /// The area of the source which has changed since it was last copied
+    damaged: Option<x::Rectangle>,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Start mirroring the given window into a new window at the given position.
+    pub(super) fn start_mirror(&mut self, source: x::Window, pos: Point) -> xcb::Result<()> {
+        if !self.composite_available {
+            eprintln!("Can't mirror windows since the Composite extension isn't available");
+            return Ok(());
+        }
+
+        // Keep the window's contents off-screen, so they can be copied even when it's covered
+        self.conn.send_and_check_request(&composite::RedirectWindow {
+            window: source,
+            update: composite::Redirect::Automatic,
+        })?;
+
+        // The viewer must have the same depth and visual as the source so we can copy between them
+        let geo = self.conn.wait_for_reply(self.conn.send_request(&x::GetGeometry {
+            drawable: x::Drawable::Window(source),
+        }))?;
+        let attrs = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::GetWindowAttributes { window: source }))?;
+        let root = self.get_root_window()?;
+
+        let colormap = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::CreateColormap {
+            alloc: x::ColormapAlloc::None,
+            mid: colormap,
+            window: root,
+            visual: attrs.visual(),
+        })?;
+
+        let viewer = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::CreateWindow {
+            depth: geo.depth(),
+            wid: viewer,
+            parent: root,
+            x: pos.x,
+            y: pos.y,
+            width: geo.width(),
+            height: geo.height(),
+            border_width: 0,
+            class: x::WindowClass::InputOutput,
+            visual: attrs.visual(),
+            value_list: &[
+                x::Cw::BackPixel(0),
+                x::Cw::BorderPixel(0),
+                // We manage this window ourselves
+                x::Cw::OverrideRedirect(true),
+                x::Cw::Colormap(colormap),
+            ],
+        })?;
+
+        let gc = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::CreateGc {
+            cid: gc,
+            drawable: x::Drawable::Window(viewer),
+            // Otherwise every copy generates an event
+            value_list: &[x::Gc::GraphicsExposures(false)],
+        })?;
+
+        // If we can, only copy the parts of the window which change rather than the whole thing
+        let damage = match self.damage_available {
+            true => {
+                let damage = self.conn.generate_id();
+                self.conn.send_and_check_request(&damage::Create {
+                    damage,
+                    drawable: x::Drawable::Window(source),
+                    level: damage::ReportLevel::BoundingBox,
+                })?;
+                Some(damage)
+            }
+            false => None,
+        };
+
+        self.conn.send_and_check_request(&x::MapWindow { window: viewer })?;
+        self.mirrors.push(Mirror {
+            source,
+            viewer,
+            colormap,
+            gc,
+            damage,
+            damaged: Some(x::Rectangle {
+                x: 0,
+                y: 0,
+                width: geo.width(),
+                height: geo.height(),
+            }),
+        });
+
+        Ok(())
+    }
+
+    /// Stop mirroring the given window, or all windows if none is given.
+    pub(super) fn stop_mirroring(&mut self, source: Option<x::Window>) {
+        let (stopped, mirrors) = std::mem::take(&mut self.mirrors)
+            .into_iter()
+            .partition::<Vec<_>, _>(|mirror| source.is_none_or(|source| source == mirror.source));
+        self.mirrors = mirrors;
+
+        for mirror in stopped {
+            // NOTE: the source may have been destroyed already, so we don't check these requests
+            self.conn.send_request(&composite::UnredirectWindow {
+                window: mirror.source,
+                update: composite::Redirect::Automatic,
+            });
+            self.conn.send_request(&x::DestroyWindow { window: mirror.viewer });
+            self.conn.send_request(&x::FreeColormap { cmap: mirror.colormap });
+            self.conn.send_request(&x::FreeGc { gc: mirror.gc });
+            if let Some(damage) = mirror.damage {
+                self.conn.send_request(&damage::Destroy { damage });
+            }
+        }
+    }
+
+    /// Called when part of a mirrored window changes.
+    pub(super) fn on_damage_notify(&mut self, ev: damage::NotifyEvent) -> xcb::Result<()> {
+        let mirror = match self
+            .mirrors
+            .iter_mut()
+            .find(|mirror| mirror.damage == Some(ev.damage()))
+        {
+            Some(mirror) => mirror,
+            None => return Ok(()),
+        };
+
+        // Keep the viewer the same size as the source
+        let geometry = ev.geometry();
+        self.conn.send_and_check_request(&x::ConfigureWindow {
+            window: mirror.viewer,
+            value_list: &[
+                x::ConfigWindow::Width(geometry.width.into()),
+                x::ConfigWindow::Height(geometry.height.into()),
+            ],
+        })?;
+
+        mirror.damaged = Some(match mirror.damaged {
+            Some(damaged) => union(&damaged, &ev.area()),
+            None => ev.area(),
+        });
+
+        // Reset the damage, so we're told the next time the window changes
+        self.conn.send_and_check_request(&damage::Subtract {
+            damage: ev.damage(),
+            repair: xcb::xfixes::Region::none(),
+            parts: xcb::xfixes::Region::none(),
+        })?;
+
+        Ok(())
+    }
+
+    /// Copy the contents of mirrored windows into their viewers, if it's time to.
+    pub(super) fn update_mirrors(&mut self) -> xcb::Result<()> {
+        if self.mirrors.is_empty() || self.last_mirror_update.elapsed() < self.config.mirror_update_interval {
+            return Ok(());
+        }
+
+        self.last_mirror_update = Instant::now();
+        let mut failed = vec![];
+        for i in 0..self.mirrors.len() {
+            if let Err(e) = self.update_mirror(i) {
+                match e {
+                    xcb::Error::Protocol(e) => {
+                        eprintln!("Failed to mirror window {:?}: {:?}", self.mirrors[i].source, e)
+                    }
+                    e => return Err(e),
+                }
+                failed.push(self.mirrors[i].source);
+            }
+        }
+
+        for source in failed {
+            self.stop_mirroring(Some(source));
+        }
+
+        Ok(())
+    }
+
+    fn update_mirror(&mut self, index: usize) -> xcb::Result<()> {
+        let mirror = &mut self.mirrors[index];
+        let area = match mirror.damage {
+            // We've been told what's changed, so only copy that
+            Some(_) => match mirror.damaged.take() {
+                Some(area) => area,
+                None => return Ok(()),
+            },
+            // Otherwise copy the whole window, and keep the viewer the same size as it
+            None => {
+                let (source, viewer) = (mirror.source, mirror.viewer);
+                let rect = self.get_window_rect(source)?;
+                self.conn.send_and_check_request(&x::ConfigureWindow {
+                    window: viewer,
+                    value_list: &[
+                        x::ConfigWindow::Width(rect.w.into()),
+                        x::ConfigWindow::Height(rect.h.into()),
+                    ],
+                })?;
+
+                x::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: rect.w,
+                    height: rect.h,
+                }
+            }
+        };
+
+        let mirror = &self.mirrors[index];
+        self.conn.send_and_check_request(&x::CopyArea {
+            src_drawable: x::Drawable::Window(mirror.source),
+            dst_drawable: x::Drawable::Window(mirror.viewer),
+            gc: mirror.gc,
+            src_x: area.x,
+            src_y: area.y,
+            dst_x: area.x,
+            dst_y: area.y,
+            width: area.width,
+            height: area.height,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// The smallest rectangle containing both rectangles.
+fn union(a: &x::Rectangle, b: &x::Rectangle) -> x::Rectangle {
+    let x = cmp::min(a.x, b.x);
+    let y = cmp::min(a.y, b.y);
+    let end_x = cmp::max(a.x as i32 + a.width as i32, b.x as i32 + b.width as i32);
+    let end_y = cmp::max(a.y as i32 + a.height as i32, b.y as i32 + b.height as i32);
+    x::Rectangle {
+        x,
+        y,
+        width: (end_x - x as i32) as u16,
+        height: (end_y - y as i32) as u16,
+    }
+}
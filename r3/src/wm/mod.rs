@@ -1,38 +1,105 @@
+mod capture;
+mod clipboard;
 mod cmd_handlers;
+mod consistency;
+mod criteria;
+mod devices;
+mod dnd;
+mod drag;
+mod dump;
+mod events;
+mod explain;
+mod faults;
+mod flash;
+mod focus;
+mod gestures;
+mod history;
+mod idle;
 mod ignored_sequences;
+mod keys;
 mod masks;
+mod mirror;
+mod outline;
+mod outputs;
+mod pick;
+mod placement;
+mod pointer;
+mod properties;
+mod reload;
+mod resize_sync;
+mod rules;
+mod tiling;
+mod titlebar;
+mod tree;
+mod urgency;
 mod windows;
 mod x_handlers;
 
+use std::collections::{HashMap, HashSet};
 use std::os::unix::prelude::OsStrExt;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 use bimap::BiHashMap;
 use mio::Waker;
-use r3lib::R3Command;
-use xcb::{x, Connection};
-
+use r3lib::ipc::CommandReply;
+use r3lib::{OutputState, Split};
+use xcb::{randr, x, Connection};
+
+use self::clipboard::ClipboardManager;
+use self::devices::DeviceGrab;
+use self::faults::FaultInjector;
+use self::gestures::TouchGesture;
 use self::ignored_sequences::IgnoredSequences;
+use self::keys::GrabbedKey;
 use self::masks::MASKS;
-use crate::config::Config;
+use self::mirror::Mirror;
+use self::outline::OUTLINE_WIDTH;
+use self::pick::PickAction;
+use self::properties::{PendingProperty, WindowProperties};
+use self::resize_sync::SyncRequest;
+use self::titlebar::{LoadedFont, Titlebar};
+use crate::config::{Config, FrameBackground, FrameColors};
+use crate::ipc::{CommandQueue, Reply, Subscriber};
+use crate::launcher::Launcher;
+use crate::layout::Container;
 use crate::point::Point;
 use crate::window_geometry::WindowGeometry;
 
+/// The most times `sync` waits for the X server, in case handling events never stops causing more
+const SYNC_ROUNDS: usize = 10;
+
 // TODO: see https://github.com/rust-x-bindings/rust-xcb/pull/182
 crate::atoms_struct! {
     #[derive(Debug)]
     struct Atoms {
         wm_protocols     => b"WM_PROTOCOLS"                 only_if_exists = false,
         wm_del_window    => b"WM_DELETE_WINDOW"             only_if_exists = false,
-        #[allow(dead_code)]
+        wm_take_focus    => b"WM_TAKE_FOCUS"                only_if_exists = false,
+        wm_window_role   => b"WM_WINDOW_ROLE"               only_if_exists = false,
         wm_active_window => b"_NET_ACTIVE_WINDOW"           only_if_exists = false,
-        #[allow(dead_code)]
-        wm_state         => b"_NET_WM_STATE"                only_if_exists = false,
-        #[allow(dead_code)]
-        wm_state_maxv    => b"_NET_WM_STATE_MAXIMIZED_VERT" only_if_exists = false,
-        #[allow(dead_code)]
-        wm_state_maxh    => b"_NET_WM_STATE_MAXIMIZED_HORZ" only_if_exists = false,
+        net_wm_name      => b"_NET_WM_NAME"                 only_if_exists = false,
+        net_wm_pid       => b"_NET_WM_PID"                  only_if_exists = false,
+        utf8_string      => b"UTF8_STRING"                  only_if_exists = false,
+        xdnd_aware       => b"XdndAware"                    only_if_exists = false,
+        xdnd_proxy       => b"XdndProxy"                    only_if_exists = false,
+        manager          => b"MANAGER"                      only_if_exists = false,
+        clipboard        => b"CLIPBOARD"                    only_if_exists = false,
+        clipboard_manager => b"CLIPBOARD_MANAGER"           only_if_exists = false,
+        save_targets     => b"SAVE_TARGETS"                 only_if_exists = false,
+        targets          => b"TARGETS"                      only_if_exists = false,
+        multiple         => b"MULTIPLE"                     only_if_exists = false,
+        timestamp        => b"TIMESTAMP"                    only_if_exists = false,
+        incr             => b"INCR"                         only_if_exists = false,
+        net_wm_window_type => b"_NET_WM_WINDOW_TYPE"        only_if_exists = false,
+        net_wm_window_type_normal => b"_NET_WM_WINDOW_TYPE_NORMAL" only_if_exists = false,
+        net_wm_window_type_menu => b"_NET_WM_WINDOW_TYPE_MENU" only_if_exists = false,
+        net_wm_window_type_dropdown_menu => b"_NET_WM_WINDOW_TYPE_DROPDOWN_MENU" only_if_exists = false,
+        net_wm_window_type_popup_menu => b"_NET_WM_WINDOW_TYPE_POPUP_MENU" only_if_exists = false,
+        net_wm_window_type_combo => b"_NET_WM_WINDOW_TYPE_COMBO" only_if_exists = false,
+        net_wm_sync_request => b"_NET_WM_SYNC_REQUEST"      only_if_exists = false,
+        net_wm_sync_request_counter => b"_NET_WM_SYNC_REQUEST_COUNTER" only_if_exists = false,
 
         // Custom atoms
 
@@ -40,16 +107,17 @@ crate::atoms_struct! {
         r3_pid           => b"R3_PID"                       only_if_exists = false,
         /// Set on the root window, and contains the path to the r3 socket
         r3_socket_path   => b"R3_SOCKET_PATH"               only_if_exists = false,
-        /// Mainly used for testing purposes: r3 will respond to ClientMessage events on
-        /// the root window, and will send back what it received to the sender
-        r3_sync          => b"R3_SYNC"                      only_if_exists = false,
+        /// Set on the root window, and contains the path to the socket speaking i3's IPC protocol
+        i3_socket_path   => b"I3_SOCKET_PATH"               only_if_exists = false,
         /// Set on every r3 frame window (to distinguish it as a frame)
         r3_frame         => b"R3_FRAME"                     only_if_exists = false,
+        /// The property we receive the clipboard's contents in when saving it
+        r3_clipboard     => b"R3_CLIPBOARD"                 only_if_exists = false,
     }
 }
 
 /// When clicking and dragging a floating window, what kind of drag operation are we performing?
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DragType {
     Move,
     Resize,
@@ -58,10 +126,24 @@ enum DragType {
 pub struct WindowManager<'a> {
     /// Our way of communicating back to the main loop
     ev_waker: Arc<Waker>,
-    ev_queue: Arc<Mutex<Vec<R3Command>>>,
+    ev_queue: CommandQueue,
+    /// IPC clients which are sent events
+    subscribers: Vec<Subscriber>,
+    /// Our IPC sockets, whose permissions change with the configuration (see `ipc::socket_mode`)
+    socket_paths: Vec<PathBuf>,
 
     /// WM Configuration
     config: Config,
+    /// The configuration file given on the command line (otherwise the default file is used)
+    config_path: Option<PathBuf>,
+    /// Used to run commands
+    launcher: Launcher,
+    /// The keys grabbed for key bindings
+    grabbed_keys: Vec<GrabbedKey>,
+    /// The keys which type Escape, which cancels picking a window
+    escape_keycodes: Vec<x::Keycode>,
+    /// The keys grabbed on specific input devices for their bindings
+    device_grabs: Vec<DeviceGrab>,
 
     /// XCB connection
     conn: &'a Connection,
@@ -69,23 +151,126 @@ pub struct WindowManager<'a> {
     atoms: Atoms,
     /// X's default screen
     default_screen: i32,
+    /// The font used for drawing titles
+    font: LoadedFont,
+    /// Graphics context used for drawing titlebars
+    titlebar_gc: x::Gcontext,
+    /// The titlebar of each framed window's frame
+    titlebars: HashMap<x::Window, Titlebar>,
 
     /// A mapping of Window -> Frame to help keep track of framed windows
     framed_clients: BiHashMap<x::Window, x::Window>,
+    /// The framed windows in the order they were framed
+    frame_order: Vec<x::Window>,
+    /// The workspace, which holds the tiled windows (in containers split one way or the other)
+    // TODO: several workspaces, and one for each output
+    workspace: Container,
+    /// Whether the tiled windows have changed, and need to be laid out again
+    layout_pending: bool,
     /// List of event sequences to ignore. Sometimes, X will trigger EnterNotify events for
     /// mapped (and unmapped!) windows; these events are indistinguishable from user-generated
     /// events, and don't provide any value for us. In these cases, we maintain a list of event
     /// sequences to ignore so we can skip them. This data structure will clean itself up and
     /// won't infinitely grow in size.
     ignored_sequences: IgnoredSequences,
-
+    /// Cached properties of managed windows
+    window_properties: HashMap<x::Window, WindowProperties>,
+    /// Property requests whose replies haven't been read yet
+    pending_properties: Vec<PendingProperty>,
+    /// Decisions made about each managed window (e.g. where it was placed), to help explain them
+    decisions: HashMap<x::Window, Vec<String>>,
+
+    /// If a drag is in progress, this is whether it's moving or resizing the window
+    drag_type: Option<DragType>,
     /// If a drag is in progress, this will contain the coordinates of its starting position
     drag_start: Option<Point>,
     /// If a drag is in progress, this will contain the starting rect of the frame dragged
     drag_start_frame_rect: Option<WindowGeometry>,
+    /// The latest position of a drag which hasn't been applied yet
+    pending_drag: Option<(x::Window, DragType, WindowGeometry)>,
+    /// When a drag was last applied to a window
+    last_drag_update: Instant,
+    /// If an outline drag is in progress, this is the window being dragged and its outline
+    drag_outline: Option<(x::Window, DragType, WindowGeometry)>,
+    /// Graphics context used for drawing drag outlines
+    outline_gc: x::Gcontext,
+    /// Each window's previous frame rects, most recent last (see `WMCommand::UndoGeometry`)
+    geometry_history: HashMap<x::Window, Vec<WindowGeometry>>,
+    /// Whether the X server supports the SYNC extension
+    sync_available: bool,
+    /// If a window which supports `_NET_WM_SYNC_REQUEST` is being resized, this tracks its redraws
+    sync_request: Option<SyncRequest>,
+
+    /// If we're waiting for the user to click on a window, this is what we'll do with it
+    pick: Option<PickAction>,
+
+    /// Whether the X server supports the Composite extension
+    composite_available: bool,
+    /// Whether the X server supports the Damage extension
+    damage_available: bool,
+    /// Windows which are being mirrored
+    mirrors: Vec<Mirror>,
+    /// When mirrors were last updated
+    last_mirror_update: Instant,
+
+    /// Whether the X server supports MIT-SHM 1.2, so big images can be read through shared memory
+    shm_available: bool,
+
+    /// Whether the X server supports RandR 1.2, which tells us when outputs change
+    randr_available: bool,
+    /// The state of each output, so we can tell what's changed when RandR tells us something has
+    outputs: HashMap<randr::Output, OutputState>,
+
+    /// Whether the X server supports XInput 2.2, which lets us grab keys on specific input devices and
+    /// watch touches for gestures
+    xinput_available: bool,
+    /// The touches of the gesture being made on a touchscreen, if there is one
+    touch_gesture: Option<TouchGesture>,
+
+    /// Whether the X server supports the MIT-SCREEN-SAVER extension, which we use to detect idleness
+    screensaver_available: bool,
+    /// How many of the idle thresholds the user has passed (zero if they're not idle)
+    idle_thresholds_passed: usize,
+    /// When we last checked whether the user is idle
+    last_idle_check: Instant,
+
+    /// The clipboard manager, if it's enabled
+    clipboard: Option<ClipboardManager>,
 
     /// The currently focused window
     focused_window: Option<x::Window>,
+    /// The window the X server was last told to focus
+    applied_focus: Option<x::Window>,
+    /// The window the pointer entered and when, which is focused once the pointer has stayed on it for
+    /// the focus delay
+    hover_focus: Option<(x::Window, Instant)>,
+    /// Windows in the order they were focused, most recent last
+    focus_history: Vec<x::Window>,
+    /// The latest timestamp we've received from the X server
+    last_event_time: x::Timestamp,
+    /// The colours each frame was last painted with, so frames are only repainted when they change
+    painted_colors: HashMap<x::Window, FrameColors>,
+    /// Windows whose titles have changed since their titlebar was drawn
+    stale_titles: HashSet<x::Window>,
+    /// Counts changes which may have changed the tree of windows (see `get_state_hash`)
+    tree_generation: u64,
+    /// When we last rendered, and whether we've skipped rendering since (see `Config::render_interval`)
+    last_render: Instant,
+    render_pending: bool,
+    /// Override-redirect windows (such as menus) which are mapped, so we can tell if a menu is open
+    /// without asking the server about every window
+    override_redirect_windows: HashSet<x::Window>,
+    /// Windows which have set the urgency hint, in the order they became urgent
+    urgent_windows: Vec<x::Window>,
+    /// Whether urgent windows are currently drawn as urgent (toggled when they're flashing)
+    urgent_flash_on: bool,
+    /// Windows which were asked to flash, and when they started
+    flashing_windows: HashMap<x::Window, Instant>,
+    /// When the window manager was started, used for time-based effects
+    start_time: Instant,
+
+    /// Faults to inject into X requests (only used when testing)
+    faults: FaultInjector,
 }
 
 impl<'a> WindowManager<'a> {
@@ -93,47 +278,195 @@ impl<'a> WindowManager<'a> {
     /// It will not attempt to become the X Server's window manager until `.run()` is called.
     pub fn new(
         (conn, default_screen): (&'a Connection, i32),
-        (ev_waker, ev_queue): (Arc<Waker>, Arc<Mutex<Vec<R3Command>>>),
+        (ev_waker, ev_queue): (Arc<Waker>, CommandQueue),
+        (config, config_path): (Config, Option<PathBuf>),
     ) -> xcb::Result<WindowManager<'a>> {
-        let atoms = Atoms::intern_all(&conn)?;
+        let atoms = Atoms::intern_all(conn)?;
+        let launcher = Launcher::new(
+            config.exec_shell.clone(),
+            config.exec_working_dir.clone(),
+            std::env::var("DISPLAY").ok(),
+        );
+
+        // Load the font and create a graphics context for drawing titles
+        let font = LoadedFont::load(conn, &config.font)?;
+        let titlebar_gc = conn.generate_id();
+        let screen = conn.get_setup().roots().nth(default_screen as usize).unwrap();
+        conn.send_and_check_request(&x::CreateGc {
+            cid: titlebar_gc,
+            drawable: x::Drawable::Window(screen.root()),
+            value_list: &[
+                x::Gc::Foreground(config.colors.unfocused.text.pixel()),
+                x::Gc::Background(config.colors.unfocused.background.pixel()),
+                x::Gc::Font(font.id),
+            ],
+        })?;
+
+        // Create a graphics context for drawing outlines: these are drawn with XOR so drawing the same
+        // outline twice removes it again
+        let outline_gc = conn.generate_id();
+        conn.send_and_check_request(&x::CreateGc {
+            cid: outline_gc,
+            drawable: x::Drawable::Window(screen.root()),
+            value_list: &[
+                x::Gc::Function(x::Gx::Xor),
+                x::Gc::Foreground(0xffffff),
+                x::Gc::LineWidth(OUTLINE_WIDTH as u32),
+                x::Gc::SubwindowMode(x::SubwindowMode::IncludeInferiors),
+            ],
+        })?;
+
+        // The Composite and Damage extensions are optional, but they must be told which version we
+        // support before they can be used
+        let composite_available = conn.active_extensions().any(|ext| ext == xcb::Extension::Composite);
+        if composite_available {
+            conn.wait_for_reply(conn.send_request(&xcb::composite::QueryVersion {
+                client_major_version: 0,
+                client_minor_version: 4,
+            }))?;
+        }
+
+        let damage_available = conn.active_extensions().any(|ext| ext == xcb::Extension::Damage);
+        if damage_available {
+            conn.wait_for_reply(conn.send_request(&xcb::damage::QueryVersion {
+                client_major_version: 1,
+                client_minor_version: 1,
+            }))?;
+        }
+
+        let sync_available = conn.active_extensions().any(|ext| ext == xcb::Extension::Sync);
+        if sync_available {
+            conn.wait_for_reply(conn.send_request(&xcb::sync::Initialize {
+                desired_major_version: 3,
+                desired_minor_version: 1,
+            }))?;
+        }
+
+        // Output change events need at least version 1.2
+        let randr_available = conn.active_extensions().any(|ext| ext == xcb::Extension::RandR)
+            && conn
+                .wait_for_reply(conn.send_request(&randr::QueryVersion {
+                    major_version: 1,
+                    minor_version: 2,
+                }))
+                .is_ok_and(|version| (version.major_version(), version.minor_version()) >= (1, 2));
+
+        // Shared memory is passed to the server as a file descriptor, which needs at least version 1.2
+        let shm_available = conn.active_extensions().any(|ext| ext == xcb::Extension::Shm)
+            && conn
+                .wait_for_reply(conn.send_request(&xcb::shm::QueryVersion {}))
+                .is_ok_and(|version| (version.major_version(), version.minor_version()) >= (1, 2));
+
+        // Grabbing keys on a single device needs XInput 2, and grabbing touches needs 2.2
+        let xinput_available = conn.active_extensions().any(|ext| ext == xcb::Extension::Input)
+            && conn
+                .wait_for_reply(conn.send_request(&xcb::xinput::XiQueryVersion {
+                    major_version: 2,
+                    minor_version: 2,
+                }))
+                .is_ok_and(|version| (version.major_version(), version.minor_version()) >= (2, 2));
+
+        let screensaver_available = conn.active_extensions().any(|ext| ext == xcb::Extension::ScreenSaver);
+        if screensaver_available {
+            conn.wait_for_reply(conn.send_request(&xcb::screensaver::QueryVersion {
+                client_major_version: 1,
+                client_minor_version: 1,
+            }))?;
+        }
+
         Ok(WindowManager {
             ev_waker,
             ev_queue,
+            subscribers: vec![],
+            socket_paths: vec![],
 
-            config: Config::new(),
+            config,
+            config_path,
+            launcher,
+            grabbed_keys: vec![],
+            escape_keycodes: vec![],
+            device_grabs: vec![],
 
             conn,
             atoms,
             default_screen,
+            font,
+            titlebar_gc,
+            titlebars: HashMap::new(),
 
             framed_clients: BiHashMap::new(),
+            frame_order: vec![],
+            workspace: Container::new(Split::Horizontal),
+            layout_pending: false,
             ignored_sequences: IgnoredSequences::new(),
+            window_properties: HashMap::new(),
+            pending_properties: vec![],
+            decisions: HashMap::new(),
 
+            drag_type: None,
             drag_start: None,
             drag_start_frame_rect: None,
+            pending_drag: None,
+            last_drag_update: Instant::now(),
+            drag_outline: None,
+            outline_gc,
+            geometry_history: HashMap::new(),
+            sync_available,
+            sync_request: None,
+
+            pick: None,
+
+            composite_available,
+            damage_available,
+            mirrors: vec![],
+            last_mirror_update: Instant::now(),
+
+            shm_available,
+
+            randr_available,
+            outputs: HashMap::new(),
+
+            xinput_available,
+            touch_gesture: None,
+
+            screensaver_available,
+            idle_thresholds_passed: 0,
+            last_idle_check: Instant::now(),
+
+            clipboard: None,
 
             focused_window: None,
+            applied_focus: None,
+            hover_focus: None,
+            focus_history: vec![],
+            last_event_time: x::CURRENT_TIME,
+            painted_colors: HashMap::new(),
+            stale_titles: HashSet::new(),
+            tree_generation: 0,
+            last_render: Instant::now(),
+            render_pending: false,
+            override_redirect_windows: HashSet::new(),
+            urgent_windows: vec![],
+            urgent_flash_on: true,
+            flashing_windows: HashMap::new(),
+            start_time: Instant::now(),
+
+            faults: FaultInjector::from_env(),
         })
     }
 
     /// Become the window manager and setup root event masks
-    pub fn become_window_manager(&mut self, socket_path: &Path) -> xcb::Result<()> {
+    pub fn become_window_manager(&mut self, socket_path: &Path, i3_socket_path: &Path) -> xcb::Result<()> {
         // Request to become the X window manager
         self.acquire_wm_event_mask()?;
 
         // Start managing any existing windows
         self.reparent_existing_windows()?;
 
-        // Bind key events on root window so they're always reported
+        // Grab key bindings on the root window so they're always reported
+        self.watch_devices()?;
+        self.grab_bindings()?;
         let root = self.get_root_window()?;
-        self.conn.send_and_check_request(&x::GrabKey {
-            grab_window: root,
-            owner_events: false,
-            key: 0x18, // Q on qwerty TODO: support keymaps
-            pointer_mode: x::GrabMode::Async,
-            keyboard_mode: x::GrabMode::Async,
-            modifiers: x::ModMask::ANY,
-        })?;
 
         // Start listening to events on the root window
         self.conn.send_and_check_request(&x::ChangeWindowAttributes {
@@ -141,7 +474,7 @@ impl<'a> WindowManager<'a> {
             value_list: &[x::Cw::EventMask(MASKS.root_window_events)],
         })?;
 
-        // Set an atom on the root window with the path to our IPC socket
+        // Set atoms on the root window with the paths to our IPC sockets
         let set_atom = |atom, data| {
             self.conn.send_and_check_request(&x::ChangeProperty {
                 mode: x::PropMode::Replace,
@@ -155,6 +488,22 @@ impl<'a> WindowManager<'a> {
         let pid = std::process::id().to_string();
         set_atom(self.atoms.r3_pid, pid.as_bytes())?;
         set_atom(self.atoms.r3_socket_path, socket_path.as_os_str().as_bytes())?;
+        set_atom(self.atoms.i3_socket_path, i3_socket_path.as_os_str().as_bytes())?;
+        self.socket_paths = vec![socket_path.to_path_buf(), i3_socket_path.to_path_buf()];
+
+        self.update_clipboard_manager()?;
+        self.watch_outputs()?;
+        self.paint_root_background()?;
+
+        // Apply the state we've inherited from existing windows (e.g. focus)
+        self.refresh()?;
+
+        // Now that we're managing windows, start anything the user wants to run with us
+        for command in &self.config.autostart {
+            if let Err(e) = self.launcher.spawn(command) {
+                eprintln!("Failed to run {:?}: {}", command, e);
+            }
+        }
 
         Ok(())
     }
@@ -171,10 +520,19 @@ impl<'a> WindowManager<'a> {
             .conn
             .wait_for_reply(self.conn.send_request(&x::QueryTree { window: root }))?;
 
+        // NOTE: children are listed in stacking order from bottom to top, and each new frame is placed
+        // on top of the stack, so framing them in this order keeps their stacking order
+        // TODO: restore their workspace from `_NET_WM_DESKTOP` (once we have workspaces)
+        let mut topmost = None;
         for window in query_tree.children() {
-            self.frame_window(*window, true)?;
+            if self.frame_window(*window, true)?.is_some() {
+                topmost = Some(*window);
+            }
         }
 
+        // Focus the window which was on top, like it probably was before we started
+        self.set_focus(topmost)?;
+
         // Allow things to happen again
         self.conn.send_and_check_request(&x::UngrabServer {})?;
 
@@ -210,20 +568,122 @@ impl<'a> WindowManager<'a> {
         Ok(root)
     }
 
-    // TODO: doc
-    // TODO: in the future, will probably have to maintain some internal state and only render diff
-    //  rather than touching every window every single time an event is received
-    fn render(&self) -> xcb::Result<()> {
+    /// Called on every iteration of the event loop, so time-based state can be updated.
+    pub fn tick(&mut self) -> xcb::Result<()> {
+        self.collect_pending_properties();
+        self.apply_pending_drag(false)?;
+        self.apply_hover_focus()?;
+        self.reject_held_touches()?;
+        self.flash_urgent_windows()?;
+        self.update_flashing_windows()?;
+        if self.render_pending {
+            self.render()?;
+        }
+        self.update_mirrors()?;
+        self.check_idle()?;
+
+        Ok(())
+    }
+
+    /// Reply once the window manager's state has settled: the X server has processed everything we've
+    /// sent, every event it sent back has been handled, and any deferred rendering has been done.
+    pub fn sync(&mut self, reply: Reply) -> xcb::Result<()> {
+        // Handling events may send more requests, which cause more events, so repeat until there are
+        // none (within reason)
+        for _ in 0..SYNC_ROUNDS {
+            // The reply to a request is sent after every event caused by the requests before it
+            self.conn.wait_for_reply(self.conn.send_request(&x::GetInputFocus {}))?;
+
+            let mut handled = false;
+            loop {
+                match self.conn.poll_for_queued_event() {
+                    Ok(None) => break,
+                    Ok(Some(ev)) => self.handle_event(Ok(ev))?,
+                    Err(e) => self.handle_event(Err(xcb::Error::Protocol(e)))?,
+                }
+                handled = true;
+            }
+            if !handled {
+                break;
+            }
+        }
+
+        if self.render_pending {
+            self.render_now()?;
+        }
+        self.conn.flush()?;
+
+        reply.send(&CommandReply::ok(vec![]));
+        Ok(())
+    }
+
+    /// Update any state derived from the current state, and render it. This should be called after
+    /// anything which may change the state of the window manager (events, commands, etc).
+    fn refresh(&mut self) -> xcb::Result<()> {
+        if self.layout_pending {
+            self.apply_layout()?;
+        }
+
+        // Focusing an urgent window clears its urgency
+        if let Some(window) = self.focused_window {
+            self.clear_urgency(window);
+        }
+
+        if !self.focus_changed() {
+            return self.render();
+        }
+        self.tree_generation += 1;
+
+        // Update the X server's focus and our borders together, so nothing sees a half-applied change
+        self.conn.send_and_check_request(&x::GrabServer {})?;
+        let result = match self.apply_focus() {
+            // The window may have been destroyed before we could focus it
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Failed to focus window {:?}: {:?}", self.focused_window, e);
+                Ok(())
+            }
+            result => result,
+        }
+        .and_then(|_| self.render());
+        self.conn.send_and_check_request(&x::UngrabServer {})?;
+
+        result
+    }
+
+    /// Paint frames whose colours have changed (e.g. their window was focused), draw titles which have
+    /// changed, and raise the frame of the focused window. If we rendered too recently, this is put off
+    /// until the next tick after the render interval has passed.
+    fn render(&mut self) -> xcb::Result<()> {
+        if self.last_render.elapsed() < self.config.render_interval {
+            self.render_pending = true;
+            return Ok(());
+        }
+
+        self.render_now()
+    }
+
+    /// Render the current state, even if it's sooner than the render interval allows.
+    fn render_now(&mut self) -> xcb::Result<()> {
+        self.render_pending = false;
+        self.last_render = Instant::now();
+
         let mut requests = vec![];
+        let mut repainted = vec![];
 
-        // Iterate through all frames and color the focused one if it exists (the focused window may
-        // be a window that's not framed)
+        // The focused window may be a window that's not framed
         for (window, frame) in &self.framed_clients {
             let is_focused = Some(*window) == self.focused_window || Some(*frame) == self.focused_window;
-            requests.push(self.conn.send_request_checked(&x::ChangeWindowAttributes {
-                window: *frame,
-                value_list: &[x::Cw::BorderPixel(if is_focused { 0xff0000 } else { 0xaaaaaa })],
-            }));
+            let colors = self.frame_colors(*window, is_focused);
+            if self.painted_colors.get(frame) != Some(&colors) {
+                requests.push(self.conn.send_request_checked(&x::ChangeWindowAttributes {
+                    window: *frame,
+                    value_list: &[
+                        self.frame_background(&colors),
+                        x::Cw::BorderPixel(colors.border.pixel()),
+                    ],
+                }));
+                repainted.push((*window, *frame, colors));
+            }
             if is_focused {
                 requests.push(self.conn.send_request_checked(&x::ConfigureWindow {
                     window: *frame,
@@ -236,17 +696,39 @@ impl<'a> WindowManager<'a> {
             self.conn.check_request(cookie)?;
         }
 
-        // If we have a focused window, then tell X to focus it specifically.
-        if let Some(target) = self.focused_window {
-            // If the focused window is a frame, then focus its window
-            let focus = *self.framed_clients.get_by_right(&target).unwrap_or(&target);
-            self.conn.send_and_check_request(&x::SetInputFocus {
-                revert_to: x::InputFocus::PointerRoot,
-                focus,
-                time: x::CURRENT_TIME,
-            })?;
+        // Changing the background doesn't repaint the titlebar, so it's drawn again
+        for (window, frame, colors) in repainted {
+            self.painted_colors.insert(frame, colors);
+            self.stale_titles.insert(window);
+        }
+        for window in std::mem::take(&mut self.stale_titles) {
+            if let Some(frame) = self.framed_clients.get_by_left(&window).copied() {
+                self.draw_titlebar(window, frame)?;
+            }
         }
 
         Ok(())
     }
+
+    /// The background attribute of frames with the given colours.
+    pub(super) fn frame_background(&self, colors: &FrameColors) -> x::Cw {
+        match self.config.frame_background {
+            FrameBackground::Solid => x::Cw::BackPixel(colors.background.pixel()),
+            FrameBackground::None => x::Cw::BackPixmap(xcb::Xid::none()),
+            FrameBackground::ParentRelative => x::Cw::BackPixmap(x::BACKPIXMAP_PARENT_RELATIVE),
+        }
+    }
+
+    /// The colours a window's frame should have, depending on its state.
+    fn frame_colors(&self, window: x::Window, is_focused: bool) -> FrameColors {
+        if self.should_draw_flash(window) {
+            self.config.colors.urgent
+        } else if is_focused {
+            self.config.colors.focused
+        } else if self.should_draw_urgent(window) {
+            self.config.colors.urgent
+        } else {
+            self.config.colors.unfocused
+        }
+    }
 }
@@ -0,0 +1,58 @@
+use xcb::x;
+
+use super::{DragType, WindowManager};
+use crate::window_geometry::WindowGeometry;
+
+/// Line width (in pixels) of the outline drawn when dragging windows
+pub const OUTLINE_WIDTH: u16 = 2;
+
+impl<'a> WindowManager<'a> {
+    /// Draw (or erase, since it's drawn with XOR) an outline on the root window.
+    fn draw_outline(&self, rect: WindowGeometry) -> xcb::Result<()> {
+        let half = (OUTLINE_WIDTH / 2) as i16;
+        self.conn.send_and_check_request(&x::PolyRectangle {
+            drawable: x::Drawable::Window(self.get_root_window()?),
+            gc: self.outline_gc,
+            rectangles: &[x::Rectangle {
+                x: rect.x + half,
+                y: rect.y + half,
+                width: rect.full_width().saturating_sub(OUTLINE_WIDTH),
+                height: rect.full_height().saturating_sub(OUTLINE_WIDTH),
+            }],
+        })?;
+
+        Ok(())
+    }
+
+    /// Move the drag outline to the given rect.
+    pub(super) fn update_drag_outline(
+        &mut self,
+        window: x::Window,
+        drag_type: DragType,
+        rect: WindowGeometry,
+    ) -> xcb::Result<()> {
+        match self.drag_outline {
+            // Erase the previous outline
+            Some((_, _, previous)) => self.draw_outline(previous)?,
+            // This is the first outline of the drag: grab the server so nothing draws underneath the
+            // outline while it's visible (which would leave artifacts behind when it's erased)
+            None => self.conn.send_and_check_request(&x::GrabServer {})?,
+        }
+
+        self.draw_outline(rect)?;
+        self.drag_outline = Some((window, drag_type, rect));
+
+        Ok(())
+    }
+
+    /// Erase the drag outline, returning where it was so the drag can be applied.
+    pub(super) fn end_drag_outline(&mut self) -> xcb::Result<Option<(x::Window, DragType, WindowGeometry)>> {
+        let outline = self.drag_outline.take();
+        if let Some((_, _, rect)) = outline {
+            self.draw_outline(rect)?;
+            self.conn.send_and_check_request(&x::UngrabServer {})?;
+        }
+
+        Ok(outline)
+    }
+}
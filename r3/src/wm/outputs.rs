@@ -0,0 +1,144 @@
+use r3lib::{OutputInfo, OutputState, R3Event, Rect};
+use xcb::{randr, x, Xid};
+
+use super::WindowManager;
+
+impl<'a> WindowManager<'a> {
+    /// Ask to be told when outputs change (see RandR's `RROutputChangeNotify`), and remember what they
+    /// look like now so we only report changes.
+    pub(super) fn watch_outputs(&mut self) -> xcb::Result<()> {
+        if !self.randr_available {
+            return Ok(());
+        }
+
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&randr::SelectInput {
+            window: root,
+            enable: randr::NotifyMask::OUTPUT_CHANGE,
+        })?;
+
+        let resources = self.conn.wait_for_reply(
+            self.conn
+                .send_request(&randr::GetScreenResourcesCurrent { window: root }),
+        )?;
+        for output in resources.outputs() {
+            match self.get_output_state(*output) {
+                Ok(state) => {
+                    self.outputs.insert(*output, state);
+                }
+                // The output was most likely removed (e.g. a dock was unplugged) while we asked about it
+                Err(xcb::Error::Protocol(e)) => eprintln!("Failed to read output {:?}: {:?}", output, e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn on_randr_notify(&mut self, ev: randr::NotifyEvent) -> xcb::Result<()> {
+        let output = match ev.u() {
+            randr::NotifyData::Oc(change) => change.output(),
+            _ => return Ok(()),
+        };
+
+        let state = match self.get_output_state(output) {
+            Ok(state) => state,
+            // The output was most likely removed, and we'll be told about whatever replaced it
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Failed to read output {:?}: {:?}", output, e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let old = self.outputs.insert(output, state.clone());
+        for event in state.events_since(old.as_ref()) {
+            self.output_changed(event);
+        }
+
+        Ok(())
+    }
+
+    /// Describe each output RandR knows about.
+    pub(super) fn get_outputs(&self) -> xcb::Result<Vec<OutputInfo>> {
+        if !self.randr_available {
+            return Ok(vec![]);
+        }
+
+        let root = self.get_root_window()?;
+        let resources = self.conn.wait_for_reply(
+            self.conn
+                .send_request(&randr::GetScreenResourcesCurrent { window: root }),
+        )?;
+        let primary = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&randr::GetOutputPrimary { window: root }))?
+            .output();
+
+        let mut outputs = vec![];
+        for output in resources.outputs() {
+            let info = self.conn.wait_for_reply(self.conn.send_request(&randr::GetOutputInfo {
+                output: *output,
+                config_timestamp: resources.config_timestamp(),
+            }))?;
+
+            let rect = match info.crtc() {
+                crtc if crtc.is_none() => None,
+                crtc => {
+                    let crtc = self.conn.wait_for_reply(self.conn.send_request(&randr::GetCrtcInfo {
+                        crtc,
+                        config_timestamp: resources.config_timestamp(),
+                    }))?;
+                    Some(Rect {
+                        x: crtc.x(),
+                        y: crtc.y(),
+                        width: crtc.width(),
+                        height: crtc.height(),
+                    })
+                }
+            };
+
+            outputs.push(OutputInfo {
+                name: String::from_utf8_lossy(info.name()).into(),
+                connected: info.connection() == randr::Connection::Connected,
+                active: rect.is_some(),
+                primary: *output == primary,
+                rect,
+            });
+        }
+
+        Ok(outputs)
+    }
+
+    fn get_output_state(&self, output: randr::Output) -> xcb::Result<OutputState> {
+        let info = self.conn.wait_for_reply(self.conn.send_request(&randr::GetOutputInfo {
+            output,
+            config_timestamp: x::CURRENT_TIME,
+        }))?;
+
+        Ok(OutputState {
+            name: String::from_utf8_lossy(info.name()).into(),
+            connected: info.connection() == randr::Connection::Connected,
+            enabled: !info.crtc().is_none(),
+        })
+    }
+
+    /// Tell subscribers about the change, and run the hook for it.
+    fn output_changed(&mut self, event: R3Event) {
+        let hooks = &self.config.output_hooks;
+        let (hook, output) = match &event {
+            R3Event::OutputConnected { output } => (&hooks.connected, output),
+            R3Event::OutputDisconnected { output } => (&hooks.disconnected, output),
+            R3Event::PanelOff { output } => (&hooks.panel_off, output),
+            R3Event::PanelOn { output } => (&hooks.panel_on, output),
+            _ => return,
+        };
+
+        if let Some(command) = hook {
+            if let Err(e) = self.launcher.spawn_with_env(command, &[("R3_OUTPUT", output)]) {
+                eprintln!("Failed to run {:?}: {}", command, e);
+            }
+        }
+
+        self.emit_event(event);
+    }
+}
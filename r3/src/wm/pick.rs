@@ -0,0 +1,144 @@
+use r3lib::WindowInfo;
+use xcb::x;
+
+use super::WindowManager;
+use crate::ipc::Reply;
+
+/// Glyph from the X cursor font used while picking a window (`XC_pirate`, a skull and crossbones)
+const PICK_CURSOR_GLYPH: u16 = 88;
+
+/// What to do with the window the user clicks on while picking
+#[derive(Debug)]
+pub enum PickAction {
+    /// Close the picked window (like `xkill`, but gracefully if the window supports it)
+    Kill,
+    /// Reply with information about the picked window (or `null` if nothing was picked)
+    Reply(Reply),
+}
+
+impl PickAction {
+    /// Called when picking was cancelled, or nothing was picked
+    fn cancel(self) {
+        match self {
+            PickAction::Kill => {}
+            PickAction::Reply(reply) => reply.send(&None::<WindowInfo>),
+        }
+    }
+}
+
+impl<'a> WindowManager<'a> {
+    /// Grab the pointer and keyboard, and wait for the user to click on a window. The next click will
+    /// perform the action on the clicked window, and pressing Escape cancels.
+    pub(super) fn start_pick(&mut self, action: PickAction) -> xcb::Result<()> {
+        // Cancel any existing pick
+        if let Some(action) = self.end_pick()? {
+            action.cancel();
+        }
+
+        let root = self.get_root_window()?;
+
+        // Create a cursor from the X cursor font so it's obvious we're in a special mode
+        let font = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::OpenFont {
+            fid: font,
+            name: b"cursor",
+        })?;
+        let cursor = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::CreateGlyphCursor {
+            cid: cursor,
+            source_font: font,
+            mask_font: font,
+            source_char: PICK_CURSOR_GLYPH,
+            mask_char: PICK_CURSOR_GLYPH + 1,
+            fore_red: 0,
+            fore_green: 0,
+            fore_blue: 0,
+            back_red: u16::MAX,
+            back_green: u16::MAX,
+            back_blue: u16::MAX,
+        })?;
+        self.conn.send_and_check_request(&x::CloseFont { font })?;
+
+        // Grab the pointer and keyboard so all clicks and key presses come to us
+        let pointer = self.conn.wait_for_reply(self.conn.send_request(&x::GrabPointer {
+            owner_events: false,
+            grab_window: root,
+            event_mask: x::EventMask::BUTTON_PRESS,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+            confine_to: xcb::Xid::none(),
+            cursor,
+            time: x::CURRENT_TIME,
+        }))?;
+        self.conn.send_and_check_request(&x::FreeCursor { cursor })?;
+        if pointer.status() != x::GrabStatus::Success {
+            eprintln!("Failed to grab pointer for picking a window: {:?}", pointer.status());
+            action.cancel();
+            return Ok(());
+        }
+
+        let keyboard = self.conn.wait_for_reply(self.conn.send_request(&x::GrabKeyboard {
+            owner_events: false,
+            grab_window: root,
+            time: x::CURRENT_TIME,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+        }))?;
+        if keyboard.status() != x::GrabStatus::Success {
+            eprintln!("Failed to grab keyboard for picking a window: {:?}", keyboard.status());
+            self.conn
+                .send_and_check_request(&x::UngrabPointer { time: x::CURRENT_TIME })?;
+            action.cancel();
+            return Ok(());
+        }
+
+        self.pick = Some(action);
+        Ok(())
+    }
+
+    /// Stop picking and release the grabs, returning the pending action if we were picking.
+    fn end_pick(&mut self) -> xcb::Result<Option<PickAction>> {
+        let action = self.pick.take();
+        if action.is_some() {
+            self.conn
+                .send_and_check_request(&x::UngrabPointer { time: x::CURRENT_TIME })?;
+            self.conn
+                .send_and_check_request(&x::UngrabKeyboard { time: x::CURRENT_TIME })?;
+        }
+
+        Ok(action)
+    }
+
+    /// Stop picking without picking anything.
+    pub(super) fn cancel_pick(&mut self) -> xcb::Result<()> {
+        if let Some(action) = self.end_pick()? {
+            action.cancel();
+        }
+
+        Ok(())
+    }
+
+    /// Called when the user clicks while picking: `child` is the top-level window which was clicked.
+    pub(super) fn on_pick(&mut self, child: x::Window) -> xcb::Result<()> {
+        let action = match self.end_pick()? {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+
+        // Clicking on the root window (or an unmanaged window) picks nothing
+        let (window, _) = match self.get_frame_and_window(child) {
+            Some(pair) => pair,
+            None => {
+                action.cancel();
+                return Ok(());
+            }
+        };
+
+        match action {
+            PickAction::Kill => self.kill_window(window)?,
+            PickAction::Reply(reply) => reply.send(&Some(self.get_window_info(window)?)),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use xcb::x;
+
+use super::WindowManager;
+use crate::config::{Gaps, PositionPolicy, WindowRule};
+use crate::point::Point;
+use crate::ret_ok_if_none;
+use crate::window_geometry::WindowGeometry;
+
+impl<'a> WindowManager<'a> {
+    /// Move a newly framed window according to the rules which match it, or else the configured
+    /// position policy.
+    pub(super) fn place_window(&mut self, window: x::Window, rules: &[WindowRule]) -> xcb::Result<()> {
+        if let Some((x, y)) = rules.iter().rev().find_map(|rule| rule.position) {
+            self.record_decision(window, format!("placed at ({}, {}) by a rule", x, y));
+            return self.move_window(window, Point::new(x, y));
+        }
+
+        let policy = self.config.position_policy;
+        if policy == PositionPolicy::Honor {
+            self.record_decision(window, "kept where it asked to be, since the position policy is Honor");
+            return Ok(());
+        }
+
+        // NOTE: this waits for the window's size hints, but only if we need them
+        if self.get_window_properties(window)?.size_hints.user_position {
+            self.record_decision(
+                window,
+                "kept where it asked to be, since the user asked for it (USPosition)",
+            );
+            return Ok(());
+        }
+
+        let frame = *ret_ok_if_none!(self.framed_clients.get_by_left(&window));
+        let rect = self.get_window_rect(frame)?;
+        let screen = self.get_window_rect(self.get_root_window()?)?;
+        let pos = match policy {
+            PositionPolicy::Honor => return Ok(()),
+            PositionPolicy::Center => center(&rect, &screen, self.config.gaps),
+            PositionPolicy::Smart => self.smart_position(frame, &rect, &screen)?,
+        };
+        self.record_decision(
+            window,
+            format!("placed at ({}, {}) by the {:?} position policy", pos.x, pos.y, policy),
+        );
+
+        self.move_window(window, pos)
+    }
+
+    /// Find the position on the screen where the frame overlaps the other frames the least. If there's
+    /// more than one, the topmost (then leftmost) is used.
+    fn smart_position(&self, frame: x::Window, rect: &WindowGeometry, screen: &WindowGeometry) -> xcb::Result<Point> {
+        let mut others = vec![];
+        for other in self.framed_clients.right_values().filter(|other| **other != frame) {
+            others.push(self.get_window_rect(*other)?);
+        }
+
+        // The best positions are always against the edge of the screen or another window (leaving gaps)
+        let Gaps { inner, outer } = self.config.gaps;
+        let (inner, outer) = (inner as i32, outer as i32);
+        let mut xs = vec![outer];
+        let mut ys = vec![outer];
+        for other in &others {
+            xs.push(other.x as i32 + other.full_width() as i32 + inner);
+            ys.push(other.y as i32 + other.full_height() as i32 + inner);
+        }
+        xs.sort_unstable();
+        ys.sort_unstable();
+
+        let mut best: Option<(u32, Point)> = None;
+        for y in ys
+            .iter()
+            .filter(|y| **y + rect.full_height() as i32 + outer <= screen.h as i32)
+        {
+            for x in xs
+                .iter()
+                .filter(|x| **x + rect.full_width() as i32 + outer <= screen.w as i32)
+            {
+                let candidate = WindowGeometry::new(*x as i16, *y as i16, rect.w, rect.h, rect.bw);
+                let overlap = others.iter().map(|other| candidate.overlap_area(other)).sum::<u32>();
+                if best.is_none_or(|(least, _)| overlap < least) {
+                    best = Some((overlap, Point::new(candidate.x, candidate.y)));
+                }
+            }
+        }
+
+        // If the window doesn't fit anywhere, just center it
+        Ok(best.map_or_else(|| center(rect, screen, self.config.gaps), |(_, pos)| pos))
+    }
+}
+
+/// The position which centers the window on the screen, keeping it at least the outer gap away from
+/// the top and left edges.
+fn center(rect: &WindowGeometry, screen: &WindowGeometry, gaps: Gaps) -> Point {
+    let outer = gaps.outer as i32;
+    Point::new(
+        ((screen.w as i32 - rect.full_width() as i32) / 2).max(outer) as i16,
+        ((screen.h as i32 - rect.full_height() as i32) / 2).max(outer) as i16,
+    )
+}
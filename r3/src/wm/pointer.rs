@@ -0,0 +1,63 @@
+use r3lib::PointerInfo;
+use xcb::x;
+
+use super::WindowManager;
+
+impl<'a> WindowManager<'a> {
+    /// Where the pointer is, and the managed window under it.
+    pub(super) fn get_pointer_info(&mut self) -> xcb::Result<PointerInfo> {
+        let reply = self.conn.wait_for_reply(self.conn.send_request(&x::QueryPointer {
+            window: self.get_root_window()?,
+        }))?;
+
+        // The child is the top-level window under the pointer, which for managed windows is their frame
+        let window = match self.framed_clients.get_by_right(&reply.child()).copied() {
+            Some(window) => Some(self.get_window_info(window)?),
+            None => None,
+        };
+
+        let (x, y) = (reply.root_x() as i32, reply.root_y() as i32);
+        let output = self
+            .get_outputs()?
+            .into_iter()
+            .find(|output| {
+                output.rect.is_some_and(|rect| {
+                    (rect.x as i32..rect.x as i32 + rect.width as i32).contains(&x)
+                        && (rect.y as i32..rect.y as i32 + rect.height as i32).contains(&y)
+                })
+            })
+            .map(|output| output.name);
+
+        Ok(PointerInfo {
+            x: reply.root_x(),
+            y: reply.root_y(),
+            window,
+            output,
+        })
+    }
+
+    /// Move the pointer to the middle of the window, unless it's already on it.
+    pub(super) fn warp_pointer_to(&mut self, window: x::Window) -> xcb::Result<()> {
+        let reply = self.conn.wait_for_reply(self.conn.send_request(&x::QueryPointer {
+            window: self.get_root_window()?,
+        }))?;
+        let frame = self.framed_clients.get_by_left(&window).copied().unwrap_or(window);
+        if reply.child() == frame {
+            return Ok(());
+        }
+
+        let rect = self.get_window_rect(window)?;
+        self.conn.send_and_check_request(&x::WarpPointer {
+            src_window: xcb::Xid::none(),
+            dst_window: window,
+            src_x: 0,
+            src_y: 0,
+            src_width: 0,
+            src_height: 0,
+            dst_x: (rect.w / 2) as i16,
+            dst_y: (rect.h / 2) as i16,
+        })?;
+
+        Ok(())
+    }
+}
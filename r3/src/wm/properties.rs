@@ -0,0 +1,370 @@
+use r3lib::WindowInfo;
+use xcb::{x, Xid};
+
+use super::WindowManager;
+use crate::config::ClassSelector;
+
+/// The `InputHint` flag in `WM_HINTS` (see ICCCM 4.1.2.4)
+const INPUT_HINT: u32 = 1 << 0;
+/// Flags in `WM_NORMAL_HINTS` which say which of its fields are set (see ICCCM 4.1.2.3)
+const US_POSITION: u32 = 1 << 0;
+const P_MIN_SIZE: u32 = 1 << 4;
+const P_MAX_SIZE: u32 = 1 << 5;
+
+/// Properties of client windows which we cache, since they're read often and rarely change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachedProperty {
+    /// `WM_CLASS`
+    Class,
+    /// `WM_PROTOCOLS`
+    Protocols,
+    /// `WM_NORMAL_HINTS`
+    NormalHints,
+    /// `WM_HINTS`
+    Hints,
+    /// `_NET_WM_PID`
+    Pid,
+    /// `WM_WINDOW_ROLE`
+    Role,
+}
+
+impl CachedProperty {
+    const ALL: [CachedProperty; 6] = [
+        CachedProperty::Class,
+        CachedProperty::Protocols,
+        CachedProperty::NormalHints,
+        CachedProperty::Hints,
+        CachedProperty::Pid,
+        CachedProperty::Role,
+    ];
+}
+
+/// The sizing hints a window has set in `WM_NORMAL_HINTS`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SizeHints {
+    /// The user asked for the window to be placed at its position
+    pub user_position: bool,
+    pub min_size: Option<(u32, u32)>,
+    pub max_size: Option<(u32, u32)>,
+}
+
+/// The cached properties of a client window
+#[derive(Debug, Clone)]
+pub struct WindowProperties {
+    pub instance: String,
+    pub class: String,
+    pub protocols: Vec<x::Atom>,
+    pub size_hints: SizeHints,
+    /// Whether the window wants us to give it input focus
+    pub accepts_input: bool,
+    /// The id of the process which owns the window (if it told us)
+    pub pid: Option<u32>,
+    /// Distinguishes the windows of a program which have the same class and instance (see ICCCM 5.1)
+    pub role: String,
+}
+
+impl Default for WindowProperties {
+    fn default() -> Self {
+        WindowProperties {
+            instance: String::new(),
+            class: String::new(),
+            protocols: vec![],
+            size_hints: SizeHints::default(),
+            // Windows which don't set WM_HINTS are assumed to want focus
+            accepts_input: true,
+            pid: None,
+            role: String::new(),
+        }
+    }
+}
+
+impl WindowProperties {
+    fn update(&mut self, property: CachedProperty, reply: &x::GetPropertyReply) {
+        match property {
+            CachedProperty::Class => {
+                // WM_CLASS is two consecutive null-terminated strings
+                let mut parts = reply
+                    .value::<u8>()
+                    .split(|b| *b == 0)
+                    .map(|part| String::from_utf8_lossy(part).into_owned());
+                self.instance = parts.next().unwrap_or_default();
+                self.class = parts.next().unwrap_or_default();
+            }
+            CachedProperty::Protocols => {
+                self.protocols = reply.value::<x::Atom>().to_vec();
+            }
+            CachedProperty::NormalHints => {
+                // flags, (4 padding fields), min size, max size, ...
+                let value = reply.value::<u32>();
+                let flags = value.first().copied().unwrap_or(0);
+                let pair = |flag: u32, idx: usize| match value.get(idx..idx + 2) {
+                    Some([a, b]) if flags & flag != 0 => Some((*a, *b)),
+                    _ => None,
+                };
+                self.size_hints = SizeHints {
+                    user_position: flags & US_POSITION != 0,
+                    min_size: pair(P_MIN_SIZE, 5),
+                    max_size: pair(P_MAX_SIZE, 7),
+                };
+            }
+            CachedProperty::Hints => {
+                // flags, input, ...
+                self.accepts_input = match reply.value::<u32>() {
+                    [flags, input, ..] if flags & INPUT_HINT != 0 => *input != 0,
+                    _ => true,
+                };
+            }
+            CachedProperty::Pid => {
+                self.pid = reply.value::<u32>().first().copied();
+            }
+            CachedProperty::Role => {
+                self.role = String::from_utf8_lossy(reply.value::<u8>()).into_owned();
+            }
+        }
+    }
+}
+
+/// A request for a cached property which has been sent, but whose reply hasn't been read yet
+#[derive(Debug)]
+pub struct PendingProperty {
+    window: x::Window,
+    property: CachedProperty,
+    cookie: x::GetPropertyCookie,
+}
+
+impl<'a> WindowManager<'a> {
+    /// If the atom is one of the properties we cache, which one is it?
+    pub(super) fn cached_property(&self, atom: x::Atom) -> Option<CachedProperty> {
+        match atom {
+            x::ATOM_WM_CLASS => Some(CachedProperty::Class),
+            x::ATOM_WM_NORMAL_HINTS => Some(CachedProperty::NormalHints),
+            x::ATOM_WM_HINTS => Some(CachedProperty::Hints),
+            atom if atom == self.atoms.wm_protocols => Some(CachedProperty::Protocols),
+            atom if atom == self.atoms.net_wm_pid => Some(CachedProperty::Pid),
+            atom if atom == self.atoms.wm_window_role => Some(CachedProperty::Role),
+            _ => None,
+        }
+    }
+
+    fn send_property_request(&self, window: x::Window, property: CachedProperty) -> x::GetPropertyCookie {
+        let (property, r#type) = match property {
+            CachedProperty::Class => (x::ATOM_WM_CLASS, x::ATOM_STRING),
+            CachedProperty::Protocols => (self.atoms.wm_protocols, x::ATOM_ATOM),
+            CachedProperty::NormalHints => (x::ATOM_WM_NORMAL_HINTS, x::ATOM_WM_SIZE_HINTS),
+            CachedProperty::Hints => (x::ATOM_WM_HINTS, x::ATOM_WM_HINTS),
+            CachedProperty::Pid => (self.atoms.net_wm_pid, x::ATOM_CARDINAL),
+            CachedProperty::Role => (self.atoms.wm_window_role, x::ATOM_STRING),
+        };
+
+        self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type,
+            long_offset: 0,
+            long_length: u32::MAX,
+        })
+    }
+
+    /// Start caching the properties of a newly managed window. This only sends the requests, the
+    /// replies are read later so we don't wait on a round trip to the X server while mapping windows.
+    pub(super) fn start_caching_properties(&mut self, window: x::Window) {
+        self.window_properties.insert(window, WindowProperties::default());
+        for property in CachedProperty::ALL {
+            self.refresh_cached_property(window, property);
+        }
+    }
+
+    /// Stop caching the properties of a window which is no longer managed.
+    pub(super) fn stop_caching_properties(&mut self, window: x::Window) {
+        // NOTE: any pending requests are left alone; their replies are discarded once they arrive
+        self.window_properties.remove(&window);
+    }
+
+    /// Re-read a cached property of a window (e.g. after it's changed).
+    pub(super) fn refresh_cached_property(&mut self, window: x::Window, property: CachedProperty) {
+        if self.window_properties.contains_key(&window) {
+            let cookie = self.send_property_request(window, property);
+            self.pending_properties.push(PendingProperty {
+                window,
+                property,
+                cookie,
+            });
+        }
+    }
+
+    fn apply_property_reply(
+        &mut self,
+        window: x::Window,
+        property: CachedProperty,
+        reply: xcb::Result<x::GetPropertyReply>,
+    ) {
+        match reply {
+            Ok(reply) => {
+                if let Some(properties) = self.window_properties.get_mut(&window) {
+                    properties.update(property, &reply);
+                }
+            }
+            // The window was most likely destroyed before we read its properties
+            Err(e) => eprintln!("Failed to read {:?} of window {:?}: {:?}", property, window, e),
+        }
+    }
+
+    /// Read the replies of any pending property requests which have arrived, without blocking.
+    pub(super) fn collect_pending_properties(&mut self) {
+        let mut still_pending = vec![];
+        for pending in std::mem::take(&mut self.pending_properties) {
+            match self.conn.poll_for_reply(&pending.cookie) {
+                Some(reply) => self.apply_property_reply(pending.window, pending.property, reply),
+                None => still_pending.push(pending),
+            }
+        }
+
+        self.pending_properties = still_pending;
+    }
+
+    /// Get the cached properties of a window, waiting for any pending replies for it. Windows we don't
+    /// manage aren't cached, so their properties are read from the X server.
+    pub(super) fn get_window_properties(&mut self, window: x::Window) -> xcb::Result<WindowProperties> {
+        let (ours, others) = std::mem::take(&mut self.pending_properties)
+            .into_iter()
+            .partition::<Vec<_>, _>(|pending| pending.window == window);
+        self.pending_properties = others;
+        for pending in ours {
+            let reply = self.conn.wait_for_reply(pending.cookie);
+            self.apply_property_reply(pending.window, pending.property, reply);
+        }
+
+        if let Some(properties) = self.window_properties.get(&window) {
+            return Ok(properties.clone());
+        }
+
+        // The requests are all sent before waiting for any replies, so this only waits for the server once
+        let cookies = CachedProperty::ALL.map(|property| (property, self.send_property_request(window, property)));
+        let mut properties = WindowProperties::default();
+        for (property, cookie) in cookies {
+            properties.update(property, &self.conn.wait_for_reply(cookie)?);
+        }
+
+        Ok(properties)
+    }
+
+    /// Read the title of a window, preferring `_NET_WM_NAME` over `WM_NAME`.
+    pub(super) fn get_window_title(&self, window: x::Window) -> xcb::Result<String> {
+        let net_wm_name = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.atoms.net_wm_name,
+            r#type: self.atoms.utf8_string,
+            long_offset: 0,
+            long_length: u32::MAX,
+        });
+        let wm_name = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_NAME,
+            r#type: x::ATOM_ANY,
+            long_offset: 0,
+            long_length: u32::MAX,
+        });
+
+        let net_wm_name = self.conn.wait_for_reply(net_wm_name)?;
+        let wm_name = self.conn.wait_for_reply(wm_name)?;
+        let title = match net_wm_name.value::<u8>() {
+            [] => wm_name.value::<u8>(),
+            value => value,
+        };
+
+        Ok(String::from_utf8_lossy(title).into_owned())
+    }
+
+    /// The class we treat a window as having, after applying any configured remaps.
+    pub(super) fn window_class(&self, properties: &WindowProperties) -> String {
+        // Only read the command line if a remap needs it, and only once
+        let mut cmdline = None;
+        for remap in &self.config.class_remaps {
+            let matches = match &remap.selector {
+                ClassSelector::Class(class) => *class == properties.class,
+                ClassSelector::Instance(instance) => *instance == properties.instance,
+                ClassSelector::Cmdline(pattern) => cmdline
+                    .get_or_insert_with(|| properties.pid.and_then(read_cmdline))
+                    .as_ref()
+                    .is_some_and(|cmdline| cmdline.contains(pattern.as_str())),
+            };
+
+            if matches {
+                return remap.class.clone();
+            }
+        }
+
+        properties.class.clone()
+    }
+
+    /// Gather information about a managed window.
+    pub(super) fn get_window_info(&mut self, window: x::Window) -> xcb::Result<WindowInfo> {
+        let properties = self.get_window_properties(window)?;
+        let class = self.window_class(&properties);
+        let instance = properties.instance;
+        Ok(WindowInfo {
+            id: window.resource_id(),
+            frame: self
+                .framed_clients
+                .get_by_left(&window)
+                .map(|frame| frame.resource_id()),
+            class,
+            instance,
+            title: self.get_window_title(window)?,
+        })
+    }
+
+    /// Read a property which holds a single value.
+    pub(super) fn get_window_property<T: x::PropEl + Clone>(
+        &self,
+        window: x::Window,
+        property: x::Atom,
+        r#type: x::Atom,
+    ) -> xcb::Result<Vec<T>> {
+        let reply = self.conn.wait_for_reply(self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type,
+            long_offset: 0,
+            long_length: 1,
+        }))?;
+
+        Ok(reply.value::<T>().to_vec())
+    }
+
+    /// Replace a property of a window.
+    pub(super) fn set_window_property<T: x::PropEl>(
+        &self,
+        window: x::Window,
+        property: x::Atom,
+        r#type: x::Atom,
+        data: &[T],
+    ) -> xcb::Result<()> {
+        self.conn.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property,
+            r#type,
+            data,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Read the command line of a process, with its arguments separated by spaces.
+fn read_cmdline(pid: u32) -> Option<String> {
+    // NOTE: this only works if the process is running on the same machine as us
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>();
+
+    Some(args.join(" "))
+}
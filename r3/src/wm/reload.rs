@@ -0,0 +1,185 @@
+use std::fs;
+use std::os::unix::prelude::PermissionsExt;
+
+use r3lib::ipc::{self, CommandReply, VersionReply};
+use xcb::x;
+
+use super::titlebar::LoadedFont;
+use super::WindowManager;
+use crate::config::{Config, IpcAccess};
+use crate::ipc::{socket_mode, Reply};
+
+impl<'a> WindowManager<'a> {
+    /// Reply with every value of the current configuration, including defaults (see
+    /// `Config::effective_table`).
+    pub fn get_config(&self, reply: Reply) {
+        match self.config.effective_table() {
+            Ok(table) => reply.send(&table),
+            Err(e) => reply.send(&CommandReply::failed(e)),
+        }
+    }
+
+    /// Change one value of the current configuration and apply it. The configuration files aren't
+    /// changed, so this only lasts until the configuration is reloaded.
+    pub fn set_config(&mut self, key: &str, value: &str, reply: Reply) -> xcb::Result<()> {
+        let config = match self.config.with_value(key, value) {
+            Ok(config) => config,
+            Err(e) => {
+                reply.send(&CommandReply::failed(e));
+                return Ok(());
+            }
+        };
+
+        self.without_enter_events(|wm| wm.apply_config(config))?;
+        reply.send(&CommandReply::ok(vec![]));
+        Ok(())
+    }
+
+    /// Reply with our version, and the version of the IPC protocol we speak.
+    pub fn get_version(&self, reply: Reply) {
+        reply.send(&VersionReply {
+            version: env!("CARGO_PKG_VERSION").into(),
+            protocol_version: ipc::PROTOCOL_VERSION,
+            config_paths: self.config.paths.clone(),
+        });
+    }
+
+    /// Who may use r3's sockets, which is checked whenever a client connects.
+    pub fn ipc_access(&self) -> &IpcAccess {
+        &self.config.ipc
+    }
+
+    /// Reply with the files the current configuration was read from.
+    pub fn get_config_paths(&self, reply: Reply) {
+        reply.send(&self.config.paths);
+    }
+
+    /// Read the configuration file again and apply it to the existing frames. If the file can't be
+    /// read then the current configuration is kept, and the error is returned.
+    pub(super) fn reload_config(&mut self) -> xcb::Result<Result<(), String>> {
+        let config = match Config::load(self.config_path.as_deref()) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Failed to reload configuration, keeping the current one: {}", e);
+                return Ok(Err(e));
+            }
+        };
+
+        self.apply_config(config)?;
+        Ok(Ok(()))
+    }
+
+    /// Replace the current configuration, and apply it to the existing frames.
+    fn apply_config(&mut self, config: Config) -> xcb::Result<()> {
+        let old_config = std::mem::replace(&mut self.config, config);
+        self.launcher.shell = self.config.exec_shell.clone();
+        self.launcher.working_dir = self.config.exec_working_dir.clone();
+        if self.config.font != old_config.font {
+            self.reload_font()?;
+        }
+        if self.config.root_background != old_config.root_background {
+            self.paint_root_background()?;
+        }
+        if self.config.ipc != old_config.ipc {
+            self.update_socket_permissions();
+        }
+
+        // Colours may have changed, so all the frames are painted again when they're next rendered. Gaps,
+        // borders and titlebars may have too, so the tiled windows are laid out again
+        self.painted_colors.clear();
+        self.layout_pending = true;
+        self.grab_bindings()?;
+        self.update_clipboard_manager()?;
+
+        let framed_clients = self.framed_clients.iter().map(|(w, f)| (*w, *f)).collect::<Vec<_>>();
+        for (window, frame) in framed_clients {
+            if let Err(e) = self.reconfigure_frame(window, frame) {
+                match e {
+                    // The window was most likely destroyed, which we'll hear about soon
+                    xcb::Error::Protocol(e) => eprintln!("Failed to reconfigure frame of {:?}: {:?}", window, e),
+                    e => return Err(e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Let the users who may now use our sockets open them, or stop the ones who may not. Clients which
+    /// are already connected keep the access they were given when they connected.
+    fn update_socket_permissions(&self) {
+        let permissions = fs::Permissions::from_mode(socket_mode(&self.config.ipc));
+        for path in &self.socket_paths {
+            if let Err(e) = fs::set_permissions(path, permissions.clone()) {
+                eprintln!("Failed to change the permissions of {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Paint the root window with the configured background colour. If there isn't one it's left alone,
+    /// since a wallpaper tool may have set it.
+    pub(super) fn paint_root_background(&mut self) -> xcb::Result<()> {
+        let color = match self.config.root_background {
+            Some(color) => color,
+            None => return Ok(()),
+        };
+
+        let root = self.get_root_window()?;
+        self.conn.send_and_check_request(&x::ChangeWindowAttributes {
+            window: root,
+            value_list: &[x::Cw::BackPixel(color.pixel())],
+        })?;
+        self.conn.send_and_check_request(&x::ClearArea {
+            exposures: false,
+            window: root,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        })?;
+
+        Ok(())
+    }
+
+    /// Replace the font used to draw titles with the configured one.
+    fn reload_font(&mut self) -> xcb::Result<()> {
+        let font = LoadedFont::load(self.conn, &self.config.font)?;
+        self.conn.send_and_check_request(&x::ChangeGc {
+            gc: self.titlebar_gc,
+            value_list: &[x::Gc::Font(font.id)],
+        })?;
+        self.conn.send_and_check_request(&x::CloseFont {
+            font: std::mem::replace(&mut self.font, font).id,
+        })?;
+
+        Ok(())
+    }
+
+    /// Apply the border width and titlebar to an existing frame, and redraw its titlebar.
+    fn reconfigure_frame(&mut self, window: x::Window, frame: x::Window) -> xcb::Result<()> {
+        let rules = self.matching_rules(window)?;
+        let titlebar = self.frame_titlebar(&rules);
+        if self.titlebars.insert(window, titlebar) != Some(titlebar) {
+            let (x, y) = titlebar.client_offset();
+            self.conn.send_and_check_request(&x::ConfigureWindow {
+                window,
+                value_list: &[x::ConfigWindow::X(x.into()), x::ConfigWindow::Y(y.into())],
+            })?;
+        }
+
+        self.grab_buttons(window)?;
+
+        let rect = self.get_window_rect(window)?;
+        let (width, height) = titlebar.frame_size(rect.w, rect.h);
+        self.conn.send_and_check_request(&x::ConfigureWindow {
+            window: frame,
+            value_list: &[
+                x::ConfigWindow::Width(width.into()),
+                x::ConfigWindow::Height(height.into()),
+                x::ConfigWindow::BorderWidth(self.frame_border_width(&rules).into()),
+            ],
+        })?;
+
+        self.draw_titlebar(window, frame)
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use xcb::{sync, x, Xid, XidNew};
+
+use super::WindowManager;
+
+/// How long to wait for a client to handle a resize before we stop waiting for it
+const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Tracks a client which supports `_NET_WM_SYNC_REQUEST` while it's being resized. Before each resize
+/// we send the client a number, and it sets its sync counter to that number once it has redrawn; we
+/// don't send another resize until then so slow clients aren't flooded with them.
+#[derive(Debug)]
+pub struct SyncRequest {
+    /// The window being resized
+    window: x::Window,
+    /// Notifies us when the counter reaches `value`
+    alarm: sync::Alarm,
+    /// The value we last asked the client to set its counter to
+    value: i64,
+    /// When we last sent a sync request, if the client hasn't acknowledged it yet
+    waiting_since: Option<Instant>,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Is the window still handling the last resize we sent it?
+    pub(super) fn awaiting_sync(&self, window: x::Window) -> bool {
+        match &self.sync_request {
+            Some(request) if request.window == window => request
+                .waiting_since
+                .is_some_and(|since| since.elapsed() < SYNC_REQUEST_TIMEOUT),
+            _ => false,
+        }
+    }
+
+    /// Ask the window to tell us when it's handled the next resize, if it supports it (see EWMH
+    /// `_NET_WM_SYNC_REQUEST`). This should be sent just before the window is resized.
+    pub(super) fn send_sync_request(&mut self, window: x::Window) -> xcb::Result<()> {
+        if !self.sync_available
+            || !self
+                .get_window_properties(window)?
+                .protocols
+                .contains(&self.atoms.net_wm_sync_request)
+        {
+            return Ok(());
+        }
+
+        if self.sync_request.as_ref().map(|request| request.window) != Some(window) {
+            self.stop_sync_request();
+            self.sync_request = self.start_sync_request(window)?;
+        }
+
+        let request = match &mut self.sync_request {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        request.value += 1;
+        request.waiting_since = Some(Instant::now());
+
+        self.conn.send_and_check_request(&sync::ChangeAlarm {
+            id: request.alarm,
+            value_list: &[sync::Ca::Value(to_int64(request.value))],
+        })?;
+
+        let value = to_int64(request.value);
+        let data = x::ClientMessageData::Data32([
+            self.atoms.net_wm_sync_request.resource_id(),
+            self.last_event_time,
+            value.lo,
+            value.hi as u32,
+            0,
+        ]);
+        self.conn.send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(window),
+            event_mask: x::EventMask::NO_EVENT,
+            event: &x::ClientMessageEvent::new(window, self.atoms.wm_protocols, data),
+        })?;
+
+        Ok(())
+    }
+
+    /// Create an alarm on the window's sync counter, if it has one.
+    fn start_sync_request(&self, window: x::Window) -> xcb::Result<Option<SyncRequest>> {
+        // NOTE: the property may also contain an extended counter, but we only use the basic one
+        let counter = match self
+            .get_window_property::<u32>(window, self.atoms.net_wm_sync_request_counter, x::ATOM_CARDINAL)?
+            .first()
+        {
+            Some(counter) => sync::Counter::new(*counter),
+            None => return Ok(None),
+        };
+
+        let reply = match self
+            .conn
+            .wait_for_reply(self.conn.send_request(&sync::QueryCounter { counter }))
+        {
+            Ok(reply) => reply,
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Window {:?} has an invalid sync counter: {:?}", window, e);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let value = from_int64(reply.counter_value());
+
+        // The alarm fires once the counter reaches the value we've asked for, and then waits until we
+        // change the value again
+        let alarm = self.conn.generate_id();
+        self.conn.send_and_check_request(&sync::CreateAlarm {
+            id: alarm,
+            value_list: &[
+                sync::Ca::Counter(counter),
+                sync::Ca::ValueType(sync::Valuetype::Absolute),
+                sync::Ca::Value(to_int64(value)),
+                sync::Ca::TestType(sync::Testtype::PositiveComparison),
+                sync::Ca::Delta(to_int64(0)),
+                sync::Ca::Events(1),
+            ],
+        })?;
+
+        Ok(Some(SyncRequest {
+            window,
+            alarm,
+            value,
+            waiting_since: None,
+        }))
+    }
+
+    /// Stop tracking the window being resized (e.g. when the drag is finished).
+    pub(super) fn stop_sync_request(&mut self) {
+        if let Some(request) = self.sync_request.take() {
+            // NOTE: this fails if the client already destroyed its counter
+            if let Err(e) = self
+                .conn
+                .send_and_check_request(&sync::DestroyAlarm { alarm: request.alarm })
+            {
+                eprintln!("Failed to destroy sync alarm: {:?}", e);
+            }
+        }
+    }
+
+    /// Stop tracking the window if it's being resized, since it's gone.
+    pub(super) fn forget_sync_request(&mut self, window: x::Window) {
+        if self
+            .sync_request
+            .as_ref()
+            .is_some_and(|request| request.window == window)
+        {
+            self.stop_sync_request();
+        }
+    }
+
+    pub(super) fn on_alarm_notify(&mut self, ev: sync::AlarmNotifyEvent) -> xcb::Result<()> {
+        let request = match &mut self.sync_request {
+            Some(request) if request.alarm == ev.alarm() => request,
+            _ => return Ok(()),
+        };
+
+        // The client has caught up, so it can be sent the next resize (this may be an old alarm from
+        // before we last changed its value though)
+        if from_int64(ev.counter_value()) >= request.value {
+            request.waiting_since = None;
+            self.apply_pending_drag(false)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn to_int64(value: i64) -> sync::Int64 {
+    sync::Int64 {
+        hi: (value >> 32) as i32,
+        lo: value as u32,
+    }
+}
+
+fn from_int64(value: sync::Int64) -> i64 {
+    ((value.hi as i64) << 32) | value.lo as i64
+}
@@ -0,0 +1,81 @@
+use xcb::x;
+
+use super::titlebar::Titlebar;
+use super::WindowManager;
+use crate::config::{TitlebarPosition, WindowRule};
+
+impl<'a> WindowManager<'a> {
+    /// The configured rules which match the window, in the order they're configured.
+    pub(super) fn matching_rules(&mut self, window: x::Window) -> xcb::Result<Vec<WindowRule>> {
+        Ok(self
+            .matching_rule_indices(window)?
+            .into_iter()
+            .map(|i| self.config.rules[i].clone())
+            .collect())
+    }
+
+    /// The positions in the configured rules of the ones which match the window.
+    pub(super) fn matching_rule_indices(&mut self, window: x::Window) -> xcb::Result<Vec<usize>> {
+        if self.config.rules.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let properties = self.get_window_properties(window)?;
+        let class = self.window_class(&properties);
+
+        // Only read the title if a rule needs it, and only once
+        let mut title = None;
+        let mut rules = vec![];
+        for (index, rule) in self.config.rules.iter().enumerate() {
+            if rule.class.as_ref().is_some_and(|c| *c != class)
+                || rule.instance.as_ref().is_some_and(|i| *i != properties.instance)
+                || rule.role.as_ref().is_some_and(|r| *r != properties.role)
+            {
+                continue;
+            }
+
+            if let Some(pattern) = &rule.title {
+                if title.is_none() {
+                    title = Some(self.get_window_title(window)?);
+                }
+                if !title.as_ref().is_some_and(|title| title.contains(pattern.as_str())) {
+                    continue;
+                }
+            }
+
+            rules.push(index);
+        }
+
+        Ok(rules)
+    }
+
+    /// Width of the border around the window's frame, from the last matching rule which sets one.
+    pub(super) fn frame_border_width(&self, rules: &[WindowRule]) -> u16 {
+        rules
+            .iter()
+            .rev()
+            .find_map(|rule| rule.border_width)
+            .unwrap_or(self.config.border_width)
+    }
+
+    /// The titlebar of the window's frame, from the last matching rule which sets its position.
+    pub(super) fn frame_titlebar(&self, rules: &[WindowRule]) -> Titlebar {
+        let position =
+            rules
+                .iter()
+                .rev()
+                .find_map(|rule| rule.titlebar_position)
+                .unwrap_or(match self.config.titlebar {
+                    true => self.config.titlebar_position,
+                    false => TitlebarPosition::None,
+                });
+
+        match position {
+            TitlebarPosition::None => Titlebar::NONE,
+            position => Titlebar {
+                position,
+                thickness: self.titlebar_thickness(),
+            },
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use r3lib::{Rect, Split};
+use xcb::{x, Xid};
+
+use super::WindowManager;
+use crate::config::WindowRule;
+use crate::ret_ok_if_none;
+use crate::window_geometry::WindowGeometry;
+
+impl<'a> WindowManager<'a> {
+    /// Tile a newly framed window after the focused window (or at the end of the workspace, if that
+    /// isn't tiled), unless tiling is off or the window should float (`rules` are the ones which match
+    /// it). Returns whether it was tiled.
+    pub(super) fn tile_window(&mut self, window: x::Window, rules: &[WindowRule]) -> xcb::Result<bool> {
+        if !self.config.tiling {
+            return Ok(false);
+        }
+        self.faults.inject(self.conn, "tile_window.get_property")?;
+        if let Some(reason) = self.floats_because(window, rules)? {
+            self.record_decision(window, format!("floating, since {}", reason));
+            return Ok(false);
+        }
+
+        let focused = self
+            .focused_window
+            .and_then(|focused| self.get_frame_and_window(focused))
+            .map(|(focused, _)| focused);
+        self.workspace.insert(window, focused);
+        self.record_decision(window, "tiled, since tiling is on");
+
+        // Lay the windows out now, so the new one doesn't appear in one place and then jump to another
+        self.apply_layout()?;
+        Ok(true)
+    }
+
+    /// Why the window floats rather than being tiled, if it does.
+    fn floats_because(&mut self, window: x::Window, rules: &[WindowRule]) -> xcb::Result<Option<&'static str>> {
+        match rules.iter().rev().find_map(|rule| rule.floating) {
+            Some(true) => return Ok(Some("a rule makes it float")),
+            Some(false) => return Ok(None),
+            None => {}
+        }
+
+        let transient_for = self.get_window_property::<x::Window>(window, x::ATOM_WM_TRANSIENT_FOR, x::ATOM_WINDOW)?;
+        if transient_for.first().is_some_and(|parent| !parent.is_none()) {
+            return Ok(Some("it's a dialog (it has WM_TRANSIENT_FOR)"));
+        }
+
+        let types = self.get_window_property::<x::Atom>(window, self.atoms.net_wm_window_type, x::ATOM_ATOM)?;
+        if types
+            .first()
+            .is_some_and(|r#type| *r#type != self.atoms.net_wm_window_type_normal)
+        {
+            return Ok(Some("it isn't a normal window (it has another _NET_WM_WINDOW_TYPE)"));
+        }
+
+        let size_hints = self.get_window_properties(window)?.size_hints;
+        if size_hints.min_size.is_some() && size_hints.min_size == size_hints.max_size {
+            return Ok(Some("it can't be resized (its minimum and maximum sizes are the same)"));
+        }
+
+        Ok(None)
+    }
+
+    /// Split the window's place in the layout (see `Container::split`). Returns whether it's tiled, since
+    /// floating windows can't be split.
+    pub(super) fn split_window(&mut self, window: x::Window, split: Split) -> bool {
+        // NOTE: splitting doesn't move any windows, so there aren't any events to tell clients about it
+        let tiled = self.workspace.split(window, split);
+        if tiled {
+            self.tree_generation += 1;
+        }
+
+        tiled
+    }
+
+    /// The area of the screen the workspace covers, which is inside the outer gaps.
+    pub(super) fn workspace_rect(&self) -> xcb::Result<Rect> {
+        let screen = self.get_window_rect(self.get_root_window()?)?;
+        let outer = self.config.gaps.outer;
+        Ok(Rect {
+            x: outer as i16,
+            y: outer as i16,
+            width: screen.w.saturating_sub(outer * 2).max(1),
+            height: screen.h.saturating_sub(outer * 2).max(1),
+        })
+    }
+
+    /// Move and resize each tiled window to fill its tile.
+    pub(super) fn apply_layout(&mut self) -> xcb::Result<()> {
+        self.layout_pending = false;
+        let tiles = self.workspace.layout(self.workspace_rect()?, self.config.gaps.inner);
+
+        self.without_enter_events(|wm| {
+            for (window, tile) in tiles {
+                match wm.fit_to_tile(window, tile) {
+                    // The window was most likely destroyed, which we'll hear about soon
+                    Err(xcb::Error::Protocol(e)) => eprintln!("Failed to tile window {:?}: {:?}", window, e),
+                    result => result?,
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Fit the window's frame (including its border) into the tile.
+    fn fit_to_tile(&self, window: x::Window, tile: Rect) -> xcb::Result<()> {
+        let frame = *ret_ok_if_none!(self.framed_clients.get_by_left(&window));
+        let bw = self.get_window_rect(frame)?.bw;
+        let rect = WindowGeometry::new(
+            tile.x,
+            tile.y,
+            tile.width.saturating_sub(bw * 2).max(1),
+            tile.height.saturating_sub(bw * 2).max(1),
+            bw,
+        );
+
+        self.set_window_rect(window, rect)
+    }
+
+    /// Tell a tiled window which asked to be moved or resized where it is instead, since its tile
+    /// decides where it goes (see ICCCM 4.1.5).
+    pub(super) fn refuse_configure_request(&self, window: x::Window) -> xcb::Result<()> {
+        let frame = *ret_ok_if_none!(self.framed_clients.get_by_left(&window));
+        let frame_rect = self.get_window_rect(frame)?;
+        let rect = self.get_window_rect(window)?;
+
+        // NOTE: the position is relative to the root window, as if the window hadn't been re-parented
+        let event = x::ConfigureNotifyEvent::new(
+            window,
+            window,
+            x::WINDOW_NONE,
+            frame_rect.x + frame_rect.bw as i16 + rect.x,
+            frame_rect.y + frame_rect.bw as i16 + rect.y,
+            rect.w,
+            rect.h,
+            rect.bw,
+            false,
+        );
+        self.conn.send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(window),
+            event_mask: x::EventMask::STRUCTURE_NOTIFY,
+            event: &event,
+        })?;
+
+        Ok(())
+    }
+}
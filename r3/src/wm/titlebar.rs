@@ -0,0 +1,323 @@
+use std::cmp;
+
+use xcb::x;
+
+use super::WindowManager;
+use crate::config::{FrameColors, TitlebarPosition};
+use crate::font::FontSpec;
+
+/// Padding (in pixels) between the titlebar's edges and its text
+const TITLEBAR_PADDING: u16 = 2;
+
+/// The font used to draw titles, along with the metrics we need for layout
+#[derive(Debug)]
+pub struct LoadedFont {
+    pub id: x::Font,
+    pub ascent: i16,
+    pub descent: i16,
+}
+
+impl LoadedFont {
+    /// Load the given font from the X server, falling back to `fixed` if it can't be found.
+    pub fn load(conn: &xcb::Connection, spec: &FontSpec) -> xcb::Result<LoadedFont> {
+        match LoadedFont::open(conn, &spec.x_font_name()) {
+            Ok(font) => Ok(font),
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Failed to load font \"{}\", falling back to \"fixed\": {:?}", spec, e);
+                LoadedFont::open(conn, "fixed")
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn open(conn: &xcb::Connection, name: &str) -> xcb::Result<LoadedFont> {
+        let id = conn.generate_id();
+        conn.send_and_check_request(&x::OpenFont {
+            fid: id,
+            name: name.as_bytes(),
+        })?;
+
+        let info = conn.wait_for_reply(conn.send_request(&x::QueryFont {
+            font: x::Fontable::Font(id),
+        }))?;
+
+        Ok(LoadedFont {
+            id,
+            ascent: info.font_ascent(),
+            descent: info.font_descent(),
+        })
+    }
+}
+
+/// Where a frame's titlebar is, and how much of the frame it takes up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Titlebar {
+    pub position: TitlebarPosition,
+    /// The titlebar's height, or its width if it's on the left or right
+    pub thickness: u16,
+}
+
+impl Titlebar {
+    /// No titlebar, e.g. for windows which aren't framed
+    pub const NONE: Titlebar = Titlebar {
+        position: TitlebarPosition::None,
+        thickness: 0,
+    };
+
+    /// The position of the client window within its frame.
+    pub fn client_offset(&self) -> (i16, i16) {
+        match self.position {
+            TitlebarPosition::Top => (0, self.thickness as i16),
+            TitlebarPosition::Left => (self.thickness as i16, 0),
+            TitlebarPosition::Bottom | TitlebarPosition::Right | TitlebarPosition::None => (0, 0),
+        }
+    }
+
+    /// The size of a frame which holds a client window of the given size.
+    pub fn frame_size(&self, width: u16, height: u16) -> (u16, u16) {
+        match self.position {
+            TitlebarPosition::Top | TitlebarPosition::Bottom => (width, height.saturating_add(self.thickness)),
+            TitlebarPosition::Left | TitlebarPosition::Right => (width.saturating_add(self.thickness), height),
+            TitlebarPosition::None => (width, height),
+        }
+    }
+
+    /// The size of the client window in a frame of the given size (which is never empty).
+    pub fn client_size(&self, width: u16, height: u16) -> (u16, u16) {
+        let (width, height) = match self.position {
+            TitlebarPosition::Top | TitlebarPosition::Bottom => (width, height.saturating_sub(self.thickness)),
+            TitlebarPosition::Left | TitlebarPosition::Right => (width.saturating_sub(self.thickness), height),
+            TitlebarPosition::None => (width, height),
+        };
+
+        (width.max(1), height.max(1))
+    }
+
+    /// The area of a frame of the given size which the titlebar covers.
+    pub fn rect(&self, width: u16, height: u16) -> x::Rectangle {
+        let (client_width, client_height) = self.client_size(width, height);
+        let (x, y, width, height) = match self.position {
+            TitlebarPosition::Top => (0, 0, width, self.thickness),
+            TitlebarPosition::Bottom => (0, client_height as i16, width, self.thickness),
+            TitlebarPosition::Left => (0, 0, self.thickness, height),
+            TitlebarPosition::Right => (client_width as i16, 0, self.thickness, height),
+            TitlebarPosition::None => (0, 0, 0, 0),
+        };
+
+        x::Rectangle { x, y, width, height }
+    }
+}
+
+impl<'a> WindowManager<'a> {
+    /// The thickness of titlebars, from the configuration or the font's metrics.
+    pub(super) fn titlebar_thickness(&self) -> u16 {
+        self.config
+            .titlebar_height
+            .unwrap_or((self.font.ascent + self.font.descent) as u16 + TITLEBAR_PADDING * 2)
+    }
+
+    /// The titlebar of the window's frame (there's none if the window isn't framed).
+    pub(super) fn titlebar(&self, window: x::Window) -> Titlebar {
+        self.titlebars.get(&window).copied().unwrap_or(Titlebar::NONE)
+    }
+
+    /// Draw the titlebar of the given frame.
+    pub(super) fn draw_titlebar(&self, window: x::Window, frame: x::Window) -> xcb::Result<()> {
+        let titlebar = self.titlebar(window);
+        if titlebar.position == TitlebarPosition::None || titlebar.thickness == 0 {
+            return Ok(());
+        }
+
+        let title = self.get_window_title(window)?;
+        let frame_rect = self.get_window_rect(frame)?;
+        let rect = titlebar.rect(frame_rect.w, frame_rect.h);
+
+        // Clear any previous title. The frame's background isn't necessarily painted (see
+        // `Config::frame_background`) so we fill the titlebar ourselves
+        let colors = match self.painted_colors.get(&frame) {
+            Some(colors) => *colors,
+            None => self.config.colors.unfocused,
+        };
+        self.conn.send_and_check_request(&x::ChangeGc {
+            gc: self.titlebar_gc,
+            value_list: &[x::Gc::Foreground(colors.background.pixel())],
+        })?;
+        self.conn.send_and_check_request(&x::PolyFillRectangle {
+            drawable: x::Drawable::Window(frame),
+            gc: self.titlebar_gc,
+            rectangles: &[rect],
+        })?;
+
+        // NOTE: ImageText16 can only draw up to 255 characters, and only those in the BMP
+        let chars = title
+            .chars()
+            .take(255)
+            .map(|c| {
+                let c = if (c as u32) > 0xffff { '?' } else { c } as u16;
+                x::Char2b {
+                    byte1: (c >> 8) as u8,
+                    byte2: c as u8,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Titlebars on the sides have their title drawn sideways (reading upwards on the left, and
+        // downwards on the right)
+        if let TitlebarPosition::Left | TitlebarPosition::Right = titlebar.position {
+            let clockwise = titlebar.position == TitlebarPosition::Right;
+            return self.draw_rotated_title(frame, rect, clockwise, colors, &chars);
+        }
+
+        self.draw_title_text(x::Drawable::Window(frame), rect.y, rect.height, colors, &chars)
+    }
+
+    /// Draw the title in a horizontal strip of the drawable which starts at `y`.
+    fn draw_title_text(
+        &self,
+        drawable: x::Drawable,
+        y: i16,
+        height: u16,
+        colors: FrameColors,
+        chars: &[x::Char2b],
+    ) -> xcb::Result<()> {
+        self.conn.send_and_check_request(&x::ChangeGc {
+            gc: self.titlebar_gc,
+            value_list: &[
+                x::Gc::Foreground(colors.text.pixel()),
+                x::Gc::Background(colors.background.pixel()),
+            ],
+        })?;
+
+        // Center the text vertically within the strip
+        let text_height = self.font.ascent + self.font.descent;
+        self.conn.send_and_check_request(&x::ImageText16 {
+            drawable,
+            gc: self.titlebar_gc,
+            x: TITLEBAR_PADDING as i16,
+            y: y + (height as i16 - text_height) / 2 + self.font.ascent,
+            string: chars,
+        })?;
+
+        Ok(())
+    }
+
+    /// Draw the title along a titlebar on the side of a frame. Core fonts can't be drawn rotated, so
+    /// the title is drawn into a pixmap as usual, and then we rotate its pixels ourselves.
+    fn draw_rotated_title(
+        &self,
+        frame: x::Window,
+        rect: x::Rectangle,
+        clockwise: bool,
+        colors: FrameColors,
+        chars: &[x::Char2b],
+    ) -> xcb::Result<()> {
+        let setup = self.conn.get_setup();
+        let depth = setup.roots().nth(self.default_screen as usize).unwrap().root_depth();
+        let format = match setup.pixmap_formats().iter().find(|format| format.depth() == depth) {
+            // NOTE: we only know how to move whole bytes around, which is all any modern server uses
+            Some(format) if format.bits_per_pixel() % 8 == 0 => ImageFormat {
+                bytes_per_pixel: format.bits_per_pixel() as usize / 8,
+                scanline_pad: format.scanline_pad() as usize,
+            },
+            _ => return Ok(()),
+        };
+
+        // The title is clipped to the length of the titlebar
+        let extents = self.conn.wait_for_reply(self.conn.send_request(&x::QueryTextExtents {
+            font: x::Fontable::Font(self.font.id),
+            string: chars,
+        }))?;
+        let text_length = extents.overall_width().max(0) as u32 + TITLEBAR_PADDING as u32 * 2;
+        let length = cmp::min(text_length, rect.height as u32) as u16;
+        let thickness = rect.width;
+        if length == 0 || thickness == 0 {
+            return Ok(());
+        }
+
+        // Draw the title horizontally, and read it back
+        let pixmap = self.conn.generate_id();
+        self.conn.send_and_check_request(&x::CreatePixmap {
+            depth,
+            pid: pixmap,
+            drawable: x::Drawable::Window(frame),
+            width: length,
+            height: thickness,
+        })?;
+        let image = (|| {
+            // NOTE: the GC's foreground is still the background colour
+            self.conn.send_and_check_request(&x::PolyFillRectangle {
+                drawable: x::Drawable::Pixmap(pixmap),
+                gc: self.titlebar_gc,
+                rectangles: &[x::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: length,
+                    height: thickness,
+                }],
+            })?;
+            self.draw_title_text(x::Drawable::Pixmap(pixmap), 0, thickness, colors, chars)?;
+
+            let size = format.stride(length as usize) * thickness as usize;
+            self.get_image(x::Drawable::Pixmap(pixmap), length, thickness, size)
+        })();
+        self.conn.send_and_check_request(&x::FreePixmap { pixmap })?;
+
+        let data = format.rotate(&image?, length as usize, thickness as usize, clockwise);
+        self.conn.send_and_check_request(&x::PutImage {
+            format: x::ImageFormat::ZPixmap,
+            drawable: x::Drawable::Window(frame),
+            gc: self.titlebar_gc,
+            width: thickness,
+            height: length,
+            dst_x: rect.x,
+            // Titles start at the bottom of titlebars on the left, since they read upwards
+            dst_y: match clockwise {
+                true => rect.y,
+                false => rect.y + (rect.height - length) as i16,
+            },
+            left_pad: 0,
+            depth,
+            data: &data,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// The layout of the pixels in a `ZPixmap` image
+struct ImageFormat {
+    bytes_per_pixel: usize,
+    /// Each row is padded to a multiple of this many bits
+    scanline_pad: usize,
+}
+
+impl ImageFormat {
+    /// The number of bytes in each row of an image of the given width.
+    fn stride(&self, width: usize) -> usize {
+        let pad = cmp::max(self.scanline_pad, 8);
+        (width * self.bytes_per_pixel * 8).div_ceil(pad) * pad / 8
+    }
+
+    /// Rotate the image by 90 degrees, clockwise or anticlockwise, returning the rotated image (which
+    /// is `height` wide and `width` tall).
+    fn rotate(&self, data: &[u8], width: usize, height: usize, clockwise: bool) -> Vec<u8> {
+        let bpp = self.bytes_per_pixel;
+        let (src_stride, dst_stride) = (self.stride(width), self.stride(height));
+        let mut rotated = vec![0; dst_stride * width];
+        for y in 0..width {
+            for x in 0..height {
+                let (src_x, src_y) = match clockwise {
+                    true => (y, height - 1 - x),
+                    false => (width - 1 - y, x),
+                };
+                let src = src_y * src_stride + src_x * bpp;
+                let dst = y * dst_stride + x * bpp;
+                if let Some(pixel) = data.get(src..src + bpp) {
+                    rotated[dst..dst + bpp].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        rotated
+    }
+}
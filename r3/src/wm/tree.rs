@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::process;
+
+use r3lib::{NodeType, Rect, Split, TreeFormat, TreeNode};
+use xcb::{x, Xid};
+
+use super::WindowManager;
+use crate::ipc::Reply;
+use crate::layout::{Container, Node};
+
+impl<'a> WindowManager<'a> {
+    /// Reply with the tree of managed windows in the given format. Floating frames are listed in
+    /// stacking order, from the bottom up.
+    pub(super) fn dump_tree(&mut self, format: TreeFormat, reply: Reply) -> xcb::Result<()> {
+        let tree = self.get_tree()?;
+        match format {
+            TreeFormat::Ascii => reply.send(&ascii_tree(&tree)),
+            TreeFormat::Dot => reply.send(&dot_tree(&tree)),
+            TreeFormat::Json => reply.send(&tree),
+        }
+
+        Ok(())
+    }
+
+    /// Reply with a hash of the tree's state, which changes whenever the tree may have changed. Clients
+    /// can poll this rather than the whole tree, and only ask for the tree when it changes.
+    pub(super) fn get_state_hash(&self, reply: Reply) {
+        // NOTE: the pid is included so the hash changes if r3 is restarted
+        reply.send(&format!("{:x}-{:x}", process::id(), self.tree_generation));
+    }
+
+    /// The tree of managed windows. The workspace (if any windows are tiled) is listed first, below
+    /// the floating frames.
+    fn get_tree(&mut self) -> xcb::Result<TreeNode> {
+        let root = self.get_root_window()?;
+        let screen = self.get_window_rect(root)?;
+        let tree = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&x::QueryTree { window: root }))?;
+
+        let mut nodes = vec![];
+        if !self.workspace.children.is_empty() {
+            let rect = self.workspace_rect()?;
+            let gap = self.config.gaps.inner;
+            let mut frames = HashMap::new();
+            for (window, _) in self.workspace.layout(rect, gap) {
+                if let Some(frame) = self.framed_clients.get_by_left(&window).copied() {
+                    frames.insert(window, self.get_frame_node(window, frame, false)?);
+                }
+            }
+            nodes.push(container_node(
+                &self.workspace,
+                NodeType::Workspace,
+                rect,
+                gap,
+                &mut frames,
+            ));
+        }
+
+        for frame in tree.children() {
+            let window = match self.framed_clients.get_by_right(frame) {
+                Some(window) if !self.workspace.contains(*window) => *window,
+                _ => continue,
+            };
+            nodes.push(self.get_frame_node(window, *frame, true)?);
+        }
+
+        Ok(TreeNode {
+            id: root.resource_id(),
+            node_type: NodeType::Root,
+            rect: Rect {
+                x: 0,
+                y: 0,
+                width: screen.w,
+                height: screen.h,
+            },
+            focused: false,
+            floating: false,
+            split: None,
+            window: None,
+            nodes,
+        })
+    }
+
+    /// A frame, holding its window.
+    fn get_frame_node(&mut self, window: x::Window, frame: x::Window, floating: bool) -> xcb::Result<TreeNode> {
+        let client = TreeNode {
+            id: window.resource_id(),
+            node_type: NodeType::Window,
+            rect: self.get_node_rect(window)?,
+            focused: self.focused_window == Some(window),
+            floating,
+            split: None,
+            window: Some(self.get_window_info(window)?),
+            nodes: vec![],
+        };
+
+        Ok(TreeNode {
+            id: frame.resource_id(),
+            node_type: NodeType::Frame,
+            rect: self.get_node_rect(frame)?,
+            focused: false,
+            floating,
+            split: None,
+            window: None,
+            nodes: vec![client],
+        })
+    }
+
+    fn get_node_rect(&self, window: x::Window) -> xcb::Result<Rect> {
+        let rect = self.get_window_rect(window)?;
+        Ok(Rect {
+            x: rect.x,
+            y: rect.y,
+            width: rect.w,
+            height: rect.h,
+        })
+    }
+}
+
+/// A workspace or container covering the rect, holding the frames of its windows (which are taken
+/// from `frames`) and the containers in it.
+fn container_node(
+    container: &Container,
+    node_type: NodeType,
+    rect: Rect,
+    gap: u16,
+    frames: &mut HashMap<x::Window, TreeNode>,
+) -> TreeNode {
+    let nodes = container
+        .children
+        .iter()
+        .zip(container.child_rects(rect, gap))
+        .filter_map(|(child, rect)| match child {
+            Node::Window(window) => frames.remove(window),
+            Node::Container(container) => Some(container_node(container, NodeType::Container, rect, gap, frames)),
+        })
+        .collect();
+
+    TreeNode {
+        id: container.id,
+        node_type,
+        rect,
+        focused: false,
+        floating: false,
+        split: Some(container.split),
+        window: None,
+        nodes,
+    }
+}
+
+/// Whether the event may change the tree: windows being mapped, moved, restacked, focused or renamed.
+/// This errs on the side of caution, since it's only used to tell clients to ask for the tree again.
+pub(super) fn may_change_tree(event: &xcb::Event) -> bool {
+    matches!(
+        event,
+        xcb::Event::X(
+            x::Event::MapNotify(_)
+                | x::Event::UnmapNotify(_)
+                | x::Event::DestroyNotify(_)
+                | x::Event::ConfigureNotify(_)
+                | x::Event::ReparentNotify(_)
+                | x::Event::FocusIn(_)
+                | x::Event::PropertyNotify(_)
+        ) | xcb::Event::RandR(_)
+    )
+}
+
+fn label(node: &TreeNode) -> String {
+    let Rect { x, y, width, height } = node.rect;
+    match (node.node_type, &node.window) {
+        (NodeType::Window, Some(info)) => {
+            let focused = if node.focused { " (focused)" } else { "" };
+            format!(
+                "window {:#x} {:?} class={:?} instance={:?}{}",
+                node.id, info.title, info.class, info.instance, focused
+            )
+        }
+        (NodeType::Root, _) => format!("root {:#x} ({}x{})", node.id, width, height),
+        (NodeType::Workspace | NodeType::Container, _) => {
+            let split = match node.split {
+                Some(Split::Vertical) => "vertical",
+                _ => "horizontal",
+            };
+            let name = match node.node_type {
+                NodeType::Workspace => "workspace",
+                _ => "container",
+            };
+            format!(
+                "{} {:#x} ({}, {}, {}x{}) split {}",
+                name, node.id, x, y, width, height, split
+            )
+        }
+        _ => format!("frame {:#x} ({}, {}, {}x{})", node.id, x, y, width, height),
+    }
+}
+
+fn ascii_tree(root: &TreeNode) -> String {
+    fn draw(node: &TreeNode, indent: &str, lines: &mut Vec<String>) {
+        for (i, child) in node.nodes.iter().enumerate() {
+            let last = i + 1 == node.nodes.len();
+            let (branch, next) = if last { ("└─", "   ") } else { ("├─", "│  ") };
+            lines.push(format!("{}{} {}", indent, branch, label(child)));
+            draw(child, &format!("{}{}", indent, next), lines);
+        }
+    }
+
+    let mut lines = vec![label(root)];
+    draw(root, "", &mut lines);
+    lines.join("\n")
+}
+
+fn dot_tree(root: &TreeNode) -> String {
+    // Labels are quoted, so only quotes and backslashes need escaping
+    fn draw(node: &TreeNode, lines: &mut Vec<String>) {
+        let escaped = label(node).replace('\\', "\\\\").replace('"', "\\\"");
+        lines.push(format!("  \"{:#x}\" [label=\"{}\"];", node.id, escaped));
+        for child in &node.nodes {
+            draw(child, lines);
+            lines.push(format!("  \"{:#x}\" -> \"{:#x}\";", node.id, child.id));
+        }
+    }
+
+    let mut lines = vec!["digraph r3 {".to_string(), "  node [shape=box];".to_string()];
+    draw(root, &mut lines);
+    lines.push("}".to_string());
+
+    lines.join("\n")
+}
@@ -0,0 +1,72 @@
+use std::time::Instant;
+
+use xcb::x;
+
+use super::WindowManager;
+
+/// The `UrgencyHint` flag in `WM_HINTS` (see ICCCM 4.1.2.4)
+const URGENCY_HINT: u32 = 1 << 8;
+
+impl<'a> WindowManager<'a> {
+    /// Read `WM_HINTS` to check if a window has set the urgency hint.
+    fn read_urgency_hint(&self, window: x::Window) -> xcb::Result<bool> {
+        let reply = self.conn.wait_for_reply(self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_HINTS,
+            r#type: x::ATOM_WM_HINTS,
+            long_offset: 0,
+            long_length: 1,
+        }))?;
+
+        Ok(matches!(reply.value::<u32>().first(), Some(flags) if flags & URGENCY_HINT != 0))
+    }
+
+    /// Re-read the urgency hint of a window and update our urgent windows accordingly.
+    pub(super) fn update_urgency(&mut self, window: x::Window) -> xcb::Result<()> {
+        let urgent = self.read_urgency_hint(window)?;
+        let is_focused = self.focused_window == Some(window)
+            || self.focused_window == self.framed_clients.get_by_left(&window).copied();
+
+        // A focused window doesn't need to demand attention
+        if urgent && !is_focused {
+            if !self.urgent_windows.contains(&window) {
+                self.urgent_windows.push(window);
+            }
+        } else {
+            self.clear_urgency(window);
+        }
+
+        Ok(())
+    }
+
+    /// Stop tracking the window as urgent.
+    pub(super) fn clear_urgency(&mut self, window: x::Window) {
+        self.urgent_windows.retain(|w| *w != window);
+    }
+
+    /// Is the given window (or frame) urgent? If urgent windows are configured to flash, this returns
+    /// false when the flash is in its "off" state.
+    pub(super) fn should_draw_urgent(&self, window: x::Window) -> bool {
+        self.urgent_windows.contains(&window) && self.urgent_flash_on
+    }
+
+    /// Flash the borders of urgent windows, if configured to.
+    pub(super) fn flash_urgent_windows(&mut self) -> xcb::Result<()> {
+        // Flash the borders of urgent windows on and off
+        let flash_on = match self.config.urgent_flash_interval {
+            Some(interval) if !self.urgent_windows.is_empty() => {
+                let elapsed = Instant::now().duration_since(self.start_time);
+                (elapsed.as_millis() / interval.as_millis().max(1)).is_multiple_of(2)
+            }
+            _ => true,
+        };
+
+        if flash_on != self.urgent_flash_on {
+            self.urgent_flash_on = flash_on;
+            self.render()?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
+use std::cmp;
+
 use xcb::{x, Xid};
 
 use super::masks::MASKS;
+use super::titlebar::Titlebar;
 use super::WindowManager;
+use crate::config::WindowRule;
 use crate::point::Point;
 use crate::ret_ok_if_none;
 use crate::window_geometry::WindowGeometry;
@@ -10,10 +14,10 @@ impl<'a> WindowManager<'a> {
     pub(super) fn get_frame_and_window(&self, target: x::Window) -> Option<(x::Window, x::Window)> {
         if let Some(frame) = self.framed_clients.get_by_left(&target) {
             Some((target, *frame))
-        } else if let Some(window) = self.framed_clients.get_by_right(&target) {
-            Some((*window, target))
         } else {
-            None
+            self.framed_clients
+                .get_by_right(&target)
+                .map(|window| (*window, target))
         }
     }
 
@@ -23,47 +27,141 @@ impl<'a> WindowManager<'a> {
         existed_before_wm: bool,
     ) -> xcb::Result<Option<x::Window>> {
         // Get window attributes
-        let geo = self.conn.wait_for_reply(self.conn.send_request(&x::GetGeometry {
+        self.faults.inject(self.conn, "frame_window.get_geometry")?;
+        let geo = self.conn.send_request(&x::GetGeometry {
             drawable: x::Drawable::Window(window),
-        }))?;
+        });
+        let attrs = existed_before_wm.then(|| self.conn.send_request(&x::GetWindowAttributes { window }));
+        let geo = self.conn.wait_for_reply(geo)?;
 
         // If window was created before window manager started, we should frame
         // it only if it is visible and doesn't set override_redirect.
-        if existed_before_wm {
-            let attrs = self
-                .conn
-                .wait_for_reply(self.conn.send_request(&x::GetWindowAttributes { window }))?;
-            if attrs.override_redirect() || attrs.map_state() != x::MapState::Viewable {
+        if let Some(attrs) = attrs {
+            let attrs = self.conn.wait_for_reply(attrs)?;
+            let viewable = attrs.map_state() == x::MapState::Viewable;
+            if attrs.override_redirect() && viewable {
+                self.override_redirect_windows.insert(window);
+            }
+            if attrs.override_redirect() || !viewable {
                 return Ok(None);
             }
         }
 
+        // Start listening to window events
+        self.faults.inject(self.conn, "frame_window.change_attributes")?;
+        self.conn.send_and_check_request(&x::ChangeWindowAttributes {
+            window,
+            // Which events to capture and send to the event loop
+            value_list: &[x::Cw::EventMask(MASKS.child_window_events)],
+        })?;
+
+        // Now that we'll be told when they change, start reading the window's properties. The rules
+        // need them, so this is done first to avoid waiting on the X server more than once
+        self.start_caching_properties(window);
+        let frame = self.create_frame(window, &geo, existed_before_wm);
+        if frame.is_err() {
+            self.stop_caching_properties(window);
+        }
+
+        frame.map(Some)
+    }
+
+    /// Creates a frame for the window, sized and decorated by the rules which match it, and moves the
+    /// window into it.
+    fn create_frame(
+        &mut self,
+        window: x::Window,
+        geo: &x::GetGeometryReply,
+        existed_before_wm: bool,
+    ) -> xcb::Result<x::Window> {
+        // Rules may change the window's size, but it's still kept within the configured size limits
+        let rules = self.matching_rules(window)?;
+        let requested = match rules.iter().rev().find_map(|rule| rule.size) {
+            Some((width, height)) => {
+                self.record_decision(window, format!("sized to {}x{} by a rule", width, height));
+                (width, height)
+            }
+            None => (geo.width(), geo.height()),
+        };
+        let (width, height) = self.constrain_floating_size(requested.0, requested.1);
+        if (width, height) != requested {
+            self.record_decision(
+                window,
+                format!(
+                    "resized from {}x{} to {}x{} to fit the floating size limits",
+                    requested.0, requested.1, width, height
+                ),
+            );
+        }
+        if (width, height) != (geo.width(), geo.height()) {
+            self.conn.send_and_check_request(&x::ConfigureWindow {
+                window,
+                value_list: &[
+                    x::ConfigWindow::Width(width.into()),
+                    x::ConfigWindow::Height(height.into()),
+                ],
+            })?;
+        }
+
+        if existed_before_wm {
+            self.record_decision(window, "adopted when r3 started");
+        }
+
         // Create frame
         let frame = self.conn.generate_id();
         let root_window = self.get_root_window()?;
+        let titlebar = self.frame_titlebar(&rules);
+        let (frame_width, frame_height) = titlebar.frame_size(width, height);
+        self.faults.inject(self.conn, "frame_window.create_frame")?;
         self.conn.send_and_check_request(&x::CreateWindow {
-            depth: x::COPY_FROM_PARENT as u8,   // TODO: ???
-            visual: x::COPY_FROM_PARENT as u32, // TODO: get from screen.root_visual()
+            depth: x::COPY_FROM_PARENT as u8, // TODO: ???
+            visual: x::COPY_FROM_PARENT,      // TODO: get from screen.root_visual()
             wid: frame,
             parent: root_window,
             x: geo.x(),
             y: geo.y(),
-            width: geo.width(),
-            height: geo.height(),
-            border_width: 10,
+            width: frame_width,
+            height: frame_height,
+            border_width: self.frame_border_width(&rules),
             class: x::WindowClass::CopyFromParent,
             value_list: &[
-                // Frame background color
-                // TODO: can I make this transparent in any way?
-                x::Cw::BackPixel(0x0000ff),
-                // Border pixel colour
-                x::Cw::BorderPixel(0xff0000),
+                // The frame is painted with the right colours for its state once it's rendered
+                self.frame_background(&self.config.colors.unfocused),
+                x::Cw::BorderPixel(self.config.colors.unfocused.border.pixel()),
                 // Which events to capture and send to the event loop
                 // NOTE: we ignore enter events during re-parenting
                 x::Cw::EventMask(MASKS.frame_window_events & !x::EventMask::ENTER_WINDOW),
             ],
         })?;
 
+        // If anything fails once the frame exists (e.g. the client was destroyed before we finished
+        // framing it) then clean up after ourselves so we don't leave an empty frame behind
+        if let Err(e) = self.setup_frame(window, frame, titlebar, &rules, existed_before_wm) {
+            self.forget_window(window);
+            // NOTE: move the client out of the frame first, otherwise destroying the frame destroys it too
+            let _ = self.conn.send_and_check_request(&x::ReparentWindow {
+                window,
+                parent: root_window,
+                x: geo.x(),
+                y: geo.y(),
+            });
+            let _ = self.conn.send_and_check_request(&x::DestroyWindow { window: frame });
+            return Err(e);
+        }
+
+        Ok(frame)
+    }
+
+    /// Configures a newly created frame, re-parents the client window into it, and tiles or places it
+    /// (windows which existed before we started are left where they are, unless they're tiled).
+    fn setup_frame(
+        &mut self,
+        window: x::Window,
+        frame: x::Window,
+        titlebar: Titlebar,
+        rules: &[WindowRule],
+        existed_before_wm: bool,
+    ) -> xcb::Result<()> {
         // Set an atom on our frame to indicate that it is indeed a frame
         self.conn.send_and_check_request(&x::ChangeProperty {
             mode: x::PropMode::Replace,
@@ -73,47 +171,45 @@ impl<'a> WindowManager<'a> {
             data: b"1",
         })?;
 
-        // Start listening to window events
-        self.conn.send_and_check_request(&x::ChangeWindowAttributes {
-            window,
-            // Which events to capture and send to the event loop
-            value_list: &[x::Cw::EventMask(MASKS.child_window_events)],
-        })?;
-
         // Add window to save set
         // TODO: doc why
+        self.faults.inject(self.conn, "frame_window.save_set")?;
         self.conn.send_and_check_request(&x::ChangeSaveSet {
             window,
             mode: x::SetMode::Insert,
         })?;
 
         // Re-parent window into frame
+        self.faults.inject(self.conn, "frame_window.reparent")?;
+        let (x, y) = titlebar.client_offset();
         self.conn.send_and_check_request(&x::ReparentWindow {
             window,
             parent: frame,
             // Offset of client window within frame
-            x: 0,
-            y: 0,
+            x,
+            y,
         })?;
 
+        // Save association b/w window and frame
+        self.framed_clients.insert(window, frame);
+        self.frame_order.push(window);
+        self.titlebars.insert(window, titlebar);
+
+        // Move the frame before it's mapped, so it doesn't appear in one place and then jump to another
+        if !self.tile_window(window, rules)? && !existed_before_wm {
+            self.place_window(window, rules)?;
+        }
+
         // Map frame
+        self.faults.inject(self.conn, "frame_window.map_frame")?;
         self.conn.send_and_check_request(&x::MapWindow { window: frame })?;
 
-        // Save association b/w window and frame
-        self.framed_clients.insert(window, frame);
+        // Let drag and drop sources find the client through its frame
+        self.update_dnd_proxy(window)?;
 
         // Button (mouse) handling
-        self.conn.send_and_check_request(&x::GrabButton {
-            grab_window: window,
-            owner_events: false,
-            event_mask: x::EventMask::BUTTON_PRESS | x::EventMask::BUTTON_RELEASE | x::EventMask::BUTTON_MOTION,
-            pointer_mode: x::GrabMode::Async,
-            keyboard_mode: x::GrabMode::Async,
-            confine_to: root_window,
-            cursor: xcb::Xid::none(),
-            button: x::ButtonIndex::Any,
-            modifiers: x::ModMask::ANY,
-        })?;
+        self.faults.inject(self.conn, "frame_window.grab_button")?;
+        self.grab_buttons(window)?;
 
         // After mapping and re-parenting, configure all the events (including enter window)
         self.conn.send_and_check_request(&x::ChangeWindowAttributes {
@@ -121,7 +217,39 @@ impl<'a> WindowManager<'a> {
             value_list: &[x::Cw::EventMask(MASKS.frame_window_events)],
         })?;
 
-        Ok(Some(frame))
+        Ok(())
+    }
+
+    /// Drop all of our state about a window (and its frame). The only requests made are to free what we
+    /// created for it (e.g. its sync alarm).
+    pub(super) fn forget_window(&mut self, window: x::Window) {
+        self.frame_order.retain(|w| *w != window);
+        if let Some((_, frame)) = self.framed_clients.remove_by_left(&window) {
+            if self.focused_window == Some(frame) {
+                self.focused_window = None;
+            }
+            self.painted_colors.remove(&frame);
+        }
+        if self.focused_window == Some(window) {
+            self.focused_window = None;
+        }
+
+        self.stop_caching_properties(window);
+        self.stop_mirroring(Some(window));
+        self.clear_urgency(window);
+        self.flashing_windows.remove(&window);
+        self.stale_titles.remove(&window);
+        self.decisions.remove(&window);
+        self.geometry_history.remove(&window);
+        self.titlebars.remove(&window);
+        self.focus_history.retain(|w| *w != window);
+        self.forget_sync_request(window);
+        if self.pending_drag.is_some_and(|(w, _, _)| w == window) {
+            self.pending_drag = None;
+        }
+        if self.workspace.remove(window) {
+            self.layout_pending = true;
+        }
     }
 
     pub(super) fn unframe_window(&mut self, target: x::Window) -> xcb::Result<()> {
@@ -129,19 +257,21 @@ impl<'a> WindowManager<'a> {
 
         // If it was the frame that was unmapped, then we don't need to do anything.
         if target == frame {
-            self.framed_clients.remove_by_right(&frame);
-            if self.focused_window == Some(frame) {
-                self.focused_window = None;
-            }
-
+            self.forget_window(window);
             return Ok(());
         }
 
+        // Forget about the window first, so our state stays consistent even if the requests below fail
+        // (e.g. if the frame or client have already been destroyed)
+        self.forget_window(window);
+
         // Unmap frame
+        self.faults.inject(self.conn, "unframe_window.unmap")?;
         self.conn.send_and_check_request(&x::UnmapWindow { window: frame })?;
 
         // Re-parent client window back to root
         // FIXME: when checked this and others below error with BadWindow(3)
+        self.faults.inject(self.conn, "unframe_window.reparent")?;
         self.conn.send_request_checked(&x::ReparentWindow {
             window,
             parent: self.get_root_window()?,
@@ -157,37 +287,21 @@ impl<'a> WindowManager<'a> {
         });
 
         // Destroy the frame
+        self.faults.inject(self.conn, "unframe_window.destroy")?;
         self.conn.send_request_checked(&x::DestroyWindow { window: frame });
 
-        // Drop window->frame association
-        self.framed_clients.remove_by_left(&window);
-
-        // If it was the focused window, remove it
-        if self.focused_window == Some(window) || self.focused_window == Some(frame) {
-            self.focused_window = None;
-        }
-
         self.conn.flush()?;
 
         Ok(())
     }
 
-    fn supports_wm_delete_window(&self, window: x::Window) -> xcb::Result<bool> {
+    fn supports_wm_delete_window(&mut self, window: x::Window) -> xcb::Result<bool> {
         // Check if the window has declared support for WM_DELETE_WINDOW
-        let property = self.conn.wait_for_reply(self.conn.send_request(&x::GetProperty {
-            delete: false,
-            window,
-            property: self.atoms.wm_protocols,
-            r#type: x::ATOM_ATOM,
-            long_offset: 0,
-            long_length: u32::MAX,
-        }))?;
-
-        let protocols = property.value::<x::Atom>();
-        Ok(protocols.contains(&self.atoms.wm_del_window))
+        let properties = self.get_window_properties(window)?;
+        Ok(properties.protocols.contains(&self.atoms.wm_del_window))
     }
 
-    pub(super) fn kill_window(&self, target: x::Window) -> xcb::Result<()> {
+    pub(super) fn kill_window(&mut self, target: x::Window) -> xcb::Result<()> {
         // If the window supports WM_DELETE_WINDOW, then we tell it to exit - when we receive the
         // UnmapNotify event for that window we'll clean up the frame. If the target doesn't support
         // WM_DELETE_WINDOW, then we just destroy the frame itself which will destroy the child window.
@@ -239,7 +353,37 @@ impl<'a> WindowManager<'a> {
         Ok(())
     }
 
+    /// Clamp the size of a floating window to the configured minimum and maximum sizes.
+    pub(super) fn constrain_floating_size(&self, width: u16, height: u16) -> (u16, u16) {
+        let (mut width, mut height) = (width, height);
+        if let Some((max_w, max_h)) = self.config.floating_maximum_size {
+            width = cmp::min(width, max_w);
+            height = cmp::min(height, max_h);
+        }
+        if let Some((min_w, min_h)) = self.config.floating_minimum_size {
+            width = cmp::max(width, min_w);
+            height = cmp::max(height, min_h);
+        }
+
+        (width, height)
+    }
+
     pub(super) fn resize_window(&self, window: x::Window, rect: WindowGeometry) -> xcb::Result<()> {
+        // NOTE: the limits apply to the client window, which doesn't include the titlebar
+        let titlebar = self.titlebar(window);
+        let (w, h) = titlebar.client_size(rect.w, rect.h);
+        let (w, h) = self.constrain_floating_size(w, h);
+        let (frame_w, frame_h) = titlebar.frame_size(w, h);
+
+        self.set_window_rect(window, WindowGeometry::new(rect.x, rect.y, frame_w, frame_h, rect.bw))
+    }
+
+    /// Move and resize the window's frame (or the window, if it isn't framed) to the rect, and fit the
+    /// window into the frame beside its titlebar.
+    pub(super) fn set_window_rect(&self, window: x::Window, rect: WindowGeometry) -> xcb::Result<()> {
+        let titlebar = self.titlebar(window);
+        let (w, h) = titlebar.client_size(rect.w, rect.h);
+
         let mut value_list = vec![
             x::ConfigWindow::X(rect.x.into()),
             x::ConfigWindow::Y(rect.y.into()),
@@ -254,9 +398,13 @@ impl<'a> WindowManager<'a> {
                 value_list: &value_list,
             })?;
 
-            // NOTE: x and y coords are relative to parent window (in this case the frame)
-            value_list[0] = x::ConfigWindow::X(0);
-            value_list[1] = x::ConfigWindow::Y(0);
+            // NOTE: x and y coords are relative to parent window (in this case the frame), and the
+            // window sits beside the titlebar
+            let (x, y) = titlebar.client_offset();
+            value_list[0] = x::ConfigWindow::X(x.into());
+            value_list[1] = x::ConfigWindow::Y(y.into());
+            value_list[2] = x::ConfigWindow::Width(w.into());
+            value_list[3] = x::ConfigWindow::Height(h.into());
         }
 
         // Move window
@@ -280,23 +428,4 @@ impl<'a> WindowManager<'a> {
         let bw = geo.border_width();
         Ok((x, y, w, h, bw).into())
     }
-
-    pub(super) fn window_at_pos(&self, root: x::Window, pos: Point) -> xcb::Result<Option<x::Window>> {
-        let query_tree = self
-            .conn
-            .wait_for_reply(self.conn.send_request(&x::QueryTree { window: root }))?;
-
-        assert_eq!(root, query_tree.root());
-
-        for window in query_tree.children() {
-            // FIXME: check visibility and stacking order, etc - this kills the wrong window if another is placed above it
-            //  this entire function can be removed once we sort out "focus"
-            let win_rect = self.get_window_rect(*window)?;
-            if win_rect.contains(&pos) {
-                return Ok(Some(*window));
-            }
-        }
-
-        Ok(None)
-    }
 }
@@ -1,16 +1,21 @@
 use std::cmp;
+use std::time::Instant;
 
 use xcb::x::{
-    self, ButtonPressEvent, ClientMessageEvent, ConfigureRequestEvent, EnterNotifyEvent, ExposeEvent, FocusInEvent,
-    FocusOutEvent, KeyPressEvent, LeaveNotifyEvent, MapRequestEvent, MotionNotifyEvent, PropertyNotifyEvent,
-    UnmapNotifyEvent,
+    self, ButtonPressEvent, ConfigureNotifyEvent, ConfigureRequestEvent, EnterNotifyEvent, ExposeEvent, FocusInEvent,
+    FocusOutEvent, KeyPressEvent, LeaveNotifyEvent, MapNotifyEvent, MapRequestEvent, MappingNotifyEvent,
+    MotionNotifyEvent, PropertyNotifyEvent, UnmapNotifyEvent,
 };
 use xcb::BaseEvent;
 
+use super::focus::event_time;
+use super::tree::may_change_tree;
 use super::{DragType, WindowManager};
+use crate::bindings::FrameAction;
+use crate::ipc::Reply;
 use crate::point::Point;
 use crate::ret_ok_if_none;
-use crate::window_geometry::Quadrant;
+use crate::window_geometry::{Quadrant, WindowGeometry};
 
 impl<'a> WindowManager<'a> {
     pub fn handle_event(&mut self, event: xcb::Result<xcb::Event>) -> xcb::Result<()> {
@@ -31,17 +36,31 @@ impl<'a> WindowManager<'a> {
             }
         };
 
+        if let Some(time) = event_time(&event) {
+            self.update_event_time(time);
+        }
+        if may_change_tree(&event) {
+            self.tree_generation += 1;
+        }
+
         match event {
             // We received a request to configure a window
             xcb::Event::X(x::Event::ConfigureRequest(ev)) => self.on_configure_request(ev)?,
+            // A window was configured, which only matters to us if it's the root window (the screen)
+            xcb::Event::X(x::Event::ConfigureNotify(ev)) => self.on_configure_notify(ev)?,
             // We received a request to map (render) a window
             xcb::Event::X(x::Event::MapRequest(ev)) => self.on_map_request(ev)?,
             // When a window is unmapped, then we "un-frame" it if we've framed it
             xcb::Event::X(x::Event::UnmapNotify(ev)) => self.on_unmap_notify(ev)?,
+            xcb::Event::X(x::Event::MapNotify(ev)) => self.on_map_notify(ev)?,
+            xcb::Event::X(x::Event::DestroyNotify(ev)) => {
+                self.override_redirect_windows.remove(&ev.window());
+            }
 
             // Handle key events
             xcb::Event::X(x::Event::KeyPress(ev)) => self.on_key_press(ev)?,
             xcb::Event::X(x::Event::KeyRelease(ev)) => self.on_key_release(ev)?,
+            xcb::Event::X(x::Event::MappingNotify(ev)) => self.on_mapping_notify(ev)?,
 
             // Handle mouse events
             xcb::Event::X(x::Event::ButtonPress(ev)) => self.on_button_press(ev)?,
@@ -56,16 +75,25 @@ impl<'a> WindowManager<'a> {
             xcb::Event::X(x::Event::FocusOut(ev)) => self.on_focus_out(ev)?,
             xcb::Event::X(x::Event::PropertyNotify(ev)) => self.on_property_notify(ev)?,
 
-            // Handle client events
-            xcb::Event::X(x::Event::ClientMessage(ev)) => self.on_client_message(ev)?,
+            // Handle selection events (used by the clipboard manager)
+            xcb::Event::X(x::Event::SelectionRequest(ev)) => self.on_selection_request(ev)?,
+            xcb::Event::X(x::Event::SelectionNotify(ev)) => self.on_selection_notify(ev)?,
+            xcb::Event::X(x::Event::SelectionClear(ev)) => self.on_selection_clear(ev)?,
+
+            // Handle extension events
+            xcb::Event::Damage(xcb::damage::Event::Notify(ev)) => self.on_damage_notify(ev)?,
+            xcb::Event::Sync(xcb::sync::Event::AlarmNotify(ev)) => self.on_alarm_notify(ev)?,
+            xcb::Event::RandR(xcb::randr::Event::Notify(ev)) => self.on_randr_notify(ev)?,
+            xcb::Event::Input(xcb::xinput::Event::KeyPress(ev)) => self.on_device_key_press(ev)?,
+            xcb::Event::Input(xcb::xinput::Event::KeyRelease(_)) => {}
+            xcb::Event::Input(xcb::xinput::Event::Hierarchy(ev)) => self.on_hierarchy(ev)?,
+            xcb::Event::Input(xcb::xinput::Event::TouchBegin(ev)) => self.on_touch_begin(ev)?,
+            xcb::Event::Input(xcb::xinput::Event::TouchUpdate(ev)) => self.on_touch_update(ev)?,
+            xcb::Event::Input(xcb::xinput::Event::TouchEnd(ev)) => self.on_touch_end(ev)?,
 
             // Ignored events
             xcb::Event::X(x::Event::ReparentNotify(_)) => {}
             xcb::Event::X(x::Event::CreateNotify(_)) => {}
-            xcb::Event::X(x::Event::DestroyNotify(_)) => {}
-            xcb::Event::X(x::Event::ConfigureNotify(_)) => {}
-            xcb::Event::X(x::Event::MappingNotify(_)) => {}
-            xcb::Event::X(x::Event::MapNotify(_)) => {}
 
             // TODO: handle all events!
             _ => {
@@ -73,44 +101,34 @@ impl<'a> WindowManager<'a> {
             }
         }
 
-        self.render()?;
+        self.refresh()?;
 
         Ok(())
     }
 
-    /**
+    /*
      * X Client Events
      */
 
-    fn on_client_message(&self, ev: ClientMessageEvent) -> xcb::Result<()> {
-        // Send a sync message back
-        if ev.r#type() == self.atoms.r3_sync {
-            eprintln!("R3_SYNC: {:?} ", ev.data());
-            let window = ev.window();
-            self.conn.send_request(&x::SendEvent {
-                propagate: false,
-                destination: x::SendEventDest::Window(window),
-                event_mask: x::EventMask::NO_EVENT,
-                event: &x::ClientMessageEvent::new(window, self.atoms.r3_sync, ev.data()),
-            });
-            self.conn.flush()?;
-            Ok(())
-        } else {
-            Ok(())
-        }
-    }
-
-    /**
+    /*
      * X Window Events
      */
 
     fn on_configure_request(&self, ev: ConfigureRequestEvent) -> xcb::Result<()> {
         let window = ev.window();
-        let value_list = [
+        if self.workspace.contains(window) {
+            return self.refuse_configure_request(window);
+        }
+
+        let (width, height) = match self.framed_clients.contains_left(&window) {
+            true => self.constrain_floating_size(ev.width(), ev.height()),
+            false => (ev.width(), ev.height()),
+        };
+        let mut value_list = [
             x::ConfigWindow::X(ev.x() as i32),
             x::ConfigWindow::Y(ev.y() as i32),
-            x::ConfigWindow::Width(ev.width() as u32),
-            x::ConfigWindow::Height(ev.height() as u32),
+            x::ConfigWindow::Width(width as u32),
+            x::ConfigWindow::Height(height as u32),
             x::ConfigWindow::BorderWidth(ev.border_width() as u32),
             // FIXME: this crashes it when ev.sibling() returns 0
             // x::ConfigWindow::Sibling(ev.sibling()),
@@ -119,10 +137,24 @@ impl<'a> WindowManager<'a> {
 
         // If we've already framed this window, also update the frame
         if let Some(frame_id) = self.framed_clients.get_by_left(&window) {
+            // The frame also contains the titlebar, and keeps its own border
+            let titlebar = self.titlebar(window);
+            let (frame_width, frame_height) = titlebar.frame_size(width, height);
             self.conn.send_and_check_request(&x::ConfigureWindow {
                 window: *frame_id,
-                value_list: &value_list,
+                value_list: &[
+                    x::ConfigWindow::X(ev.x() as i32),
+                    x::ConfigWindow::Y(ev.y() as i32),
+                    x::ConfigWindow::Width(frame_width as u32),
+                    x::ConfigWindow::Height(frame_height as u32),
+                    x::ConfigWindow::StackMode(ev.stack_mode()),
+                ],
             })?;
+
+            // NOTE: x and y coords are relative to parent window (in this case the frame)
+            let (x, y) = titlebar.client_offset();
+            value_list[0] = x::ConfigWindow::X(x.into());
+            value_list[1] = x::ConfigWindow::Y(y.into());
         }
 
         // Pass request straight through to the X server for window
@@ -134,24 +166,57 @@ impl<'a> WindowManager<'a> {
         Ok(())
     }
 
+    fn on_configure_notify(&mut self, ev: ConfigureNotifyEvent) -> xcb::Result<()> {
+        // The screen was resized (e.g. an output was added), so the tiled windows are laid out again
+        if ev.window() == self.get_root_window()? {
+            self.layout_pending = true;
+        }
+
+        Ok(())
+    }
+
     fn on_map_request(&mut self, ev: MapRequestEvent) -> xcb::Result<()> {
         // We ignore all other events generated by this map request, since they're not useful to us
         self.ignored_sequences.add(ev.sequence());
 
         // Re-parent the window with a frame if needed
         let window = ev.window();
-        let frame = self.frame_window(window, false)?;
+        let frame = match self.frame_window(window, false) {
+            Ok(frame) => frame,
+            // If framing failed (e.g. the window was destroyed before we could frame it) then we
+            // carry on and try to map it without a frame
+            Err(xcb::Error::Protocol(e)) => {
+                eprintln!("Failed to frame window {:?}: {:?}", window, e);
+                None
+            }
+            Err(e) => return Err(e),
+        };
 
         // Allow the window to be mapped
-        self.conn.send_and_check_request(&x::MapWindow { window })?;
+        if let Err(e) = self.conn.send_and_check_request(&x::MapWindow { window }) {
+            eprintln!("Failed to map window {:?}: {:?}", window, e);
+            return Ok(());
+        }
 
         // Focus the newly mapped window or frame if one was created
-        self.focused_window = frame.or(Some(window));
+        self.set_focus(frame.or(Some(window)))?;
+        self.record_decision(window, "focused when it was mapped");
+
+        Ok(())
+    }
+
+    fn on_map_notify(&mut self, ev: MapNotifyEvent) -> xcb::Result<()> {
+        // Override-redirect windows aren't managed, but we keep track of them to know if menus are open
+        if ev.override_redirect() && ev.event() == self.get_root_window()? {
+            self.override_redirect_windows.insert(ev.window());
+        }
 
         Ok(())
     }
 
     fn on_unmap_notify(&mut self, ev: UnmapNotifyEvent) -> xcb::Result<()> {
+        self.override_redirect_windows.remove(&ev.window());
+
         // We ignore all EnterNotify events that have the same sequence as an UnmapNotify event, since
         // they're not useful for us (and are indistinguishable from user EnterNotify events).
         self.ignored_sequences
@@ -163,31 +228,33 @@ impl<'a> WindowManager<'a> {
             return Ok(());
         }
 
-        self.unframe_window(ev.window())?;
+        match self.unframe_window(ev.window()) {
+            // The window (or its frame) may have already been destroyed, which is fine since we've
+            // already stopped tracking it
+            Err(xcb::Error::Protocol(e)) => eprintln!("Failed to unframe window {:?}: {:?}", ev.window(), e),
+            result => result?,
+        }
 
         Ok(())
     }
 
-    /**
+    /*
      * Key Events
      */
 
-    // TODO: remove hardcoded values when configuration is available
     fn on_key_press(&mut self, ev: KeyPressEvent) -> xcb::Result<()> {
-        // CTRL + SHIFT + Q - kill window manager
-        // TODO: this has to be fired on a window
-        if ev.state().contains(x::KeyButMask::CONTROL | x::KeyButMask::SHIFT) && ev.detail() == 0x18 {
-            self.ev_queue.lock().unwrap().push(r3lib::R3Command::Exit);
-            self.ev_waker.wake().unwrap();
+        // While picking a window, all keys are swallowed and Escape cancels
+        if self.pick.is_some() {
+            if self.escape_keycodes.contains(&ev.detail()) {
+                self.cancel_pick()?;
+            }
             return Ok(());
         }
 
-        // TODO: we choose focused window by cursor right now, but that's not right (should be whichever has focus, or is active, etc)
-        if let Some(window) = self.window_at_pos(ev.root(), (ev.root_x(), ev.root_y()).into())? {
-            // CTRL + Q (on qwerty) - kill window
-            if ev.state().contains(x::KeyButMask::CONTROL) && ev.detail() == 0x18 {
-                self.kill_window(window)?;
-            }
+        // Bound commands are run by the event loop, just like commands from IPC clients
+        if let Some(command) = self.bound_command(ev.detail(), ev.state()) {
+            self.ev_queue.lock().unwrap().push(command.clone(), Reply::none());
+            self.ev_waker.wake().unwrap();
         }
 
         Ok(())
@@ -197,23 +264,65 @@ impl<'a> WindowManager<'a> {
         Ok(())
     }
 
-    /**
+    fn on_mapping_notify(&mut self, ev: MappingNotifyEvent) -> xcb::Result<()> {
+        // The keyboard changed, so the keys which type our bindings may have too
+        match ev.request() {
+            x::Mapping::Keyboard | x::Mapping::Modifier => self.grab_bindings(),
+            x::Mapping::Pointer => Ok(()),
+        }
+    }
+
+    /*
      * Mouse Events
      */
 
     fn on_button_press(&mut self, ev: ButtonPressEvent) -> xcb::Result<()> {
+        // If we're picking a window, the pointer is grabbed on the root window and `child` is the
+        // top-level window which was clicked
+        if self.pick.is_some() {
+            return self.on_pick(ev.child());
+        }
+
         let target = ev.event();
+        if target == self.get_root_window()? {
+            return self.on_root_click(ev);
+        }
         let (window, frame) = ret_ok_if_none!(self.get_frame_and_window(target));
 
-        // Start a drag if Ctrl is pressed
-        // TODO: configurable modifier
-        if ev.state().contains(x::KeyButMask::CONTROL) || target == frame {
+        // Clicks on the frame do what they're bound to (dragging by default), and so do clicks on the
+        // window while the drag modifier is held. Otherwise the pointer is frozen by our grab, and the
+        // click is passed on to the window
+        let action = if target == frame {
+            self.frame_action(window, frame, &ev)?
+        } else if self.drag_modifier_held(ev.state()) {
+            self.drag_action(ev.detail())
+        } else {
+            self.conn.send_and_check_request(&x::AllowEvents {
+                mode: x::Allow::ReplayPointer,
+                time: ev.time(),
+            })?;
+            None
+        };
+
+        let drag_type = match action {
+            // Tiled windows stay where the layout puts them
+            Some(FrameAction::Move | FrameAction::Resize) if self.workspace.contains(window) => None,
+            Some(FrameAction::Move) => Some(DragType::Move),
+            Some(FrameAction::Resize) => Some(DragType::Resize),
+            Some(FrameAction::Command(command)) => {
+                self.run_frame_command(window, command);
+                None
+            }
+            None => None,
+        };
+        if drag_type.is_some() {
+            self.drag_type = drag_type;
             self.drag_start = Some((ev.root_x(), ev.root_y()).into());
             self.drag_start_frame_rect = Some(self.get_window_rect(frame)?);
         }
 
         // Focus and raise window
-        self.focused_window = Some(window);
+        self.set_focus(Some(window))?;
         self.conn.send_and_check_request(&x::ConfigureWindow {
             window: frame,
             value_list: &[x::ConfigWindow::StackMode(x::StackMode::Above)],
@@ -222,7 +331,6 @@ impl<'a> WindowManager<'a> {
         Ok(())
     }
 
-    // TODO: remove hardcoded values when configuration is available
     fn on_motion_notify(&mut self, ev: MotionNotifyEvent) -> xcb::Result<()> {
         let target = ev.event();
         let (window, _) = ret_ok_if_none!(self.get_frame_and_window(target));
@@ -231,61 +339,77 @@ impl<'a> WindowManager<'a> {
         let drag_start_frame_rect = ret_ok_if_none!(self.drag_start_frame_rect);
 
         let delta = Point::new(ev.root_x(), ev.root_y()) - drag_start;
-        let drag_type = ret_ok_if_none!(if ev.state().contains(x::KeyButMask::BUTTON1) {
-            Some(DragType::Move)
-        } else if ev.state().contains(x::KeyButMask::BUTTON3) {
-            Some(DragType::Resize)
-        } else {
-            None
-        });
-
-        match drag_type {
-            DragType::Move => self.move_window(
-                window,
-                (drag_start_frame_rect.x + delta.x, drag_start_frame_rect.y + delta.y).into(),
-            )?,
-            DragType::Resize => self.resize_window(
-                window,
-                match ret_ok_if_none!(drag_start_frame_rect.quadrant(&drag_start)) {
-                    Quadrant::TopLeft => (
-                        drag_start_frame_rect.x + delta.x,
-                        drag_start_frame_rect.y + delta.y,
-                        cmp::max(1, drag_start_frame_rect.w as i32 - delta.x as i32) as u16,
-                        cmp::max(1, drag_start_frame_rect.h as i32 - delta.y as i32) as u16,
-                    ),
-                    Quadrant::TopRight => (
-                        drag_start_frame_rect.x,
-                        drag_start_frame_rect.y + delta.y,
-                        cmp::max(1, drag_start_frame_rect.w as i32 + delta.x as i32) as u16,
-                        cmp::max(1, drag_start_frame_rect.h as i32 - delta.y as i32) as u16,
-                    ),
-                    Quadrant::BottomLeft => (
-                        drag_start_frame_rect.x + delta.x,
-                        drag_start_frame_rect.y,
-                        cmp::max(1, drag_start_frame_rect.w as i32 - delta.x as i32) as u16,
-                        cmp::max(1, drag_start_frame_rect.h as i32 + delta.y as i32) as u16,
-                    ),
-                    Quadrant::BottomRight => (
-                        drag_start_frame_rect.x,
-                        drag_start_frame_rect.y,
-                        cmp::max(1, drag_start_frame_rect.w as i32 + delta.x as i32) as u16,
-                        cmp::max(1, drag_start_frame_rect.h as i32 + delta.y as i32) as u16,
-                    ),
-                }
-                .into(),
-            )?,
-        }
+        let drag_type = ret_ok_if_none!(self.drag_type);
+
+        // The new rect of the frame being dragged
+        let (x, y, w, h) = match drag_type {
+            DragType::Move => (
+                drag_start_frame_rect.x + delta.x,
+                drag_start_frame_rect.y + delta.y,
+                drag_start_frame_rect.w,
+                drag_start_frame_rect.h,
+            ),
+            DragType::Resize => match ret_ok_if_none!(drag_start_frame_rect.quadrant(&drag_start)) {
+                Quadrant::TopLeft => (
+                    drag_start_frame_rect.x + delta.x,
+                    drag_start_frame_rect.y + delta.y,
+                    cmp::max(1, drag_start_frame_rect.w as i32 - delta.x as i32) as u16,
+                    cmp::max(1, drag_start_frame_rect.h as i32 - delta.y as i32) as u16,
+                ),
+                Quadrant::TopRight => (
+                    drag_start_frame_rect.x,
+                    drag_start_frame_rect.y + delta.y,
+                    cmp::max(1, drag_start_frame_rect.w as i32 + delta.x as i32) as u16,
+                    cmp::max(1, drag_start_frame_rect.h as i32 - delta.y as i32) as u16,
+                ),
+                Quadrant::BottomLeft => (
+                    drag_start_frame_rect.x + delta.x,
+                    drag_start_frame_rect.y,
+                    cmp::max(1, drag_start_frame_rect.w as i32 - delta.x as i32) as u16,
+                    cmp::max(1, drag_start_frame_rect.h as i32 + delta.y as i32) as u16,
+                ),
+                Quadrant::BottomRight => (
+                    drag_start_frame_rect.x,
+                    drag_start_frame_rect.y,
+                    cmp::max(1, drag_start_frame_rect.w as i32 + delta.x as i32) as u16,
+                    cmp::max(1, drag_start_frame_rect.h as i32 + delta.y as i32) as u16,
+                ),
+            },
+        };
+        let rect = WindowGeometry::new(x, y, w, h, drag_start_frame_rect.bw);
+
+        // Motion events arrive far more often than we need to update the window, so we only keep the
+        // latest position here and it's applied once per update interval (see `tick`)
+        self.pending_drag = Some((window, drag_type, rect));
 
         Ok(())
     }
 
-    fn on_button_release(&mut self, _ev: ButtonPressEvent) -> xcb::Result<()> {
+    fn on_button_release(&mut self, ev: ButtonPressEvent) -> xcb::Result<()> {
+        self.drag_type = None;
         self.drag_start = None;
-        self.drag_start_frame_rect = None;
+        let drag_start_frame_rect = self.drag_start_frame_rect.take();
+
+        // Make sure the final position of the drag is applied
+        self.apply_pending_drag(true)?;
+        self.stop_sync_request();
+
+        // If we were dragging an outline, now's the time to actually move the window
+        if let Some((window, drag_type, rect)) = self.end_drag_outline()? {
+            self.apply_drag(window, drag_type, rect)?;
+        }
+
+        // Remember where the window was, so the drag can be undone
+        if let (Some(rect), Some((window, frame))) = (drag_start_frame_rect, self.get_frame_and_window(ev.event())) {
+            if self.get_window_rect(frame)? != rect {
+                self.remember_geometry(window, rect);
+            }
+        }
+
         Ok(())
     }
 
-    /**
+    /*
      * Window Events
      */
 
@@ -299,18 +423,44 @@ impl<'a> WindowManager<'a> {
         };
 
         if self.config.focus_follows_mouse {
+            // Crossing events caused by grabs (e.g. when a menu closes) don't mean the pointer moved,
+            // and focusing another window while a menu is open would close it
+            if ev.mode() != x::NotifyMode::Normal || self.menu_open()? {
+                return Ok(());
+            }
+
             let target = ev.event();
-            self.focused_window = Some(*self.framed_clients.get_by_right(&target).unwrap_or(&target));
+            if self.config.focus_follows_mouse_delay.is_zero() {
+                self.set_focus(Some(target))?;
+            } else {
+                self.hover_focus = Some((target, Instant::now()));
+            }
         }
 
         Ok(())
     }
 
-    fn on_leave_notify(&self, _ev: LeaveNotifyEvent) -> xcb::Result<()> {
+    fn on_leave_notify(&mut self, ev: LeaveNotifyEvent) -> xcb::Result<()> {
+        // The pointer didn't stay on the window for long enough to focus it (moving onto the client
+        // inside the frame doesn't count as leaving it)
+        let left = ev.detail() != x::NotifyDetail::Inferior;
+        if left && matches!(self.hover_focus, Some((window, _)) if window == ev.event()) {
+            self.hover_focus = None;
+        }
+
         Ok(())
     }
 
-    fn on_expose(&self, _ev: ExposeEvent) -> xcb::Result<()> {
+    fn on_expose(&self, ev: ExposeEvent) -> xcb::Result<()> {
+        // Only redraw once the last expose event in a series arrives
+        if ev.count() != 0 {
+            return Ok(());
+        }
+
+        if let Some(window) = self.framed_clients.get_by_right(&ev.window()) {
+            self.draw_titlebar(*window, ev.window())?;
+        }
+
         Ok(())
     }
 
@@ -322,7 +472,28 @@ impl<'a> WindowManager<'a> {
         Ok(())
     }
 
-    fn on_property_notify(&self, _ev: PropertyNotifyEvent) -> xcb::Result<()> {
+    fn on_property_notify(&mut self, ev: PropertyNotifyEvent) -> xcb::Result<()> {
+        // Track windows which demand attention
+        if ev.atom() == x::ATOM_WM_HINTS && self.framed_clients.contains_left(&ev.window()) {
+            self.update_urgency(ev.window())?;
+        }
+
+        // Keep our cached properties up to date
+        if let Some(property) = self.cached_property(ev.atom()) {
+            self.refresh_cached_property(ev.window(), property);
+        }
+
+        // Keep the frame's drag and drop properties in sync with the client's
+        if ev.atom() == self.atoms.xdnd_aware || ev.atom() == self.atoms.xdnd_proxy {
+            self.update_dnd_proxy(ev.window())?;
+        }
+
+        // Redraw the titlebar when a window's title changes (which happens when we next render)
+        let is_title = ev.atom() == x::ATOM_WM_NAME || ev.atom() == self.atoms.net_wm_name;
+        if is_title && self.framed_clients.contains_left(&ev.window()) {
+            self.stale_titles.insert(ev.window());
+        }
+
         Ok(())
     }
 }
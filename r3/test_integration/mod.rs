@@ -1,5 +1,13 @@
 use x_test_runner::XTestRunner;
 
+mod test_clipboard;
+mod test_config;
+mod test_events;
+mod test_exec;
+mod test_faults;
+mod test_ipc;
+mod test_real_apps;
+mod test_tiling;
 mod test_window;
 mod x_test_runner;
 
@@ -12,7 +20,42 @@ macro_rules! wm_test {
     ($name:ident, $func:expr) => {
         #[test]
         fn $name() {
-            $func(crate::X_TEST_RUNNER.test());
+            $func($crate::X_TEST_RUNNER.test());
+        }
+    };
+    ($name:ident, env = [$(($key:expr, $value:expr)),* $(,)?], $func:expr) => {
+        #[test]
+        fn $name() {
+            $func($crate::X_TEST_RUNNER.test_with_env(&[$(($key, $value)),*]));
+        }
+    };
+    ($name:ident, config = $config:expr, $func:expr) => {
+        #[test]
+        fn $name() {
+            $func($crate::X_TEST_RUNNER.test_with_config($config));
+        }
+    };
+}
+
+/// Like `wm_test!`, but only runs when `TEST_ENABLE_REAL_APPS` is set and all the listed
+/// programs can be found in `$PATH`.
+#[macro_export]
+macro_rules! wm_app_test {
+    ($name:ident, [$($program:expr),+], $func:expr) => {
+        #[test]
+        fn $name() {
+            if std::env::var("TEST_ENABLE_REAL_APPS").is_err() {
+                eprintln!("skipping: TEST_ENABLE_REAL_APPS is not set");
+                return;
+            }
+            $(
+                if which::which($program).is_err() {
+                    eprintln!("skipping: {} was not found", $program);
+                    return;
+                }
+            )+
+
+            $func($crate::X_TEST_RUNNER.test());
         }
     };
 }
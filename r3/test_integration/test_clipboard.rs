@@ -0,0 +1,20 @@
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+wm_test!(
+    keeps_clipboard_after_owner_closes,
+    config = "clipboard_manager = true",
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        assert!(t.save_clipboard(&w, "hello"));
+        w.close();
+
+        let other = t.open_window((0, 0, 100, 100));
+        assert_eq!(Some("hello".to_string()), t.read_clipboard(&other));
+    }
+);
+
+wm_test!(no_clipboard_manager_by_default, |t: XTestCase| {
+    let w = t.open_window((0, 0, 100, 100));
+    assert!(!t.save_clipboard(&w, "hello"));
+});
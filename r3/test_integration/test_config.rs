@@ -0,0 +1,702 @@
+use std::env;
+use std::process::{Command, Output};
+use std::thread;
+use std::time::Duration;
+
+use r3lib::{R3Command, WMCommand};
+
+use crate::wm_test;
+use crate::x_test_runner::{XTestCase, XWindow};
+
+wm_test!(
+    reads_border_width_from_config,
+    config = "border_width = 3",
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+
+        assert_eq!(3, w.get_frame().border_width());
+    }
+);
+
+wm_test!(reloads_config, |t: XTestCase| {
+    let w = t.open_window((0, 0, 100, 100));
+    w.map();
+    t.sync();
+    assert_eq!(10, w.get_frame().border_width());
+
+    // Existing frames are updated
+    t.write_config("border_width = 3");
+    assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+    t.sync();
+    assert_eq!(3, w.get_frame().border_width());
+
+    // Invalid configuration is reported, and the current configuration is kept
+    t.write_config("border_width = -1");
+    assert!(!t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+    t.sync();
+    assert_eq!(3, w.get_frame().border_width());
+});
+
+wm_test!(reports_config_paths, |t: XTestCase| {
+    assert_eq!(
+        format!("[\"{}\"]", t.config_path().display()),
+        t.command(R3Command::GetConfigPaths)
+    );
+
+    // Included files are reported too, after the files which include them
+    let include = t.config_path().with_extension("include.toml");
+    std::fs::write(&include, "border_width = 3").unwrap();
+    t.write_config(&format!("include = [\"{}\"]", include.display()));
+    assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+    assert_eq!(
+        format!("[\"{}\",\"{}\"]", t.config_path().display(), include.display()),
+        t.command(R3Command::GetConfigPaths)
+    );
+    let _ = std::fs::remove_file(&include);
+});
+
+wm_test!(
+    gets_and_sets_config_at_runtime,
+    config = "border_width = 3",
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+        // The configuration includes the defaults of everything which wasn't set
+        let get_config = || serde_json::from_str::<serde_json::Value>(&t.command(R3Command::GetConfig)).unwrap();
+        let config = get_config();
+        assert_eq!(3, config["border_width"]);
+        assert_eq!(true, config["focus_follows_mouse"]);
+        assert_eq!("ctrl", config["drag_modifier"]);
+        assert_eq!("#ff0000", config["colors"]["focused"]["border"]);
+        assert_eq!("exit", config["bindings"]["ctrl+shift+q"]);
+
+        // Changes are applied immediately
+        let set = |key: &str, value: &str| {
+            t.run(R3Command::SetConfig {
+                key: key.into(),
+                value: value.into(),
+            })
+        };
+        assert!(set("border_width", "7").success);
+        assert!(set("gaps.inner", "5").success);
+        t.sync();
+        assert_eq!(7, w.get_frame().border_width());
+        let config = get_config();
+        assert_eq!(7, config["border_width"]);
+        assert_eq!(serde_json::json!({ "inner": 5, "outer": 0 }), config["gaps"]);
+
+        // Invalid changes are rejected, and the current configuration is kept
+        let error = |key: &str, value: &str| {
+            let reply = set(key, value);
+            assert!(!reply.success);
+            reply.error.unwrap()
+        };
+        assert!(error("border_widht", "2").contains("unknown field `border_widht`"));
+        assert!(error("border_width", "-1").contains("invalid value"));
+        assert!(error("border_width.inner", "1").contains("`border_width` isn't a table"));
+        assert_eq!(7, w.get_frame().border_width());
+
+        // Reloading the configuration discards the changes
+        assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+        t.sync();
+        assert_eq!(3, w.get_frame().border_width());
+    }
+);
+
+wm_test!(
+    runs_bound_commands,
+    config = r#"
+        [bindings]
+        "ctrl+q" = "close-window"
+    "#,
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+        assert_eq!(1, t.get_all_windows().len());
+
+        // Control_L and Q on a US keyboard
+        t.press_keys(&[0x25, 0x18]);
+        t.wait_for(Duration::from_secs(1), || t.get_all_windows().is_empty().then_some(()));
+    }
+);
+
+wm_test!(
+    runs_commands_bound_to_devices,
+    config = r#"
+        [device_bindings."Virtual core XTEST keyboard"]
+        "ctrl+e" = "close-window"
+
+        [device_bindings."Macro Pad"]
+        "ctrl+w" = "close-window"
+    "#,
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+
+        // Control_L and W on a US keyboard, which is only bound on a device that isn't connected
+        t.press_keys(&[0x25, 0x19]);
+        t.sync();
+        assert_eq!(1, t.get_all_windows().len());
+
+        // Control_L and E, pressed on the XTEST keyboard
+        t.press_keys(&[0x25, 0x1a]);
+        t.wait_for(Duration::from_secs(1), || t.get_all_windows().is_empty().then_some(()));
+    }
+);
+
+wm_test!(
+    applies_window_rules,
+    config = r#"
+        [[rules]]
+        class = "Special"
+        border_width = 0
+        position = [50, 60]
+        size = [70, 80]
+    "#,
+    |t: XTestCase| {
+        let special = t.open_window((0, 0, 100, 100));
+        special.set_class("special", "Special");
+        special.map();
+        let other = t.open_window((0, 0, 100, 100));
+        other.map();
+        t.sync();
+
+        let frame = special.get_frame();
+        assert_eq!(0, frame.border_width());
+        assert_eq!((50, 60), (frame.rect().0, frame.rect().1));
+        assert_eq!((70, 80), (special.rect().2, special.rect().3));
+
+        // Windows which don't match are left alone
+        assert_eq!(10, other.get_frame().border_width());
+        assert_eq!((100, 100), (other.rect().2, other.rect().3));
+    }
+);
+
+wm_test!(
+    places_titlebars_on_configured_edge,
+    config = r#"
+        titlebar = true
+        titlebar_height = 12
+        titlebar_position = "left"
+        [[rules]]
+        class = "Bottom"
+        titlebar_position = "bottom"
+        [[rules]]
+        class = "Bare"
+        titlebar_position = "none"
+    "#,
+    |t: XTestCase| {
+        let left = t.open_window((0, 0, 100, 100));
+        left.map();
+        let bottom = t.open_window((0, 0, 100, 100));
+        bottom.set_class("bottom", "Bottom");
+        bottom.map();
+        let bare = t.open_window((0, 0, 100, 100));
+        bare.set_class("bare", "Bare");
+        bare.map();
+        t.sync();
+
+        // The client sits beside its titlebar, and the frame grows to fit both
+        assert_eq!((12, 0, 100, 100), left.rect());
+        assert_eq!((112, 100), (left.get_frame().rect().2, left.get_frame().rect().3));
+        assert_eq!((0, 0, 100, 100), bottom.rect());
+        assert_eq!((100, 112), (bottom.get_frame().rect().2, bottom.get_frame().rect().3));
+        assert_eq!((0, 0, 100, 100), bare.rect());
+        assert_eq!((100, 100), (bare.get_frame().rect().2, bare.get_frame().rect().3));
+
+        // Frames are rearranged when the position changes
+        t.write_config("titlebar = true\ntitlebar_height = 12");
+        assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+        t.sync();
+        assert_eq!((0, 12, 100, 100), left.rect());
+        assert_eq!((100, 112), (left.get_frame().rect().2, left.get_frame().rect().3));
+    }
+);
+
+wm_test!(
+    draws_titles_sideways_in_side_titlebars,
+    config = r##"
+        border_width = 0
+        titlebar = true
+        titlebar_height = 16
+        titlebar_position = "right"
+        [colors.focused]
+        background = "#0000ff"
+        text = "#ff0000"
+        [colors.unfocused]
+        background = "#0000ff"
+        text = "#ff0000"
+        [[rules]]
+        class = "Left"
+        titlebar_position = "left"
+    "##,
+    |t: XTestCase| {
+        let right = t.open_window((0, 0, 100, 200));
+        right.set_title("WWWW");
+        right.map();
+        let left = t.open_window((300, 0, 100, 200));
+        left.set_title("WWWW");
+        left.set_class("left", "Left");
+        left.map();
+        t.sync();
+
+        // Whether any of the titlebar's pixels between the given distances from the frame's top are
+        // the text's colour
+        let has_text = |w: &XWindow, titlebar_x: i16, from: i16, to: i16| {
+            let (x, y, ..) = w.get_frame().rect();
+            (from..to).any(|dy| (0..16).any(|dx| t.pixel_at(x + titlebar_x + dx, y + dy) == 0xff0000))
+        };
+
+        // The title reads downwards from the top on the right, and upwards from the bottom on the left
+        assert!(has_text(&right, 100, 0, 50));
+        assert!(!has_text(&right, 100, 150, 200));
+        assert!(!has_text(&left, 0, 0, 50));
+        assert!(has_text(&left, 0, 150, 200));
+
+        // Long titles are big enough to be read back through shared memory, which draws them the same
+        let long = t.open_window((600, 0, 100, 1200));
+        long.set_title(&"W".repeat(150));
+        long.map();
+        t.sync();
+        assert!(has_text(&long, 100, 0, 50));
+        assert!(has_text(&long, 100, 1000, 1100));
+    }
+);
+
+wm_test!(
+    runs_commands_bound_to_frame_clicks,
+    config = r#"
+        border_width = 0
+        titlebar = true
+        titlebar_height = 12
+        [frame_bindings]
+        "titlebar+button2" = "close-window"
+    "#,
+    |t: XTestCase| {
+        let clicked = t.open_window((0, 0, 100, 100));
+        clicked.map();
+        let focused = t.open_window((300, 0, 100, 100));
+        focused.map();
+        t.sync();
+
+        // The command acts on the window whose titlebar was clicked, rather than the focused one
+        let (x, y, ..) = clicked.get_frame().rect();
+        t.click((x + 50, y + 6), 2);
+        t.wait_for(Duration::from_secs(1), || {
+            (t.get_all_windows().len() == 1).then_some(())
+        });
+        assert_eq!(focused.get_frame().id, t.get_all_windows()[0].id);
+    }
+);
+
+wm_test!(
+    runs_commands_bound_to_desktop_clicks,
+    config = r#"
+        [root_bindings]
+        "button2" = "close-window"
+    "#,
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+
+        // Clicks on windows go to them, and clicks on the desktop run their bindings
+        t.click((50, 50), 2);
+        t.click((500, 500), 1);
+        t.sync();
+        assert_eq!(1, t.get_all_windows().len());
+        t.click((500, 500), 2);
+        t.wait_for(Duration::from_secs(1), || t.get_all_windows().is_empty().then_some(()));
+    }
+);
+
+wm_test!(
+    matches_window_rules_by_role,
+    config = r#"
+        [[rules]]
+        role = "toolbox"
+        border_width = 0
+    "#,
+    |t: XTestCase| {
+        let toolbox = t.open_window((0, 0, 100, 100));
+        toolbox.set_class("gimp", "Gimp");
+        toolbox.set_role("toolbox");
+        toolbox.map();
+        let image = t.open_window((0, 0, 100, 100));
+        image.set_class("gimp", "Gimp");
+        image.set_role("image-window");
+        image.map();
+        t.sync();
+
+        assert_eq!(0, toolbox.get_frame().border_width());
+        assert_eq!(10, image.get_frame().border_width());
+    }
+);
+
+wm_test!(
+    paints_frames_with_configured_colors,
+    config = r##"
+        [colors.focused]
+        border = "#00ff00"
+    "##,
+    |t: XTestCase| {
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        t.sync();
+        assert_eq!(0x00ff00, t.pixel_at(1, 1));
+
+        // Colours which aren't configured keep their default
+        let b = t.open_window((200, 200, 100, 100));
+        b.map();
+        t.sync();
+        assert_eq!(0xaaaaaa, t.pixel_at(1, 1));
+        assert_eq!(0x00ff00, t.pixel_at(201, 201));
+    }
+);
+
+wm_test!(
+    paints_root_with_configured_background,
+    config = r##"root_background = "#336699""##,
+    |t: XTestCase| {
+        t.sync();
+        assert_eq!(0x336699, t.pixel_at(10, 10));
+
+        // The new colour is painted when the configuration changes
+        t.command(R3Command::SetConfig {
+            key: "root_background".into(),
+            value: "#000080".into(),
+        });
+        t.sync();
+        assert_eq!(0x000080, t.pixel_at(10, 10));
+    }
+);
+
+wm_test!(
+    paces_rendering,
+    config = r#"render_interval = "500ms""#,
+    |t: XTestCase| {
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        let b = t.open_window((200, 200, 100, 100));
+        b.map();
+        t.sync();
+        thread::sleep(Duration::from_millis(600));
+
+        // Rendering straight away is fine, since nothing has been drawn for a while
+        t.command(R3Command::WM(WMCommand::FocusLast));
+        t.sync();
+        assert_eq!(0xff0000, t.pixel_at(1, 1));
+
+        // But the next change isn't drawn until the interval has passed
+        t.command(R3Command::WM(WMCommand::FocusLast));
+        t.sync();
+        assert_eq!(0xff0000, t.pixel_at(1, 1));
+        assert_eq!(0xaaaaaa, t.pixel_at(201, 201));
+        thread::sleep(Duration::from_millis(600));
+        t.sync();
+        assert_eq!(0xaaaaaa, t.pixel_at(1, 1));
+        assert_eq!(0xff0000, t.pixel_at(201, 201));
+    }
+);
+
+wm_test!(
+    drags_with_configured_modifier_and_buttons,
+    config = r#"
+        drag_modifier = "alt"
+        drag_move_button = 3
+        drag_resize_button = 1
+    "#,
+    |t: XTestCase| {
+        let w = t.open_window((100, 100, 50, 50));
+        w.map();
+        t.sync();
+        let (x, y, _, _) = w.get_frame().rect();
+
+        // The old modifier doesn't do anything (Control_L on a US keyboard)
+        t.drag(&[0x25], (110, 110), (160, 170), 3);
+        t.sync();
+        assert_eq!((x, y), (w.get_frame().rect().0, w.get_frame().rect().1));
+
+        // Alt_L on a US keyboard
+        t.drag(&[0x40], (110, 110), (160, 170), 3);
+        t.sync();
+        assert_eq!((x + 50, y + 60), (w.get_frame().rect().0, w.get_frame().rect().1));
+    }
+);
+
+wm_test!(
+    reloading_does_not_move_focus_to_window_under_pointer,
+    config = "border_width = 1",
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 100, 100));
+        w.map();
+        t.sync();
+        t.move_pointer(150, 50);
+        t.sync();
+        assert_eq!(None, t.get_active_window());
+
+        // The frame grows until it's under the pointer
+        t.write_config("border_width = 60");
+        t.command(R3Command::WM(WMCommand::ReloadConfig));
+        t.sync();
+        assert_eq!(None, t.get_active_window());
+    }
+);
+
+/// Run `r3 --check-config` with the given configuration (this doesn't need an X server).
+fn check_config(name: &str, config: &str) -> Output {
+    let path = env::temp_dir().join(format!("r3-check-config.{}.toml", name));
+    std::fs::write(&path, config).unwrap();
+    let output = Command::new(env::current_dir().unwrap().join("../target/debug/r3"))
+        .arg("--check-config")
+        .arg("--config")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    output
+}
+
+#[test]
+fn check_config_accepts_valid_config() {
+    let output = check_config(
+        "valid",
+        r##"
+            border_width = 2
+            frame_background = "parent_relative"
+            [gaps]
+            inner = 5
+            [output_hooks]
+            panel_off = "systemctl suspend"
+            [colors.urgent]
+            border = "#ff8800"
+            [bindings]
+            "mod+Return" = "exec alacritty -e vim"
+            "ctrl+shift+q" = "exit"
+            "ctrl+plus" = "exec xbacklight -inc 10"
+            [gestures]
+            "3-finger-swipe-left" = "focus-last"
+            [frame_bindings]
+            "titlebar+button2" = "close-window"
+            "corner+button1" = "resize"
+            [root_bindings]
+            "button2" = "exec rofi -show run"
+            "mod+button4" = "focus-last"
+        "##,
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn check_config_rejects_invalid_config() {
+    for (name, config, error) in [
+        ("unknown", "border_widht = 2", "unknown field `border_widht`"),
+        ("color", "[colors.focused]\nborder = \"red\"", "expected a colour"),
+        ("button", "drag_move_button = 9", "mouse buttons are numbered 1 to 5"),
+        ("modifier", "drag_modifier = \"hyper\"", "unknown modifier"),
+        (
+            "num lock",
+            "[bindings]\n\"mod2+q\" = \"exit\"",
+            "unknown modifier \"mod2\"",
+        ),
+        ("keysym", "[bindings]\n\"ctrl+nope\" = \"exit\"", "unknown key \"nope\""),
+        ("command", "[bindings]\n\"ctrl+q\" = \"nope\"", "invalid command"),
+        (
+            "gesture",
+            "[gestures]\n\"3-finger-pinch\" = \"exit\"",
+            "expected a gesture",
+        ),
+        (
+            "fingers",
+            "[gestures]\n\"1-finger-swipe-up\" = \"exit\"",
+            "2 to 5 fingers",
+        ),
+        (
+            "frame",
+            "[frame_bindings]\n\"tab+button1\" = \"move\"",
+            "unknown part of a frame",
+        ),
+        (
+            "frame button",
+            "[frame_bindings]\n\"titlebar+button9\" = \"move\"",
+            "expected button1 to button5",
+        ),
+        (
+            "root",
+            "[root_bindings]\n\"hyper+button1\" = \"exit\"",
+            "unknown modifier \"hyper\"",
+        ),
+        (
+            "conflict",
+            "[bindings]\n\"ctrl+q\" = \"exit\"\n\"Control+Q\" = \"close-window\"",
+            "bound to the same keys",
+        ),
+    ] {
+        let output = check_config(name, config);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{}", name);
+        assert!(stderr.contains(error), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn check_config_expands_variables_and_includes_files() {
+    let include_path = env::temp_dir().join("r3-check-config.included.toml");
+    std::fs::write(
+        &include_path,
+        "[variables]\nmod = \"ctrl\"\n[bindings]\n\"ctrl+q\" = \"exit\"",
+    )
+    .unwrap();
+    let include = format!("include = [{:?}]\n", include_path);
+
+    // Variables from included files can be used, and bindings from both files are merged
+    let output = check_config(
+        "include",
+        &format!("{}[bindings]\n\"$mod+w\" = \"exec echo $HOME\"", include),
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = check_config(
+        "include-conflict",
+        &format!("{}[bindings]\n\"$mod+Q\" = \"close-window\"", include),
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bound to the same keys"));
+    let _ = std::fs::remove_file(&include_path);
+
+    let output = check_config("include-cycle", "include = [\"r3-check-config.include-cycle.toml\"]");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("includes itself"));
+
+    // Files which include themselves by another path are caught too
+    let temp_dir = env::temp_dir().canonicalize().unwrap();
+    let output = check_config(
+        "include-alias",
+        &format!(
+            "include = [\"../{}/r3-check-config.include-alias.toml\"]",
+            temp_dir.file_name().unwrap().to_string_lossy()
+        ),
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("includes itself"));
+
+    // Mistakes in included files are reported with the file and line they're on
+    std::fs::write(&include_path, "border_width = 1\n[gaps]\ninner = \"wide\"").unwrap();
+    let output = check_config("include-error", &include);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("Failed to parse {}", include_path.display())) && stderr.contains("line 3"),
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_file(&include_path);
+}
+
+#[test]
+fn layers_system_and_user_configs() {
+    let dir = env::temp_dir().join("r3-layered-config");
+    let write = |name: &str, config: &str| {
+        let path = dir.join(name).join("r3/config.toml");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, config).unwrap();
+        path
+    };
+    let run = || {
+        Command::new(env::current_dir().unwrap().join("../target/debug/r3"))
+            .arg("--check-config")
+            .env("XDG_CONFIG_HOME", dir.join("home"))
+            .env(
+                "XDG_CONFIG_DIRS",
+                format!("{}:{}", dir.join("high").display(), dir.join("low").display()),
+            )
+            .output()
+            .unwrap()
+    };
+
+    // Files are read from the lowest priority to the highest
+    let low = write("low", "[bindings]\n\"ctrl+q\" = \"exit\"");
+    let high = write("high", "border_width = 3");
+    let home = write("home", "border_width = 4");
+    let output = run();
+    assert!(output.status.success());
+    assert_eq!(
+        format!(
+            "Read {}\nRead {}\nRead {}\nConfiguration is valid\n",
+            low.display(),
+            high.display(),
+            home.display()
+        ),
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    // Tables are merged, so the user's bindings are added to the system's
+    write("home", "[bindings]\n\"Control+Q\" = \"close-window\"");
+    let output = run();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bound to the same keys"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn imports_i3_config() {
+    let path = env::temp_dir().join("r3-import-i3.config");
+    std::fs::write(
+        &path,
+        r##"
+# i3 config file (v4)
+set $mod Mod4
+font pango:DejaVu Sans Mono 8
+floating_modifier $mod
+default_border pixel 3
+focus_follows_mouse no
+
+exec --no-startup-id nm-applet
+bindsym $mod+Return exec i3-sensible-terminal
+bindsym $mod+Shift+q kill
+bindsym $mod+Shift+r restart
+bindsym $mod+h focus left
+
+client.focused #4c7899 #285577 #ffffff #2e9ef4 #285577
+
+mode "resize" {
+    bindsym h resize shrink width 10 px or 10 ppt
+}
+"##,
+    )
+    .unwrap();
+    let output = Command::new(env::current_dir().unwrap().join("../target/debug/r3"))
+        .arg("--import-i3")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success());
+
+    let config = String::from_utf8_lossy(&output.stdout);
+    for expected in [
+        "drag_modifier = \"$mod\"",
+        "border_width = 3",
+        "focus_follows_mouse = false",
+        "autostart = [\"nm-applet\"]",
+        "\"$mod+Return\" = \"exec i3-sensible-terminal\"",
+        "\"$mod+Shift+q\" = \"close-window\"",
+        "mod = \"Mod4\"",
+        "border = \"#285577\"",
+    ] {
+        assert!(config.contains(expected), "{}: {}", expected, config);
+    }
+
+    // Anything which couldn't be translated is reported
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 13: `bindsym $mod+h focus left`"), "{}", stderr);
+    assert!(stderr.contains("line 17: `mode \"resize\" {`"), "{}", stderr);
+    assert!(!stderr.contains("resize shrink"), "{}", stderr);
+
+    // And the translated config is valid
+    let output = check_config("imported", &config);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
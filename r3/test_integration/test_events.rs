@@ -0,0 +1,94 @@
+use r3lib::ipc::{self, CommandReply};
+use r3lib::{parse_commands, OutputState, R3Event};
+
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+wm_test!(
+    sends_idle_and_resume_events,
+    config = r#"idle_thresholds = ["200ms", "100ms"]"#,
+    |t: XTestCase| {
+        let mut events = t.subscribe();
+
+        // Thresholds are passed in order, no matter how they're configured
+        assert_eq!(Some(R3Event::Idle { threshold: 100 }), events.next());
+        assert_eq!(Some(R3Event::Idle { threshold: 200 }), events.next());
+
+        t.move_pointer(10, 10);
+        assert_eq!(Some(R3Event::Resume), events.next());
+    }
+);
+
+wm_test!(sends_ticks_to_subscribers, |t: XTestCase| {
+    let mut events = t.subscribe();
+    let mut other = t.subscribe();
+
+    // The tick is run after the commands queued before it
+    let chain = parse_commands("set-config border_width 4; send-tick done").unwrap();
+    let mut c = t.connect();
+    ipc::send(&mut c, &chain).unwrap();
+    let replies: Vec<CommandReply> = ipc::recv(&mut c).unwrap().unwrap();
+    assert!(replies.iter().all(|reply| reply.success));
+
+    let tick = R3Event::Tick { payload: "done".into() };
+    assert_eq!(Some(tick.clone()), events.next());
+    assert_eq!(Some(tick), other.next());
+});
+
+#[test]
+fn reports_output_and_panel_changes() {
+    let state = |name: &str, connected, enabled| OutputState {
+        name: name.into(),
+        connected,
+        enabled,
+    };
+    let events = |new: &OutputState, old: Option<&OutputState>| new.events_since(old);
+    let output = || "eDP-1".to_string();
+
+    // Outputs which appear already connected are reported, and so are panels which are on
+    let panel = state("eDP-1", true, true);
+    assert_eq!(
+        vec![
+            R3Event::OutputConnected { output: output() },
+            R3Event::PanelOn { output: output() },
+        ],
+        events(&panel, None)
+    );
+    assert_eq!(Vec::<R3Event>::new(), events(&panel, Some(&panel)));
+
+    // Closing the lid turns the panel off without disconnecting it
+    let lid_closed = state("eDP-1", true, false);
+    assert_eq!(
+        vec![R3Event::PanelOff { output: output() }],
+        events(&lid_closed, Some(&panel))
+    );
+    assert_eq!(
+        vec![R3Event::PanelOn { output: output() }],
+        events(&panel, Some(&lid_closed))
+    );
+
+    // Disconnecting a panel which is on turns it off too
+    let disconnected = state("eDP-1", false, true);
+    assert_eq!(
+        vec![
+            R3Event::OutputDisconnected { output: output() },
+            R3Event::PanelOff { output: output() },
+        ],
+        events(&disconnected, Some(&panel))
+    );
+
+    // Other outputs are never panels
+    let monitor = state("HDMI-1", true, true);
+    assert_eq!(
+        vec![R3Event::OutputConnected {
+            output: "HDMI-1".into()
+        }],
+        events(&monitor, None)
+    );
+    assert_eq!(
+        vec![R3Event::OutputDisconnected {
+            output: "HDMI-1".into()
+        }],
+        events(&state("HDMI-1", false, false), Some(&monitor))
+    );
+}
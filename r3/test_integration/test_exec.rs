@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use r3lib::ipc::CommandReply;
+use r3lib::{R3Command, WMCommand};
+
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+wm_test!(exec_runs_command, env = [("R3_TEST_VAR", "1")], |t: XTestCase| {
+    let path = PathBuf::from(format!("/tmp/r3-exec-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // The command shouldn't see r3's environment variables, but should see its display
+    let command = format!("echo \"$DISPLAY ${{R3_TEST_VAR:-unset}}\" > {}", path.display());
+    let reply = t.run(R3Command::WM(WMCommand::Exec { command }));
+    assert_eq!(CommandReply::ok(vec![]), reply);
+
+    let output = t.wait_for(Duration::from_secs(5), || {
+        std::fs::read_to_string(&path).ok().filter(|s| s.ends_with('\n'))
+    });
+    let _ = std::fs::remove_file(&path);
+    assert!(output.trim().ends_with(" unset"), "unexpected output: {:?}", output);
+    assert!(output.starts_with(':'), "unexpected output: {:?}", output);
+});
+
+wm_test!(exec_advertises_socket_path, |t: XTestCase| {
+    let path = PathBuf::from(format!("/tmp/r3-exec-socket-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let command = format!("echo \"$R3SOCK\" > {}", path.display());
+    assert!(t.run(R3Command::WM(WMCommand::Exec { command })).success);
+
+    let output = t.wait_for(Duration::from_secs(5), || {
+        std::fs::read_to_string(&path).ok().filter(|s| s.ends_with('\n'))
+    });
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(format!("{}\n", t.get_socket_path()), output);
+});
+
+wm_test!(
+    autostart_runs_commands,
+    config = &format!(
+        "autostart = [\"echo started > /tmp/r3-autostart-test-{}\"]",
+        std::process::id()
+    ),
+    |t: XTestCase| {
+        let path = PathBuf::from(format!("/tmp/r3-autostart-test-{}", std::process::id()));
+        let output = t.wait_for(Duration::from_secs(5), || {
+            std::fs::read_to_string(&path).ok().filter(|s| s.ends_with('\n'))
+        });
+        let _ = std::fs::remove_file(&path);
+        assert_eq!("started\n", output);
+    }
+);
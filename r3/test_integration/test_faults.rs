@@ -0,0 +1,93 @@
+//! These tests rely on r3 being built with the `debug` feature, since that's what enables the
+//! `R3_FAULTS` environment variable.
+
+use r3lib::{R3Command, WMCommand};
+
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+wm_test!(
+    maps_window_without_frame_when_reparent_fails,
+    env = [("R3_FAULTS", "frame_window.reparent=BadWindow")],
+    |mut t: XTestCase| {
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        t.sync();
+
+        // r3 should survive, and the window should still be mapped directly on the root
+        assert!(t.r3_is_running());
+        let windows = t.get_all_windows();
+        assert_eq!(1, windows.len());
+        assert_eq!(w.id, windows[0].id);
+        assert!(!windows[0].is_frame());
+    }
+);
+
+wm_test!(
+    maps_window_without_frame_when_tiling_fails,
+    env = [("R3_FAULTS", "tile_window.get_property=BadWindow")],
+    |mut t: XTestCase| {
+        t.write_config("tiling = true");
+        assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        t.sync();
+
+        // The frame is cleaned up as if framing failed, and the window is still mapped
+        assert!(t.r3_is_running());
+        let windows = t.get_all_windows();
+        assert_eq!(1, windows.len());
+        assert_eq!(w.id, windows[0].id);
+        assert!(!windows[0].is_frame());
+    }
+);
+
+wm_test!(
+    cleans_up_frame_when_grab_fails,
+    env = [("R3_FAULTS", "frame_window.grab_button=BadMatch")],
+    |mut t: XTestCase| {
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        t.sync();
+
+        // The half-created frame should have been destroyed, leaving only the client window
+        assert!(t.r3_is_running());
+        let windows = t.get_all_windows();
+        assert_eq!(1, windows.len());
+        assert_eq!(w.id, windows[0].id);
+    }
+);
+
+wm_test!(
+    survives_failure_when_unframing,
+    env = [("R3_FAULTS", "unframe_window.unmap=BadWindow")],
+    |mut t: XTestCase| {
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        t.sync();
+        assert_eq!(1, t.get_all_windows().len());
+
+        w.close();
+        t.sync();
+        assert!(t.r3_is_running());
+
+        // r3 should have forgotten about the window, so this shouldn't try to close anything
+        t.command(R3Command::WM(WMCommand::CloseWindow));
+        t.sync();
+        assert!(t.r3_is_running());
+    }
+);
+
+wm_test!(
+    handles_delayed_requests,
+    env = [("R3_FAULTS", "frame_window.get_geometry=delay:200")],
+    |mut t: XTestCase| {
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        t.sync();
+
+        assert!(t.r3_is_running());
+        assert!(w.get_frame().is_frame());
+    }
+);
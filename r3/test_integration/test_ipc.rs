@@ -0,0 +1,416 @@
+use std::io::{Cursor, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::time::Duration;
+use std::{env, fs};
+
+use nix::unistd::Uid;
+use r3lib::ipc::{self, CommandReply, Encoding, ErrorReply, Message, VersionReply};
+use r3lib::{parse_commands, Criteria, QueuedCommandInfo, R3Command, R3Event, WMCommand};
+use xcb::Xid;
+
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+#[test]
+fn frames_messages_with_their_length() {
+    let mut buffer = vec![];
+    ipc::send(&mut buffer, &R3Command::GetConfig).unwrap();
+    ipc::write_message(&mut buffer, b"null").unwrap();
+    assert_eq!(&[0, 0, 0, 11], &buffer[..4]);
+
+    let mut reader = Cursor::new(buffer);
+    assert_eq!(Some(R3Command::GetConfig), ipc::recv(&mut reader).unwrap());
+    assert_eq!(Some(b"null".to_vec()), ipc::read_message(&mut reader).unwrap());
+    assert_eq!(None, ipc::read_message(&mut reader).unwrap());
+
+    // Lengths which are too long are rejected rather than read
+    let mut reader = Cursor::new(u32::MAX.to_be_bytes());
+    assert!(ipc::read_message(&mut reader).is_err());
+}
+
+#[test]
+fn encodes_messages_as_cbor() {
+    let mut buffer = vec![];
+    let chain = Message::Chain(vec![R3Command::GetConfig, R3Command::WM(WMCommand::FocusLast)]);
+    let event = R3Event::OutputConnected {
+        output: "HDMI-1".into(),
+    };
+    ipc::send_as(&mut buffer, Encoding::Cbor, &chain).unwrap();
+    ipc::send_as(&mut buffer, Encoding::Cbor, &event).unwrap();
+
+    // Tagged and untagged values are read back as they were written
+    let mut reader = Cursor::new(buffer);
+    assert_eq!(Some(chain), ipc::recv_as(&mut reader, Encoding::Cbor).unwrap());
+    assert_eq!(Some(event), ipc::recv_as(&mut reader, Encoding::Cbor).unwrap());
+}
+
+#[test]
+fn parses_chains_of_commands() {
+    assert_eq!(
+        Ok(vec![
+            R3Command::WM(WMCommand::FocusLast),
+            R3Command::WM(WMCommand::CloseWindow),
+        ]),
+        parse_commands("focus-last; wm close-window;")
+    );
+
+    // `exec` takes the rest of the line, semicolons included
+    assert_eq!(
+        Ok(vec![
+            R3Command::GetConfig,
+            R3Command::WM(WMCommand::Exec {
+                command: "sleep 1; notify-send hi".into()
+            }),
+        ]),
+        parse_commands("get-config; exec sleep 1; notify-send hi")
+    );
+
+    // Errors say which command was wrong
+    let error = parse_commands("focus-last; nope").unwrap_err();
+    assert!(error.starts_with("`nope`: "), "{}", error);
+    assert!(parse_commands(" ").is_err());
+}
+
+#[test]
+fn parses_criteria() {
+    let criteria = Criteria {
+        class: Some("^Firefox$".into()),
+        title: Some("a \"quoted\"; title]".into()),
+        id: Some(0x1234),
+        ..Criteria::default()
+    };
+    assert_eq!(
+        Ok(vec![
+            R3Command::WithCriteria {
+                criteria,
+                command: WMCommand::CloseWindow
+            },
+            R3Command::WM(WMCommand::FocusLast),
+        ]),
+        parse_commands(r#"[class="^Firefox$" title="a \"quoted\"; title]" id=0x1234] close-window; focus-last"#)
+    );
+
+    for (command, error) in [
+        ("[] focus", "no criteria were given"),
+        ("[class=firefox focus", "missing `]`"),
+        ("[shape=round] focus", "unknown criterion `shape`"),
+        ("[class=firefox] get-config", "only window management commands"),
+    ] {
+        let e = command.parse::<R3Command>().unwrap_err();
+        assert!(e.contains(error), "{}: {}", command, e);
+    }
+}
+
+wm_test!(answers_several_commands_per_connection, |t: XTestCase| {
+    let mut c = t.connect();
+    for _ in 0..3 {
+        ipc::send(&mut c, &R3Command::GetConfigPaths).unwrap();
+    }
+
+    let expected = format!("[\"{}\"]", t.config_path().display());
+    for _ in 0..3 {
+        let reply = ipc::read_message(&mut c).unwrap().unwrap();
+        assert_eq!(expected, String::from_utf8(reply).unwrap());
+    }
+});
+
+wm_test!(replies_with_an_error_to_invalid_commands, |t: XTestCase| {
+    let mut c = t.connect();
+    ipc::write_message(&mut c, br#"{"Nope":null}"#).unwrap();
+    let reply: ErrorReply = ipc::recv(&mut c).unwrap().unwrap();
+    assert!(
+        reply.error.starts_with("Invalid command"),
+        "unexpected error: {}",
+        reply.error
+    );
+
+    // The connection can still be used
+    ipc::send(&mut c, &R3Command::GetConfig).unwrap();
+    assert_eq!(Some(b"{}".to_vec()), ipc::read_message(&mut c).unwrap());
+});
+
+/// Run `r3-msg` with the given arguments.
+fn r3_msg(args: &[&str]) -> Output {
+    Command::new(env::current_dir().unwrap().join("../target/debug/r3-msg"))
+        .args(args)
+        .env_remove(ipc::SOCKET_PATH_ENV)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn r3_msg_exits_with_meaningful_status() {
+    // The command is invalid
+    assert_eq!(Some(2), r3_msg(&["-s", "/nonexistent", "nope"]).status.code());
+    assert_eq!(Some(2), r3_msg(&["-s", "/nonexistent"]).status.code());
+
+    // r3 can't be reached
+    assert_eq!(Some(3), r3_msg(&["-s", "/nonexistent", "close-window"]).status.code());
+}
+
+wm_test!(r3_msg_sends_commands, config = "border_width = 3", |t: XTestCase| {
+    let socket = t.get_socket_path();
+
+    // Replies are pretty-printed, unless they're wanted raw
+    let output = r3_msg(&["-s", &socket, "-t", "get_config"]);
+    assert!(output.status.success());
+    assert_eq!("{\n  \"border_width\": 3\n}\n", String::from_utf8_lossy(&output.stdout));
+    let output = r3_msg(&["-s", &socket, "--raw", "get-config"]);
+    assert_eq!("{\"border_width\":3}\n", String::from_utf8_lossy(&output.stdout));
+
+    // Commands can be given as one string, and errors are reported
+    let output = r3_msg(&["-s", &socket, "set-config border_widht 2"]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `border_widht`"));
+});
+
+wm_test!(runs_chains_of_commands, |t: XTestCase| {
+    let set = |key: &str, value: &str| R3Command::SetConfig {
+        key: key.into(),
+        value: value.into(),
+    };
+
+    // Each command is replied to, even if one before it failed
+    let mut c = t.connect();
+    let chain = vec![set("border_width", "5"), set("border_widht", "6"), R3Command::GetConfig];
+    ipc::send(&mut c, &Message::Chain(chain)).unwrap();
+    let replies: Vec<serde_json::Value> = ipc::recv(&mut c).unwrap().unwrap();
+    assert_eq!(3, replies.len(), "{:?}", replies);
+    assert_eq!(serde_json::json!({ "success": true, "windows": [] }), replies[0]);
+    assert_eq!(false, replies[1]["success"], "{:?}", replies);
+    assert!(replies[1]["error"].is_string(), "{:?}", replies);
+    assert_eq!(serde_json::json!({ "border_width": 5 }), replies[2]);
+
+    // Subscribing can't be part of a chain
+    ipc::send(&mut c, &Message::Chain(vec![R3Command::Subscribe])).unwrap();
+    let reply: ErrorReply = ipc::recv(&mut c).unwrap().unwrap();
+    assert!(reply.error.contains("can't be chained"), "{}", reply.error);
+
+    // r3-msg sends chains, and fails if any command in it did
+    let socket = t.get_socket_path();
+    let output = r3_msg(&["-s", &socket, "set-config gaps.inner 2; get-config"]);
+    assert!(output.status.success());
+    let output = r3_msg(&["-s", &socket, "set-config nope 2; get-config"]);
+    assert_eq!(Some(1), output.status.code());
+});
+
+wm_test!(replies_with_version, |t: XTestCase| {
+    let reply: VersionReply = serde_json::from_str(&t.command(R3Command::GetVersion)).unwrap();
+    assert_eq!(env!("CARGO_PKG_VERSION"), reply.version);
+    assert_eq!(ipc::PROTOCOL_VERSION, reply.protocol_version);
+    assert_eq!(vec![t.config_path()], reply.config_paths);
+
+    // r3-msg checks the version before sending its command
+    let output = r3_msg(&["-s", &t.get_socket_path(), "-t", "get_version"]);
+    assert!(output.status.success());
+    let expected = format!("\"protocol_version\": {}", ipc::PROTOCOL_VERSION);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&expected));
+});
+
+/// Send a message over i3's IPC protocol, and read the reply's type and payload
+fn i3_message(c: &mut UnixStream, message_type: u32, payload: &str) -> (u32, serde_json::Value) {
+    c.write_all(b"i3-ipc").unwrap();
+    c.write_all(&(payload.len() as u32).to_ne_bytes()).unwrap();
+    c.write_all(&message_type.to_ne_bytes()).unwrap();
+    c.write_all(payload.as_bytes()).unwrap();
+
+    let mut header = [0; 14];
+    c.read_exact(&mut header).unwrap();
+    assert_eq!(b"i3-ipc", &header[..6]);
+    let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let reply_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut reply = vec![0; len as usize];
+    c.read_exact(&mut reply).unwrap();
+    (reply_type, serde_json::from_slice(&reply).unwrap())
+}
+
+wm_test!(speaks_i3_ipc, |t: XTestCase| {
+    let window = t.open_window((10, 10, 100, 100));
+    window.map();
+    window.set_title("i3 client");
+    t.sync();
+
+    let mut c = UnixStream::connect(t.get_i3_socket_path()).unwrap();
+    c.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // GET_TREE: the window is in a floating container on the (only) workspace
+    let (reply_type, tree) = i3_message(&mut c, 4, "");
+    assert_eq!(4, reply_type);
+    assert_eq!("root", tree["type"]);
+    let workspace = &tree["nodes"][0]["nodes"][0]["nodes"][0];
+    assert_eq!("workspace", workspace["type"]);
+    let con = &workspace["floating_nodes"][0]["nodes"][0];
+    assert_eq!(window.id.resource_id(), con["window"]);
+    assert_eq!("i3 client", con["name"]);
+
+    // GET_WORKSPACES
+    let (_, workspaces) = i3_message(&mut c, 1, "");
+    assert_eq!("1", workspaces[0]["name"]);
+    assert_eq!(true, workspaces[0]["focused"]);
+
+    // RUN_COMMAND: i3 commands with an r3 equivalent, as well as r3 commands
+    let (_, reply) = i3_message(&mut c, 0, "kill");
+    assert_eq!(true, reply[0]["success"]);
+    let (_, reply) = i3_message(&mut c, 0, "focus-last; nope");
+    assert_eq!(false, reply[0]["success"]);
+
+    // SUBSCRIBE: i3 sends a tick straight away
+    let (reply_type, reply) = i3_message(&mut c, 2, r#"["output", "tick"]"#);
+    assert_eq!(2, reply_type);
+    assert_eq!(true, reply["success"]);
+    let mut header = [0; 14];
+    c.read_exact(&mut header).unwrap();
+    assert_eq!(1 << 31 | 7, u32::from_ne_bytes(header[10..14].try_into().unwrap()));
+});
+
+wm_test!(replies_with_the_command_queue, |t: XTestCase| {
+    // Commands are taken from the queue as soon as they arrive, so there's nothing waiting
+    let queue: Vec<QueuedCommandInfo> = serde_json::from_str(&t.command(R3Command::GetQueue)).unwrap();
+    assert_eq!(Vec::<QueuedCommandInfo>::new(), queue);
+
+    // In a chain it's run by the event loop, which has already taken the rest of the chain
+    let mut c = t.connect();
+    let chain = parse_commands("get-queue; reload-config").unwrap();
+    ipc::send(&mut c, &chain).unwrap();
+    let replies: Vec<serde_json::Value> = ipc::recv(&mut c).unwrap().unwrap();
+    assert_eq!(serde_json::json!([]), replies[0]);
+    assert_eq!(true, replies[1]["success"]);
+});
+
+wm_test!(
+    cancels_queued_commands,
+    env = [("R3_FAULTS", "frame_window.get_geometry=delay:2000")],
+    |t: XTestCase| {
+        assert!(!t.run(R3Command::CancelQueued { index: 0 }).success);
+
+        // While r3 is held up framing a window, commands wait in the queue
+        let w = t.open_window((0, 0, 30, 30));
+        w.map();
+        let tick = t.command_in_background(R3Command::SendTick {
+            payload: "cancelled".into(),
+        });
+        t.wait_for(Duration::from_secs(1), || {
+            let queue: Vec<QueuedCommandInfo> = serde_json::from_str(&t.command(R3Command::GetQueue)).unwrap();
+            (!queue.is_empty()).then_some(())
+        });
+
+        // And the client waiting for a cancelled command is told it won't be run
+        assert!(t.run(R3Command::CancelQueued { index: 0 }).success);
+        let reply: CommandReply = serde_json::from_str(&tick.join().unwrap()).unwrap();
+        assert_eq!(Some("cancelled".to_string()), reply.error);
+    }
+);
+
+fn socket_mode(path: &str) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+wm_test!(
+    checks_the_credentials_of_clients,
+    config = "[ipc]\ncheck_credentials = true",
+    |t: XTestCase| {
+        // Only r3's user can open the sockets, and its clients can run commands
+        assert_eq!(0o600, socket_mode(&t.get_socket_path()));
+        assert_eq!(0o600, socket_mode(&t.get_i3_socket_path()));
+        assert!(t.run(R3Command::SendTick { payload: "".into() }).success);
+    }
+);
+
+wm_test!(
+    opens_the_sockets_to_read_only_users,
+    config = "[ipc]\nread_only_users = [65534]",
+    |t: XTestCase| {
+        assert_eq!(0o666, socket_mode(&t.get_socket_path()));
+        assert_eq!(0o666, socket_mode(&t.get_i3_socket_path()));
+        // They can reach the sockets, but not list or change what else is in their directory
+        let socket_path = PathBuf::from(t.get_socket_path());
+        assert_eq!(0o711, socket_mode(socket_path.parent().unwrap().to_str().unwrap()));
+
+        // r3's own user still has full access
+        assert!(t.run(R3Command::SendTick { payload: "".into() }).success);
+    }
+);
+
+wm_test!(
+    refuses_commands_from_read_only_users,
+    env = [("R3_IPC_PEER_UID", "65534")],
+    |mut t: XTestCase| {
+        // NOTE: `R3_IPC_PEER_UID` only works when r3 is built with the `test-hooks` feature
+        // Every client pretends to be run by another user, who has full access until they're made
+        // read-only, which opens the sockets to them
+        t.write_config("[ipc]\nread_only_users = [65534]");
+        assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+        assert_eq!(0o666, socket_mode(&t.get_socket_path()));
+        assert_eq!(0o666, socket_mode(&t.get_i3_socket_path()));
+
+        let set_config = R3Command::SetConfig {
+            key: "border_width".into(),
+            value: "5".into(),
+        };
+        for command in [set_config.clone(), R3Command::Exit] {
+            let reply: ErrorReply = serde_json::from_str(&t.command(command)).unwrap();
+            assert!(reply.error.contains("read-only client"), "{}", reply.error);
+        }
+        // Even if they're chained after commands the client may run
+        let mut c = t.connect();
+        ipc::send(&mut c, &vec![R3Command::GetVersion, set_config]).unwrap();
+        let reply: ErrorReply = ipc::recv(&mut c).unwrap().unwrap();
+        assert!(reply.error.contains("read-only client"), "{}", reply.error);
+        assert!(t.r3_is_running());
+
+        // But they can still ask for things
+        let reply: VersionReply = serde_json::from_str(&t.command(R3Command::GetVersion)).unwrap();
+        assert_eq!(ipc::PROTOCOL_VERSION, reply.protocol_version);
+    }
+);
+
+wm_test!(
+    refuses_other_users_when_sockets_are_in_tmp,
+    env = [("XDG_RUNTIME_DIR", ""), ("R3_IPC_PEER_UID", "65533")],
+    |mut t: XTestCase| {
+        // NOTE: `R3_IPC_PEER_UID` only works when r3 is built with the `test-hooks` feature
+        // Without a runtime directory the sockets are in /tmp, in a directory of r3's user
+        let socket_path = PathBuf::from(t.get_socket_path());
+        let dir = socket_path.parent().unwrap();
+        assert_eq!(PathBuf::from(format!("/tmp/r3-{}", Uid::current())), dir);
+        assert_eq!(0o711, socket_mode(dir.to_str().unwrap()));
+        assert_eq!(0o600, socket_mode(&t.get_socket_path()));
+
+        // Every client pretends to be run by a user who isn't r3's, so once read-only users can open
+        // the sockets, they're refused
+        t.write_config("[ipc]\nread_only_users = [65534]");
+        assert!(t.run(R3Command::WM(WMCommand::ReloadConfig)).success);
+        assert_eq!(0o666, socket_mode(&t.get_socket_path()));
+        let mut c = t.connect();
+        let _ = ipc::send(&mut c, &R3Command::GetVersion);
+        assert!(!matches!(ipc::read_message(&mut c), Ok(Some(_))));
+        assert!(t.r3_is_running());
+    }
+);
+
+wm_test!(switches_to_cbor, |t: XTestCase| {
+    let mut c = t.connect();
+    ipc::send(
+        &mut c,
+        &R3Command::SetEncoding {
+            encoding: Encoding::Cbor,
+        },
+    )
+    .unwrap();
+    let reply: CommandReply = ipc::recv(&mut c).unwrap().unwrap();
+    assert!(reply.success);
+
+    // Everything after the reply is CBOR, including chains
+    ipc::send_as(&mut c, Encoding::Cbor, &R3Command::GetVersion).unwrap();
+    let reply: VersionReply = ipc::recv_as(&mut c, Encoding::Cbor).unwrap().unwrap();
+    assert_eq!(ipc::PROTOCOL_VERSION, reply.protocol_version);
+
+    let chain = parse_commands("get-config-paths; send-tick cbor").unwrap();
+    ipc::send_as(&mut c, Encoding::Cbor, &chain).unwrap();
+    let (paths, tick): (Vec<PathBuf>, CommandReply) = ipc::recv_as(&mut c, Encoding::Cbor).unwrap().unwrap();
+    assert_eq!(vec![t.config_path()], paths);
+    assert!(tick.success);
+});
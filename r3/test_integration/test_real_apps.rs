@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use r3lib::{R3Command, WMCommand};
+
+use crate::wm_app_test;
+use crate::x_test_runner::{XTestCase, XWindow};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait for the next framed client window to appear and return (frame, client)
+fn wait_for_framed_client(t: &XTestCase, count: usize) -> (XWindow, XWindow) {
+    t.wait_for(TIMEOUT, || {
+        t.sync();
+        let mut frames = t.get_all_windows();
+        if frames.len() < count {
+            return None;
+        }
+
+        // Windows returned from QueryTree are in stacking order, so the newest frame is last
+        let frame = frames.pop().unwrap();
+        let client = frame.get_children().pop()?;
+        Some((frame, client))
+    })
+}
+
+wm_app_test!(xterm_is_framed, ["xterm"], |t: XTestCase| {
+    let _xterm = t.spawn_client("xterm", &[]);
+    let (frame, client) = wait_for_framed_client(&t, 1);
+
+    assert!(frame.is_frame());
+    assert_eq!(10, frame.border_width());
+
+    // The client should fill its frame
+    let (_, _, frame_w, frame_h) = frame.rect();
+    assert_eq!((0, 0, frame_w, frame_h), client.rect());
+});
+
+wm_app_test!(xterm_respects_min_size_hints, ["xterm"], |t: XTestCase| {
+    let _xterm = t.spawn_client("xterm", &[]);
+    let (_, client) = wait_for_framed_client(&t, 1);
+
+    let (_, _, w, h) = client.rect();
+    if let Some((min_w, min_h)) = client.min_size_hints() {
+        assert!(w as u32 >= min_w, "width {} is less than min width {}", w, min_w);
+        assert!(h as u32 >= min_h, "height {} is less than min height {}", h, min_h);
+    }
+});
+
+wm_app_test!(xterm_is_closed_with_wm_delete_window, ["xterm"], |t: XTestCase| {
+    let mut xterm = t.spawn_client("xterm", &[]);
+    wait_for_framed_client(&t, 1);
+
+    t.command(R3Command::WM(WMCommand::CloseWindow));
+
+    // xterm supports WM_DELETE_WINDOW, so it should exit on its own
+    t.wait_for(TIMEOUT, || xterm.has_exited().then_some(()));
+    t.wait_for(TIMEOUT, || {
+        t.sync();
+        t.get_all_windows().is_empty().then_some(())
+    });
+});
+
+wm_app_test!(focus_moves_to_newly_mapped_xterm, ["xterm"], |t: XTestCase| {
+    let _first = t.spawn_client("xterm", &[]);
+    let (_, first) = wait_for_framed_client(&t, 1);
+    assert_eq!(first.id, t.get_input_focus());
+
+    let _second = t.spawn_client("xterm", &[]);
+    let (_, second) = wait_for_framed_client(&t, 2);
+    assert_eq!(second.id, t.get_input_focus());
+});
+
+wm_app_test!(gtk_demo_is_framed_and_closed, ["gtk3-demo"], |t: XTestCase| {
+    let mut demo = t.spawn_client("gtk3-demo", &[]);
+    let (frame, client) = wait_for_framed_client(&t, 1);
+
+    assert!(frame.is_frame());
+    assert_eq!(client.id, t.get_input_focus());
+
+    t.command(R3Command::WM(WMCommand::CloseWindow));
+    t.wait_for(TIMEOUT, || demo.has_exited().then_some(()));
+});
@@ -0,0 +1,126 @@
+use r3lib::{parse_commands, NodeType, R3Command, Split, TreeFormat, TreeNode, WMCommand};
+use xcb::Xid;
+
+use crate::wm_test;
+use crate::x_test_runner::XTestCase;
+
+#[test]
+fn parses_split_commands() {
+    let split = |split| R3Command::WM(WMCommand::Split { split });
+    assert_eq!(
+        Ok(vec![split(Split::Horizontal), split(Split::Vertical)]),
+        parse_commands("split horizontal; split v")
+    );
+    assert!(parse_commands("split diagonal").is_err());
+}
+
+wm_test!(
+    tiles_windows_side_by_side,
+    config = r#"
+        tiling = true
+        border_width = 0
+        [gaps]
+        inner = 10
+        outer = 5
+    "#,
+    |t: XTestCase| {
+        // The screen is 800x600, so the workspace is 790x590 inside the outer gaps
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        t.sync();
+        assert_eq!((5, 5, 790, 590), a.get_frame().rect());
+        assert_eq!((0, 0, 790, 590), a.rect());
+
+        // New windows share the space, with the inner gap between them
+        let b = t.open_window((0, 0, 100, 100));
+        b.map();
+        t.sync();
+        assert_eq!((5, 5, 390, 590), a.get_frame().rect());
+        assert_eq!((405, 5, 390, 590), b.get_frame().rect());
+        assert_eq!((0, 0, 390, 590), b.rect());
+
+        // And take it back when they're closed
+        b.close();
+        t.sync();
+        assert_eq!((5, 5, 790, 590), a.get_frame().rect());
+    }
+);
+
+wm_test!(
+    splits_tiled_windows,
+    config = "tiling = true\nborder_width = 0",
+    |t: XTestCase| {
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        let b = t.open_window((0, 0, 100, 100));
+        b.map();
+        t.sync();
+
+        // The next window opens below the focused one
+        let split_vertical = R3Command::WM(WMCommand::Split { split: Split::Vertical });
+        assert!(t.run(split_vertical.clone()).success);
+        let c = t.open_window((0, 0, 100, 100));
+        c.map();
+        t.sync();
+        assert_eq!((0, 0, 400, 600), a.get_frame().rect());
+        assert_eq!((400, 0, 400, 300), b.get_frame().rect());
+        assert_eq!((400, 300, 400, 300), c.get_frame().rect());
+
+        // The tree shows the workspace and the container, with the tiled frames in order
+        let reply = t.command(R3Command::WM(WMCommand::GetTree {
+            format: TreeFormat::Json,
+        }));
+        let tree: TreeNode = serde_json::from_str(&reply).unwrap();
+        assert_eq!(tree.nodes.len(), 1, "{:?}", tree);
+        let workspace = &tree.nodes[0];
+        assert_eq!(workspace.node_type, NodeType::Workspace);
+        assert_eq!(workspace.split, Some(Split::Horizontal));
+        assert_eq!(workspace.nodes[0].id, a.get_frame().id.resource_id());
+        assert!(!workspace.nodes[0].floating);
+        let container = &workspace.nodes[1];
+        assert_eq!(container.node_type, NodeType::Container);
+        assert_eq!(container.split, Some(Split::Vertical));
+        let frames = container.nodes.iter().map(|node| node.id).collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            vec![b.get_frame().id.resource_id(), c.get_frame().id.resource_id()]
+        );
+
+        // Windows which float can't be split
+        let dialog = t.open_window((10, 10, 100, 100));
+        dialog.set_window_type(t.atoms.net_wm_window_type_dialog);
+        dialog.map();
+        t.sync();
+        assert_eq!((10, 10, 100, 100), dialog.get_frame().rect());
+        assert!(!t.run(split_vertical).success);
+    }
+);
+
+wm_test!(
+    applies_floating_rules,
+    config = r#"
+        tiling = true
+        border_width = 0
+        [[rules]]
+        class = "Floating"
+        floating = true
+        [[rules]]
+        class = "Tiled"
+        floating = false
+    "#,
+    |t: XTestCase| {
+        let floating = t.open_window((10, 10, 100, 100));
+        floating.set_class("floating", "Floating");
+        floating.map();
+        t.sync();
+        assert_eq!((10, 10, 100, 100), floating.get_frame().rect());
+
+        // Rules can tile windows which would float otherwise
+        let dialog = t.open_window((10, 10, 100, 100));
+        dialog.set_class("tiled", "Tiled");
+        dialog.set_window_type(t.atoms.net_wm_window_type_dialog);
+        dialog.map();
+        t.sync();
+        assert_eq!((0, 0, 800, 600), dialog.get_frame().rect());
+    }
+);
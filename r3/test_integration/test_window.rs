@@ -1,4 +1,11 @@
-use r3lib::{R3Command, WMCommand};
+use std::thread;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use r3lib::ipc::CommandReply;
+use r3lib::{
+    NodeType, OutputInfo, PointerInfo, R3Command, TreeFormat, TreeNode, WMCommand, WindowExplanation, WindowInfo,
+};
 use xcb::{x, Xid};
 
 use crate::wm_test;
@@ -15,7 +22,7 @@ wm_test!(maps_a_window, |t: XTestCase| {
 
     // Check a frame was created
     let f = w.get_frame();
-    assert_eq!(true, f.is_frame());
+    assert!(f.is_frame());
     assert_eq!(10, f.border_width());
 });
 
@@ -68,3 +75,709 @@ wm_test!(can_gracefully_kill_window, |t: XTestCase| {
     t.sync();
     assert_eq!(0, t.get_all_windows().len());
 });
+
+wm_test!(focus_is_set_on_x_server, |t: XTestCase| {
+    let first = t.open_window((0, 0, 30, 30));
+    first.map();
+    let second = t.open_window((50, 50, 30, 30));
+    second.map();
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+    assert_eq!(Some(second.id), t.get_active_window());
+
+    // Clicking a window focuses it
+    t.click((15, 15), 1);
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+    assert_eq!(Some(first.id), t.get_active_window());
+
+    // Closing the focused window clears the active window
+    first.close();
+    t.sync();
+    assert_eq!(None, t.get_active_window());
+});
+
+wm_test!(focus_last_switches_between_two_windows, |t: XTestCase| {
+    let first = t.open_window((0, 0, 30, 30));
+    first.map();
+    t.sync();
+    let second = t.open_window((50, 50, 30, 30));
+    second.map();
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+
+    t.command(R3Command::WM(WMCommand::FocusLast));
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+
+    t.command(R3Command::WM(WMCommand::FocusLast));
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+});
+
+wm_test!(focuses_next_window_of_same_class, |t: XTestCase| {
+    let first = t.open_window((0, 0, 30, 30));
+    first.set_class("term", "Term");
+    first.map();
+    let other = t.open_window((50, 50, 30, 30));
+    other.set_class("browser", "Browser");
+    other.map();
+    let second = t.open_window((100, 100, 30, 30));
+    second.set_class("term", "Term");
+    second.map();
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+
+    // The window with another class is skipped, and cycling wraps around
+    t.command(R3Command::WM(WMCommand::FocusNextOfClass { reverse: false }));
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+
+    t.command(R3Command::WM(WMCommand::FocusNextOfClass { reverse: false }));
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+});
+
+wm_test!(
+    remaps_window_classes,
+    config = &format!(
+        r#"
+        [[class_remaps]]
+        selector = {{ instance = "slack" }}
+        class = "Slack"
+        [[class_remaps]]
+        selector = {{ cmdline = "{}" }}
+        class = "Tests"
+        "#,
+        env::current_exe().unwrap().file_name().unwrap().to_string_lossy()
+    ),
+    |t: XTestCase| {
+        let by_instance = t.open_window((0, 0, 30, 30));
+        by_instance.set_class("slack", "Electron");
+        by_instance.map();
+        // This process is the test binary, so its command line matches the second remap
+        let by_cmdline = t.open_window((50, 50, 30, 30));
+        by_cmdline.set_class("other", "Electron");
+        by_cmdline.set_pid(process::id());
+        by_cmdline.map();
+        let unmatched = t.open_window((100, 100, 30, 30));
+        unmatched.set_class("other", "Electron");
+        unmatched.map();
+        t.sync();
+
+        let reply = t.command(R3Command::WM(WMCommand::GetTree {
+            format: TreeFormat::Json,
+        }));
+        let tree: TreeNode = serde_json::from_str(&reply).unwrap();
+        let classes = tree
+            .nodes
+            .iter()
+            .map(|node| node.window.as_ref().unwrap().class.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(classes, vec!["Slack", "Tests", "Electron"]);
+    }
+);
+
+wm_test!(focuses_urgent_window, |t: XTestCase| {
+    let first = t.open_window((0, 0, 30, 30));
+    first.map();
+    let second = t.open_window((50, 50, 30, 30));
+    second.map();
+    let third = t.open_window((100, 100, 30, 30));
+    third.map();
+    t.sync();
+    assert_eq!(third.id, t.get_input_focus());
+
+    first.set_urgent(true);
+    second.set_urgent(true);
+    t.sync();
+
+    // Newest urgent window first
+    t.command(R3Command::WM(WMCommand::FocusUrgent { oldest: false }));
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+
+    // Focusing it cleared its urgency, so the next urgent window is the first
+    t.command(R3Command::WM(WMCommand::FocusUrgent { oldest: false }));
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+});
+
+wm_test!(kill_mode_closes_clicked_window, |t: XTestCase| {
+    let w = t.open_window((100, 100, 30, 30));
+    w.map();
+    t.sync();
+    assert_eq!(1, t.get_all_windows().len());
+
+    t.command(R3Command::WM(WMCommand::KillMode));
+    t.sync();
+    t.click((115, 115), 1);
+    t.sync();
+    assert_eq!(0, t.get_all_windows().len());
+});
+
+wm_test!(kill_mode_can_be_cancelled, |t: XTestCase| {
+    let w = t.open_window((100, 100, 30, 30));
+    w.map();
+    t.sync();
+
+    t.command(R3Command::WM(WMCommand::KillMode));
+    t.sync();
+    // Escape on most keyboards
+    t.press_key(0x09);
+    t.sync();
+    t.click((115, 115), 1);
+    t.sync();
+    assert_eq!(1, t.get_all_windows().len());
+});
+
+wm_test!(pick_window_replies_with_clicked_window, |t: XTestCase| {
+    let w = t.open_window((100, 100, 30, 30));
+    w.map();
+    t.sync();
+
+    let reply = t.command_in_background(R3Command::WM(WMCommand::PickWindow));
+    t.wait_for_pick();
+    t.click((115, 115), 1);
+    let info: Option<WindowInfo> = serde_json::from_str(&reply.join().unwrap()).unwrap();
+
+    let info = info.expect("a window should have been picked");
+    assert_eq!(w.id.resource_id(), info.id);
+    assert_eq!(Some(w.get_frame().id.resource_id()), info.frame);
+});
+
+wm_test!(pick_window_replies_with_null_when_cancelled, |t: XTestCase| {
+    let reply = t.command_in_background(R3Command::WM(WMCommand::PickWindow));
+    t.wait_for_pick();
+    // Escape on most keyboards
+    t.press_key(0x09);
+    assert_eq!("null", reply.join().unwrap());
+});
+
+wm_test!(mirrors_focused_window, |t: XTestCase| {
+    let w = t.open_window((0, 0, 30, 30));
+    w.map();
+    t.sync();
+    assert_eq!(1, t.get_all_windows().len());
+
+    t.command(R3Command::WM(WMCommand::MirrorWindow { x: 100, y: 100 }));
+    t.sync();
+    let windows = t.get_all_windows();
+    assert_eq!(2, windows.len());
+    assert_eq!((100, 100, 30, 30), windows[1].rect());
+
+    // Closing the window stops mirroring it
+    w.close();
+    t.sync();
+    assert_eq!(0, t.get_all_windows().len());
+});
+
+wm_test!(state_is_consistent, |t: XTestCase| {
+    let first = t.open_window((0, 0, 30, 30));
+    first.map();
+    let second = t.open_window((50, 50, 30, 30));
+    second.map();
+    t.sync();
+    first.close();
+    t.sync();
+
+    let problems = t.command(R3Command::CheckConsistency { repair: false });
+    assert_eq!("[]", problems);
+});
+
+wm_test!(dumps_state, |t: XTestCase| {
+    let w = t.open_window((0, 0, 30, 30));
+    w.map();
+    t.sync();
+
+    let reply = t.command(R3Command::DumpState { file: None });
+    let state: String = serde_json::from_str(&reply).unwrap();
+    let frame = w.get_frame();
+    assert!(state.contains(&format!("{:#x} -> {:#x}", w.id.resource_id(), frame.id.resource_id())));
+    assert!(state.contains(&format!("focused: Some(\"{:#x}\")", w.id.resource_id())));
+
+    // The same description can be written to a file instead
+    let path = env::temp_dir().join(format!("r3-test-state.{}.txt", process::id()));
+    assert!(
+        t.run(R3Command::DumpState {
+            file: Some(path.clone())
+        })
+        .success
+    );
+    assert!(fs::read_to_string(&path).unwrap().contains("## Windows"));
+    fs::remove_file(path).unwrap();
+});
+
+wm_test!(runs_commands_on_windows_matching_criteria, |t: XTestCase| {
+    let firefox = t.open_window((0, 0, 30, 30));
+    firefox.set_class("Navigator", "Firefox");
+    firefox.map();
+    let terminal = t.open_window((50, 50, 30, 30));
+    terminal.set_class("alacritty", "Alacritty");
+    terminal.map();
+    t.sync();
+    assert_eq!(Some(terminal.id), t.get_active_window());
+
+    let run = |command: &str| t.run(command.parse().unwrap());
+    assert_eq!(
+        CommandReply::ok(vec![firefox.id.resource_id()]),
+        run(r#"[class="^Fire"] focus"#)
+    );
+    t.sync();
+    assert_eq!(Some(firefox.id), t.get_active_window());
+
+    // Commands which don't act on a window, and invalid expressions, are rejected
+    assert!(!run("[class=Firefox] focus-last").success);
+    assert!(!run("[title=\"(\"] focus").success);
+
+    // Nothing matches, so nothing is closed
+    assert_eq!(CommandReply::ok(vec![]), run("[instance=^Alacritty$] close-window"));
+    assert_eq!(
+        CommandReply::ok(vec![terminal.id.resource_id()]),
+        run("[instance=^alacritty$] close-window")
+    );
+    t.wait_for(Duration::from_secs(1), || {
+        (t.get_all_windows().len() == 1).then_some(())
+    });
+});
+
+wm_test!(
+    explains_window,
+    config = r#"
+        [[rules]]
+        class = "Explained"
+        border_width = 1
+        [[rules]]
+        class = "Other"
+        border_width = 2
+        [[rules]]
+        instance = "explained"
+        title = "window"
+        border_width = 3
+    "#,
+    |t: XTestCase| {
+        let w = t.open_window((0, 0, 30, 30));
+        w.set_class("explained", "Explained");
+        w.set_title("explained window");
+        w.map();
+        t.sync();
+
+        let reply = t.command(R3Command::WM(WMCommand::ExplainWindow { id: w.id.resource_id() }));
+        let explanation: Option<WindowExplanation> = serde_json::from_str(&reply).unwrap();
+        let explanation = explanation.expect("managed windows should be explained");
+        assert_eq!(w.id.resource_id(), explanation.info.id);
+        assert!(explanation
+            .decisions
+            .contains(&"focused when it was mapped".to_string()));
+        assert_eq!(
+            vec![
+                "rule 1: class = \"Explained\"".to_string(),
+                "rule 3: instance = \"explained\", title = \"window\"".to_string(),
+            ],
+            explanation.rules
+        );
+
+        // Windows we don't manage can't be explained
+        let reply = t.command(R3Command::WM(WMCommand::ExplainWindow { id: 0 }));
+        assert_eq!("null", reply);
+    }
+);
+
+wm_test!(replies_with_pointer_info, |t: XTestCase| {
+    let w = t.open_window((100, 100, 50, 50));
+    w.map();
+    t.sync();
+
+    t.move_pointer(120, 120);
+    let reply = t.command(R3Command::WM(WMCommand::GetPointer));
+    let pointer: PointerInfo = serde_json::from_str(&reply).unwrap();
+    assert_eq!((120, 120), (pointer.x, pointer.y));
+    assert_eq!(Some(w.id.resource_id()), pointer.window.map(|info| info.id));
+
+    // The pointer is on the output covering that part of the screen
+    let reply = t.command(R3Command::WM(WMCommand::GetOutputs));
+    let outputs: Vec<OutputInfo> = serde_json::from_str(&reply).unwrap();
+    let output = outputs.into_iter().find(|output| {
+        output
+            .rect
+            .is_some_and(|rect| rect.x <= 120 && rect.y <= 120 && rect.width > 120 && rect.height > 120)
+    });
+    assert_eq!(output.map(|output| output.name), pointer.output);
+
+    t.move_pointer(5, 5);
+    let reply = t.command(R3Command::WM(WMCommand::GetPointer));
+    let pointer: PointerInfo = serde_json::from_str(&reply).unwrap();
+    assert_eq!(None, pointer.window);
+});
+
+wm_test!(reports_outputs, |t: XTestCase| {
+    let reply = t.command(R3Command::WM(WMCommand::GetOutputs));
+    let outputs: Vec<OutputInfo> = serde_json::from_str(&reply).unwrap();
+    let active = outputs.iter().filter(|output| output.active).collect::<Vec<_>>();
+    assert!(!active.is_empty(), "{:?}", outputs);
+    for output in active {
+        assert!(output.connected, "{:?}", output);
+        assert!(
+            output.rect.is_some_and(|rect| rect.width > 0 && rect.height > 0),
+            "{:?}",
+            output
+        );
+    }
+});
+
+wm_test!(frames_proxy_drag_and_drop_to_clients, |t: XTestCase| {
+    let w = t.open_window((0, 0, 30, 30));
+    w.map();
+    t.sync();
+
+    // Frames of windows which don't support drag and drop aren't targets
+    assert_eq!((None, None), w.get_frame().dnd_properties());
+
+    w.set_dnd_aware(5);
+    t.sync();
+    assert_eq!((Some(5), Some(w.id)), w.get_frame().dnd_properties());
+    assert_eq!((Some(5), Some(w.id)), w.dnd_properties());
+});
+
+wm_test!(waits_for_clients_to_handle_resizes, |t: XTestCase| {
+    let w = t.open_window((100, 100, 100, 100));
+    w.enable_sync_request();
+    w.map();
+    t.sync();
+
+    // Resize from the bottom right corner (Control_L on a US keyboard)
+    let (x, y, width, height) = w.get_frame().rect();
+    let corner = (x + width as i16 - 5, y + height as i16 - 5);
+    t.drag(&[0x25], corner, (corner.0 + 20, corner.1 + 30), 3);
+    let first = t.wait_for_sync_request(&w);
+    w.ack_sync_request(first);
+
+    t.drag(
+        &[0x25],
+        (corner.0 + 20, corner.1 + 30),
+        (corner.0 + 40, corner.1 + 40),
+        3,
+    );
+    let second = t.wait_for_sync_request(&w);
+    assert!(second > first);
+    w.ack_sync_request(second);
+
+    t.sync();
+    assert_eq!(
+        (width + 40, height + 40),
+        (w.get_frame().rect().2, w.get_frame().rect().3)
+    );
+});
+
+wm_test!(undoes_moves_and_resizes, |t: XTestCase| {
+    let w = t.open_window((100, 100, 100, 100));
+    w.map();
+    t.sync();
+    let original = w.get_frame().rect();
+
+    // Move then resize the window (Control_L on a US keyboard)
+    let (x, y, width, height) = original;
+    t.drag(&[0x25], (x + 10, y + 10), (x + 60, y + 30), 1);
+    t.sync();
+    let moved = w.get_frame().rect();
+    assert_ne!(original, moved);
+
+    let corner = (moved.0 + width as i16 - 5, moved.1 + height as i16 - 5);
+    t.drag(&[0x25], corner, (corner.0 + 20, corner.1 + 20), 3);
+    t.sync();
+    assert_ne!(moved, w.get_frame().rect());
+
+    t.command(R3Command::WM(WMCommand::UndoGeometry));
+    t.sync();
+    assert_eq!(moved, w.get_frame().rect());
+
+    t.command(R3Command::WM(WMCommand::UndoGeometry));
+    t.sync();
+    assert_eq!(original, w.get_frame().rect());
+});
+
+wm_test!(
+    leaves_gaps_when_placing_windows,
+    config = r#"
+        position_policy = "smart"
+        border_width = 10
+
+        [gaps]
+        inner = 10
+        outer = 20
+    "#,
+    |t: XTestCase| {
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        t.sync();
+        assert_eq!((20, 20), (a.get_frame().rect().0, a.get_frame().rect().1));
+
+        // To the right of the first window (which is 120 pixels wide with its borders)
+        let b = t.open_window((0, 0, 100, 100));
+        b.map();
+        t.sync();
+        assert_eq!((150, 20), (b.get_frame().rect().0, b.get_frame().rect().1));
+    }
+);
+
+wm_test!(
+    places_new_windows_by_policy,
+    config = "position_policy = \"center\"\nborder_width = 0",
+    |t: XTestCase| {
+        // The screen is 800x600
+        let a = t.open_window((0, 0, 100, 100));
+        a.map();
+        t.sync();
+        assert_eq!((350, 250, 100, 100), a.get_frame().rect());
+
+        // Unless the user asked for the window to be somewhere
+        let b = t.open_window((30, 40, 100, 100));
+        b.set_user_position();
+        b.map();
+        t.sync();
+        assert_eq!((30, 40, 100, 100), b.get_frame().rect());
+    }
+);
+
+wm_test!(
+    focus_follows_mouse_after_delay,
+    config = r#"focus_follows_mouse_delay = "200ms""#,
+    |t: XTestCase| {
+        let first = t.open_window((0, 0, 100, 100));
+        first.map();
+        let second = t.open_window((200, 0, 100, 100));
+        second.map();
+        t.sync();
+        t.move_pointer(50, 50);
+        t.wait_for(Duration::from_secs(1), || {
+            (t.get_input_focus() == first.id).then_some(())
+        });
+
+        // Sweeping the pointer across a window doesn't focus it
+        t.move_pointer(250, 50);
+        t.move_pointer(50, 50);
+        thread::sleep(Duration::from_millis(300));
+        t.sync();
+        assert_eq!(first.id, t.get_input_focus());
+
+        t.move_pointer(250, 50);
+        t.wait_for(Duration::from_secs(1), || {
+            (t.get_input_focus() == second.id).then_some(())
+        });
+    }
+);
+
+wm_test!(
+    warps_pointer_to_windows_focused_by_commands,
+    config = "mouse_warping = true",
+    |t: XTestCase| {
+        let first = t.open_window((0, 0, 100, 100));
+        first.map();
+        t.sync();
+        let second = t.open_window((200, 0, 100, 100));
+        second.map();
+        t.sync();
+        t.move_pointer(250, 50);
+        t.sync();
+
+        t.command(R3Command::WM(WMCommand::FocusLast));
+        t.sync();
+        assert_eq!(first.id, t.get_input_focus());
+        let reply = t.command(R3Command::WM(WMCommand::GetPointer));
+        let pointer: PointerInfo = serde_json::from_str(&reply).unwrap();
+        assert_eq!(Some(first.id.resource_id()), pointer.window.map(|w| w.id));
+    }
+);
+
+wm_test!(dumps_window_tree, |t: XTestCase| {
+    let w = t.open_window((0, 0, 30, 30));
+    w.set_class("tree_instance", "TreeClass");
+    w.map();
+    t.sync();
+    let frame = format!("{:#x}", w.get_frame().id.resource_id());
+    let window = format!("{:#x}", w.id.resource_id());
+
+    let reply = t.command(R3Command::WM(WMCommand::GetTree {
+        format: TreeFormat::Ascii,
+    }));
+    let tree: String = serde_json::from_str(&reply).unwrap();
+    let lines = tree.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with("root "), "{}", tree);
+    assert!(lines[1].starts_with(&format!("└─ frame {} ", frame)), "{}", tree);
+    assert!(lines[2].starts_with(&format!("   └─ window {} ", window)), "{}", tree);
+    assert!(lines[2].contains("class=\"TreeClass\""), "{}", tree);
+    assert!(lines[2].ends_with("(focused)"), "{}", tree);
+
+    let reply = t.command(R3Command::WM(WMCommand::GetTree {
+        format: TreeFormat::Dot,
+    }));
+    let tree: String = serde_json::from_str(&reply).unwrap();
+    assert!(tree.starts_with("digraph r3 {"), "{}", tree);
+    assert!(tree.contains(&format!("\"{}\" -> \"{}\";", frame, window)), "{}", tree);
+
+    let reply = t.command(R3Command::WM(WMCommand::GetTree {
+        format: TreeFormat::Json,
+    }));
+    let tree: TreeNode = serde_json::from_str(&reply).unwrap();
+    assert_eq!(tree.node_type, NodeType::Root);
+    assert_eq!(tree.nodes.len(), 1, "{:?}", tree);
+    let frame = &tree.nodes[0];
+    assert_eq!(frame.node_type, NodeType::Frame);
+    assert_eq!(frame.id, w.get_frame().id.resource_id());
+    assert!(frame.floating);
+    let client = &frame.nodes[0];
+    assert_eq!(client.node_type, NodeType::Window);
+    assert_eq!(client.id, w.id.resource_id());
+    assert!(client.focused);
+    assert_eq!((client.rect.width, client.rect.height), (30, 30));
+    assert_eq!(client.window.as_ref().unwrap().class, "TreeClass");
+});
+
+wm_test!(changes_the_state_hash_when_the_tree_changes, |t: XTestCase| {
+    let state_hash = || t.command(R3Command::WM(WMCommand::GetStateHash));
+    let w = t.open_window((0, 0, 30, 30));
+    w.map();
+    t.sync();
+
+    // Nothing has changed
+    let hash = state_hash();
+    t.move_pointer(100, 100);
+    t.sync();
+    assert_eq!(hash, state_hash());
+
+    w.set_title("renamed");
+    t.sync();
+    let renamed = state_hash();
+    assert_ne!(hash, renamed);
+
+    w.close();
+    t.sync();
+    assert_ne!(renamed, state_hash());
+});
+
+wm_test!(focus_does_not_follow_mouse_while_menu_is_open, |t: XTestCase| {
+    let first = t.open_window((0, 0, 100, 100));
+    first.map();
+    let second = t.open_window((200, 0, 100, 100));
+    second.map();
+    t.sync();
+    t.move_pointer(50, 50);
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+
+    let menu = t.open_override_redirect_window((50, 150, 50, 50));
+    menu.set_window_type(t.atoms.net_wm_window_type_popup_menu);
+    menu.map();
+    t.move_pointer(250, 50);
+    t.sync();
+    assert_eq!(first.id, t.get_input_focus());
+
+    menu.close();
+    t.move_pointer(50, 50);
+    t.move_pointer(250, 50);
+    t.sync();
+    assert_eq!(second.id, t.get_input_focus());
+});
+
+wm_test!(tracks_open_menus, |t: XTestCase| {
+    let open_menus = || {
+        let state: String = serde_json::from_str(&t.command(R3Command::DumpState { file: None })).unwrap();
+        state
+            .lines()
+            .find_map(|line| line.strip_prefix("override-redirect windows: "))
+            .unwrap()
+            .to_string()
+    };
+
+    let menu = t.open_override_redirect_window((50, 150, 50, 50));
+    menu.set_window_type(t.atoms.net_wm_window_type_popup_menu);
+    t.sync();
+    assert_eq!("[]", open_menus());
+
+    // Menus are tracked while they're mapped, and may be opened again once they're closed
+    let listed = format!("[{:#x}]", menu.id.resource_id());
+    menu.map();
+    t.sync();
+    assert_eq!(listed, open_menus());
+    menu.unmap();
+    t.sync();
+    assert_eq!("[]", open_menus());
+    menu.map();
+    t.sync();
+    assert_eq!(listed, open_menus());
+
+    // Destroying a menu while it's open stops tracking it too
+    menu.close();
+    t.sync();
+    assert_eq!("[]", open_menus());
+});
+
+wm_test!(
+    delayed_focus_is_dropped_when_menu_opens,
+    config = r#"focus_follows_mouse_delay = "200ms""#,
+    |t: XTestCase| {
+        let first = t.open_window((0, 0, 100, 100));
+        first.map();
+        let second = t.open_window((200, 0, 100, 100));
+        second.map();
+        t.sync();
+        t.move_pointer(50, 50);
+        t.wait_for(Duration::from_secs(1), || {
+            (t.get_input_focus() == first.id).then_some(())
+        });
+
+        // The menu opens while focus is waiting to follow the pointer
+        t.move_pointer(250, 50);
+        let menu = t.open_override_redirect_window((50, 150, 50, 50));
+        menu.set_window_type(t.atoms.net_wm_window_type_popup_menu);
+        menu.map();
+        thread::sleep(Duration::from_millis(300));
+        t.sync();
+        assert_eq!(first.id, t.get_input_focus());
+    }
+);
+
+wm_test!(flashes_windows_on_request, |t: XTestCase| {
+    let a = t.open_window((0, 0, 100, 100));
+    a.map();
+    let b = t.open_window((200, 200, 100, 100));
+    b.map();
+    t.sync();
+    assert_eq!(0xaaaaaa, t.pixel_at(1, 1));
+
+    // The window is drawn with the urgent colours for a moment, then goes back to normal
+    t.command(R3Command::WM(WMCommand::FlashWindow {
+        id: Some(a.id.resource_id()),
+    }));
+    t.sync();
+    assert_eq!(0xffaa00, t.pixel_at(1, 1));
+    thread::sleep(Duration::from_secs(1));
+    t.sync();
+    assert_eq!(0xaaaaaa, t.pixel_at(1, 1));
+
+    // Without an id, the focused window is flashed
+    t.command(R3Command::WM(WMCommand::FlashWindow { id: None }));
+    t.sync();
+    assert_eq!(0xffaa00, t.pixel_at(201, 201));
+});
+
+wm_test!(replies_with_the_windows_commands_acted_on, |t: XTestCase| {
+    let w = t.open_window((0, 0, 30, 30));
+    w.map();
+    t.sync();
+
+    let reply = t.run(R3Command::WM(WMCommand::FlashWindow { id: None }));
+    assert_eq!(CommandReply::ok(vec![w.id.resource_id()]), reply);
+    let reply = t.run(R3Command::WM(WMCommand::CloseWindow));
+    assert_eq!(CommandReply::ok(vec![w.id.resource_id()]), reply);
+    t.sync();
+
+    // Commands with nothing to act on still succeed
+    let reply = t.run(R3Command::WM(WMCommand::CloseWindow));
+    assert_eq!(CommandReply::ok(vec![]), reply);
+
+    // Commands which can't do what they were asked say why
+    let reply = t.run(R3Command::WM(WMCommand::FlashWindow { id: Some(1) }));
+    assert!(!reply.success);
+    assert_eq!(Some("No managed window has the id 0x1"), reply.error.as_deref());
+});
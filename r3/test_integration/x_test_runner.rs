@@ -1,18 +1,25 @@
 use core::panic;
 use std::fmt::Debug;
-use std::io::{Read, Write};
-use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
-use std::{env, thread};
+use std::{env, fs, iter, thread};
 
-use r3lib::R3Command;
-use rand::Rng;
+use r3lib::ipc::{self, CommandReply};
+use r3lib::{R3Command, R3Event};
 use xcb::Xid;
 
+// X event codes used with the XTEST extension
+const KEY_PRESS: u8 = 2;
+const KEY_RELEASE: u8 = 3;
+const BUTTON_PRESS: u8 = 4;
+const BUTTON_RELEASE: u8 = 5;
+const MOTION_NOTIFY: u8 = 6;
+
 pub struct XTestRunner {
     display_num: AtomicUsize,
 }
@@ -25,8 +32,19 @@ impl XTestRunner {
     }
 
     pub fn test(&self) -> XTestCase {
+        self.test_with_env(&[])
+    }
+
+    /// Start a test with extra environment variables set for r3
+    pub fn test_with_env(&self, r3_env: &[(&str, &str)]) -> XTestCase {
+        let n = self.display_num.fetch_add(1, Ordering::SeqCst);
+        XTestCase::start(n, r3_env, "")
+    }
+
+    /// Start a test with r3 reading the given configuration
+    pub fn test_with_config(&self, config: &str) -> XTestCase {
         let n = self.display_num.fetch_add(1, Ordering::SeqCst);
-        XTestCase::start(n)
+        XTestCase::start(n, &[], config)
     }
 }
 
@@ -35,10 +53,29 @@ xcb::atoms_struct! {
     pub struct Atoms {
         pub wm_protocols     => b"WM_PROTOCOLS",
         pub wm_del_window    => b"WM_DELETE_WINDOW",
+        pub net_active_window => b"_NET_ACTIVE_WINDOW",
+        pub wm_window_role   => b"WM_WINDOW_ROLE",
+        pub net_wm_pid       => b"_NET_WM_PID",
 
-        pub r3_sync          => b"R3_SYNC",
         pub r3_socket_path   => b"R3_SOCKET_PATH",
+        pub i3_socket_path   => b"I3_SOCKET_PATH",
         pub r3_frame         => b"R3_FRAME",
+
+        pub xdnd_aware       => b"XdndAware",
+        pub xdnd_proxy       => b"XdndProxy",
+
+        pub clipboard        => b"CLIPBOARD",
+        pub clipboard_manager => b"CLIPBOARD_MANAGER",
+        pub save_targets     => b"SAVE_TARGETS",
+        pub targets          => b"TARGETS",
+        pub utf8_string      => b"UTF8_STRING",
+
+        pub net_wm_window_type => b"_NET_WM_WINDOW_TYPE",
+        pub net_wm_window_type_popup_menu => b"_NET_WM_WINDOW_TYPE_POPUP_MENU",
+        pub net_wm_window_type_dialog => b"_NET_WM_WINDOW_TYPE_DIALOG",
+
+        pub net_wm_sync_request => b"_NET_WM_SYNC_REQUEST",
+        pub net_wm_sync_request_counter => b"_NET_WM_SYNC_REQUEST_COUNTER",
     }
 }
 
@@ -50,18 +87,20 @@ pub struct XTestCase {
     pub conn: Arc<xcb::Connection>,
     /// Some X Atoms we need
     pub atoms: Arc<Atoms>,
+    /// The display of the X server used for this test
+    display: String,
     /// Handle to the root window
     root: xcb::x::Window,
-    /// Handle to a special window we use for syncing with r3
-    sync_window: Option<xcb::x::Window>,
     /// The handle to the child process for r3
     r3_child: Child,
     /// The handle to the child process for the X server
     x_child: Child,
+    /// The configuration file r3 was started with
+    config_path: PathBuf,
 }
 
 impl XTestCase {
-    fn start(display_num: usize) -> XTestCase {
+    fn start(display_num: usize, r3_env: &[(&str, &str)], config: &str) -> XTestCase {
         // Spawn X server
         let display = format!(":{}", display_num);
         let (program, extra_args) = match env::var("TEST_ENABLE_XEPHYR") {
@@ -89,7 +128,11 @@ impl XTestCase {
             let mut attempt = 0;
             let max_attempts = 5;
             loop {
-                match xcb::Connection::connect_with_extensions(Some(&display), &[], &[]) {
+                match xcb::Connection::connect_with_extensions(
+                    Some(&display),
+                    &[],
+                    &[xcb::Extension::Sync, xcb::Extension::Test],
+                ) {
                     Ok(inner) => break inner,
                     Err(_) => {
                         if attempt == max_attempts {
@@ -105,6 +148,10 @@ impl XTestCase {
             }
         };
 
+        // Always give r3 a configuration file, so the user's own configuration is never used
+        let config_path = env::temp_dir().join(format!("r3-test-config.{}.toml", display_num));
+        fs::write(&config_path, config).unwrap();
+
         // Spawn r3
         let r3_stdio = match env::var("TEST_ENABLE_R3_STDIO") {
             Ok(_) => Stdio::inherit,
@@ -112,7 +159,10 @@ impl XTestCase {
         };
         let r3_bin_path = env::current_dir().unwrap().join("../target/debug/r3");
         let r3_child = Command::new(r3_bin_path)
-            .env("DISPLAY", display)
+            .arg("--config")
+            .arg(&config_path)
+            .env("DISPLAY", &display)
+            .envs(r3_env.iter().copied())
             .stdout(r3_stdio())
             .stderr(r3_stdio())
             .spawn()
@@ -132,17 +182,16 @@ impl XTestCase {
             atoms
         };
 
-        let mut t = XTestCase {
+        XTestCase {
+            display,
             root: conn.get_setup().roots().nth(n as usize).unwrap().root(),
-            sync_window: None,
             conn: Arc::new(conn),
             atoms: Arc::new(atoms),
             r3_child,
             x_child,
+            config_path,
             start: Instant::now(),
-        };
-        XTestCase::create_sync_window(&mut t);
-        t
+        }
     }
 
     // TODO: more ergonomic configuration here - use a vec of enums for Cw attrs?
@@ -150,12 +199,17 @@ impl XTestCase {
         self._open_window(rect, false)
     }
 
+    /// Open a window which isn't managed by the window manager, like menus and tooltips.
+    pub fn open_override_redirect_window(&self, rect: (i16, i16, u16, u16)) -> XWindow {
+        self._open_window(rect, true)
+    }
+
     fn _open_window(&self, (x, y, w, h): (i16, i16, u16, u16), override_redirect: bool) -> XWindow {
         let wid = self.conn.generate_id();
         self.conn
             .send_and_check_request(&xcb::x::CreateWindow {
                 depth: xcb::x::COPY_FROM_PARENT as u8,
-                visual: xcb::x::COPY_FROM_PARENT as u32,
+                visual: xcb::x::COPY_FROM_PARENT,
                 wid,
                 parent: self.root,
                 x,
@@ -179,53 +233,16 @@ impl XTestCase {
         }
     }
 
-    fn create_sync_window(&mut self) {
-        // Create a hidden un-managed window which will be used for syncing
-        if let None = self.sync_window {
-            // Create window outside of viewport, and set override redirect (so WM doesn't manage it)
-            let w = self._open_window((-15, -15, 10, 10), true);
-            w.map();
-            self.sync_window = Some(w.id);
-        }
-    }
-
+    /// Wait for r3 to handle everything we've done so far
     pub fn sync(&self) {
-        // This is created in the `::new()` call
-        let window_id = self.sync_window.unwrap();
-
-        // Send a randomly generated number to r3
-        let mut rng = rand::thread_rng();
-        let magic: u32 = rng.gen();
-        let data = xcb::x::ClientMessageData::Data32([
-            window_id.resource_id(),
-            magic, // random data
-            0,
-            0,
-            0, // padding
-        ]);
-
-        eprintln!("[sync] send: {}", magic);
-        self.conn.send_request(&xcb::x::SendEvent {
-            propagate: false,
-            destination: xcb::x::SendEventDest::Window(self.root),
-            event_mask: xcb::x::EventMask::SUBSTRUCTURE_REDIRECT,
-            event: &xcb::x::ClientMessageEvent::new(window_id, self.atoms.r3_sync, data),
-        });
-        self.conn.flush().unwrap();
+        // Once the X server has replied to us, it's processed our requests and sent r3 their events
+        self.conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetInputFocus {}))
+            .unwrap();
 
-        // Wait for r3 to process the sync message, and send the reply back to our sync window
-        eprintln!("[sync] wait: {}", magic);
-        loop {
-            let event = self.conn.wait_for_event().unwrap();
-            match event {
-                xcb::Event::X(xcb::x::Event::ClientMessage(ev)) => match ev.data() {
-                    xcb::x::ClientMessageData::Data32([_wid, n, _, _, _]) if n == magic => break,
-                    _ => {}
-                },
-                _ => {}
-            }
-        }
-        eprintln!("[sync] recv: {}", magic);
+        eprintln!("[sync] send");
+        assert!(self.run(R3Command::Sync).success);
+        eprintln!("[sync] recv");
     }
 
     pub fn get_all_windows(&self) -> Vec<XWindow> {
@@ -236,8 +253,7 @@ impl XTestCase {
 
         query_tree
             .children()
-            .into_iter()
-            .filter(|id| **id != self.sync_window.unwrap())
+            .iter()
             .map(|id| XWindow {
                 id: *id,
                 conn: self.conn.clone(),
@@ -246,13 +262,278 @@ impl XTestCase {
             .collect()
     }
 
+    /// Spawn a real X client application connected to this test's X server.
+    /// The process is killed when the returned `XClient` is dropped.
+    pub fn spawn_client(&self, program: &str, args: &[&str]) -> XClient {
+        let stdio = match env::var("TEST_ENABLE_CLIENT_STDIO") {
+            Ok(_) => Stdio::inherit,
+            Err(_) => Stdio::null,
+        };
+        let child = Command::new(which::which(program).unwrap())
+            .args(args)
+            .env("DISPLAY", &self.display)
+            .stdout(stdio())
+            .stderr(stdio())
+            .spawn()
+            .unwrap();
+
+        XClient { child }
+    }
+
+    /// Repeatedly evaluate `f` until it returns `Some`, panicking if `timeout` elapses first.
+    /// Real applications map their windows asynchronously, so this is used rather than `sync`.
+    pub fn wait_for<T>(&self, timeout: Duration, mut f: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+
+            if start.elapsed() > timeout {
+                panic!("Timed out after {:?} waiting for condition", timeout);
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Wait until r3 asks the window to acknowledge its next resize, and return the counter value it
+    /// asked for (see `XWindow::enable_sync_request`).
+    pub fn wait_for_sync_request(&self, window: &XWindow) -> i64 {
+        loop {
+            let event = self.conn.wait_for_event().unwrap();
+            if let xcb::Event::X(xcb::x::Event::ClientMessage(ev)) = event {
+                if ev.window() != window.id || ev.r#type() != self.atoms.wm_protocols {
+                    continue;
+                }
+                match ev.data() {
+                    xcb::x::ClientMessageData::Data32([atom, _time, lo, hi, _])
+                        if atom == self.atoms.net_wm_sync_request.resource_id() =>
+                    {
+                        return ((hi as i64) << 32) | lo as i64;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Move the pointer to the given position on the root window using the XTEST extension.
+    pub fn move_pointer(&self, x: i16, y: i16) {
+        self.fake_input(MOTION_NOTIFY, 0, (x, y));
+    }
+
+    /// Click the given mouse button at the given position using the XTEST extension.
+    pub fn click(&self, (x, y): (i16, i16), button: u8) {
+        self.move_pointer(x, y);
+        self.fake_input(BUTTON_PRESS, button, (0, 0));
+        self.fake_input(BUTTON_RELEASE, button, (0, 0));
+    }
+
+    /// Drag from one position to another with the given mouse button, while holding the given keys.
+    pub fn drag(&self, keycodes: &[u8], from: (i16, i16), to: (i16, i16), button: u8) {
+        for keycode in keycodes {
+            self.fake_input(KEY_PRESS, *keycode, (0, 0));
+        }
+        self.move_pointer(from.0, from.1);
+        self.fake_input(BUTTON_PRESS, button, (0, 0));
+        self.move_pointer(to.0, to.1);
+        self.fake_input(BUTTON_RELEASE, button, (0, 0));
+        for keycode in keycodes.iter().rev() {
+            self.fake_input(KEY_RELEASE, *keycode, (0, 0));
+        }
+    }
+
+    /// Press and release a key (by its keycode) using the XTEST extension.
+    pub fn press_key(&self, keycode: u8) {
+        self.fake_input(KEY_PRESS, keycode, (0, 0));
+        self.fake_input(KEY_RELEASE, keycode, (0, 0));
+    }
+
+    /// Press each key in order, then release them in reverse order (e.g. to press keys with modifiers)
+    pub fn press_keys(&self, keycodes: &[u8]) {
+        for keycode in keycodes {
+            self.fake_input(KEY_PRESS, *keycode, (0, 0));
+        }
+        for keycode in keycodes.iter().rev() {
+            self.fake_input(KEY_RELEASE, *keycode, (0, 0));
+        }
+    }
+
+    fn fake_input(&self, r#type: u8, detail: u8, (root_x, root_y): (i16, i16)) {
+        self.conn
+            .send_and_check_request(&xcb::xtest::FakeInput {
+                r#type,
+                detail,
+                time: xcb::x::CURRENT_TIME,
+                root: self.root,
+                root_x,
+                root_y,
+                deviceid: 0,
+            })
+            .unwrap();
+    }
+
+    /// Copy text to the clipboard from the window, then ask the clipboard manager to save it (as clients
+    /// do before they exit). Returns whether it was saved.
+    pub fn save_clipboard(&self, owner: &XWindow, text: &str) -> bool {
+        let atoms = &self.atoms;
+        self.conn
+            .send_and_check_request(&xcb::x::SetSelectionOwner {
+                owner: owner.id,
+                selection: atoms.clipboard,
+                time: xcb::x::CURRENT_TIME,
+            })
+            .unwrap();
+        self.conn
+            .send_and_check_request(&xcb::x::ConvertSelection {
+                requestor: owner.id,
+                selection: atoms.clipboard_manager,
+                target: atoms.save_targets,
+                property: atoms.save_targets,
+                time: xcb::x::CURRENT_TIME,
+            })
+            .unwrap();
+
+        // Give the clipboard's contents to the clipboard manager as it asks for them, until it's done
+        loop {
+            match self.conn.wait_for_event().unwrap() {
+                xcb::Event::X(xcb::x::Event::SelectionRequest(ev)) => {
+                    let property = if ev.target() == atoms.targets {
+                        owner.set_property(ev.property(), xcb::x::ATOM_ATOM, &[atoms.targets, atoms.utf8_string]);
+                        ev.property()
+                    } else if ev.target() == atoms.utf8_string {
+                        owner.set_property(ev.property(), atoms.utf8_string, text.as_bytes());
+                        ev.property()
+                    } else {
+                        xcb::x::ATOM_NONE
+                    };
+
+                    self.conn
+                        .send_and_check_request(&xcb::x::SendEvent {
+                            propagate: false,
+                            destination: xcb::x::SendEventDest::Window(ev.requestor()),
+                            event_mask: xcb::x::EventMask::NO_EVENT,
+                            event: &xcb::x::SelectionNotifyEvent::new(
+                                ev.time(),
+                                ev.requestor(),
+                                ev.selection(),
+                                ev.target(),
+                                property,
+                            ),
+                        })
+                        .unwrap();
+                }
+                xcb::Event::X(xcb::x::Event::SelectionNotify(ev)) if ev.target() == atoms.save_targets => {
+                    return !ev.property().is_none();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read the text on the clipboard, if there is any.
+    pub fn read_clipboard(&self, requestor: &XWindow) -> Option<String> {
+        self.conn
+            .send_and_check_request(&xcb::x::ConvertSelection {
+                requestor: requestor.id,
+                selection: self.atoms.clipboard,
+                target: self.atoms.utf8_string,
+                property: self.atoms.clipboard,
+                time: xcb::x::CURRENT_TIME,
+            })
+            .unwrap();
+
+        loop {
+            if let xcb::Event::X(xcb::x::Event::SelectionNotify(ev)) = self.conn.wait_for_event().unwrap() {
+                if ev.property().is_none() {
+                    return None;
+                }
+
+                let reply = self
+                    .conn
+                    .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
+                        delete: true,
+                        window: requestor.id,
+                        property: ev.property(),
+                        r#type: xcb::x::ATOM_ANY,
+                        long_offset: 0,
+                        long_length: u32::MAX / 4,
+                    }))
+                    .unwrap();
+                return Some(String::from_utf8(reply.value::<u8>().into()).unwrap());
+            }
+        }
+    }
+
+    /// The colour of the pixel on the screen at the given position, as `0xrrggbb`
+    pub fn pixel_at(&self, x: i16, y: i16) -> u32 {
+        let image = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetImage {
+                format: xcb::x::ImageFormat::ZPixmap,
+                drawable: xcb::x::Drawable::Window(self.root),
+                x,
+                y,
+                width: 1,
+                height: 1,
+                plane_mask: u32::MAX,
+            }))
+            .unwrap();
+
+        match image.data() {
+            [b, g, r, ..] => u32::from_be_bytes([0, *r, *g, *b]),
+            data => panic!("unexpected image data: {:?}", data),
+        }
+    }
+
+    /// Returns the window which currently has input focus.
+    pub fn get_input_focus(&self) -> xcb::x::Window {
+        self.conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetInputFocus {}))
+            .unwrap()
+            .focus()
+    }
+
+    /// Returns the window r3 has set as `_NET_ACTIVE_WINDOW`.
+    pub fn get_active_window(&self) -> Option<xcb::x::Window> {
+        self.conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
+                delete: false,
+                window: self.root,
+                property: self.atoms.net_active_window,
+                r#type: xcb::x::ATOM_WINDOW,
+                long_offset: 0,
+                long_length: 1,
+            }))
+            .unwrap()
+            .value::<xcb::x::Window>()
+            .first()
+            .copied()
+            .filter(|window| !window.is_none())
+    }
+
+    /// Returns true if r3 is still running (e.g. it hasn't crashed).
+    pub fn r3_is_running(&mut self) -> bool {
+        matches!(self.r3_child.try_wait(), Ok(None))
+    }
+
     pub fn get_socket_path(&self) -> String {
+        self.get_root_string(self.atoms.r3_socket_path)
+    }
+
+    /// The path of the socket speaking i3's IPC protocol
+    pub fn get_i3_socket_path(&self) -> String {
+        self.get_root_string(self.atoms.i3_socket_path)
+    }
+
+    fn get_root_string(&self, property: xcb::x::Atom) -> String {
         let reply = self
             .conn
             .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
                 delete: false,
                 window: self.root,
-                property: self.atoms.r3_socket_path,
+                property,
                 r#type: xcb::x::ATOM_STRING,
                 long_offset: 0,
                 long_length: 1024,
@@ -262,26 +543,102 @@ impl XTestCase {
         String::from_utf8(reply.value::<u8>().into()).unwrap()
     }
 
-    pub fn command(&self, command: R3Command) {
-        eprintln!("[command] send: {:?}", command);
-        let mut c = UnixStream::connect(self.get_socket_path()).unwrap();
-        c.write_all(&serde_json::to_vec(&command).unwrap()).unwrap();
-        c.shutdown(Shutdown::Write).unwrap();
+    /// Replace the contents of r3's configuration file (it's only read again when r3 is told to)
+    pub fn write_config(&self, config: &str) {
+        fs::write(&self.config_path, config).unwrap();
+    }
+
+    /// The file r3 reads its configuration from, which tests may include other files next to
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// Send a command to r3, and wait for its response
+    pub fn command(&self, command: R3Command) -> String {
+        send_command(&self.get_socket_path(), command)
+    }
+
+    /// Send a command which isn't a query, and read whether it worked
+    pub fn run(&self, command: R3Command) -> CommandReply {
+        let reply = self.command(command);
+        serde_json::from_str(&reply).unwrap_or_else(|e| panic!("unexpected reply {:?}: {}", reply, e))
+    }
+
+    /// Connect to r3's socket, to speak the IPC protocol directly (waiting up to 5 seconds for reads)
+    pub fn connect(&self) -> UnixStream {
+        let c = UnixStream::connect(self.get_socket_path()).unwrap();
+        c.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        c
+    }
+
+    /// Subscribe to r3's events, which are read as they arrive (waiting up to 5 seconds for each)
+    pub fn subscribe(&self) -> impl Iterator<Item = R3Event> {
+        let mut c = self.connect();
+        ipc::send(&mut c, &R3Command::Subscribe).unwrap();
+
+        iter::from_fn(move || {
+            let event = ipc::read_message(&mut c).unwrap()?;
+            eprintln!("[event] recv: {:?}", String::from_utf8_lossy(&event));
+            Some(serde_json::from_slice(&event).unwrap())
+        })
+    }
 
-        // Read response
-        let mut buffer = String::new();
-        c.read_to_string(&mut buffer).unwrap();
-        eprintln!("[command] recv: {:?}", buffer);
-        // TODO: return response once a format for it exists
+    /// Send a command to r3 without waiting for its response, useful for commands which won't
+    /// reply until some other interaction has occurred. The command may not have been run when this
+    /// returns, so wait for its effect before interacting (e.g. with `wait_for_pick`).
+    pub fn command_in_background(&self, command: R3Command) -> JoinHandle<String> {
+        let socket_path = self.get_socket_path();
+        thread::spawn(move || send_command(&socket_path, command))
+    }
+
+    /// Wait until r3 is picking a window, and has grabbed the pointer and keyboard to do so
+    pub fn wait_for_pick(&self) {
+        self.wait_for(Duration::from_secs(1), || {
+            let state = self.command(R3Command::DumpState { file: None });
+            (!state.contains("pick: None")).then_some(())
+        });
     }
 }
 
+fn send_command(socket_path: &str, command: R3Command) -> String {
+    eprintln!("[command] send: {:?}", command);
+    let mut c = UnixStream::connect(socket_path).unwrap();
+    ipc::send(&mut c, &command).unwrap();
+
+    // Read response
+    let reply = ipc::read_message(&mut c)
+        .unwrap()
+        .expect("r3 closed the connection without replying");
+    let reply = String::from_utf8(reply).unwrap();
+    eprintln!("[command] recv: {:?}", reply);
+    reply
+}
+
 pub struct XWindow {
     pub id: xcb::x::Window,
     conn: Arc<xcb::Connection>,
     atoms: Arc<Atoms>,
 }
 
+/// A real X client application spawned during a test.
+pub struct XClient {
+    child: Child,
+}
+
+impl XClient {
+    /// Returns true if the client process has exited.
+    pub fn has_exited(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(Some(_)))
+    }
+}
+
+impl Drop for XClient {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
 impl Debug for XWindow {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("XWindow")
@@ -299,12 +656,162 @@ impl XWindow {
             .unwrap();
     }
 
+    pub fn unmap(&self) {
+        self.conn
+            .send_and_check_request(&xcb::x::UnmapWindow { window: self.id })
+            .unwrap();
+    }
+
     pub fn close(self) {
         self.conn
             .send_and_check_request(&xcb::x::DestroyWindow { window: self.id })
             .unwrap();
     }
 
+    /// Set or clear the urgency hint in WM_HINTS
+    pub fn set_urgent(&self, urgent: bool) {
+        // See ICCCM 4.1.2.4: WM_HINTS is 9 fields, the first being flags
+        const URGENCY_HINT: u32 = 1 << 8;
+        let flags = if urgent { URGENCY_HINT } else { 0 };
+        self.conn
+            .send_and_check_request(&xcb::x::ChangeProperty {
+                mode: xcb::x::PropMode::Replace,
+                window: self.id,
+                property: xcb::x::ATOM_WM_HINTS,
+                r#type: xcb::x::ATOM_WM_HINTS,
+                data: &[flags, 0, 0, 0, 0, 0, 0, 0, 0],
+            })
+            .unwrap();
+    }
+
+    /// Set the USPosition flag in WM_NORMAL_HINTS, which says the user asked for the window's position
+    pub fn set_user_position(&self) {
+        // See ICCCM 4.1.2.3: WM_NORMAL_HINTS is 18 fields, the first being flags
+        const US_POSITION: u32 = 1 << 0;
+        let mut hints = [0; 18];
+        hints[0] = US_POSITION;
+        self.set_property(xcb::x::ATOM_WM_NORMAL_HINTS, xcb::x::ATOM_WM_SIZE_HINTS, &hints);
+    }
+
+    /// Set WM_NAME, the window's title
+    pub fn set_title(&self, title: &str) {
+        self.set_property(xcb::x::ATOM_WM_NAME, xcb::x::ATOM_STRING, title.as_bytes());
+    }
+
+    /// Set WM_CLASS, which is the instance and class as two null-terminated strings
+    pub fn set_class(&self, instance: &str, class: &str) {
+        let data = format!("{}\0{}\0", instance, class);
+        self.conn
+            .send_and_check_request(&xcb::x::ChangeProperty {
+                mode: xcb::x::PropMode::Replace,
+                window: self.id,
+                property: xcb::x::ATOM_WM_CLASS,
+                r#type: xcb::x::ATOM_STRING,
+                data: data.as_bytes(),
+            })
+            .unwrap();
+    }
+
+    /// Set _NET_WM_PID, the id of the process which owns the window
+    pub fn set_pid(&self, pid: u32) {
+        self.set_property(self.atoms.net_wm_pid, xcb::x::ATOM_CARDINAL, &[pid]);
+    }
+
+    fn set_property<P: xcb::x::PropEl>(&self, property: xcb::x::Atom, r#type: xcb::x::Atom, data: &[P]) {
+        self.conn
+            .send_and_check_request(&xcb::x::ChangeProperty {
+                mode: xcb::x::PropMode::Replace,
+                window: self.id,
+                property,
+                r#type,
+                data,
+            })
+            .unwrap();
+    }
+
+    /// Support `_NET_WM_SYNC_REQUEST`, by creating a sync counter and telling the window manager about it
+    pub fn enable_sync_request(&self) {
+        let counter = self.conn.generate_id();
+        self.conn
+            .send_and_check_request(&xcb::sync::CreateCounter {
+                id: counter,
+                initial_value: xcb::sync::Int64 { hi: 0, lo: 0 },
+            })
+            .unwrap();
+        self.set_property(
+            self.atoms.net_wm_sync_request_counter,
+            xcb::x::ATOM_CARDINAL,
+            &[counter.resource_id()],
+        );
+        self.set_property(
+            self.atoms.wm_protocols,
+            xcb::x::ATOM_ATOM,
+            &[self.atoms.net_wm_sync_request],
+        );
+    }
+
+    /// Tell the window manager we've handled the resize it sent with the given sync request value
+    pub fn ack_sync_request(&self, value: i64) {
+        let reply = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
+                delete: false,
+                window: self.id,
+                property: self.atoms.net_wm_sync_request_counter,
+                r#type: xcb::x::ATOM_CARDINAL,
+                long_offset: 0,
+                long_length: 1,
+            }))
+            .unwrap();
+        self.conn
+            .send_and_check_request(&xcb::sync::SetCounter {
+                counter: xcb::XidNew::new(reply.value::<u32>()[0]),
+                value: xcb::sync::Int64 {
+                    hi: (value >> 32) as i32,
+                    lo: value as u32,
+                },
+            })
+            .unwrap();
+    }
+
+    /// Set _NET_WM_WINDOW_TYPE
+    pub fn set_window_type(&self, window_type: xcb::x::Atom) {
+        self.set_property(self.atoms.net_wm_window_type, xcb::x::ATOM_ATOM, &[window_type]);
+    }
+
+    /// Set WM_WINDOW_ROLE
+    pub fn set_role(&self, role: &str) {
+        self.set_property(self.atoms.wm_window_role, xcb::x::ATOM_STRING, role.as_bytes());
+    }
+
+    /// Set XdndAware, which marks the window as a drag and drop target supporting the given version
+    pub fn set_dnd_aware(&self, version: u32) {
+        self.set_property(self.atoms.xdnd_aware, xcb::x::ATOM_ATOM, &[version]);
+    }
+
+    /// Returns the XdndAware version and the XdndProxy window, if they're set
+    pub fn dnd_properties(&self) -> (Option<u32>, Option<xcb::x::Window>) {
+        let get = |property, r#type| {
+            self.conn
+                .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
+                    delete: false,
+                    window: self.id,
+                    property,
+                    r#type,
+                    long_offset: 0,
+                    long_length: 1,
+                }))
+                .unwrap()
+        };
+
+        let aware = get(self.atoms.xdnd_aware, xcb::x::ATOM_ATOM);
+        let proxy = get(self.atoms.xdnd_proxy, xcb::x::ATOM_WINDOW);
+        (
+            aware.value::<u32>().first().copied(),
+            proxy.value::<xcb::x::Window>().first().copied(),
+        )
+    }
+
     pub fn is_frame(&self) -> bool {
         let reply = self
             .conn
@@ -334,6 +841,45 @@ impl XWindow {
         }
     }
 
+    pub fn get_children(&self) -> Vec<XWindow> {
+        let query_tree = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::QueryTree { window: self.id }))
+            .unwrap();
+
+        query_tree
+            .children()
+            .iter()
+            .map(|id| XWindow {
+                id: *id,
+                conn: self.conn.clone(),
+                atoms: self.atoms.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the (min_width, min_height) from WM_NORMAL_HINTS if the window set them.
+    pub fn min_size_hints(&self) -> Option<(u32, u32)> {
+        let reply = self
+            .conn
+            .wait_for_reply(self.conn.send_request(&xcb::x::GetProperty {
+                delete: false,
+                window: self.id,
+                property: xcb::x::ATOM_WM_NORMAL_HINTS,
+                r#type: xcb::x::ATOM_WM_SIZE_HINTS,
+                long_offset: 0,
+                long_length: 18,
+            }))
+            .unwrap();
+
+        // See ICCCM 4.1.2.3: flags, (4 padding fields), min_width, min_height, ...
+        const P_MIN_SIZE: u32 = 1 << 4;
+        match reply.value::<u32>() {
+            [flags, _, _, _, _, min_w, min_h, ..] if flags & P_MIN_SIZE != 0 => Some((*min_w, *min_h)),
+            _ => None,
+        }
+    }
+
     pub fn rect(&self) -> (i16, i16, u16, u16) {
         let geo = self
             .conn
@@ -361,5 +907,6 @@ impl Drop for XTestCase {
     fn drop(&mut self) {
         self.r3_child.kill().unwrap();
         self.x_child.kill().unwrap();
+        let _ = fs::remove_file(&self.config_path);
     }
 }
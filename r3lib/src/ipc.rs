@@ -0,0 +1,180 @@
+//! The protocol spoken over r3's socket. Each message is a JSON value, preceded by its length in bytes
+//! as a big-endian `u32`. Clients send an `R3Command` per message, and r3 replies to each one with a
+//! single message, in order: queries with what was asked for, and other commands with a
+//! `CommandReply`. `Subscribe` is replied to with a message per event instead, until the client
+//! disconnects. A connection may be used for as many commands as the client likes.
+//!
+//! A message may also be an array of commands (see `Message::Chain`), which are run one after the
+//! other without anything else happening in between, and replied to with an array of their replies.
+//!
+//! Messages are encoded as JSON, unless the client switches to another encoding with `SetEncoding`.
+
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use clap_derive::ArgEnum;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::R3Command;
+
+/// The version of the protocol, which changes whenever messages or replies change in a way that older
+/// clients wouldn't understand. Clients should check it with `GetVersion` before sending anything else
+///
+/// - 2: added `SetEncoding`
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The environment variable r3 sets to the path of its socket, so the processes it starts can find it
+/// without asking the X server. The path is also in the `R3_SOCKET_PATH` property of the root window
+pub const SOCKET_PATH_ENV: &str = "R3SOCK";
+
+/// The longest message which will be read, so a bad length can't make us allocate all our memory
+pub const MAX_MESSAGE_LEN: u32 = 16 * 1024 * 1024;
+
+/// A message sent to r3
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Message {
+    Command(R3Command),
+    /// Commands which are run in order, e.g. from `parse_commands`. The reply is an array with each
+    /// command's reply, so commands which failed are replied to with an `ErrorReply` in their place.
+    /// `Subscribe` can't be chained.
+    Chain(Vec<R3Command>),
+}
+
+/// The reply to a query which failed, or a message which couldn't be understood. Commands which fail
+/// reply with a `CommandReply`, which has the same `error` field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReply {
+    pub error: String,
+}
+
+/// The reply to `GetVersion`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionReply {
+    /// The version of r3 which is running
+    pub version: String,
+    /// The version of the protocol r3 speaks (see `PROTOCOL_VERSION`)
+    pub protocol_version: u32,
+    /// The files the configuration was read from
+    pub config_paths: Vec<PathBuf>,
+}
+
+/// The reply to a command which does something (rather than asking for something, like `GetTree`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandReply {
+    pub success: bool,
+    /// Why the command failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The X ids of the windows the command acted on
+    #[serde(default)]
+    pub windows: Vec<u32>,
+}
+
+impl CommandReply {
+    pub fn ok(windows: Vec<u32>) -> CommandReply {
+        CommandReply {
+            success: true,
+            error: None,
+            windows,
+        }
+    }
+
+    pub fn failed(error: impl Into<String>) -> CommandReply {
+        CommandReply {
+            success: false,
+            error: Some(error.into()),
+            windows: vec![],
+        }
+    }
+}
+
+/// How messages are encoded. Every connection starts with JSON, and CBOR (a binary encoding of the same
+/// values) can be used instead to save time encoding and decoding messages, e.g. for subscribers
+/// receiving many events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn encode<T: Serialize>(self, value: &T) -> io::Result<Vec<u8>> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|e| invalid(e.to_string())),
+            Encoding::Cbor => {
+                let mut message = vec![];
+                ciborium::ser::into_writer(value, &mut message).map_err(|e| invalid(e.to_string()))?;
+                Ok(message)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, message: &[u8]) -> io::Result<T> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            Encoding::Json => serde_json::from_slice(message).map_err(|e| invalid(e.to_string())),
+            Encoding::Cbor => ciborium::de::from_reader(message).map_err(|e| invalid(e.to_string())),
+        }
+    }
+}
+
+/// Write a single message.
+pub fn write_message(writer: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(message.len())
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message is too long"))?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(message)?;
+    writer.flush()
+}
+
+/// Read a single message. Returns `None` if the connection was closed before another message began.
+pub fn read_message(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message is too long ({} bytes)", len),
+        ));
+    }
+
+    let mut message = vec![0; len as usize];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Serialise the value as JSON and write it as a message.
+pub fn send<T: Serialize>(writer: &mut impl Write, value: &T) -> io::Result<()> {
+    send_as(writer, Encoding::Json, value)
+}
+
+/// Read a JSON message and deserialise it. Returns `None` if the connection was closed.
+pub fn recv<T: DeserializeOwned>(reader: &mut impl Read) -> io::Result<Option<T>> {
+    recv_as(reader, Encoding::Json)
+}
+
+/// Serialise the value with the encoding and write it as a message.
+pub fn send_as<T: Serialize>(writer: &mut impl Write, encoding: Encoding, value: &T) -> io::Result<()> {
+    write_message(writer, &encoding.encode(value)?)
+}
+
+/// Read a message with the encoding and deserialise it. Returns `None` if the connection was closed.
+pub fn recv_as<T: DeserializeOwned>(reader: &mut impl Read, encoding: Encoding) -> io::Result<Option<T>> {
+    match read_message(reader)? {
+        Some(message) => encoding.decode(&message).map(Some),
+        None => Ok(None),
+    }
+}
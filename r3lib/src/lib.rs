@@ -1,22 +1,572 @@
-use clap_derive::Subcommand;
+pub mod ipc;
+
+use std::iter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{CommandFactory, Parser};
+use clap_derive::{ArgEnum, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Subcommand)]
+use crate::ipc::Encoding;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Subcommand)]
 pub enum WMCommand {
     /// Close the currently focused window
     CloseWindow,
+    /// Close the next window that's clicked on (press Escape to cancel)
+    KillMode,
+    /// Wait for a window to be clicked on, and reply with information about it (press Escape to cancel)
+    PickWindow,
+    /// Focus the most recent urgent window, clearing its urgency
+    FocusUrgent {
+        /// Focus the oldest urgent window instead
+        #[clap(long)]
+        oldest: bool,
+    },
+    /// Focus the window matched by criteria, e.g. `[class="firefox"] focus` (if several windows match,
+    /// the last one is focused). Without criteria, this does nothing
+    Focus,
+    /// Focus the previously focused window (so repeating this switches between two windows)
+    FocusLast,
+    /// Focus the next window with the same class as the focused window
+    FocusNextOfClass {
+        /// Cycle through the windows in the opposite direction
+        #[clap(long)]
+        reverse: bool,
+    },
+    /// Move and resize the focused window back to where it was before it was last moved or resized
+    UndoGeometry,
+    /// Open the next window beside the focused tiled window (`horizontal`) or below it (`vertical`), by
+    /// splitting its container that way. Floating windows can't be split
+    Split {
+        /// Which way to split
+        #[clap(arg_enum)]
+        split: Split,
+    },
+    /// Show a live copy of the focused window in a new window (experimental)
+    MirrorWindow {
+        /// X position of the copy
+        #[clap(long, default_value = "0")]
+        x: i16,
+        /// Y position of the copy
+        #[clap(long, default_value = "0")]
+        y: i16,
+    },
+    /// Stop showing copies of windows
+    StopMirroring,
+    /// Run a command with the shell, replying with an error if it couldn't be started
+    Exec {
+        /// The command to run
+        command: String,
+    },
+    /// Briefly flash the border of a window so it's easy to find
+    FlashWindow {
+        /// The X id of the window (if not given, the focused window is flashed)
+        id: Option<u32>,
+    },
+    /// Reply with the hints which were read from a window, and the decisions made about it
+    ExplainWindow {
+        /// The X id of the window
+        id: u32,
+    },
+    /// Reply with the position of the pointer, and the managed window under it
+    GetPointer,
+    /// Reply with the outputs (monitors) RandR knows about, or an empty list if it isn't available
+    GetOutputs,
+    /// Reply with the tree of managed windows (the root window, frames and their clients), either
+    /// drawn so it's easy to read or as a `TreeNode`
+    GetTree {
+        /// How to draw the tree
+        #[clap(long, arg_enum, default_value = "ascii")]
+        format: TreeFormat,
+    },
+    /// Reply with a string which changes whenever the tree may have changed, so clients can poll it
+    /// instead of the whole tree. Chain it with `get-tree` to read both at once
+    GetStateHash,
+    /// Read the configuration file again and apply it, replying with an error if it couldn't be read.
+    /// If it can't be read then the current configuration is kept.
+    ReloadConfig,
     // TODO: get window state, etc
 }
 
-#[derive(Debug, Serialize, Deserialize, Subcommand)]
+/// How the tree of windows is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeFormat {
+    /// Indented text
+    Ascii,
+    /// A Graphviz graph, which can be drawn with `dot`
+    Dot,
+    /// A `TreeNode` for the root window, for scripts
+    Json,
+}
+
+/// Which way the children of a container are arranged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Split {
+    /// Side by side
+    #[clap(alias = "h")]
+    Horizontal,
+    /// One above the other
+    #[clap(alias = "v")]
+    Vertical,
+}
+
+/// What a node in the tree of windows is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeType {
+    Root,
+    /// Holds the tiled windows (there's only one workspace for now)
+    Workspace,
+    /// Holds tiled windows (and other containers) split one way
+    Container,
+    Frame,
+    Window,
+}
+
+/// The position and size of a node in the tree of windows, or of an output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    /// X position, relative to the parent node (or the screen, for outputs)
+    pub x: i16,
+    /// Y position, relative to the parent node (or the screen, for outputs)
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// A node in the tree of windows: the root window, the workspace or a container of tiled windows, a
+/// frame or a managed window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeNode {
+    /// The X id of the node's window (workspaces and containers aren't windows, so they have their own
+    /// ids, which don't clash with X's)
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    pub rect: Rect,
+    /// Whether this is the focused window
+    pub focused: bool,
+    /// Whether the node floats above the layout (tiled frames and windows, and containers, don't)
+    pub floating: bool,
+    /// How the children of a workspace or container are arranged
+    #[serde(default)]
+    pub split: Option<Split>,
+    /// The window's class, instance and title, if the node is a managed window
+    pub window: Option<WindowInfo>,
+    /// The node's children, in stacking order from the bottom up (or left to right and top to bottom,
+    /// for the children of workspaces and containers)
+    pub nodes: Vec<TreeNode>,
+}
+
+/// Information about a managed window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowInfo {
+    /// The X id of the window
+    pub id: u32,
+    /// The X id of the frame containing the window, if it has one
+    pub frame: Option<u32>,
+    /// The class part of `WM_CLASS`
+    pub class: String,
+    /// The instance part of `WM_CLASS`
+    pub instance: String,
+    /// The window's title
+    pub title: String,
+}
+
+/// An output (monitor)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputInfo {
+    /// The name the driver gave the output, e.g. `eDP-1` or `HDMI-1`
+    pub name: String,
+    pub connected: bool,
+    /// Whether the output is showing part of the screen
+    pub active: bool,
+    pub primary: bool,
+    /// The part of the screen the output shows, if it's active
+    pub rect: Option<Rect>,
+    // TODO: the workspace shown on the output, once we have workspaces
+}
+
+/// Prefixes of the names drivers give built-in laptop panels
+const INTERNAL_OUTPUT_PREFIXES: &[&str] = &["eDP", "LVDS", "DSI"];
+
+/// What the window manager last knew about an output, to work out which events a change to it causes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputState {
+    pub name: String,
+    pub connected: bool,
+    /// Whether the output is showing anything (i.e. it has a CRTC)
+    pub enabled: bool,
+}
+
+impl OutputState {
+    /// Whether this is a laptop's built-in panel
+    pub fn is_internal(&self) -> bool {
+        INTERNAL_OUTPUT_PREFIXES
+            .iter()
+            .any(|prefix| self.name.starts_with(prefix))
+    }
+
+    /// Whether a built-in panel is showing anything
+    pub fn panel_on(&self) -> bool {
+        self.is_internal() && self.connected && self.enabled
+    }
+
+    /// The events caused by the output changing to this state from `old` (or appearing, if it wasn't
+    /// known before).
+    pub fn events_since(&self, old: Option<&OutputState>) -> Vec<R3Event> {
+        let (was_connected, panel_was_on) = old.map_or((false, false), |old| (old.connected, old.panel_on()));
+        let output = self.name.clone();
+
+        let mut events = vec![];
+        if self.connected != was_connected {
+            events.push(match self.connected {
+                true => R3Event::OutputConnected { output: output.clone() },
+                false => R3Event::OutputDisconnected { output: output.clone() },
+            });
+        }
+        if self.panel_on() != panel_was_on {
+            events.push(match self.panel_on() {
+                true => R3Event::PanelOn { output },
+                false => R3Event::PanelOff { output },
+            });
+        }
+
+        events
+    }
+}
+
+/// The order queued commands are run in: commands with a higher priority are run first, and commands
+/// with the same priority are run in the order they were queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Commands which are slow, or act on every window (e.g. reloading the configuration)
+    Bulk,
+    Normal,
+    /// Commands the user is waiting to see the result of (e.g. focusing or closing a window)
+    Interactive,
+}
+
+/// A command waiting to be run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedCommandInfo {
+    pub command: R3Command,
+    pub priority: Priority,
+    /// How many clients are waiting for the command's reply (more than one if it was queued again
+    /// before it was run)
+    pub waiting: usize,
+}
+
+/// Where the pointer is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointerInfo {
+    /// X position of the pointer on the screen
+    pub x: i16,
+    /// Y position of the pointer on the screen
+    pub y: i16,
+    /// The managed window under the pointer, if there is one
+    pub window: Option<WindowInfo>,
+    /// The name of the output the pointer is on (if RandR is available)
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+/// Why a window is the way it is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowExplanation {
+    /// The window being explained
+    pub info: WindowInfo,
+    /// The hints read from the window's properties
+    pub hints: Vec<String>,
+    /// The configured rules which match the window (numbered from 1 in the order they're configured),
+    /// described by their criteria
+    #[serde(default)]
+    pub rules: Vec<String>,
+    /// The decisions made about the window, in the order they were made
+    pub decisions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Subcommand)]
 pub enum R3Command {
     /// Commands specific to Window Management
     #[clap(subcommand)]
     WM(WMCommand),
-    /// Returns the currently running version
+    /// Returns the running version of r3, the version of the IPC protocol it speaks and the files its
+    /// configuration was read from
     GetVersion,
-    /// Returns the current configuration
+    /// Returns the effective configuration: the values set by the configuration files, and the defaults
+    /// of everything else
     GetConfig,
+    /// Change a configuration value until the configuration is next reloaded, e.g.
+    /// `set-config focus_follows_mouse true`
+    SetConfig {
+        /// The key to change, with the keys of tables separated by dots, e.g. `gaps.inner`
+        key: String,
+        /// The new value, written as it would be in the configuration file
+        value: String,
+    },
+    /// Returns the configuration files which were read, in the order they were read (later files
+    /// override earlier ones)
+    GetConfigPaths,
+    /// Keep the connection open and receive events as they happen, one per line
+    Subscribe,
+    /// Returns the commands waiting to be run, in the order they'll be run
+    GetQueue,
+    /// Remove the command at this position in the queue (counting from 0, in the order `get-queue`
+    /// lists them), so it isn't run. The clients waiting for it are told it was cancelled
+    CancelQueued { index: usize },
+    /// Encode every message after the reply to this one (in both directions) differently. This can only
+    /// be sent by IPC clients, on its own (not in a chain)
+    SetEncoding {
+        #[clap(arg_enum)]
+        encoding: Encoding,
+    },
+    /// Reply once the window manager has handled everything the X server has sent it, and finished
+    /// updating the screen
+    Sync,
+    /// Send a tick event with the payload to every subscriber. Ticks are run after every command which
+    /// was queued before them, so scripts can wait for a tick to know their commands have been run
+    SendTick {
+        #[clap(default_value = "")]
+        payload: String,
+    },
+    /// Check that the window manager's state matches the X server's, and reply with any problems found
+    CheckConsistency {
+        /// Fix any problems that are found
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Reply with a description of the window manager's internal state (managed windows and their
+    /// cached properties, focus, drags, grabs, ignored sequences, ...), to attach to bug reports
+    DumpState {
+        /// Write the description to this file instead, replying with whether it could be written
+        #[clap(long)]
+        file: Option<PathBuf>,
+    },
+    /// Run a window management command on the windows matching the criteria rather than the focused
+    /// window. This is written as criteria in front of the command, e.g. `[class="firefox"] close-window`
+    // NOTE: clap still generates code for the fields of skipped variants, so they're skipped too
+    #[clap(skip)]
+    WithCriteria {
+        #[clap(skip)]
+        criteria: Criteria,
+        #[clap(skip = WMCommand::Focus)]
+        command: WMCommand,
+    },
     /// Exit the app
     Exit,
 }
+
+/// Criteria which pick the windows a command acts on, written like i3's: `[class="^Firefox$"
+/// title="Mozilla"]`. Each value is a regular expression which has to match part of the window's
+/// property (so `^` and `$` are needed to match all of it), and windows have to match every criterion.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Criteria {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Matches `WM_WINDOW_ROLE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The window's X id, written in decimal or hex (`0x...`), which has to be equal rather than match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    // TODO: `con_mark` once windows can be marked
+}
+
+impl FromStr for Criteria {
+    type Err = String;
+
+    /// Parse criteria without their brackets, e.g. `class="firefox" title=Mozilla`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut criteria = Criteria::default();
+        let mut rest = s.trim_start();
+        while !rest.is_empty() {
+            let (key, value) = rest
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value` in criteria, not `{}`", rest))?;
+
+            // Values may be quoted (with `\"` for quotes inside them), otherwise they end at whitespace
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(quoted) => {
+                    let mut value = String::new();
+                    let mut chars = quoted.char_indices();
+                    let end = loop {
+                        match chars.next() {
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, '"')) => value.push('"'),
+                                Some((_, c)) => value.extend(['\\', c]),
+                                None => return Err(format!("unterminated quote in criteria `{}`", s)),
+                            },
+                            Some((i, '"')) => break i + 1,
+                            Some((_, c)) => value.push(c),
+                            None => return Err(format!("unterminated quote in criteria `{}`", s)),
+                        }
+                    };
+                    (value, &quoted[end..])
+                }
+                None => {
+                    let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                    (value[..end].to_string(), &value[end..])
+                }
+            };
+
+            match key.trim() {
+                "class" => criteria.class = Some(value),
+                "instance" => criteria.instance = Some(value),
+                "window_role" => criteria.window_role = Some(value),
+                "title" => criteria.title = Some(value),
+                "id" => {
+                    let id = match value.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => value.parse(),
+                    };
+                    criteria.id = Some(id.map_err(|_| format!("invalid window id `{}` in criteria", value))?);
+                }
+                key => return Err(format!("unknown criterion `{}`", key)),
+            }
+            rest = remaining.trim_start();
+        }
+
+        match criteria == Criteria::default() {
+            true => Err("no criteria were given".into()),
+            false => Ok(criteria),
+        }
+    }
+}
+
+/// Split criteria in brackets from the start of a command, e.g. `[class="firefox"] close-window`.
+/// Brackets inside quoted values don't end the criteria.
+fn split_criteria(s: &str) -> Result<Option<(Criteria, &str)>, String> {
+    let inner = match s.trim_start().strip_prefix('[') {
+        Some(inner) => inner,
+        None => return Ok(None),
+    };
+
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted => return Ok(Some((inner[..i].parse()?, &inner[i + 1..]))),
+            _ => {}
+        }
+    }
+
+    Err(format!("missing `]` after criteria `{}`", s.trim()))
+}
+
+/// Used to parse commands from strings
+#[derive(Debug, Parser)]
+#[clap(no_binary_name = true)]
+struct CommandLine {
+    #[clap(subcommand)]
+    command: R3Command,
+}
+
+/// Commands are written the same way as they are with `r3-msg`, e.g. `wm focus-urgent --oldest`,
+/// except that `wm` may be left out. `exec` uses the rest of the line as its command, so it doesn't
+/// need to be quoted.
+impl FromStr for R3Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((criteria, command)) = split_criteria(s)? {
+            return match command.parse()? {
+                R3Command::WM(command) => Ok(R3Command::WithCriteria { criteria, command }),
+                _ => Err("only window management commands can be given criteria".into()),
+            };
+        }
+
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let command = match words.as_slice() {
+            ["wm", command @ ..] | command => command,
+        };
+        if command.first() == Some(&"exec") {
+            // Skip past "exec", keeping the command's whitespace intact
+            let start = s.find("exec").unwrap() + "exec".len();
+            return Ok(R3Command::WM(WMCommand::Exec {
+                command: s[start..].trim().into(),
+            }));
+        }
+
+        // Anything which isn't a top-level command is a window management command
+        let top_level = words
+            .first()
+            .is_some_and(|name| CommandLine::command().find_subcommand(*name).is_some());
+        let parsed = match top_level {
+            true => CommandLine::try_parse_from(&words),
+            false => CommandLine::try_parse_from(iter::once("wm").chain(words.iter().copied())),
+        };
+
+        // Only use the first line of the error, the rest explains how to use `r3-msg`
+        parsed.map(|line| line.command).map_err(|e| {
+            let message = e.to_string();
+            let line = message.lines().next().unwrap_or_default();
+            line.trim_start_matches("error: ").into()
+        })
+    }
+}
+
+/// Parse a chain of commands separated by `;`, e.g. `focus-last; [class="firefox"] close-window`.
+/// Since `exec` uses the rest of the line as its command, anything after it is part of that command.
+pub fn parse_commands(s: &str) -> Result<Vec<R3Command>, String> {
+    let mut commands = vec![];
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        // Criteria may have `;` in their values, so the command starts after them
+        let start = match split_criteria(rest)? {
+            Some((_, command)) => rest.len() - command.len(),
+            None => 0,
+        };
+
+        let words = rest[start..].split_whitespace().take(2).collect::<Vec<_>>();
+        let exec = matches!(words.as_slice(), ["exec", ..] | ["wm", "exec"]);
+        let (command, remaining) = match rest[start..].split_once(';') {
+            Some((command, remaining)) if !exec => (&rest[..start + command.len()], remaining),
+            _ => (rest, ""),
+        };
+
+        let command = command.trim();
+        commands.push(command.parse().map_err(|e| format!("`{}`: {}", command, e))?);
+        rest = remaining.trim();
+    }
+
+    match commands.is_empty() {
+        true => Err("no command was given".into()),
+        false => Ok(commands),
+    }
+}
+
+/// Events sent to subscribed clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum R3Event {
+    /// The user hasn't used the keyboard or mouse for one of the configured idle thresholds
+    Idle {
+        /// The threshold which was passed, in milliseconds
+        threshold: u64,
+    },
+    /// The user used the keyboard or mouse again after being idle
+    Resume,
+    /// An output (monitor) was connected
+    OutputConnected { output: String },
+    /// An output was disconnected
+    OutputDisconnected { output: String },
+    /// A laptop's built-in panel was turned off or disconnected, usually because its lid was closed
+    PanelOff { output: String },
+    /// A laptop's built-in panel was turned on again
+    PanelOn { output: String },
+    /// A client sent a tick (see `R3Command::SendTick`)
+    Tick { payload: String },
+}